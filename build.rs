@@ -0,0 +1,29 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    generate_header();
+    #[cfg(feature = "server")]
+    generate_service();
+}
+
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap())
+        .generate()
+        .expect("failed to generate the C header")
+        .write_to_file(format!("{}/include/pcq.h", crate_dir));
+}
+
+// the gRPC service's messages, server and client, from `proto/pc.proto`, with a protoc of its own, so that building
+// doesn't need one installed
+#[cfg(feature = "server")]
+fn generate_service() {
+    println!("cargo:rerun-if-changed=proto/pc.proto");
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    tonic_build::compile_protos("proto/pc.proto").expect("failed to generate the gRPC service");
+}
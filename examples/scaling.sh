@@ -0,0 +1,17 @@
+#!/bin/sh
+# Throughput of the single-lock backends against the sharded one, as the number of threads on each side grows.
+# Sharding only helps with several cores: run on a machine with at least 2 * the largest thread count.
+# usage: examples/scaling.sh [duration in seconds] [thread counts...]
+set -e
+duration=${1:-2}
+shift 2>/dev/null || true
+counts=${*:-1 2 4 8 16}
+
+cargo build --release --quiet
+for backend in condvar deque sharded; do
+    for n in $counts; do
+        target/release/pc bench --backend "$backend" --shards "$n" --producers "$n" --consumers "$n" \
+            --capacity $((n * 16)) --duration "$duration" | sed -n 1,2p | tr "\n" " "
+        echo
+    done
+done
@@ -0,0 +1,139 @@
+// The `Queue` backends, picked by `input.variant`: pushes, pops and their batches. The condvar backend, with each
+// overflow policy and resizing, and the deque backend are checked against the model; the others against the deque
+// backend, the reference implementation, running the same operations. Operations which would block are skipped, once
+// the backend agrees that they would; as `Queue` has no non-blocking operations, the tries are the same as the others.
+#![no_main]
+
+use std::{collections::VecDeque, sync::Arc};
+use libfuzzer_sys::fuzz_target;
+use rpc::{
+    Locker, Overflow, Queue, Strategy, SyncedBoundedBuffer,
+    deque::DequeBoundedBuffer,
+    eventcount::EventCountBoundedBuffer,
+    waiters::WaiterQueueBoundedBuffer,
+};
+#[cfg(target_os = "linux")]
+use rpc::futex::FutexBoundedBuffer;
+use rpc_fuzz::{Input, Model, Op, capacity};
+
+type MakeQueue = fn(usize) -> Arc<dyn Queue>;
+
+// what a backend is checked against
+enum Reference {
+    // the condvar backend, to be resized on `Op::Resize`, if it's that, and its overflow policy
+    Model(Option<Arc<SyncedBoundedBuffer>>, Overflow),
+    Deque,
+}
+
+// the condvar backend with each overflow policy, the deque backend, then the others; the sharded backend isn't FIFO,
+// and the double buffer has pushes wait while its producers' half is full, however much room the other has, so
+// they're left out
+fn make_queue(variant: u8, capacity: usize) -> (Arc<dyn Queue>, Reference) {
+    let overflows = [Overflow::Block, Overflow::DropOldest, Overflow::DropNewest];
+    let others: &[MakeQueue] = &[
+        |capacity| Arc::new(EventCountBoundedBuffer::new(capacity, false)),
+        |capacity| Arc::new(WaiterQueueBoundedBuffer::new(capacity, false)),
+        #[cfg(target_os = "linux")]
+        |capacity| Arc::new(FutexBoundedBuffer::new(capacity, false)),
+    ];
+    let variant = variant as usize % (overflows.len() + 1 + others.len());
+    match overflows.get(variant) {
+        Some(&overflow) => {
+            let buffer = Arc::new(SyncedBoundedBuffer::builder().capacity(capacity).overflow(overflow).build());
+            (buffer.clone(), Reference::Model(Some(buffer), overflow))
+        },
+        None if variant == overflows.len() =>
+            (Arc::new(DequeBoundedBuffer::new(capacity, false)), Reference::Model(None, Overflow::Block)),
+        None => (others[variant - overflows.len() - 1](capacity), Reference::Deque),
+    }
+}
+
+// push to the model as the backend would; returns whether it would have blocked instead
+fn model_push(model: &mut Model, overflow: Overflow, item: isize) -> bool {
+    match overflow {
+        _ if !model.full() => {},
+        Overflow::Block => return true,
+        Overflow::DropOldest => while model.full() { model.items.pop_front(); },
+        Overflow::DropNewest => return false,
+    }
+    model.push(item);
+    false
+}
+
+fn check_against_model(queue: &dyn Queue, condvar: Option<&SyncedBoundedBuffer>, overflow: Overflow, ops: Vec<Op>) {
+    let mut model = Model::new(queue.capacity());
+    let mut locker = Locker::new(Strategy::Block, "fuzz", 0);
+    for op in ops {
+        match op {
+            Op::Push(item) | Op::TryPush(item) => match model_push(&mut model, overflow, item as isize) {
+                true => assert!(queue.n_items() >= queue.capacity()),
+                false => queue.push(item as isize, &mut locker).unwrap(),
+            },
+            // a batch which doesn't fit would block partway through
+            Op::PushBatch(items) => if overflow != Overflow::Block || items.len() <= model.room() {
+                for &item in &items { model_push(&mut model, overflow, item as isize); }
+                let mut items: VecDeque<_> = items.into_iter().map(|item| item as isize).collect();
+                queue.push_batch(&mut items, &mut locker).unwrap();
+                assert!(items.is_empty());
+            },
+            Op::Pop | Op::TryPop => match model.pop().flatten() {
+                None => assert_eq!(queue.n_items(), 0),
+                Some(item) => assert_eq!(queue.pop(&mut locker).unwrap(), item),
+            },
+            Op::PopBatch(n) => if !model.items.is_empty() {
+                let popped: Vec<_> = (0..capacity(n)).map_while(|_| model.pop().flatten()).collect();
+                assert_eq!(queue.pop_batch(capacity(n), &mut locker).unwrap(), popped);
+            },
+            Op::Resize(n) => if let Some(condvar) = condvar {
+                if model.resize(capacity(n)) { condvar.set_capacity(capacity(n)); }
+            },
+            Op::Close { .. } => {},
+        }
+        assert_eq!(queue.capacity(), model.capacity);
+        assert_eq!(queue.n_items(), model.items.len());
+        assert_eq!(queue.snapshot(), Vec::from(model.items.clone()));
+    }
+}
+
+// the same operations on `queue` and on the deque backend, which must agree on every result and every state
+fn check_against_deque(queue: &dyn Queue, ops: Vec<Op>) {
+    let reference = DequeBoundedBuffer::new(queue.capacity(), false);
+    let mut locker = Locker::new(Strategy::Block, "fuzz", 0);
+    for op in ops {
+        let room = reference.capacity() - reference.n_items();
+        match op {
+            Op::Push(item) | Op::TryPush(item) => match room {
+                0 => assert!(queue.n_items() >= queue.capacity()),
+                _ => {
+                    reference.push(item as isize, &mut locker).unwrap();
+                    queue.push(item as isize, &mut locker).unwrap();
+                },
+            },
+            Op::PushBatch(items) => if items.len() <= room {
+                let items: VecDeque<_> = items.into_iter().map(|item| item as isize).collect();
+                reference.push_batch(&mut items.clone(), &mut locker).unwrap();
+                queue.push_batch(&mut items.clone(), &mut locker).unwrap();
+            },
+            Op::Pop | Op::TryPop => match reference.n_items() {
+                0 => assert_eq!(queue.n_items(), 0),
+                _ => assert_eq!(queue.pop(&mut locker), reference.pop(&mut locker)),
+            },
+            Op::PopBatch(n) => if reference.n_items() > 0 {
+                let popped = reference.pop_batch(capacity(n), &mut locker);
+                assert_eq!(queue.pop_batch(capacity(n), &mut locker), popped);
+            },
+            Op::Resize(_) | Op::Close { .. } => {},
+        }
+        assert_eq!(queue.capacity(), reference.capacity());
+        assert_eq!(queue.n_items(), reference.n_items());
+        assert_eq!(queue.snapshot(), reference.snapshot());
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let (queue, reference) = make_queue(input.variant, capacity(input.capacity));
+    match reference {
+        Reference::Model(condvar, overflow) => check_against_model(&*queue, condvar.as_deref(), overflow, input.ops),
+        Reference::Deque => check_against_deque(&*queue, input.ops),
+    }
+});
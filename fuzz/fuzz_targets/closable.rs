@@ -0,0 +1,68 @@
+// `ClosableBuffer`: blocking and non-blocking pushes and pops, and closing, against the model. A blocking operation
+// which would wait forever on the single thread takes one pass through its wait loop instead.
+#![no_main]
+
+use std::time::Duration;
+use libfuzzer_sys::fuzz_target;
+use rpc::{ClosableBuffer, OnClose, Pass, PopError, PushError};
+use rpc_fuzz::{Input, Model, Op, capacity};
+
+// returns what the model made of the push
+fn push(buffer: &ClosableBuffer<isize>, model: &mut Model, item: isize) -> Option<bool> {
+    let pushed = model.push(item);
+    match pushed {
+        None        => assert_eq!(buffer.produce_one(item), Pass::Wait(item)),
+        Some(true)  => assert_eq!(buffer.push(item), Ok(())),
+        Some(false) => assert_eq!(buffer.push(item), Err(PushError::Closed(item))),
+    }
+    pushed
+}
+
+fuzz_target!(|input: Input| {
+    let buffer = ClosableBuffer::new(capacity(input.capacity));
+    let mut model = Model::new(buffer.capacity());
+    for op in input.ops {
+        match op {
+            Op::Push(item) => { push(&buffer, &mut model, item as isize); },
+            Op::PushBatch(items) => for item in items {
+                if push(&buffer, &mut model, item as isize) != Some(true) { break; }
+            },
+            Op::TryPush(item) => {
+                let item = item as isize;
+                let expected = match model.push(item) {
+                    None        => Err(PushError::Timeout(item)),
+                    Some(true)  => Ok(()),
+                    Some(false) => Err(PushError::Closed(item)),
+                };
+                assert_eq!(buffer.push_timeout(item, Duration::ZERO), expected);
+            },
+            Op::Pop => match model.pop() {
+                None => assert_eq!(buffer.consume_one(), Pass::Wait(())),
+                Some(item) => assert_eq!(buffer.pop(), item.ok_or(PopError::Closed)),
+            },
+            Op::TryPop => {
+                let expected = match model.pop() {
+                    None => Err(PopError::Timeout),
+                    Some(item) => item.ok_or(PopError::Closed),
+                };
+                assert_eq!(buffer.pop_timeout(Duration::ZERO), expected);
+            },
+            Op::PopBatch(n) => {
+                let popped: Vec<_> = (0..capacity(n)).map_while(|_| model.pop().flatten()).collect();
+                let expected = match popped.is_empty() {
+                    false => Ok(popped),
+                    true if model.closed => Err(PopError::Closed),
+                    true => Err(PopError::Timeout),
+                };
+                assert_eq!(buffer.pop_batch_min(1, capacity(n), Duration::ZERO), expected);
+            },
+            Op::Close { discard } => {
+                let on_close = if discard { OnClose::Discard } else { OnClose::Drain };
+                assert_eq!(buffer.close_with(on_close), model.close(discard));
+            },
+            Op::Resize(_) => {},
+        }
+        assert_eq!(buffer.closed(), model.closed);
+        assert_eq!(buffer.snapshot(), Vec::from(model.items.clone()));
+    }
+});
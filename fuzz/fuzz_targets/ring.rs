@@ -0,0 +1,35 @@
+// The unsynchronized ring under every buffer: pushes, pops and resizes, against the model.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpc::RingBuffer;
+use rpc_fuzz::{Input, Model, Op, capacity};
+
+fuzz_target!(|input: Input| {
+    let mut ring = RingBuffer::new(capacity(input.capacity));
+    let mut model = Model::new(ring.capacity());
+    for op in input.ops {
+        match op {
+            Op::Push(item) | Op::TryPush(item) => {
+                assert_eq!(ring.full(), model.full());
+                if model.push(item as isize) == Some(true) { ring.push(item as isize); }
+            },
+            Op::PushBatch(items) => for item in items {
+                if model.push(item as isize) != Some(true) { break; }
+                ring.push(item as isize);
+            },
+            Op::Pop | Op::TryPop => {
+                assert_eq!(ring.empty(), model.items.is_empty());
+                if let Some(Some(item)) = model.pop() { assert_eq!(ring.pop(), item); }
+            },
+            Op::PopBatch(n) => for _ in 0..capacity(n) {
+                let Some(Some(item)) = model.pop() else { break };
+                assert_eq!(ring.pop(), item);
+            },
+            Op::Resize(n) => if model.resize(capacity(n)) { ring.resize(capacity(n)); },
+            Op::Close { .. } => {},
+        }
+        assert_eq!(ring.capacity(), model.capacity);
+        assert!(ring.iter().copied().eq(model.items.iter().copied()), "{:?}", model);
+    }
+});
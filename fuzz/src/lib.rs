@@ -0,0 +1,84 @@
+// What the fuzz targets share: the operations they decode their input into, and the model queue they check the
+// buffers against.
+
+use std::collections::VecDeque;
+use arbitrary::Arbitrary;
+
+// the largest capacity the targets try, small so that buffers are often full
+pub const MAX_CAPACITY: u8 = 16;
+
+// `n` as a capacity between 1 and `MAX_CAPACITY`
+pub fn capacity(n: u8) -> usize { 1 + (n % MAX_CAPACITY) as usize }
+
+/* One operation on a buffer. Each target runs those its buffer has, and skips the rest: there's a single thread, so an
+operation which would block instead only checks that the buffer agrees it would (by asking it to try, where it can).
+*/
+#[derive(Arbitrary, Debug)]
+pub enum Op {
+    // push, blocking while full
+    Push(i16),
+    // push if there's room, without waiting
+    TryPush(i16),
+    PushBatch(Vec<i16>),
+    // pop, blocking while empty
+    Pop,
+    // pop if there's an item, without waiting
+    TryPop,
+    // pop up to `capacity(n)` items
+    PopBatch(u8),
+    Close { discard: bool },
+    // change the capacity to `capacity(n)`
+    Resize(u8),
+}
+
+#[derive(Arbitrary, Debug)]
+pub struct Input {
+    pub capacity: u8,
+    // which variant of the buffer to run, for targets with several
+    pub variant: u8,
+    pub ops: Vec<Op>,
+}
+
+/* A bounded FIFO queue which can be closed, written as plainly as possible, for the buffers to agree with: pushes fail
+once it's closed, and pops once it's closed and empty. Operations which would block return `None`.
+*/
+#[derive(Debug)]
+pub struct Model {
+    pub items: VecDeque<isize>,
+    pub capacity: usize,
+    pub closed: bool,
+}
+impl Model {
+
+    pub fn new(capacity: usize) -> Self { Model { items: VecDeque::new(), capacity, closed: false } }
+
+    pub fn full(&self) -> bool { self.items.len() >= self.capacity }
+    pub fn room(&self) -> usize { self.capacity.saturating_sub(self.items.len()) }
+
+    // whether the item went in (`Some(false)` once closed)
+    pub fn push(&mut self, item: isize) -> Option<bool> {
+        if self.closed { return Some(false); }
+        if self.full() { return None; }
+        self.items.push_back(item);
+        Some(true)
+    }
+
+    // the item, if there was one (`Some(None)` once closed and empty)
+    pub fn pop(&mut self) -> Option<Option<isize>> {
+        if self.items.is_empty() && !self.closed { return None; }
+        Some(self.items.pop_front())
+    }
+
+    // the items which were in it, if discarded
+    pub fn close(&mut self, discard: bool) -> Vec<isize> {
+        self.closed = true;
+        if discard { self.items.drain(..).collect() } else { Vec::new() }
+    }
+
+    // whether the items fit in the new capacity, as otherwise resizing would block until consumers had made room
+    pub fn resize(&mut self, capacity: usize) -> bool {
+        if self.items.len() > capacity { return false; }
+        self.capacity = capacity;
+        true
+    }
+}
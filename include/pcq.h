@@ -0,0 +1,69 @@
+#ifndef PCQ_H
+#define PCQ_H
+
+/* Generated by cbindgen from src/ffi.rs; don't edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Success.
+ */
+#define PCQ_OK 0
+
+/**
+ * The queue has been closed (and, for `pcq_pop`, drained).
+ */
+#define PCQ_CLOSED 1
+
+/**
+ * A null pointer was passed.
+ */
+#define PCQ_INVALID -1
+
+/**
+ * Opaque to C.
+ */
+typedef struct PcqQueue PcqQueue;
+
+/**
+ * Returns null if `capacity` is 0. The queue must be freed with `pcq_destroy`.
+ */
+struct PcqQueue *pcq_create(uintptr_t capacity);
+
+/**
+ * Blocks while the queue is full. Returns `PCQ_CLOSED` (without pushing) if the queue is closed.
+ *
+ * # Safety
+ * `queue` must be null or a pointer returned by `pcq_create` which hasn't been passed to `pcq_destroy`.
+ */
+int32_t pcq_push(struct PcqQueue *queue,
+                 intptr_t item);
+
+/**
+ * Blocks while the queue is empty. Items pushed before closing are still popped after it; once the queue is closed
+ * and empty, returns `PCQ_CLOSED` and leaves `*item` untouched.
+ *
+ * # Safety
+ * `queue` must be as for `pcq_push`, and `item` must be null or valid for writes.
+ */
+int32_t pcq_pop(struct PcqQueue *queue,
+                intptr_t *item);
+
+/**
+ * Wakes all blocked threads; subsequent pushes fail, and pops fail once the remaining items are gone.
+ *
+ * # Safety
+ * As for `pcq_push`.
+ */
+int32_t pcq_close(struct PcqQueue *queue);
+
+/**
+ * Frees the queue and any items still in it. No other thread may be using it, or use it afterwards.
+ *
+ * # Safety
+ * As for `pcq_push`.
+ */
+void pcq_destroy(struct PcqQueue *queue);
+
+#endif  /* PCQ_H */
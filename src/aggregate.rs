@@ -0,0 +1,35 @@
+// Summary statistics of a measurement repeated over several runs.
+
+pub struct Summary {
+    pub mean: f64,
+    // the sample standard deviation (0 for a single run)
+    pub sd: f64,
+    pub min: f64,
+    pub max: f64,
+    // the indices of the runs outside Tukey's fences: more than 1.5 interquartile ranges below the first quartile or
+    // above the third, which doesn't assume the values are normally distributed
+    pub outliers: Vec<usize>,
+}
+
+// `values` mustn't be empty
+pub fn summarize(values: &[f64]) -> Summary {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let (q1, q3) = (quantile(&sorted, 0.25), quantile(&sorted, 0.75));
+    let (low, high) = (q1 - 1.5 * (q3 - q1), q3 + 1.5 * (q3 - q1));
+    Summary {
+        mean, sd: variance.sqrt(), min: sorted[0], max: sorted[sorted.len() - 1],
+        outliers: (0..values.len()).filter(|&i| values[i] < low || values[i] > high).collect(),
+    }
+}
+
+// interpolating linearly between the closest ranks of `sorted`
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
@@ -0,0 +1,135 @@
+use std::{collections::VecDeque, ops::{Add, Deref}, sync::Arc};
+
+// what a producer's arenas have been through so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    // batches which needed a new arena, and those which reused one whose items had all been dropped
+    pub n_made: u64,
+    pub n_reused: u64,
+    // the arenas some of whose items are still around
+    pub n_held: usize,
+}
+// for totting up several producers'
+impl Add for ArenaStats {
+    type Output = ArenaStats;
+    fn add(self, other: ArenaStats) -> ArenaStats {
+        ArenaStats {
+            n_made: self.n_made + other.n_made, n_reused: self.n_reused + other.n_reused,
+            n_held: self.n_held + other.n_held,
+        }
+    }
+}
+
+/* Arena allocation for byte payloads, whose lifetime is tied to a batch: a producer writes a batch's payloads one after
+the other into a single arena, then hands out a `Payload` for each, a handle to its slice of the arena, so that a
+batch takes one allocation rather than one per item. The arena lives for as long as any of its payloads does; once
+the consumers have dropped every one, the next batch reuses it, so that in the steady state nothing is allocated.
+
+An `Arenas` belongs to a single producer, and keeps a reference to each arena it's handed payloads out from, oldest
+first; a batch reuses the oldest if that's the only reference left, and makes a new arena otherwise. Consumers popping
+in order free them in order, so this finds one whenever one is free, more or less.
+*/
+pub struct Arenas {
+    // the bytes each new arena starts with room for
+    arena_bytes: usize,
+    arenas: VecDeque<Arc<Vec<u8>>>,
+    // where each payload of the batch being written is in its arena, kept to save allocating for every batch
+    ranges: Vec<(usize, usize)>,
+    n_made: u64,
+    n_reused: u64,
+}
+impl Arenas {
+
+    pub fn new(arena_bytes: usize) -> Self {
+        Arenas { arena_bytes, arenas: VecDeque::new(), ranges: Vec::new(), n_made: 0, n_reused: 0 }
+    }
+
+    // start writing a batch, in an arena whose payloads have all been dropped if there is one
+    pub fn batch(&mut self) -> Batch<'_> {
+        let reusable = self.arenas.front_mut().is_some_and(|arena| Arc::get_mut(arena).is_some());
+        let mut arena = if reusable {
+            self.n_reused += 1;
+            self.arenas.pop_front().unwrap()
+        } else {
+            self.n_made += 1;
+            Arc::new(Vec::with_capacity(self.arena_bytes))
+        };
+        Arc::get_mut(&mut arena).unwrap().clear();
+        self.ranges.clear();
+        Batch { arenas: self, arena }
+    }
+
+    pub fn stats(&self) -> ArenaStats {
+        let n_held = self.arenas.iter().filter(|arena| Arc::strong_count(arena) > 1).count();
+        ArenaStats { n_made: self.n_made, n_reused: self.n_reused, n_held }
+    }
+}
+
+// a batch of payloads being written into an arena; an arena too small for them grows
+pub struct Batch<'a> {
+    arenas: &'a mut Arenas,
+    // only referred to from here until `finish`
+    arena: Arc<Vec<u8>>,
+}
+impl<'a> Batch<'a> {
+
+    // a payload of `len` bytes, which `fill` writes, starting from zeros
+    pub fn push_with(&mut self, len: usize, fill: impl FnOnce(&mut [u8])) {
+        let bytes = Arc::get_mut(&mut self.arena).unwrap();
+        let start = bytes.len();
+        bytes.resize(start + len, 0);
+        fill(&mut bytes[start..]);
+        self.arenas.ranges.push((start, len));
+    }
+
+    pub fn len(&self) -> usize { self.arenas.ranges.len() }
+    pub fn is_empty(&self) -> bool { self.arenas.ranges.is_empty() }
+
+    // a handle to each payload, in the order they were written
+    pub fn finish(self) -> impl Iterator<Item = Payload> + 'a {
+        let Batch { arenas, arena } = self;
+        arenas.arenas.push_back(arena.clone());
+        arenas.ranges.drain(..).map(move |(start, len)| Payload { arena: arena.clone(), start, len })
+    }
+}
+
+// a payload in an arena, which keeps the arena alive
+pub struct Payload {
+    arena: Arc<Vec<u8>>,
+    start: usize,
+    len: usize,
+}
+impl Deref for Payload {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.arena[self.start..self.start + self.len] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a batch's payloads share an arena, which the next batch reuses only once they've all been dropped
+    #[test]
+    fn reuses_an_arena_once_its_payloads_are_dropped() {
+        let mut arenas = Arenas::new(16);
+        let mut batch = arenas.batch();
+        for (i, len) in [3, 5].into_iter().enumerate() { batch.push_with(len, |bytes| bytes.fill(i as u8 + 1)); }
+        let first: Vec<_> = batch.finish().collect();
+        assert_eq!((&first[0][..], &first[1][..]), (&[1; 3][..], &[2; 5][..]));
+        assert_eq!(first[0].as_ptr().wrapping_add(3), first[1].as_ptr());
+
+        let mut batch = arenas.batch();
+        batch.push_with(2, |bytes| bytes.fill(3));
+        let second: Vec<_> = batch.finish().collect();
+        assert_ne!(second[0].as_ptr(), first[0].as_ptr());
+        assert_eq!(arenas.stats(), ArenaStats { n_made: 2, n_reused: 0, n_held: 2 });
+
+        let pointer = first[0].as_ptr();
+        drop(first);
+        let mut batch = arenas.batch();
+        batch.push_with(4, |bytes| bytes.fill(4));
+        let third: Vec<_> = batch.finish().collect();
+        assert_eq!((third[0].as_ptr(), &third[0][..]), (pointer, &[4; 4][..]));
+        assert_eq!(arenas.stats(), ArenaStats { n_made: 2, n_reused: 1, n_held: 2 });
+    }
+}
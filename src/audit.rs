@@ -0,0 +1,53 @@
+// Counting the allocations the producers and consumers make, to check that their hot paths don't allocate.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+};
+
+/* The system allocator, counting the allocations (and reallocations) made by the threads which have called `start`;
+frees aren't counted, as freeing what was allocated before is fine.
+*/
+struct Counting;
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+static N_ALLOCS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // const, so that accessing it doesn't allocate
+    static AUDITED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count() {
+    // `try_with`, as the thread may be allocating while being torn down
+    if AUDITED.try_with(Cell::get).unwrap_or(false) { N_ALLOCS.fetch_add(1, Relaxed); }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) { System.dealloc(ptr, layout) }
+}
+
+// count the calling thread's allocations from now on
+pub fn start() { AUDITED.with(|audited| audited.set(true)); }
+
+// the number of allocations counted since the last `reset`
+pub fn n_allocs() -> u64 { N_ALLOCS.load(Relaxed) }
+pub fn reset() { N_ALLOCS.store(0, Relaxed); }
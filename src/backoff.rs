@@ -0,0 +1,146 @@
+use std::{
+    thread,
+    hint,
+    time::{Duration, Instant},
+};
+
+const MIN_SPINS: u32 = 1;
+const MAX_SPINS: u32 = 1 << 10;
+const MAX_SLEEP: Duration = Duration::from_micros(1 << 10);
+// weight of the newest sample in the moving average of contention
+const SMOOTHING: f64 = 0.125;
+// above this the thread backs off harder, below this it becomes more aggressive
+const HIGH_CONTENTION: f64 = 0.5;
+const LOW_CONTENTION:  f64 = 0.1;
+
+/* Per-thread lock acquisition strategy which adapts to contention.
+A thread first tries to take the lock `spins` times without blocking; if all of those attempts fail it sleeps for
+`sleep` and then blocks on the lock. Every acquisition (and every time a thread is woken from a condvar only to
+find that it has to wait again) is a sample of how contended the buffer is; a moving average of those samples
+decides whether spinning is paying off (low contention: spin longer, sleep less) or wasting CPU (high contention:
+spin less, sleep longer).
+*/
+pub struct AdaptiveBackoff {
+    spins: u32,
+    sleep: Duration,
+    contention: f64,
+    start: Instant,
+    // every change of parameters since the last report, as (time since start, spins, sleep, contention)
+    history: Vec<(Duration, u32, Duration, f64)>,
+}
+impl AdaptiveBackoff {
+
+    pub fn new() -> Self {
+        let start = Instant::now();
+        AdaptiveBackoff {
+            spins: MIN_SPINS, sleep: Duration::ZERO, contention: 0.0,
+            start, history: vec![(Duration::ZERO, MIN_SPINS, Duration::ZERO, 0.0)],
+        }
+    }
+
+    // take a lock given a non-blocking and a blocking way to take it
+    pub fn acquire<G>(&mut self, mut try_lock: impl FnMut() -> Option<G>, lock: impl FnOnce() -> G) -> G {
+        for attempt in 0..self.spins {
+            match try_lock() {
+                Some(guard) => {
+                    self.observe(attempt as f64 / self.spins as f64);
+                    return guard;
+                },
+                None => hint::spin_loop(),
+            }
+        }
+
+        // every attempt failed, which is as contended as it gets
+        self.observe(1.0);
+        if !self.sleep.is_zero() { thread::sleep(self.sleep); }
+        lock()
+    }
+
+    // to be called when a thread woken from a condvar has to wait again straight away
+    pub fn reblocked(&mut self) { self.observe(1.0); }
+
+    fn observe(&mut self, sample: f64) {
+        self.contention += SMOOTHING * (sample - self.contention);
+
+        let (spins, sleep) =
+            if self.contention > HIGH_CONTENTION {
+                let sleep = if self.sleep.is_zero() { Duration::from_micros(1) } else { self.sleep * 2 };
+                ((self.spins / 2).max(MIN_SPINS), sleep.min(MAX_SLEEP))
+            }
+            else if self.contention < LOW_CONTENTION { ((self.spins * 2).min(MAX_SPINS), self.sleep / 2) }
+            else { (self.spins, self.sleep) };
+
+        if (spins, sleep) != (self.spins, self.sleep) {
+            self.spins = spins;
+            self.sleep = sleep;
+            self.history.push((self.start.elapsed(), spins, sleep, self.contention));
+        }
+    }
+
+    // print the parameter changes since the last report to stderr, and forget them
+    pub fn report(&mut self, thread_name: &str) {
+        for (time, spins, sleep, contention) in self.history.drain(..) {
+            eprintln!(
+                "[{:>10.3}s] {}: spins = {}, sleep = {:?} (contention {:.2})",
+                time.as_secs_f64(), thread_name, spins, sleep, contention,
+            );
+        }
+    }
+}
+impl Default for AdaptiveBackoff {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sustained contention cuts the spins to the minimum and doubles the sleep up to the maximum
+    #[test]
+    fn backs_off_under_contention() {
+        let mut backoff = AdaptiveBackoff::new();
+        let mut sleep = backoff.sleep;
+        for _ in 0..100 {
+            backoff.observe(1.0);
+            assert!(backoff.sleep >= sleep && backoff.sleep <= MAX_SLEEP);
+            sleep = backoff.sleep;
+        }
+        assert_eq!((backoff.spins, backoff.sleep), (MIN_SPINS, MAX_SLEEP));
+    }
+
+    // once contention falls away, the sleep halves down to nothing and the spins double up to the maximum
+    #[test]
+    fn spins_longer_without_contention() {
+        let mut backoff = AdaptiveBackoff::new();
+        for _ in 0..100 { backoff.observe(1.0); }
+        let mut spins = backoff.spins;
+        for _ in 0..100 {
+            backoff.observe(0.0);
+            assert!(backoff.spins >= spins && backoff.spins <= MAX_SPINS);
+            spins = backoff.spins;
+        }
+        assert_eq!((backoff.spins, backoff.sleep), (MAX_SPINS, Duration::ZERO));
+    }
+
+    // a lock which can never be taken without blocking counts as fully contended
+    #[test]
+    fn failed_spins_are_contention() {
+        let mut backoff = AdaptiveBackoff::new();
+        for _ in 0..20 { backoff.acquire(|| None, || ()); }
+        assert!(backoff.contention > HIGH_CONTENTION && !backoff.sleep.is_zero());
+    }
+
+    // reports forget the changes they print, so the history doesn't grow over a long run
+    #[test]
+    fn reports_drain_the_history() {
+        let mut backoff = AdaptiveBackoff::new();
+        for _ in 0..100 { backoff.observe(1.0); }
+        assert!(backoff.history.len() > 1);
+        backoff.report("thread");
+        assert!(backoff.history.is_empty());
+        backoff.observe(0.0);
+        assert!(backoff.history.is_empty());
+        for _ in 0..100 { backoff.observe(0.0); }
+        assert!(!backoff.history.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+/* A short stress test of every backend, to run under ThreadSanitizer, which catches data races (e.g. in unsafe code, or
+in atomics with too weak an ordering) as they happen instead of only when they corrupt something:
+
+    RUSTFLAGS="-Zsanitizer=thread" cargo +nightly run -Zbuild-std --target x86_64-unknown-linux-gnu --bin stress
+
+Instrumented code runs many times slower, so each round passes only a few items, between a few threads, through a small
+buffer, which keeps the threads contending; after each round it checks that every item was popped exactly once. It
+exits with a nonzero status if any round failed (or if the sanitizer reported a race, with its default options).
+
+    stress [--rounds <n>] [--items <n>]
+
+`--rounds` is how many rounds each backend runs (default 100), and `--items` how many items each producer pushes in each
+(default 200). A new backend only needs adding to `QUEUES`, or a round of its own if it isn't a `Queue`.
+*/
+
+use std::{
+    env,
+    process,
+    sync::{Arc, atomic::{AtomicUsize, Ordering::Relaxed}},
+    thread,
+    time::Instant,
+};
+use rpc::{
+    ClosableBuffer, Queue, SpinBoundedBuffer, Strategy, SyncedBoundedBuffer,
+    deque::DequeBoundedBuffer,
+    eventcount::EventCountBoundedBuffer,
+    lanes::LanedBoundedBuffer,
+    runner::Runner,
+    sharded::ShardedBoundedBuffer,
+    swap::SwapBoundedBuffer,
+    waiters::WaiterQueueBoundedBuffer,
+};
+#[cfg(target_os = "linux")]
+use rpc::futex::FutexBoundedBuffer;
+
+type MakeQueue = fn(usize) -> Arc<dyn Queue>;
+// runs round k, returning whether it passed
+type Round = Box<dyn Fn(usize) -> bool>;
+
+const QUEUES: &[(&str, MakeQueue)] = &[
+    ("condvar",    |capacity| Arc::new(SyncedBoundedBuffer::new(capacity, false))),
+    #[cfg(target_os = "linux")]
+    ("futex",      |capacity| Arc::new(FutexBoundedBuffer::new(capacity, false))),
+    ("eventcount", |capacity| Arc::new(EventCountBoundedBuffer::new(capacity, false))),
+    ("deque",      |capacity| Arc::new(DequeBoundedBuffer::new(capacity, false))),
+    ("sharded",    |capacity| Arc::new(ShardedBoundedBuffer::new(capacity, 2, false).with_stealing(true))),
+    ("lanes",      |capacity| Arc::new(LanedBoundedBuffer::new(capacity, false))),
+    ("waiters",    |capacity| Arc::new(WaiterQueueBoundedBuffer::new(capacity, false))),
+    // with a half of each capacity
+    ("swap",       |capacity| Arc::new(SwapBoundedBuffer::new(2 * capacity, false))),
+];
+
+// round `round` has 1 to 3 producers and 1 to 3 consumers, and a capacity of 1 or 4
+fn shape(round: usize) -> (usize, usize, usize) { (1 + round % 3, 1 + round / 3 % 3, [1, 4][round / 9 % 2]) }
+
+// whether every item below `n_total` is in `popped` exactly once
+fn exactly_once(popped: impl IntoIterator<Item = usize>, n_total: usize) -> bool {
+    let mut n_times_popped = vec![0; n_total];
+    for item in popped {
+        match n_times_popped.get_mut(item) {
+            Some(n) => *n += 1,
+            None => return false,
+        }
+    }
+    n_times_popped.iter().all(|&n| n == 1)
+}
+
+// as `pc verify` does: consumers claim an item before popping it, so that they stop once every item has been claimed
+fn queue_round(make_queue: MakeQueue, round: usize, n_items: usize) -> bool {
+    let (n_producers, n_consumers, capacity) = shape(round);
+    let n_total = n_producers * n_items;
+    let mut runner = Runner::new(make_queue(capacity), Strategy::Block);
+    for i in 0..n_producers {
+        runner.spawn_producer(move |queue, locker| {
+            for item in i * n_items..(i + 1) * n_items { queue.push(item as isize, locker).unwrap(); }
+        });
+    }
+    let n_unclaimed = Arc::new(AtomicUsize::new(n_total));
+    for _ in 0..n_consumers {
+        let n_unclaimed = n_unclaimed.clone();
+        runner.spawn_consumer(move |queue, locker| {
+            let mut popped = Vec::new();
+            while n_unclaimed.fetch_update(Relaxed, Relaxed, |n| n.checked_sub(1)).is_ok() {
+                popped.push(queue.pop(locker).unwrap() as usize);
+            }
+            popped
+        });
+    }
+    let popped = runner.join().consumers.into_iter().flat_map(|popped| popped.unwrap());
+    exactly_once(popped, n_total)
+}
+
+// consumers pop until the buffer is closed and empty, which happens once the producers are done
+fn closable_round(round: usize, n_items: usize) -> bool {
+    let (n_producers, n_consumers, capacity) = shape(round);
+    let buffer = Arc::new(ClosableBuffer::new(capacity));
+    let producers: Vec<_> = (0..n_producers).map(|i| {
+        let buffer = buffer.clone();
+        thread::spawn(move || for item in i * n_items..(i + 1) * n_items { buffer.push(item).unwrap(); })
+    }).collect();
+    let consumers: Vec<_> = (0..n_consumers).map(|_| {
+        let buffer = buffer.clone();
+        thread::spawn(move || std::iter::from_fn(|| buffer.pop().ok()).collect::<Vec<_>>())
+    }).collect();
+    for producer in producers { producer.join().unwrap(); }
+    buffer.close();
+    exactly_once(consumers.into_iter().flat_map(|consumer| consumer.join().unwrap()), n_producers * n_items)
+}
+
+// the spinning buffer's capacity is fixed when it's compiled, so only the numbers of threads vary; its threads yield
+// instead of spinning while they wait, as spinning on fewer CPUs than threads could take a whole time slice each time
+fn spin_round(round: usize, n_items: usize) -> bool {
+    let (n_producers, n_consumers, _) = shape(round);
+    let n_total = n_producers * n_items;
+    let buffer = Arc::new(SpinBoundedBuffer::<usize, 4>::new());
+    let producers: Vec<_> = (0..n_producers).map(|i| {
+        let buffer = buffer.clone();
+        thread::spawn(move || for item in i * n_items..(i + 1) * n_items {
+            while buffer.try_push(item).is_err() { thread::yield_now(); }
+        })
+    }).collect();
+    let n_unclaimed = Arc::new(AtomicUsize::new(n_total));
+    let consumers: Vec<_> = (0..n_consumers).map(|_| {
+        let (buffer, n_unclaimed) = (buffer.clone(), n_unclaimed.clone());
+        thread::spawn(move || {
+            let mut popped = Vec::new();
+            while n_unclaimed.fetch_update(Relaxed, Relaxed, |n| n.checked_sub(1)).is_ok() {
+                popped.push(loop {
+                    if let Some(item) = buffer.try_pop() { break item; }
+                    thread::yield_now();
+                });
+            }
+            popped
+        })
+    }).collect();
+    for producer in producers { producer.join().unwrap(); }
+    exactly_once(consumers.into_iter().flat_map(|consumer| consumer.join().unwrap()), n_total)
+}
+
+fn parse_args() -> Result<(usize, usize), String> {
+    let (mut n_rounds, mut n_items) = (100, 200);
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("`{}` needs a value", flag))?;
+        let value = value.parse().map_err(|_| format!("invalid value `{}` for `{}`", value, flag))?;
+        match flag.as_str() {
+            "--rounds" => n_rounds = value,
+            "--items"  => n_items = value,
+            _ => return Err(format!("unknown option `{}`", flag)),
+        }
+    }
+    if n_rounds == 0 || n_items == 0 { return Err("`--rounds` and `--items` must be at least 1".to_string()); }
+    Ok((n_rounds, n_items))
+}
+
+fn main() {
+    let (n_rounds, n_items) = parse_args().unwrap_or_else(|e| {
+        eprintln!("error: {}\n\nUsage: stress [--rounds <n>] [--items <n>]", e);
+        process::exit(2);
+    });
+    let mut rounds: Vec<(&str, Round)> = QUEUES.iter()
+        .map(|&(name, make_queue)| (name, Box::new(move |round| queue_round(make_queue, round, n_items)) as Box<_>))
+        .collect();
+    rounds.push(("closable", Box::new(move |round| closable_round(round, n_items))));
+    rounds.push(("spin", Box::new(move |round| spin_round(round, n_items))));
+
+    let mut ok = true;
+    for (name, round) in rounds {
+        let start = Instant::now();
+        let n_failed = (0..n_rounds).filter(|&k| !round(k)).count();
+        println!("{:<10} {} rounds in {:.2}s, {} failed", name, n_rounds, start.elapsed().as_secs_f64(), n_failed);
+        ok &= n_failed == 0;
+    }
+    if !ok { process::exit(1); }
+}
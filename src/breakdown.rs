@@ -0,0 +1,172 @@
+/* Where a pipeline's items spend their time, for `bench --breakdown`: each item's trace id (the item itself, with
+`--generator tagged`) is stamped as it's pushed to and popped from every buffer, and once it's popped from the last,
+its time is split into segments, the time queued in buffer 1, in stage 2 (working on it, and waiting to push it on),
+queued in buffer 2, and so on, whose percentiles over every item which went all the way through are reported.
+
+Only items pushed by the producers after the warm-up are stamped. An item seen again before it's gone all the way
+through, as with `--redeliver`, is stamped out of turn, and left out.
+
+A push is stamped once it returns, but its stamp is set aside before it starts, so that a consumer popping the item
+before then finds its place taken rather than the item out of turn; the push stamp is then no later than the pop's.
+The stamps are kept in shards by item, so that threads stamping different items rarely wait for each other.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use rpc::{Hooked, Hooks, Locker, Queue};
+
+// how many shards the stamps are kept in
+const N_SHARDS: usize = 16;
+// a push stamp set aside, but not yet made
+const PENDING: u64 = u64::MAX;
+
+#[derive(Default)]
+struct Stamps {
+    // each item's stamps so far, in nanoseconds since the start: pushed to buffer 1, popped from it, pushed to buffer
+    // 2, ...
+    items: HashMap<isize, Vec<u64>>,
+    // for every item which went all the way through, the time it spent in each segment
+    segments: Vec<Vec<u64>>,
+}
+
+pub struct Breakdown {
+    start: Instant,
+    // when to start stamping items
+    measure_from: Instant,
+    n_buffers: usize,
+    shards: Box<[Mutex<Stamps>]>,
+}
+impl Breakdown {
+
+    pub fn new(n_buffers: usize, measure_from: Instant) -> Arc<Self> {
+        let shards = (0..N_SHARDS).map(|_| {
+            Mutex::new(Stamps { items: HashMap::new(), segments: vec![Vec::new(); 2 * n_buffers - 1] })
+        }).collect();
+        Arc::new(Breakdown { start: Instant::now(), measure_from, n_buffers, shards })
+    }
+
+    // `queue`, stamping the items pushed to and popped from it as those of buffer number `buffer`, from 0
+    pub fn stamp(self: &Arc<Self>, queue: Arc<dyn Queue>, buffer: usize) -> Arc<dyn Queue> {
+        Arc::new(Hooked::new(queue, Stamping { breakdown: self.clone(), buffer }))
+    }
+
+    fn shard(&self, item: isize) -> &Mutex<Stamps> { &self.shards[item.rem_euclid(N_SHARDS as isize) as usize] }
+
+    // set aside `item`'s `n`th stamp, that of its push to buffer `n / 2`, just before the push
+    fn set_aside(&self, items: &[isize], n: usize) {
+        if n == 0 && Instant::now() < self.measure_from { return; }
+        for item in items {
+            let mut stamps = self.shard(*item).lock().unwrap();
+            if n == 0 {
+                stamps.items.insert(*item, vec![PENDING]);
+                continue;
+            }
+            let Some(times) = stamps.items.get_mut(item) else { continue };
+            match times.len() == n {
+                true => times.push(PENDING),
+                false => { stamps.items.remove(item); },
+            }
+        }
+    }
+
+    // a push set aside which didn't happen, after all
+    fn forget(&self, items: &[isize], n: usize) {
+        for item in items {
+            let mut stamps = self.shard(*item).lock().unwrap();
+            if stamps.items.get(item).is_some_and(|times| times.get(n) == Some(&PENDING)) {
+                stamps.items.remove(item);
+            }
+        }
+    }
+
+    // the `n`th stamp of `item`: the push to buffer `n / 2` if `n` is even, which has been set aside, or the pop from
+    // it
+    fn record(&self, items: &[isize], n: usize) {
+        let nanos = (Instant::now() - self.start).as_nanos() as u64;
+        for item in items {
+            let mut stamps = self.shard(*item).lock().unwrap();
+            let Stamps { items: stamped, segments } = &mut *stamps;
+            let Some(times) = stamped.get_mut(item) else { continue };
+            if n.is_multiple_of(2) {
+                if times.get(n) != Some(&PENDING) { continue; }
+                // no later than the pop, if that's been stamped already
+                times[n] = times.get(n + 1).map_or(nanos, |&pop| nanos.min(pop));
+            } else if times.len() == n {
+                times.push(nanos);
+            } else {
+                stamped.remove(item);
+                continue;
+            }
+            if times.len() == 2 * self.n_buffers && !times.contains(&PENDING) {
+                let times = stamped.remove(item).unwrap();
+                for (segment, pair) in segments.iter_mut().zip(times.windows(2)) { segment.push(pair[1] - pair[0]); }
+            }
+        }
+    }
+
+    // the time each item which went all the way through since the last time spent in each segment, by segment, for
+    // `bench --target-p99` to look at as it goes; those taken are left out of `report`
+    pub fn take(&self) -> Vec<Vec<u64>> {
+        let mut segments = vec![Vec::new(); 2 * self.n_buffers - 1];
+        for shard in &self.shards {
+            let mut stamps = shard.lock().unwrap();
+            for (all, segment) in segments.iter_mut().zip(&mut stamps.segments) { all.append(segment); }
+        }
+        segments
+    }
+
+    // every shard's segments, in the same order, so that the `i`th time of each segment is the same item's
+    fn segments(&self) -> Vec<Vec<u64>> {
+        let mut segments = vec![Vec::new(); 2 * self.n_buffers - 1];
+        for shard in &self.shards {
+            let stamps = shard.lock().unwrap();
+            for (all, segment) in segments.iter_mut().zip(&stamps.segments) { all.extend_from_slice(segment); }
+        }
+        segments
+    }
+
+    // the names of the segments, and for each, the median, 90th and 99th percentiles and the longest, in order, then
+    // the same for the whole journey, and the number of items which made it
+    pub fn report(&self) -> (Vec<(String, [Duration; 4])>, usize) {
+        let segments = self.segments();
+        let n_items = segments[0].len();
+        let mut totals = vec![0; n_items];
+        let mut rows: Vec<_> = segments.iter().enumerate().map(|(s, segment)| {
+            for (total, nanos) in totals.iter_mut().zip(segment) { *total += nanos; }
+            let name = match s % 2 {
+                0 => format!("queued in buffer {}", s / 2 + 1),
+                _ => format!("in stage {}", s / 2 + 2),
+            };
+            (name, percentiles(segment.clone()))
+        }).collect();
+        rows.push(("all the way through".to_string(), percentiles(totals)));
+        (rows, n_items)
+    }
+}
+
+// the median, 90th and 99th percentiles and the longest
+pub fn percentiles(mut nanos: Vec<u64>) -> [Duration; 4] {
+    nanos.sort_unstable();
+    [0.5, 0.9, 0.99, 1.0].map(|q| {
+        let i = ((q * nanos.len() as f64).ceil() as usize).max(1) - 1;
+        Duration::from_nanos(nanos.get(i).copied().unwrap_or(0))
+    })
+}
+
+// stamping the items pushed to and popped from buffer number `buffer`
+struct Stamping {
+    breakdown: Arc<Breakdown>,
+    buffer: usize,
+}
+impl Hooks for Stamping {
+    fn before_push(&self, items: &[isize]) { self.breakdown.set_aside(items, 2 * self.buffer); }
+    // if cancelled partway, those pushed so far are stamped, and the rest forgotten
+    fn after_push(&self, pushed: &[isize], left: &[isize], _: &Locker) {
+        self.breakdown.record(pushed, 2 * self.buffer);
+        self.breakdown.forget(left, 2 * self.buffer);
+    }
+    fn after_pop(&self, popped: &[isize], _: &Locker) { self.breakdown.record(popped, 2 * self.buffer + 1); }
+}
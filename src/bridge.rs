@@ -0,0 +1,41 @@
+use std::sync::Arc;
+use tokio::{sync::mpsc, task::{self, JoinHandle}};
+use tokio_stream::wrappers::ReceiverStream;
+use crate::{Locker, Queue};
+
+/* Adapters between a blocking buffer and async code, for applications which are partly threaded and partly async.
+Each runs the blocking side on tokio's blocking thread pool (`spawn_blocking`), so it must be called from within a
+tokio runtime, and holds one of that pool's threads for as long as it runs. Between the two sides is a channel of
+capacity 1, so that the buffer's capacity still bounds the number of items in flight, give or take one or two.
+*/
+
+/* Pop items from `queue` and yield them as a stream, until the locker's token is cancelled (which ends the stream) or
+the stream is dropped. The blocking side only notices the stream is gone once it has popped another item, which is
+then lost, so to stop it without losing items cancel the token instead.
+*/
+pub fn to_tokio_stream(queue: Arc<dyn Queue>, mut locker: Locker) -> ReceiverStream<isize> {
+    let (sender, receiver) = mpsc::channel(1);
+    task::spawn_blocking(move || {
+        while let Ok(item) = queue.pop(&mut locker) {
+            if sender.blocking_send(item).is_err() { break; }
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+/* Push whatever async code sends on the returned sender into `queue`, waiting while the buffer is full, so that a
+full buffer makes `send(item).await` wait. It stops once every clone of the sender has been dropped, or the locker's
+token is cancelled (after which sends fail); the handle gives how many items were pushed.
+*/
+pub fn from_async_producer(queue: Arc<dyn Queue>, mut locker: Locker) -> (mpsc::Sender<isize>, JoinHandle<usize>) {
+    let (sender, mut receiver) = mpsc::channel(1);
+    let pusher = task::spawn_blocking(move || {
+        let mut n_pushed = 0;
+        while let Some(item) = receiver.blocking_recv() {
+            if queue.push(item, &mut locker).is_err() { break; }
+            n_pushed += 1;
+        }
+        n_pushed
+    });
+    (sender, pusher)
+}
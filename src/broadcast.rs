@@ -0,0 +1,220 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Condvar, Mutex, MutexGuard},
+};
+use crate::closable::{PopError, PushError};
+
+// where a subscriber is up to, by item number
+#[derive(Clone, Copy)]
+struct Cursor {
+    // the next item it pops
+    next: u64,
+    // the first item pushed after it subscribed
+    joined: u64,
+}
+
+struct State<T> {
+    // the items from number `first` on: those some subscriber hasn't popped yet, and those retained for replaying
+    items: VecDeque<T>,
+    first: u64,
+    // by subscriber id, for the subscribers which haven't been dropped
+    cursors: HashMap<usize, Cursor>,
+    // the id the next subscriber gets
+    next_id: usize,
+    closed: bool,
+}
+impl<T> State<T> {
+    // the number the next item pushed will have
+    fn end(&self) -> u64 { self.first + self.items.len() as u64 }
+    // the first item some subscriber hasn't popped, or the end if there are none
+    fn oldest_unpopped(&self) -> u64 { self.cursors.values().map(|c| c.next).min().unwrap_or(self.end()) }
+    // the same, but leaving out the items pushed before each subscriber joined, which don't hold up pushes
+    fn oldest_live(&self) -> u64 {
+        self.cursors.values().map(|c| c.next.max(c.joined)).min().unwrap_or(self.end())
+    }
+}
+
+/* Bounded buffer whose every item goes to every subscriber, instead of to one consumer: each subscriber pops the items
+in the order they were pushed, from where it subscribed. Pushes wait while the subscriber furthest behind has `capacity`
+items left to pop, so the slowest one sets the pace. Items are dropped once every subscriber has popped them, except for
+the last `retention` pushed (none unless set with `with_retention`), which a subscriber joining late can replay before
+the live ones, e.g. to catch up on recent state; those don't count against the capacity, so a subscriber catching up
+doesn't hold up pushes until it reaches the items pushed since it joined. Closing makes pushes fail, and pops once each
+subscriber has popped what was left for it.
+*/
+pub struct BroadcastBuffer<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    retention: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+impl<T: Clone> BroadcastBuffer<T> {
+
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        BroadcastBuffer {
+            state: Mutex::new(State {
+                items: VecDeque::new(), first: 0, cursors: HashMap::new(), next_id: 0, closed: false,
+            }),
+            capacity,
+            retention: 0,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    // keep the last `retention` items pushed for subscribers to replay, even once every subscriber has popped them
+    pub fn with_retention(mut self, retention: usize) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn capacity (&self) -> usize { self.capacity }
+    pub fn retention(&self) -> usize { self.retention }
+    // the items held, whether still to be popped or retained
+    pub fn n_items  (&self) -> usize { self.state.lock().unwrap().items.len() }
+
+    /* Subscribe to every item pushed from now on, after replaying up to `replay` of the last ones pushed before, as
+    many as are retained (or not yet popped by every other subscriber), oldest first.
+    */
+    pub fn subscribe(&self, replay: usize) -> Subscriber<'_, T> {
+        let mut state = self.state.lock().unwrap();
+        let (joined, n_replayed) = (state.end(), replay.min(state.items.len()) as u64);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.cursors.insert(id, Cursor { next: joined - n_replayed, joined });
+        Subscriber { buffer: self, id }
+    }
+
+    // block while the subscriber furthest behind has a full buffer's worth of items pushed since it joined to pop
+    pub fn push(&self, item: T) -> Result<(), PushError<T>> {
+        let mut state = self.state.lock().unwrap();
+        while state.end() - state.oldest_live() >= self.capacity as u64 && !state.closed {
+            state = self.not_full.wait(state).unwrap();
+        }
+        if state.closed { return Err(PushError::Closed(item)); }
+        state.items.push_back(item);
+        self.trim(&mut state);
+        self.not_empty.notify_all();
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    // drop the items which every subscriber has popped, and which are too old to retain
+    fn trim(&self, state: &mut MutexGuard<State<T>>) {
+        let keep_from = state.oldest_unpopped().min(state.end().saturating_sub(self.retention as u64));
+        while state.first < keep_from {
+            state.items.pop_front();
+            state.first += 1;
+        }
+    }
+}
+
+// one subscriber to a `BroadcastBuffer`, which unsubscribes when dropped, so that pushes no longer wait for it
+pub struct Subscriber<'a, T: Clone> {
+    buffer: &'a BroadcastBuffer<T>,
+    id: usize,
+}
+impl<T: Clone> Subscriber<'_, T> {
+
+    // the next item, blocking while there isn't one; fails once the buffer is closed and there are none left
+    pub fn pop(&mut self) -> Result<T, PopError> {
+        let buffer = self.buffer;
+        let mut state = buffer.state.lock().unwrap();
+        let next = loop {
+            let next = state.cursors[&self.id].next;
+            if next < state.end() { break next; }
+            if state.closed { return Err(PopError::Closed); }
+            state = buffer.not_empty.wait(state).unwrap();
+        };
+        let item = state.items[(next - state.first) as usize].clone();
+        state.cursors.get_mut(&self.id).unwrap().next = next + 1;
+        buffer.trim(&mut state);
+        buffer.not_full.notify_all();
+        Ok(item)
+    }
+
+    // how many items are waiting for this subscriber
+    pub fn n_pending(&self) -> usize {
+        let state = self.buffer.state.lock().unwrap();
+        (state.end() - state.cursors[&self.id].next) as usize
+    }
+}
+impl<T: Clone> Drop for Subscriber<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.buffer.state.lock().unwrap();
+        state.cursors.remove(&self.id);
+        self.buffer.trim(&mut state);
+        self.buffer.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    // a subscriber joining late replays the last retained items, as many as it asks for, then gets the live ones
+    #[test]
+    fn late_subscriber_catches_up() {
+        let buffer = BroadcastBuffer::new(2).with_retention(3);
+        for item in 0..5 { buffer.push(item).unwrap(); }
+        assert_eq!(buffer.n_items(), 3);
+        let mut all = buffer.subscribe(10);
+        let mut last = buffer.subscribe(1);
+        let mut live = buffer.subscribe(0);
+        buffer.push(5).unwrap();
+        buffer.close();
+        assert_eq!(std::iter::from_fn(|| all.pop().ok()).collect::<Vec<_>>(), [2, 3, 4, 5]);
+        assert_eq!(std::iter::from_fn(|| last.pop().ok()).collect::<Vec<_>>(), [4, 5]);
+        assert_eq!(std::iter::from_fn(|| live.pop().ok()).collect::<Vec<_>>(), [5]);
+    }
+
+    // every subscriber gets every item, and the slowest holds up the producer, which never gets more than a buffer's
+    // worth ahead of it
+    #[test]
+    fn every_subscriber_gets_every_item() {
+        const N_ITEMS: usize = 100;
+        let buffer = BroadcastBuffer::new(4);
+        thread::scope(|scope| {
+            let subscribers: Vec<_> = (0..3).map(|_| buffer.subscribe(0)).collect();
+            let popped: Vec<_> = subscribers.into_iter().map(|mut subscriber| scope.spawn(move || {
+                let mut popped = Vec::new();
+                while let Ok(item) = subscriber.pop() {
+                    assert!(subscriber.n_pending() <= 4);
+                    popped.push(item);
+                }
+                popped
+            })).collect();
+            for item in 0..N_ITEMS { buffer.push(item).unwrap(); }
+            buffer.close();
+            for popped in popped { assert_eq!(popped.join().unwrap(), (0..N_ITEMS).collect::<Vec<_>>()); }
+        });
+        assert_eq!(buffer.n_items(), 0);
+        assert_eq!(buffer.push(0), Err(PushError::Closed(0)));
+    }
+
+    // a dropped subscriber's cursor goes with it, so subscribers coming and going don't pile up, and pushes stop
+    // waiting for it
+    #[test]
+    fn dropped_subscribers_leave_no_cursor() {
+        let buffer = BroadcastBuffer::new(2);
+        let mut stays = buffer.subscribe(0);
+        for item in 0..100 {
+            let leaves = buffer.subscribe(0);
+            buffer.push(item).unwrap();
+            assert_eq!(stays.pop(), Ok(item));
+            drop(leaves);
+        }
+        assert_eq!(buffer.state.lock().unwrap().cursors.len(), 1);
+        assert_eq!(buffer.n_items(), 0);
+        drop(stays);
+        assert!(buffer.state.lock().unwrap().cursors.is_empty());
+    }
+}
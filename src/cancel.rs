@@ -0,0 +1,32 @@
+use std::{
+    sync::{Arc, atomic::{AtomicBool, Ordering::Relaxed}},
+    time::Duration,
+};
+
+/* Cancels the blocking operations of every thread whose `Locker` was given it (or a clone of it), without touching the
+buffer itself, so that an application can stop a whole pipeline from outside. Once cancelled, those threads' pushes
+and pops fail with `Cancelled` instead of waiting or taking effect, including any which are already waiting.
+It can share an existing flag: `CancellationToken::from(flag)`, where setting `flag` cancels.
+*/
+#[derive(Clone, Default)]
+pub struct CancellationToken { cancelled: Arc<AtomicBool> }
+impl CancellationToken {
+
+    pub fn new() -> Self { Self::default() }
+
+    pub fn cancel(&self) { self.cancelled.store(true, Relaxed); }
+    pub fn is_cancelled(&self) -> bool { self.cancelled.load(Relaxed) }
+}
+impl From<Arc<AtomicBool>> for CancellationToken {
+    fn from(cancelled: Arc<AtomicBool>) -> Self { CancellationToken { cancelled } }
+}
+
+// the error from a push or pop by a thread whose `CancellationToken` was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+/* How often a waiting thread with a token wakes up to check it.
+Cancelling doesn't wake anyone, since the token doesn't know which buffers its threads wait on; polling costs threads
+without a token nothing, as they wait without a timeout.
+*/
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
@@ -0,0 +1,1408 @@
+use std::{
+    fmt,
+    fs,
+    net::SocketAddr,
+    str::FromStr,
+    time::Duration,
+};
+use serde::Deserialize;
+use rpc::{Bias, Strategy, Wake, tap::Sampling};
+
+pub const USAGE: &str = "\
+Usage: pc <command> [options]
+
+Commands:
+    run        run producers and consumers forever, printing the buffer after every operation
+    bench      run for a fixed time without printing, then report throughput
+    verify     pass a fixed number of items through the buffer, and check that none are lost, duplicated or
+               reordered
+    simulate   take turns between producers and consumers on a single thread, printing every step
+    soak       `verify` over and over for hours, with every backend but `spin` and a changing mix of threads,
+               capacities and batch sizes, to catch slow leaks and rare races
+    orchestrate
+               measure the throughput with producers and consumers in processes of their own, against that with
+               threads, to see what crossing processes costs
+    worker     one of `orchestrate`'s producer or consumer processes; not for running by hand
+    serve      serve the buffer to clients as a gRPC service, with which they push, pop, read its statistics and
+               close it (needs the `server` feature)
+
+Options for every command:
+    --config <file.toml>                   read options from a file; options given on the command line override it
+    --producers <n>                        number of producer threads (default 1)
+    --consumers <n>                        number of consumer threads (default 1)
+    --rate <items per second>              how fast each producer pushes (default unlimited)
+    --arrivals <even|poisson>              at `--rate`, whether producers push at even intervals, or at random times
+                                           (a Poisson process) determined by `--seed` (default even)
+    --generator <tagged|index|counter|rand>
+                                           what producers push (default tagged): producer p pushes p000000000,
+                                           p000000001, ...; or their own index every time; or 0, 1, 2, ...; or
+                                           pseudo-random numbers below 1000000000 (`--gen` is short for this)
+    --seed <n>                             seed for `--generator rand`, `--arrivals poisson`, work time distributions
+                                           and `bench --item-bytes uniform:`; the same seed gives the same items and
+                                           times (default 0)
+    --producer-batch <n>                   how many items producers hold back to push together (default 1)
+    --flush-interval <duration>            how long producers may hold back an item before pushing what they have
+                                           (default 5ms)
+    --redeliver <fraction>                 push that fraction of the items (chosen by `--seed`) a second time, 1 to
+                                           100 items later, as at-least-once delivery would (default 0)
+    --work-time <duration>                 how long consumers spend on each item, e.g. `5ms` (default 0); or a
+                                           distribution to draw each item's time from, determined by `--seed`:
+                                           `uniform:<min>,<max>`, `normal:<mean>,<sd>` or `exp:<mean>`
+    --prefetch <n>                         for `run` and `bench`, how many items consumers pop at a time into a local
+                                           stash to work through; `bench` reports how long items wait there (default 1)
+    --dedup-window <n>                     have consumers remember the last n distinct items, and skip any which they
+                                           have seen among them, counting the duplicates (default none); needs
+                                           `--generator tagged`
+    --capacity <n>                         capacity of the buffer (default 30)
+    --strategy <block|adaptive>            how threads take the buffer's lock (default block)
+    --backend <condvar|futex|eventcount|deque|sharded|spin|waiters|swap>
+                                           what the buffer is synchronized with (default condvar); `deque` is a plain
+                                           `VecDeque` under a mutex, as a baseline; `sharded` splits the buffer into
+                                           independently locked shards, and can't be recorded; `spin` is the condvar
+                                           buffer with a spinlock, whose waiting threads busy-wait instead of sleeping;
+                                           `waiters` queues waiting threads to wake one for each item or slot;
+                                           `swap` splits the capacity (at least 2) into a half which producers fill
+                                           and one which consumers drain, swapping them when it's empty
+    --wake <all|one|transition>            for `--backend condvar` and `spin`, which waiting threads on the other side a
+                                           push or pop wakes: all of them, one, or all of them but only when it found
+                                           the buffer empty (for a push) or full (for a pop), which saves a wake on
+                                           most operations (default all)
+    --bias <none|drain|fill>               for `--backend condvar` and `spin`, which side takes the lock first when
+                                           producers and consumers both want it: consumers, to keep the buffer
+                                           emptier, or producers, to keep it fuller (default none); see
+                                           `bench --histogram`
+    --shards <n>                           number of shards for `--backend sharded` (default 4)
+    --steal <true|false>                   for `--backend sharded`, whether consumers whose own shard is empty pop
+                                           from the others (default true); without it, shards beyond the number of
+                                           consumers are never emptied
+    --numa-node <n>                        on Linux, allocate the buffer on NUMA node n, and run every thread on its
+                                           CPUs (default anywhere)
+    --consumer-node <n>                    run the consumers on NUMA node n instead, e.g. to measure what passing items
+                                           between nodes costs (default `--numa-node`)
+    --tap <n|n/s>                          for `run` and `bench`, pass 1 in n of the items popped, or at most n a
+                                           second, to a thread watching the buffer, which `run` has print them to
+                                           stderr, without holding up the consumers (default none)
+    --hold-times <true|false>              for `run` and `bench` with `--backend condvar` or `spin`, time how long
+                                           each push and pop holds the buffer's lock, and report the median, 99th
+                                           percentile and longest, e.g. to see what echoing costs (default false)
+    --color <auto|always|never>            color the buffer's contents `run` prints after each operation by the thread
+                                           which did it, producers in warm colors and consumers in cool ones, and
+                                           highlight those which filled or emptied the buffer; `auto` only when
+                                           stdout is a terminal (default auto)
+Options for `run`:
+    --grace-period <duration>              on SIGINT or SIGTERM, the producers stop, and the consumers have this long to
+                                           empty the buffer before the process exits anyway, printing the items left
+                                           over (default 10s)
+    --items <n>                            have each producer finish after pushing n items (default unlimited)
+    --on-starved <warn|exit>               once every producer has finished and the buffer is empty, so that the
+                                           consumers wait for items which will never come, warn about it, or exit as on
+                                           SIGINT (default warn)
+    --stall-after <duration>               warn about a thread which hasn't started or finished a push or a pop for
+                                           this long, saying whether it's blocked on the buffer, or stuck working on an
+                                           item (or making one) outside it, or for 10 of its producers' intervals
+                                           between pushes at `--rate` if that's longer (default 10s)
+    --progress <true|false>                with `--items`, draw bars on stderr for the items pushed and popped out of
+                                           the total, with the rate and how long the rest should take, instead of
+                                           echoing the buffer or printing the rates (default true when stderr is a
+                                           terminal)
+    --health <ip:port>                     answer HTTP health probes on that address, e.g. `0.0.0.0:8080`: `/healthz`
+                                           while the process is up, and `/readyz` while it's running and none of its
+                                           threads has stalled for `--stall-after`, with 503 and the reason otherwise,
+                                           e.g. once it's stopping (default none)
+    --ws-port <port>                       serve a page on that port on the loopback, e.g. `http://localhost:8000`,
+                                           which draws the buffer's occupancy and its latest pushes and pops as they
+                                           happen, sent to it as JSON over a WebSocket at `/events` (default none)
+Options for `bench`:
+    --duration <seconds>                   (default 5)
+    --warmup <duration>                    run for this long before the `--duration` measured, so that the results
+                                           aren't skewed by the threads starting up (default 0)
+    --runs <n>                             run n times, then report the mean, standard deviation, minimum and maximum
+                                           throughput over the runs, and which runs were outliers (default 1)
+    --matrix <true|false>                  instead, compare the throughput of every backend with 1, 2, 4 and 8 producers
+                                           and as many consumers, and capacities of 1, 16 and 256, running each for
+                                           `--duration`, e.g. `--duration 0.5` (default false)
+    --matrix-csv <file>                    `--matrix`, also writing the results to a CSV file
+    --sweep <n>                            instead, measure the throughput with 1, 2, 4, ... up to n producers and as
+                                           many consumers, each for `--duration`, and how well it scales: the speedup
+                                           over 1 of each, and that divided by the number of threads
+    --item-bytes <n|uniform:min,max>       instead, measure the throughput of items which are n-byte payloads (or of
+                                           sizes spread evenly between min and max), which producers allocate and
+                                           fill, and consumers read and free, to include the cost of copying and
+                                           allocating them; as the backends only hold integers, this uses the generic
+                                           buffer the language bindings use, and only takes the numbers of producers
+                                           and consumers, the capacity, `--work-time` and `--seed` from the other
+                                           options
+    --byte-budget <n>                      `--item-bytes`, with the buffer bounded by the payloads' total size, n
+                                           bytes, instead of their number, as a network buffer would be
+    --recycle <n>                          `--item-bytes`, with consumers giving each payload back to a free list of up
+                                           to n, made up front, for producers to fill again instead of allocating a
+                                           new one, and reporting how often they found one there (default none)
+    --arena-batch <n>                      `--item-bytes`, with each producer writing n payloads at a time into an
+                                           arena, one allocation for the batch, and pushing a handle to each one's
+                                           slice of it; an arena is reused once the consumers have dropped all its
+                                           payloads (default none)
+    --zero-copy <true|false>               `--item-bytes`, with the payloads written and read in place, in slots of
+                                           the biggest size the buffer owns, `--capacity` of them, which producers
+                                           reserve and commit, and consumers release once read, so that nothing is
+                                           copied or allocated per item (default false)
+    --mode <items|taskqueue>               with `taskqueue`, instead, run a bounded thread pool: producers submit
+                                           tasks (boxed closures, which sleep for `--work-time`) and consumers run
+                                           them, catching any panic; then report how many ran, how long they took, and
+                                           which panicked; like `--item-bytes`, this only takes the numbers of threads,
+                                           the capacity, `--work-time` and `--seed` (default items)
+    --keys <n>                             `--mode taskqueue`, giving each task one of n keys, drawn by `--seed`, and
+                                           running at most `--per-key` tasks of a key at once (default no keys)
+    --per-key <n>                          with `--keys`, the most tasks of a key to run at once; with 1, a key's tasks
+                                           run one at a time, in the order they were submitted (default 1)
+    --target-p99 <duration>                instead, run with an experimental controller adjusting the capacity every
+                                           100ms, up to `--capacity`, to hold the 99th percentile of the time items
+                                           spend queued in the buffer below that, e.g. `5ms`, logging its decisions
+                                           to stderr; only for `--backend condvar` and `--generator tagged`
+    --stages <n>                           run a pipeline of n stages with a buffer between each and the next: the
+                                           producers, then n - 2 stages of `--consumers` workers each, which push
+                                           every item they've worked on to the next buffer, then the consumers; and
+                                           chart each buffer's occupancy over time (default 2, no pipeline)
+    --stage<k>-work <duration>             how long stage k (from 2, after the producers) spends on each item, like
+                                           `--work-time`, which is the default for every stage
+    --occupancy-csv <file>                 also write the occupancy the chart is drawn from to a CSV file
+    --max-in-flight <n>                    allow at most n items anywhere in the pipeline at once, in its buffers or
+                                           being worked on, by having producers wait for one to be done with before
+                                           pushing another once there are n (default unlimited)
+    --reorder-window <n>                   have the stages between the producers and the consumers pass items on in
+                                           the order they popped them, holding back up to n which they finish early
+                                           (default none, passing them on as they're done)
+    --breakdown <true|false>               stamp each item (by its trace id, which `--generator tagged` makes the item
+                                           itself) as it passes through every buffer, and report the median, 90th and
+                                           99th percentiles and longest of the time items spent queued in each buffer,
+                                           in each stage (working on them, and waiting to push them on), and all the
+                                           way through (default false)
+    --queueing <true|false>                compare the buffer's utilization, queue length and waiting time with those of
+                                           an M/M/c queue with the measured arrival and service rates, and check that
+                                           Little's law holds for them; producers push timestamps instead of the
+                                           generator's items (default false)
+    --switches <true|false>                on Linux, report how often each producer and consumer was switched out
+                                           while measuring: voluntarily, when it blocked (e.g. a wait which parked it
+                                           rather than spinning), or involuntarily, when preempted (default false)
+    --histogram <true|false>               sample how many items the buffer holds every millisecond while measuring,
+                                           and chart how often it was empty, full, or in between (default false)
+    --echo <true|false>                    print the buffer after every operation, as `run` does, to measure what
+                                           that costs; the results come last (default false)
+    --baseline <file>                      compare each buffer's throughput (the mean, with `--runs`) with that in a
+                                           report `bench --report-json` wrote earlier, for the buffer of the same
+                                           name, and fail if any fell by more than `--max-regression`
+    --max-regression <percent>             how far below the baseline the throughput may fall, e.g. `10%` (default
+                                           10%)
+    --save-baseline <name>                 keep the results as a baseline called name, in `target/pc-baselines` (or
+                                           `$CARGO_TARGET_DIR/pc-baselines`), to compare later runs with, e.g. before
+                                           and after changing a backend; like `--report-json`, not for several runs, a
+                                           pipeline or another benchmark
+    --compare <name>                       print how each of the buffers' metrics (throughput, waits, wakes, peak
+                                           occupancy, time blocked and lock hold times) changed since the baseline
+                                           called name was saved; with `--save-baseline` too, before replacing it
+Options for `verify`:
+    --items <n>                            number of items each producer pushes (default 10000)
+Options for `soak`:
+    --hours <hours>                        how long to keep going (default 8)
+    --items <n>                            number of items each producer pushes in each round (default 10000)
+    --checkpoint <duration>                how often to report the rounds passed and the memory in use (default 1m)
+    --round <n>                            run only round n's options, over and over, e.g. to reproduce a failure
+Options for `orchestrate`:
+    --duration <seconds>                   how long to measure for, first with threads, then with processes, each of
+                                           which the buffer's process serves over TCP on the loopback, one round trip
+                                           for each push or pop; only takes the numbers of producers and consumers,
+                                           the capacity, `--backend` and its own options, and `--strategy` from the
+                                           other options (default 5)
+    --credit-window <n>                    instead of a round trip for each push or pop, let each worker have up to n
+                                           items in flight, with credit-based flow control: the side sending the items
+                                           spends a credit on each, and the other grants them back in batches of n / 4
+                                           once done with them (default 0, for a round trip each)
+Options for `serve`:
+    --listen <ip:port>                     the address to accept clients on (default 127.0.0.1:7878), which call the
+                                           `Push`, `Pop`, `Stats` and `Control` RPCs of `proto/pc.proto`; only takes
+                                           the capacity, `--backend` and its own options, `--strategy` and
+                                           `--numa-node` from the other options
+    --allow-shutdown <true|false>          whether a client may stop the server, with `Control` `SHUTDOWN`, which
+                                           closes the buffer, answers the requests in hand and exits (default false)
+Options for `bench` and `verify`:
+    --record <file>                        write the order of every push and pop to a trace file
+Options for `run` and `bench`:
+    --journey <file>                       write a line to the file for every push and pop, through every buffer of a
+                                           pipeline, with the item's trace id (the item itself, with `--generator
+                                           tagged`, as echo shows it), the buffer and the thread, so that an item's
+                                           journey can be found with `grep -w <id>`; not for several runs, `--queueing`
+                                           or another benchmark instead
+Options for `run`, `bench` and `verify`:
+    --report-json <file>                   also write the results to a JSON file, for scripts to check: each buffer's
+                                           options, counts, time blocked, lock hold times, occupancy histogram and
+                                           what each producer and consumer did, and for `verify`, the verdict; not for
+                                           a `bench` of several runs, a pipeline, or another benchmark instead
+Options for `simulate`:
+    --steps <n>                            (default 100)
+    --replay <file>                        replay a trace file written by `--record`, instead of taking turns
+    --rewind <k>                           after the last step, rewind the buffer and the producers to how they were
+                                           after step k, and run the steps from there again, checking that they go the
+                                           same way; fails if the simulation goes idle before step k
+    --speed <0.1x|1x|10x|max>              with `--replay`, take each step when it happened in the recorded run,
+                                           stretched or compressed that many times, e.g. `0.1x` to watch an
+                                           interleaving a tenth as fast, or as fast as possible (default max)
+
+Each of the options for every command (except `--config`, which is `PC_CONFIG`), and `--grace-period`, can also be
+given as an environment variable, e.g. `PC_PRODUCERS=4` or `PC_WORK_TIME=5ms`; flags take precedence over environment
+variables, which take precedence over the config file.
+
+A config file has the sections `[producers]` (`count`, `rate`, `arrivals`, `generator`, `seed`, `batch`,
+`flush_interval`, `redeliver`), `[consumers]` (`count`, `work_time`, `prefetch`, `grace_period`, `numa_node`,
+`dedup_window`), `[buffer]` (`capacity`, `backend`, `strategy`, `wake`, `bias`, `shards`, `steal`, `numa_node`) and
+`[output]` (`echo`, `stats_interval`, `tap`, `hold_times`, `color`); see `examples/run.toml`. For `run` and `bench`, it
+can also define several buffers to run side by side, each with its own producers and consumers, as `[[buffers]]` tables
+with a `name` and any of the options above as keys (with `_` for `-`, except `grace_period`), which override the others
+for that buffer; see `examples/buffers.toml`.
+
+`pc` exits with status 1 when `verify` or `soak` finds an item lost, duplicated or popped out of order, `bench
+--baseline` finds a regression, `run` can't empty its buffers in time, or one of `orchestrate`'s workers fails; and with
+status 2 for invalid options.
+";
+
+// which synchronization primitives the buffer is built on
+#[derive(Clone, Copy)]
+pub enum Backend {
+    Condvar,
+    #[cfg(target_os = "linux")]
+    Futex,
+    EventCount,
+    Deque,
+    Sharded,
+    Spin,
+    Waiters,
+    Swap,
+}
+impl FromStr for Backend {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "condvar"    => Ok(Backend::Condvar),
+            #[cfg(target_os = "linux")]
+            "futex"      => Ok(Backend::Futex),
+            "eventcount" => Ok(Backend::EventCount),
+            "deque"      => Ok(Backend::Deque),
+            "sharded"    => Ok(Backend::Sharded),
+            "spin"       => Ok(Backend::Spin),
+            "waiters"    => Ok(Backend::Waiters),
+            "swap"       => Ok(Backend::Swap),
+            _ => Err(()),
+        }
+    }
+}
+impl Backend {
+    // every backend there is on this platform
+    pub fn all() -> impl Iterator<Item = Backend> {
+        ["condvar", "futex", "eventcount", "deque", "sharded", "spin", "waiters", "swap"]
+            .into_iter()
+            .filter_map(|name| name.parse().ok())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Condvar    => "condvar",
+            #[cfg(target_os = "linux")]
+            Backend::Futex      => "futex",
+            Backend::EventCount => "eventcount",
+            Backend::Deque      => "deque",
+            Backend::Sharded    => "sharded",
+            Backend::Spin       => "spin",
+            Backend::Waiters    => "waiters",
+            Backend::Swap       => "swap",
+        }
+    }
+
+    // the smallest capacity it can be built with
+    pub fn min_capacity(self) -> usize { if matches!(self, Backend::Swap) { 2 } else { 1 } }
+}
+
+// how producers space out the items they push at `--rate`
+#[derive(Clone, Copy)]
+pub enum Arrivals {
+    Even,
+    // at random times, independently of each other: exponentially distributed gaps, determined by the seed
+    Poisson,
+}
+impl FromStr for Arrivals {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "even"    => Ok(Arrivals::Even),
+            "poisson" => Ok(Arrivals::Poisson),
+            _ => Err(()),
+        }
+    }
+}
+
+// which side an `orchestrate` worker process is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Producer,
+    Consumer,
+}
+impl FromStr for Role {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "producer" => Ok(Role::Producer),
+            "consumer" => Ok(Role::Consumer),
+            _ => Err(()),
+        }
+    }
+}
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Producer => "producer",
+            Role::Consumer => "consumer",
+        }
+    }
+}
+
+// what `run` does once its consumers are starved for good: every producer has finished, and the buffer is empty
+#[derive(Clone, Copy)]
+pub enum OnStarved {
+    // say so, and keep running until stopped
+    Warn,
+    // stop, as on SIGINT
+    Exit,
+}
+impl FromStr for OnStarved {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(OnStarved::Warn),
+            "exit" => Ok(OnStarved::Exit),
+            _ => Err(()),
+        }
+    }
+}
+
+// whether echo colors each thread's lines
+#[derive(Clone, Copy)]
+pub enum Color {
+    // when stdout is a terminal
+    Auto,
+    Always,
+    Never,
+}
+impl FromStr for Color {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto"   => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never"  => Ok(Color::Never),
+            _ => Err(()),
+        }
+    }
+}
+
+// what `bench` passes through the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // items, as everything else does
+    Items,
+    // boxed closures, which the consumers run, so that the buffer is a bounded thread pool's queue
+    TaskQueue,
+}
+impl FromStr for Mode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "items"     => Ok(Mode::Items),
+            "taskqueue" => Ok(Mode::TaskQueue),
+            _ => Err(()),
+        }
+    }
+}
+
+// what producers push
+#[derive(Clone, Copy)]
+pub enum Generator {
+    // an increasing sequence in a range of its own for each producer, so every item shows who pushed it and when
+    Tagged,
+    // the producer's own index, every time
+    Index,
+    // 0, 1, 2, ...
+    Counter,
+    // pseudo-random, but determined by the seed, the producer and `n`, however the threads interleave
+    Random,
+}
+impl FromStr for Generator {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tagged"  => Ok(Generator::Tagged),
+            "index"   => Ok(Generator::Index),
+            "counter" => Ok(Generator::Counter),
+            "rand"    => Ok(Generator::Random),
+            _ => Err(()),
+        }
+    }
+}
+// the size of each producer's range for `Generator::Tagged`; a power of 10 so that items are readable in decimal
+const TAG_RANGE: usize = 1_000_000_000;
+
+// how long consumers spend on each item: a fixed time, or one drawn from a distribution for every item
+#[derive(Clone, Copy)]
+pub enum Work {
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    // mean and standard deviation; times below 0 are taken as 0
+    Normal(Duration, Duration),
+    // mean
+    Exponential(Duration),
+}
+
+// e.g. `5ms`, `uniform:1ms,3ms`, `normal:5ms,1ms` or `exp:5ms`
+pub fn parse_work(s: &str) -> Result<Work, String> {
+    let Some((distribution, params)) = s.split_once(':') else { return parse_duration(s).map(Work::Fixed) };
+    let params = params.split(',').map(parse_duration).collect::<Result<Vec<_>, _>>()?;
+    match (distribution, params.as_slice()) {
+        ("uniform", &[min, max]) if min <= max => Ok(Work::Uniform(min, max)),
+        ("normal", &[mean, sd])                => Ok(Work::Normal(mean, sd)),
+        ("exp", &[mean])                       => Ok(Work::Exponential(mean)),
+        _ => Err(format!(
+            "invalid work time `{}` (expected a duration, `uniform:<min>,<max>`, `normal:<mean>,<sd>` or `exp:<mean>`)",
+            s,
+        )),
+    }
+}
+
+// the sizes of `bench --item-bytes`'s payloads, in bytes: all the same, or uniformly distributed between the two
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemBytes {
+    Fixed(usize),
+    Uniform(usize, usize),
+}
+
+// e.g. `4096` or `uniform:512,8192`
+pub fn parse_item_bytes(s: &str) -> Result<ItemBytes, String> {
+    let invalid = || format!("invalid item size `{}` (expected a number of bytes or `uniform:<min>,<max>`)", s);
+    match s.strip_prefix("uniform:").map(|params| params.split_once(',')) {
+        None => s.parse().map(ItemBytes::Fixed).map_err(|_| invalid()),
+        Some(Some((min, max))) => match (min.parse(), max.parse()) {
+            (Ok(min), Ok(max)) if min <= max => Ok(ItemBytes::Uniform(min, max)),
+            _ => Err(invalid()),
+        },
+        Some(None) => Err(invalid()),
+    }
+}
+
+impl ItemBytes {
+    // the size of producer `producer`'s `n`th payload; like `Work::sample`, determined by the seed
+    pub fn sample(self, seed: u64, producer: usize, n: usize) -> usize {
+        match self {
+            ItemBytes::Fixed(n_bytes) => n_bytes,
+            ItemBytes::Uniform(min, max) =>
+                min + (mix(mix(mix(seed) ^ producer as u64) ^ n as u64) % (max - min + 1) as u64) as usize,
+        }
+    }
+}
+impl ItemBytes {
+    pub fn max(self) -> usize {
+        match self {
+            ItemBytes::Fixed(n_bytes)  => n_bytes,
+            ItemBytes::Uniform(_, max) => max,
+        }
+    }
+}
+impl fmt::Display for ItemBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ItemBytes::Fixed(n)          => write!(f, "{} bytes", n),
+            ItemBytes::Uniform(min, max) => write!(f, "{} to {} bytes", min, max),
+        }
+    }
+}
+
+// `n` for 1 in n items, or `n/s` for at most n a second
+pub fn parse_sampling(s: &str) -> Result<Sampling, String> {
+    let sampling = match s.strip_suffix("/s") {
+        Some(rate) => rate.parse().ok().filter(|&rate: &f64| rate > 0.0 && rate.is_finite()).map(Sampling::PerSecond),
+        None => s.parse().ok().filter(|&n| n > 0).map(Sampling::OneIn),
+    };
+    sampling.ok_or_else(|| format!("invalid tap `{}` (expected `<n>` for 1 in n items, or `<n>/s`)", s))
+}
+
+impl Work {
+    pub fn is_zero(self) -> bool { matches!(self, Work::Fixed(time) if time.is_zero()) }
+
+    // the time for consumer `consumer` of `stage`'s `n`th item; like `Generator::Random`, determined by the seed
+    pub fn sample(self, seed: u64, stage: usize, consumer: usize, n: usize) -> Duration {
+        let bits = mix(mix(mix(mix(seed) ^ stage as u64) ^ consumer as u64) ^ n as u64);
+        // two independent uniform numbers in (0, 1]
+        let uniform = |bits: u64| ((bits >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        let (u, v) = (uniform(bits), uniform(mix(bits)));
+        match self {
+            Work::Fixed(time)         => time,
+            Work::Uniform(min, max)   => min + (max - min).mul_f64(u),
+            // Box-Muller
+            Work::Normal(mean, sd)    => {
+                let z = (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos();
+                Duration::from_secs_f64((mean.as_secs_f64() + sd.as_secs_f64() * z).max(0.0))
+            },
+            Work::Exponential(mean)   => mean.mul_f64(-u.ln()),
+        }
+    }
+}
+impl fmt::Display for Work {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Work::Fixed(time)       => write!(f, "{:?}", time),
+            Work::Uniform(min, max) => write!(f, "uniform:{:?},{:?}", min, max),
+            Work::Normal(mean, sd)  => write!(f, "normal:{:?},{:?}", mean, sd),
+            Work::Exponential(mean) => write!(f, "exp:{:?}", mean),
+        }
+    }
+}
+
+// SplitMix64's output function: a cheap bijection whose outputs look independent even for consecutive inputs
+pub fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// the most items after an item that a producer redelivers it
+const MAX_REDELIVERY_LAG: usize = 100;
+
+// if `producer` is to redeliver its `n`th item, given the `fraction` redelivered, how many items later: a random 1 to
+// `MAX_REDELIVERY_LAG`
+pub fn redelivery(fraction: f64, seed: u64, producer: usize, n: usize) -> Option<usize> {
+    let bits = mix(mix(mix(seed) ^ !(producer as u64)) ^ n as u64);
+    let uniform = (bits >> 11) as f64 / (1u64 << 53) as f64;
+    (uniform < fraction).then(|| (mix(bits) % MAX_REDELIVERY_LAG as u64) as usize + 1)
+}
+
+impl Generator {
+    // the `n`th item pushed by `producer`; `seed` is only used by `Random`
+    pub fn item(self, seed: u64, producer: usize, n: usize) -> isize {
+        match self {
+            // (wrapping around within the range, after a billion items)
+            Generator::Tagged  => (producer * TAG_RANGE + n % TAG_RANGE) as isize,
+            Generator::Index   => producer as isize,
+            Generator::Counter => n as isize,
+            Generator::Random  => (mix(mix(mix(seed) ^ producer as u64) ^ n as u64) % TAG_RANGE as u64) as isize,
+        }
+    }
+}
+
+// options shared by every command
+#[derive(Clone)]
+pub struct Config {
+    pub n_producers: usize,
+    // items per second per producer; `None` is unlimited
+    pub rate: Option<f64>,
+    pub arrivals: Arrivals,
+    pub generator: Generator,
+    pub seed: u64,
+    // how many items producers push at a time, and how long they may hold them back while collecting them
+    pub producer_batch: usize,
+    // the fraction of items producers push a second time, a little later, as with at-least-once delivery
+    pub redeliver: f64,
+    pub flush_interval: Duration,
+    pub n_consumers: usize,
+    pub work_time: Work,
+    pub prefetch: usize,
+    // how many of the latest distinct items consumers remember, to skip duplicates of them
+    pub dedup_window: Option<usize>,
+    // for `run`: how long consumers get to empty the buffer once it's been told to stop
+    pub grace_period: Duration,
+    pub capacity: usize,
+    pub strategy: Strategy,
+    pub backend: Backend,
+    // for `Backend::Condvar` and `Backend::Spin`
+    pub wake: Wake,
+    pub bias: Bias,
+    // for `Backend::Sharded`
+    pub n_shards: usize,
+    pub steal: bool,
+    // on Linux: the NUMA node to allocate the buffer on and run the producers on, and the one to run the consumers on
+    // if not the same
+    pub numa_node: Option<usize>,
+    pub consumer_node: Option<usize>,
+    // for `run`: print the buffer after every operation (also for `bench --echo`), and the throughput every
+    // `stats_interval`
+    pub echo: bool,
+    pub stats_interval: Duration,
+    // for `run` and `bench`: which of the items popped to pass to a thread watching the buffer
+    pub tap: Option<Sampling>,
+    // for `run` and `bench`, with `Backend::Condvar` or `Backend::Spin`: time how long pushes and pops hold the lock
+    pub hold_times: bool,
+    // whether to color what echo prints
+    pub color: Color,
+    // for `run` and `bench`: named buffers to run side by side instead of just one, each with its own producers and
+    // consumers, and the options above except where the config file overrides them
+    pub buffers: Vec<(String, Config)>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            n_producers: 1, rate: None, arrivals: Arrivals::Even, generator: Generator::Tagged, seed: 0,
+            producer_batch: 1, flush_interval: Duration::from_millis(5), redeliver: 0.0,
+            n_consumers: 1, work_time: Work::Fixed(Duration::ZERO), prefetch: 1, dedup_window: None,
+            grace_period: Duration::from_secs(10),
+            capacity: 30, // arbitrary choice
+            strategy: Strategy::Block, backend: Backend::Condvar, wake: Wake::NotifyAll, bias: Bias::None,
+            n_shards: 4, steal: true,
+            numa_node: None, consumer_node: None,
+            echo: true, stats_interval: Duration::from_secs(1), tap: None, hold_times: false,
+            color: Color::Auto,
+            buffers: Vec::new(),
+        }
+    }
+}
+
+// `n_items` is how many items each producer pushes before finishing, if limited, and `on_starved` what happens once
+// they all have and the buffer is empty; a thread which hasn't gone into or out of the buffer for `stall_after` is
+// warned about; `report` (for `Bench` and `Verify` too) is the JSON file to write the results to, if any; `health`
+// is the address to answer health probes on, if any, and `ws_port` the port to stream events from, if any;
+// `journey` (for `Bench` too) is the file to write every push and pop to, by the item's trace id, if any
+pub struct Run {
+    pub n_items: Option<usize>, pub on_starved: OnStarved, pub stall_after: Duration, pub progress: Option<bool>,
+    pub report: Option<String>, pub health: Option<SocketAddr>, pub ws_port: Option<u16>, pub journey: Option<String>,
+}
+
+// `record` is the file to write a trace to, if any
+// `stages` is the length of the pipeline, 2 for just producers and consumers; `csv` is the file to write its
+// buffers' occupancy over time to, if any; `max_in_flight` bounds the number of items anywhere in it, and with
+// `reorder_window` its middle stages pass items on in order; `breakdown` reports where its items spend their time
+// `warmup` is how long to run before measuring anything, and `runs` how many times to run; `work` is how long each
+// stage after the producers spends on an item; `queueing` compares the run with queueing theory; `switches` reports
+// how often each thread was switched out; `matrix` compares the backends with various numbers of threads and
+// capacities instead, writing the results to `matrix_csv` too;
+// `sweep` measures how the backend scales with up to that many producers and consumers instead, and `item_bytes`
+// passes payloads of that many bytes through a `ClosableBuffer` instead, bounded by `byte_budget` bytes if there is
+// one, and recycled through a free list of up to `recycle` if set, or written `arena_batch` at a time into
+// arenas, or written in place into the slots of a `SlotBuffer` with `zero_copy`, and `mode` `TaskQueue` has the
+// consumers run tasks the producers submit instead, each of one of `n_keys` keys if set, with at most `per_key` of a
+// key running at once; `histogram` charts how full the buffer was, and `echo` prints it after every operation (which
+// sets `Config::echo`); with `baseline`, the throughput fails if it's more than `max_regression` (a fraction, 10% if
+// not given) below that in the report there; `save_baseline` keeps the results under that name, and `compare` prints
+// how they differ from those kept under that name
+pub struct Bench {
+    pub duration: Duration, pub warmup: Duration, pub runs: usize, pub record: Option<String>,
+    pub stages: usize, pub csv: Option<String>, pub work: Vec<Work>, pub queueing: bool, pub matrix: bool,
+    pub matrix_csv: Option<String>, pub sweep: Option<usize>, pub item_bytes: Option<ItemBytes>,
+    pub byte_budget: Option<usize>, pub recycle: Option<usize>, pub arena_batch: Option<usize>, pub zero_copy: bool,
+    pub switches: bool, pub max_in_flight: Option<usize>, pub reorder_window: Option<usize>, pub histogram: bool,
+    pub mode: Mode, pub n_keys: Option<usize>, pub per_key: usize, pub echo: bool, pub report: Option<String>,
+    pub baseline: Option<String>, pub max_regression: Option<f64>, pub save_baseline: Option<String>,
+    pub compare: Option<String>, pub journey: Option<String>, pub breakdown: bool, pub target_p99: Option<Duration>,
+}
+
+pub enum Command {
+    Run(Run),
+    // boxed, as it's much the biggest
+    Bench(Box<Bench>),
+    Verify   { n_items: usize, record: Option<String>, report: Option<String> },
+    // `checkpoint` is how often to report on the soak so far; `round`, the only round to run, if set
+    Soak     { duration: Duration, n_items: usize, checkpoint: Duration, round: Option<usize> },
+    // `replay` is the trace file to follow, if any, at `speed` times the recorded pace (or as fast as possible if
+    // `None`), and `rewind` the step to go back to and run again from, if any
+    Simulate { n_steps: usize, replay: Option<String>, rewind: Option<usize>, speed: Option<f64> },
+    // `duration` is how long to measure each way for, and `credit_window` each worker's window of credits, if not 0
+    Orchestrate { duration: Duration, credit_window: usize },
+    // `connect` is the address of the `orchestrate` process to push to or pop from, as `role`
+    Worker   { role: Option<Role>, connect: Option<String>, credit_window: usize },
+    // `listen` is the address to accept clients on, and `allow_shutdown` whether they may stop the server
+    Serve    { listen: SocketAddr, allow_shutdown: bool },
+}
+
+// e.g. `1.5s`, `20ms`, `100us`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid duration `{}`", s))?;
+    let secs = match unit {
+        "ns" => number * 1e-9,
+        "us" | "µs" => number * 1e-6,
+        "ms" => number * 1e-3,
+        "s"  => number,
+        "m"  => number * 60.0,
+        "h"  => number * 3600.0,
+        _ => return Err(format!("invalid duration `{}` (expected a unit: ns, us, ms, s, m or h)", s)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration `{}`: {}", s, e))
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<&str>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("`{}` needs a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value `{}` for `{}`", value, flag))
+}
+
+// like `parse_value`, but with `parse`, e.g. `parse_duration`, naming `flag` in its errors
+fn parse_with<T>(flag: &str, value: Option<&str>, parse: fn(&str) -> Result<T, String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("`{}` needs a value", flag))?;
+    parse(value).map_err(|e| format!("{} for `{}`", e, flag))
+}
+
+// the slowest `--rate`, in items per second
+const MIN_RATE: f64 = 1.0 / 86_400.0;
+
+// e.g. `10%`, as a fraction from 0 to 1
+fn parse_percent(flag: &str, value: Option<&str>) -> Result<f64, String> {
+    let value = value.ok_or_else(|| format!("`{}` needs a value", flag))?;
+    match value.strip_suffix('%').unwrap_or(value).parse::<f64>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent / 100.0),
+        _ => Err(format!("invalid value `{}` for `{}`; it should be a percentage from 0 to 100", value, flag)),
+    }
+}
+
+// a number of seconds, e.g. `5` or `0.5`
+fn parse_secs(flag: &str, value: Option<&str>) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(parse_value(flag, value)?).map_err(|e| format!("invalid value for `{}`: {}", flag, e))
+}
+
+// e.g. `10x` or `0.5` times the recorded pace, or `None` for `max`
+fn parse_speed(flag: &str, value: Option<&str>) -> Result<Option<f64>, String> {
+    let value = value.ok_or_else(|| format!("`{}` needs a value", flag))?;
+    if value == "max" { return Ok(None); }
+    match value.strip_suffix('x').unwrap_or(value).parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(Some(speed)),
+        _ => Err(format!("invalid value `{}` for `{}`; it should be e.g. `0.1x`, `1x`, `10x` or `max`", value, flag)),
+    }
+}
+
+// a name for `bench` to keep a baseline under, as a file of its own
+fn parse_baseline_name(flag: &str, value: Option<&str>) -> Result<String, String> {
+    let name = value.ok_or_else(|| format!("`{}` needs a value", flag))?;
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!(
+            "invalid baseline name `{}` for `{}`; it's a file name, without `/` or a leading `.`", name, flag,
+        ));
+    }
+    Ok(name.to_string())
+}
+
+// the layout of a config file; everything is optional, and overrides the defaults
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    producers: FileProducers,
+    consumers: FileConsumers,
+    buffer: FileBuffer,
+    output: FileOutput,
+    // `[[buffers]]`: a `name`, and any of `ENV_OPTIONS` (with `-` as `_`) except `grace_period`
+    buffers: Vec<toml::Table>,
+}
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileProducers {
+    count: Option<usize>, rate: Option<f64>, arrivals: Option<String>, generator: Option<String>, seed: Option<u64>,
+    batch: Option<usize>, flush_interval: Option<String>, redeliver: Option<f64>,
+}
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConsumers {
+    count: Option<usize>, work_time: Option<String>, prefetch: Option<usize>, grace_period: Option<String>,
+    numa_node: Option<usize>, dedup_window: Option<usize>,
+}
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileBuffer {
+    capacity: Option<usize>, backend: Option<String>, strategy: Option<String>, wake: Option<String>,
+    bias: Option<String>, shards: Option<usize>, steal: Option<bool>, numa_node: Option<usize>,
+}
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileOutput {
+    echo: Option<bool>, stats_interval: Option<String>, tap: Option<String>, hold_times: Option<bool>,
+    color: Option<String>,
+}
+
+impl Config {
+
+    // returns the file's `[[buffers]]`, which are applied last, on top of every other source
+    fn apply_file(&mut self, path: &str) -> Result<Vec<toml::Table>, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read `{}`: {}", path, e))?;
+        let file: File = toml::from_str(&text).map_err(|e| format!("invalid config file `{}`: {}", path, e))?;
+
+        let in_file = |key: &str| format!("{} in `{}`", key, path);
+        if let Some(count) = file.producers.count { self.n_producers = count; }
+        if let Some(rate) = file.producers.rate { self.rate = Some(rate); }
+        if let Some(arrivals) = &file.producers.arrivals {
+            self.arrivals = parse_value(&in_file("producers.arrivals"), Some(arrivals))?;
+        }
+        if let Some(generator) = &file.producers.generator {
+            self.generator = parse_value(&in_file("producers.generator"), Some(generator))?;
+        }
+        if let Some(seed) = file.producers.seed { self.seed = seed; }
+        if let Some(batch) = file.producers.batch { self.producer_batch = batch; }
+        if let Some(interval) = &file.producers.flush_interval { self.flush_interval = parse_duration(interval)?; }
+        if let Some(redeliver) = file.producers.redeliver { self.redeliver = redeliver; }
+        if let Some(count) = file.consumers.count { self.n_consumers = count; }
+        if let Some(work_time) = &file.consumers.work_time { self.work_time = parse_work(work_time)?; }
+        if let Some(prefetch) = file.consumers.prefetch { self.prefetch = prefetch; }
+        if let Some(grace) = &file.consumers.grace_period { self.grace_period = parse_duration(grace)?; }
+        if let Some(node) = file.consumers.numa_node { self.consumer_node = Some(node); }
+        if let Some(window) = file.consumers.dedup_window { self.dedup_window = Some(window); }
+        if let Some(capacity) = file.buffer.capacity { self.capacity = capacity; }
+        if let Some(backend) = &file.buffer.backend {
+            self.backend = parse_value(&in_file("buffer.backend"), Some(backend))?;
+        }
+        if let Some(strategy) = &file.buffer.strategy {
+            self.strategy = parse_value(&in_file("buffer.strategy"), Some(strategy))?;
+        }
+        if let Some(wake) = &file.buffer.wake { self.wake = parse_value(&in_file("buffer.wake"), Some(wake))?; }
+        if let Some(bias) = &file.buffer.bias { self.bias = parse_value(&in_file("buffer.bias"), Some(bias))?; }
+        if let Some(shards) = file.buffer.shards { self.n_shards = shards; }
+        if let Some(steal) = file.buffer.steal { self.steal = steal; }
+        if let Some(node) = file.buffer.numa_node { self.numa_node = Some(node); }
+        if let Some(echo) = file.output.echo { self.echo = echo; }
+        if let Some(interval) = &file.output.stats_interval { self.stats_interval = parse_duration(interval)?; }
+        if let Some(tap) = &file.output.tap { self.tap = Some(parse_sampling(tap)?); }
+        if let Some(hold_times) = file.output.hold_times { self.hold_times = hold_times; }
+        if let Some(color) = &file.output.color { self.color = parse_value(&in_file("output.color"), Some(color))?; }
+        Ok(file.buffers)
+    }
+}
+
+// options which can be set with a flag `--<name>` or an environment variable `PC_<NAME>` (with `-` as `_`)
+const ENV_OPTIONS: [&str; 25] = [
+    "producers", "rate", "arrivals", "generator", "seed", "producer-batch", "flush-interval", "redeliver",
+    "consumers", "work-time", "prefetch", "grace-period", "consumer-node", "dedup-window",
+    "capacity", "strategy", "backend", "wake", "bias", "shards", "steal", "numa-node", "tap", "hold-times",
+    "color",
+];
+
+impl Config {
+
+    // set one of `ENV_OPTIONS`; `source` describes where the value came from, for errors
+    fn set(&mut self, option: &str, value: Option<&str>, source: &str) -> Result<(), String> {
+        match option {
+            "producers" => self.n_producers = parse_value(source, value)?,
+            "rate"      => self.rate        = Some(parse_value(source, value)?),
+            "arrivals"  => self.arrivals    = parse_value(source, value)?,
+            "generator" => self.generator   = parse_value(source, value)?,
+            "seed"      => self.seed        = parse_value(source, value)?,
+            "producer-batch" => self.producer_batch = parse_value(source, value)?,
+            "flush-interval" => self.flush_interval = parse_with(source, value, parse_duration)?,
+            "redeliver" => self.redeliver   = parse_value(source, value)?,
+            "consumers" => self.n_consumers = parse_value(source, value)?,
+            "work-time" => self.work_time   = parse_with(source, value, parse_work)?,
+            "prefetch"  => self.prefetch    = parse_value(source, value)?,
+            "grace-period" => self.grace_period = parse_with(source, value, parse_duration)?,
+            "consumer-node" => self.consumer_node = Some(parse_value(source, value)?),
+            "dedup-window" => self.dedup_window = Some(parse_value(source, value)?),
+            "tap"       => self.tap         = Some(parse_with(source, value, parse_sampling)?),
+            "hold-times" => self.hold_times = parse_value(source, value)?,
+            "color"     => self.color       = parse_value(source, value)?,
+            "capacity"  => self.capacity    = parse_value(source, value)?,
+            "strategy"  => self.strategy    = parse_value(source, value)?,
+            "backend"   => self.backend     = parse_value(source, value)?,
+            "wake"      => self.wake        = parse_value(source, value)?,
+            "bias"      => self.bias        = parse_value(source, value)?,
+            "shards"    => self.n_shards    = parse_value(source, value)?,
+            "steal"     => self.steal       = parse_value(source, value)?,
+            "numa-node" => self.numa_node   = Some(parse_value(source, value)?),
+            _ => unreachable!("not one of `ENV_OPTIONS`"),
+        }
+        Ok(())
+    }
+
+    // a copy of this config, with the options in one of a config file's `[[buffers]]` tables set, and its name
+    fn buffer(&self, table: &toml::Table, path: &str) -> Result<(String, Config), String> {
+        let Some(toml::Value::String(name)) = table.get("name") else {
+            return Err(format!("every `[[buffers]]` in `{}` needs a `name`", path));
+        };
+        let mut config = self.clone();
+        for (key, value) in table.iter().filter(|(key, _)| *key != "name") {
+            let option = key.replace('_', "-");
+            // the grace period is for the whole run
+            if option == "grace-period" || !ENV_OPTIONS.contains(&option.as_str()) {
+                return Err(format!("unknown key `{}` in buffer `{}` in `{}`", key, name, path));
+            }
+            // strings without their quotes, everything else as written
+            let value = match value { toml::Value::String(value) => value.clone(), value => value.to_string() };
+            config.set(&option, Some(&value), &format!("{} in buffer `{}`", key, name))?;
+        }
+        Ok((name.clone(), config))
+    }
+
+    // the buffers to run: the named ones, or else a single unnamed one with these options
+    pub fn instances(&self) -> Vec<(&str, &Config)> {
+        if self.buffers.is_empty() { return vec![("", self)]; }
+        self.buffers.iter().map(|(name, config)| (name.as_str(), config)).collect()
+    }
+
+    fn check(&self, command: &Command) -> Result<(), String> {
+        let bench = match command {
+            Command::Bench(bench) => Some(&**bench),
+            _ => None,
+        };
+        match (self.n_producers, self.n_consumers) {
+            (0, 0) => return Err("there are no producers or consumers, so there's nothing to run; set `--producers` \
+                and `--consumers` to at least 1".to_string()),
+            // a simulation just gives the other side every turn
+            _ if matches!(command, Command::Simulate { .. }) => {},
+            (_, 0) => return Err("there are producers but no consumers, so the producers would block forever once the \
+                buffer is full; set `--consumers` to at least 1".to_string()),
+            (0, _) => return Err("there are consumers but no producers, so the consumers would block forever waiting \
+                for items; set `--producers` to at least 1".to_string()),
+            _ => {},
+        }
+        if self.capacity < self.backend.min_capacity() {
+            return Err(format!(
+                "the capacity must be at least {} for the {} backend", self.backend.min_capacity(), self.backend.name(),
+            ));
+        }
+        if self.n_shards == 0 { return Err("there must be at least 1 shard".to_string()); }
+        if self.hold_times && !matches!(self.backend, Backend::Condvar | Backend::Spin) {
+            return Err("`--hold-times` is only for `--backend condvar` and `spin`".to_string());
+        }
+        if self.producer_batch == 0 { return Err("producers must push at least 1 item at a time".to_string()); }
+        if self.prefetch == 0 { return Err("consumers must prefetch at least 1 item".to_string()); }
+        let recording = bench.is_some_and(|bench| bench.record.is_some())
+            || matches!(command, Command::Verify { record: Some(_), .. });
+        if recording && matches!(self.backend, Backend::Sharded) {
+            return Err("the sharded backend can't be recorded".to_string());
+        }
+        // `verify`'s consumers stop after popping their share of the items, wherever those are
+        if matches!(command, Command::Verify { .. }) && matches!(self.backend, Backend::Sharded) && !self.steal {
+            return Err("`verify` needs `--steal true` with the sharded backend".to_string());
+        }
+        for node in self.numa_node.iter().chain(&self.consumer_node) { crate::numa::cpus(*node)?; }
+        if !(0.0..=1.0).contains(&self.redeliver) {
+            return Err("the fraction of items to redeliver must be between 0 and 1".to_string());
+        }
+        if self.dedup_window == Some(0) { return Err("the dedup window must hold at least 1 item".to_string()); }
+        // other generators repeat items, which would be taken for duplicates
+        if self.dedup_window.is_some() && !matches!(self.generator, Generator::Tagged) {
+            return Err("`--dedup-window` needs `--generator tagged`, whose items are all distinct".to_string());
+        }
+        // and whose journeys couldn't be told apart
+        let journey = match command {
+            Command::Run(run) => run.journey.is_some(),
+            Command::Bench(bench) => bench.journey.is_some(),
+            _ => false,
+        };
+        if journey && !matches!(self.generator, Generator::Tagged) {
+            return Err("`--journey` needs `--generator tagged`, whose items are their own trace ids".to_string());
+        }
+        if bench.is_some_and(|bench| bench.breakdown) && !matches!(self.generator, Generator::Tagged) {
+            return Err("`--breakdown` needs `--generator tagged`, whose items are their own trace ids".to_string());
+        }
+        if bench.is_some_and(|bench| bench.target_p99.is_some()) {
+            // as only it can change its capacity
+            if !matches!(self.backend, Backend::Condvar) {
+                return Err("`--target-p99` is only for `--backend condvar`".to_string());
+            }
+            if !matches!(self.generator, Generator::Tagged) {
+                return Err("`--target-p99` needs `--generator tagged`, whose items are their own trace \
+                    ids".to_string());
+            }
+        }
+        let queueing = bench.is_some_and(|bench| bench.queueing);
+        if queueing && (self.redeliver > 0.0 || self.dedup_window.is_some()) {
+            return Err("`--queueing` can't be combined with `--redeliver` or `--dedup-window`".to_string());
+        }
+        if self.rate.is_some_and(|rate| rate <= 0.0 || !rate.is_finite()) {
+            return Err("the rate must be positive".to_string());
+        }
+        // slower, and the time between pushes would overflow when drawn from an exponential distribution
+        if self.rate.is_some_and(|rate| rate < MIN_RATE) {
+            return Err("the rate must be at least 1 item a day".to_string());
+        }
+        Ok(())
+    }
+}
+
+// the stage `--stage<k>-work` is for
+fn stage_work(flag: &str) -> Option<usize> {
+    flag.strip_prefix("--stage")?.strip_suffix("-work")?.parse().ok()
+}
+
+fn env_name(option: &str) -> String { format!("PC_{}", option.to_uppercase().replace('-', "_")) }
+
+/* `args` excludes the program name; `env` looks up environment variables.
+Options are taken from, in increasing order of precedence: the defaults, the config file (`--config`, or else
+`PC_CONFIG`), environment variables, and the remaining flags. So a container or CI job can set environment variables as
+fallbacks for a shared config file, and a person can still override anything on the command line.
+*/
+pub fn parse(mut args: impl Iterator<Item = String>, env: impl Fn(&str) -> Option<String>)
+    -> Result<(Config, Command), String>
+{
+    let mut command = match args.next().as_deref() {
+        Some("run")      => Command::Run(Run {
+            n_items: None, on_starved: OnStarved::Warn, stall_after: Duration::from_secs(10), progress: None,
+            report: None, health: None, ws_port: None, journey: None,
+        }),
+        Some("bench")    => Command::Bench(Box::new(Bench {
+            duration: Duration::from_secs(5), warmup: Duration::ZERO, runs: 1, record: None,
+            stages: 2, csv: None, work: Vec::new(), queueing: false, matrix: false, matrix_csv: None, sweep: None,
+            item_bytes: None, byte_budget: None, recycle: None, arena_batch: None, zero_copy: false, switches: false,
+            max_in_flight: None, reorder_window: None, histogram: false, mode: Mode::Items, n_keys: None, per_key: 1,
+            echo: false, report: None, baseline: None, max_regression: None, save_baseline: None, compare: None,
+            journey: None, breakdown: false, target_p99: None,
+        })),
+        Some("verify")   => Command::Verify   { n_items: 10_000, record: None, report: None },
+        Some("simulate") => Command::Simulate { n_steps: 100, replay: None, rewind: None, speed: None },
+        Some("orchestrate") => Command::Orchestrate { duration: Duration::from_secs(5), credit_window: 0 },
+        Some("worker")   => Command::Worker   { role: None, connect: None, credit_window: 0 },
+        Some("serve")    => Command::Serve    {
+            listen: SocketAddr::from(([127, 0, 0, 1], 7878)), allow_shutdown: false,
+        },
+        Some("soak")     => Command::Soak     {
+            duration: Duration::from_secs(8 * 3600), n_items: 10_000, checkpoint: Duration::from_secs(60), round: None,
+        },
+        Some(other) => return Err(format!("unknown command `{}`", other)),
+        None => return Err("missing command".to_string()),
+    };
+
+    // accept both `--flag value` and `--flag=value`
+    let mut flags = Vec::new();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let value = value.or_else(|| args.next());
+        flags.push((flag, value));
+    }
+
+    let mut config = Config::default();
+    let mut stage_works = Vec::new();
+    let config_flag = flags.iter().rev().find(|(flag, _)| flag == "--config");
+    let path = match config_flag {
+        Some((flag, value)) => Some(parse_value::<String>(flag, value.as_deref())?),
+        None => env("PC_CONFIG"),
+    };
+    let buffers = match &path {
+        Some(path) => config.apply_file(path)?,
+        None => Vec::new(),
+    };
+
+    for option in ENV_OPTIONS {
+        let name = env_name(option);
+        if let Some(value) = env(&name) { config.set(option, Some(&value), &name)?; }
+    }
+
+    for (flag, value) in &flags {
+        let value = value.as_deref();
+        match (flag.as_str(), &mut command) {
+            ("--config", _) => {},
+            ("--gen", _) => config.set("generator", value, flag)?,
+            ("--duration", Command::Bench(bench)) => bench.duration = parse_secs(flag, value)?,
+            ("--duration", Command::Orchestrate { duration, .. }) => *duration = parse_secs(flag, value)?,
+            ("--warmup", Command::Bench(bench)) => bench.warmup = parse_with(flag, value, parse_duration)?,
+            ("--runs", Command::Bench(bench)) => bench.runs = parse_value(flag, value)?,
+            ("--baseline", Command::Bench(bench)) => bench.baseline = Some(parse_value(flag, value)?),
+            ("--max-regression", Command::Bench(bench)) =>
+                bench.max_regression = Some(parse_percent(flag, value)?),
+            ("--save-baseline", Command::Bench(bench)) =>
+                bench.save_baseline = Some(parse_baseline_name(flag, value)?),
+            ("--compare", Command::Bench(bench)) => bench.compare = Some(parse_baseline_name(flag, value)?),
+            ("--stages", Command::Bench(bench)) => bench.stages = parse_value(flag, value)?,
+            ("--occupancy-csv", Command::Bench(bench)) => bench.csv = Some(parse_value(flag, value)?),
+            ("--max-in-flight", Command::Bench(bench)) =>
+                bench.max_in_flight = Some(parse_value(flag, value)?),
+            ("--reorder-window", Command::Bench(bench)) =>
+                bench.reorder_window = Some(parse_value(flag, value)?),
+            ("--breakdown", Command::Bench(bench)) => bench.breakdown = parse_value(flag, value)?,
+            ("--target-p99", Command::Bench(bench)) =>
+                bench.target_p99 = Some(parse_with(flag, value, parse_duration)?),
+            ("--queueing", Command::Bench(bench)) => bench.queueing = parse_value(flag, value)?,
+            ("--switches", Command::Bench(bench)) => bench.switches = parse_value(flag, value)?,
+            ("--histogram", Command::Bench(bench)) => bench.histogram = parse_value(flag, value)?,
+            ("--echo",      Command::Bench(bench))      => bench.echo      = parse_value(flag, value)?,
+            ("--matrix", Command::Bench(bench)) => bench.matrix = parse_value(flag, value)?,
+            ("--sweep", Command::Bench(bench)) => bench.sweep = Some(parse_value(flag, value)?),
+            ("--item-bytes", Command::Bench(bench)) =>
+                bench.item_bytes = Some(parse_with(flag, value, parse_item_bytes)?),
+            ("--byte-budget", Command::Bench(bench)) => bench.byte_budget = Some(parse_value(flag, value)?),
+            ("--recycle", Command::Bench(bench)) => bench.recycle = Some(parse_value(flag, value)?),
+            ("--arena-batch", Command::Bench(bench)) => bench.arena_batch = Some(parse_value(flag, value)?),
+            ("--zero-copy", Command::Bench(bench)) => bench.zero_copy = parse_value(flag, value)?,
+            ("--mode", Command::Bench(bench)) => bench.mode = parse_value(flag, value)?,
+            ("--keys", Command::Bench(bench)) => bench.n_keys = Some(parse_value(flag, value)?),
+            ("--per-key", Command::Bench(bench)) => bench.per_key = parse_value(flag, value)?,
+            ("--matrix-csv", Command::Bench(bench)) => {
+                bench.matrix = true;
+                bench.matrix_csv = Some(parse_value(flag, value)?);
+            },
+            (flag, Command::Bench(_)) if stage_work(flag).is_some() =>
+                stage_works.push((stage_work(flag).unwrap(), parse_with(flag, value, parse_work)?)),
+            ("--items", Command::Verify { n_items, .. }) => *n_items = parse_value(flag, value)?,
+            ("--items", Command::Run(run)) => run.n_items = Some(parse_value(flag, value)?),
+            ("--items", Command::Soak { n_items, .. }) => *n_items = parse_value(flag, value)?,
+            ("--hours", Command::Soak { duration, .. }) =>
+                *duration = Duration::try_from_secs_f64(parse_value::<f64>(flag, value)? * 3600.0)
+                    .map_err(|e| format!("invalid value for `{}`: {}", flag, e))?,
+            ("--checkpoint", Command::Soak { checkpoint, .. }) =>
+                *checkpoint = parse_with(flag, value, parse_duration)?,
+            ("--round", Command::Soak { round, .. }) => *round = Some(parse_value(flag, value)?),
+            ("--on-starved", Command::Run(run)) => run.on_starved = parse_value(flag, value)?,
+            ("--stall-after", Command::Run(run)) =>
+                run.stall_after = parse_with(flag, value, parse_duration)?,
+            ("--progress", Command::Run(run)) => run.progress = Some(parse_value(flag, value)?),
+            ("--health", Command::Run(run)) => run.health = Some(parse_value(flag, value)?),
+            ("--ws-port", Command::Run(run)) => run.ws_port = Some(parse_value(flag, value)?),
+            ("--journey", Command::Run(run)) => run.journey = Some(parse_value(flag, value)?),
+            ("--journey", Command::Bench(bench)) => bench.journey = Some(parse_value(flag, value)?),
+            ("--record", Command::Bench(bench)) => bench.record = Some(parse_value(flag, value)?),
+            ("--record", Command::Verify { record, .. }) => *record = Some(parse_value(flag, value)?),
+            ("--report-json", Command::Run(run)) => run.report = Some(parse_value(flag, value)?),
+            ("--report-json", Command::Bench(bench)) => bench.report = Some(parse_value(flag, value)?),
+            ("--report-json", Command::Verify { report, .. }) => *report = Some(parse_value(flag, value)?),
+            ("--steps", Command::Simulate { n_steps, .. }) => *n_steps = parse_value(flag, value)?,
+            ("--replay", Command::Simulate { replay, .. }) => *replay = Some(parse_value(flag, value)?),
+            ("--rewind", Command::Simulate { rewind, .. }) => *rewind = Some(parse_value(flag, value)?),
+            ("--speed", Command::Simulate { speed, .. }) => *speed = parse_speed(flag, value)?,
+            ("--role", Command::Worker { role, .. }) => *role = Some(parse_value(flag, value)?),
+            ("--connect", Command::Worker { connect, .. }) => *connect = Some(parse_value(flag, value)?),
+            (
+                "--credit-window",
+                Command::Orchestrate { credit_window, .. } | Command::Worker { credit_window, .. },
+            ) => {
+                *credit_window = parse_value(flag, value)?;
+                if u32::try_from(*credit_window).is_err() {
+                    return Err(format!("`{}` can't be more than {}", flag, u32::MAX));
+                }
+            },
+            ("--listen", Command::Serve { listen, .. }) => *listen = parse_value(flag, value)?,
+            ("--allow-shutdown", Command::Serve { allow_shutdown, .. }) => *allow_shutdown = parse_value(flag, value)?,
+            (flag, _) => match flag.strip_prefix("--").filter(|option| ENV_OPTIONS.contains(option)) {
+                Some(option) => config.set(option, value, flag)?,
+                None => return Err(format!("unknown option `{}`", flag)),
+            },
+        }
+    }
+
+    if let Command::Bench(bench) = &mut command {
+        let Bench { stages, work, .. } = &mut **bench;
+        *work = vec![config.work_time; stages.saturating_sub(1)];
+        for &(stage, stage_work) in &stage_works {
+            if !(2..=*stages).contains(&stage) { return Err(format!("there's no stage {} to set the work of", stage)); }
+            work[stage - 2] = stage_work;
+        }
+        // without a pipeline, stage 2 is the consumers
+        if *stages == 2 { config.work_time = work[0]; }
+    }
+
+    // each buffer's own options take precedence over everything else, as they're the most specific
+    for table in &buffers {
+        let (name, buffer) = config.buffer(table, path.as_deref().unwrap_or_default())?;
+        if config.buffers.iter().any(|(other, _)| *other == name) {
+            return Err(format!("there are two buffers named `{}`", name));
+        }
+        config.buffers.push((name, buffer));
+    }
+    let bench = match &command {
+        Command::Bench(bench) => Some(&**bench),
+        _ => None,
+    };
+    let pipeline = bench.is_some_and(|bench| bench.stages > 2 || bench.csv.is_some());
+    if bench.is_some_and(|bench| bench.duration.is_zero()) {
+        return Err("there's nothing to measure in no time; set `--duration` to more than 0".to_string());
+    }
+    if matches!(&command, Command::Soak { duration, checkpoint, .. } if duration.is_zero() || checkpoint.is_zero()) {
+        return Err("a soak needs `--hours` and `--checkpoint` to be more than 0".to_string());
+    }
+    let n_items = match &command {
+        Command::Run(run) => run.n_items,
+        Command::Verify { n_items, .. } | Command::Soak { n_items, .. } => Some(*n_items),
+        _ => None,
+    };
+    if n_items == Some(0) {
+        return Err("with `--items 0` the producers would push nothing; set it to at least 1".to_string());
+    }
+    if matches!(&command, Command::Run(Run { n_items: None, progress: Some(true), .. })) {
+        return Err("`--progress` is only for `--items`, as there's no total to show progress towards".to_string());
+    }
+    if bench.is_some_and(|bench| bench.stages < 2) {
+        return Err("a pipeline needs at least 2 stages".to_string());
+    }
+    if pipeline && bench.is_some_and(|bench| bench.record.is_some()) {
+        return Err("a pipeline can't be recorded".to_string());
+    }
+    if let Some(max_in_flight) = bench.and_then(|bench| bench.max_in_flight) {
+        if !pipeline { return Err("`--max-in-flight` is only for a pipeline".to_string()); }
+        if max_in_flight == 0 { return Err("a pipeline must allow at least 1 item in flight".to_string()); }
+    }
+    if let Some(Bench { stages, reorder_window: Some(window), .. }) = bench {
+        if *stages < 3 { return Err("`--reorder-window` is only for a pipeline of 3 or more stages".to_string()); }
+        if *window == 0 { return Err("the reorder window must be at least 1".to_string()); }
+    }
+    if !pipeline && bench.is_some_and(|bench| bench.breakdown) {
+        return Err("`--breakdown` is only for a pipeline".to_string());
+    }
+    if pipeline && bench.is_some_and(|bench| bench.queueing) {
+        return Err("`--queueing` is only for a single stage of consumers".to_string());
+    }
+    // the benchmarks which replace the usual one
+    let mut modes = Vec::new();
+    if let Some(Bench {
+        runs, record, queueing, matrix, sweep, item_bytes, byte_budget, recycle, arena_batch, zero_copy, switches,
+        histogram, mode, n_keys, per_key, echo, report, baseline, max_regression, save_baseline, compare, journey,
+        target_p99, ..
+    }) = bench {
+        modes = [
+            (*matrix, "--matrix"), (sweep.is_some(), "--sweep"), (item_bytes.is_some(), "--item-bytes"),
+            (*mode == Mode::TaskQueue, "--mode taskqueue"), (target_p99.is_some(), "--target-p99"),
+        ]
+            .into_iter().filter(|&(on, _)| on).map(|(_, flag)| flag).collect();
+        if let [first, second, ..] = modes[..] {
+            return Err(format!("`{}` and `{}` can't be combined", first, second));
+        }
+        if let Some(flag) = modes.first().filter(|_| pipeline || *runs > 1 || record.is_some() || *queueing) {
+            return Err(format!("`{}` can't be combined with `--stages`, `--runs`, `--record` or `--queueing`", flag));
+        }
+        if *sweep == Some(0) { return Err("`--sweep` needs at least 1 thread".to_string()); }
+        if target_p99.is_some_and(|target| target.is_zero()) {
+            return Err("`--target-p99` must be more than 0".to_string());
+        }
+        match (byte_budget, item_bytes) {
+            (Some(_), None) => return Err("`--byte-budget` is only for `--item-bytes`".to_string()),
+            (Some(0), _) => return Err("the byte budget must be at least 1".to_string()),
+            _ => {},
+        }
+        match (recycle, item_bytes) {
+            (Some(_), None) => return Err("`--recycle` is only for `--item-bytes`".to_string()),
+            (Some(0), _) => return Err("`--recycle` must keep at least 1 payload".to_string()),
+            _ => {},
+        }
+        match (arena_batch, item_bytes, recycle) {
+            (Some(_), None, _) => return Err("`--arena-batch` is only for `--item-bytes`".to_string()),
+            (Some(_), _, Some(_)) => return Err("`--arena-batch` and `--recycle` can't be combined".to_string()),
+            (Some(0), ..) => return Err("an arena batch must hold at least 1 payload".to_string()),
+            _ => {},
+        }
+        if *zero_copy {
+            if item_bytes.is_none() { return Err("`--zero-copy` is only for `--item-bytes`".to_string()); }
+            if byte_budget.is_some() || recycle.is_some() || arena_batch.is_some() {
+                return Err(
+                    "`--zero-copy` can't be combined with `--byte-budget`, `--recycle` or `--arena-batch`".to_string(),
+                );
+            }
+        }
+        if n_keys.is_some() && *mode != Mode::TaskQueue {
+            return Err("`--keys` is only for `--mode taskqueue`".to_string());
+        }
+        match (n_keys, per_key) {
+            (Some(0), _) | (_, 0) => return Err("`--keys` and `--per-key` must be at least 1".to_string()),
+            (None, 2..) => return Err("`--per-key` is only for `--keys`".to_string()),
+            _ => {},
+        }
+        if *switches && (pipeline || !modes.is_empty()) {
+            return Err("`--switches` is only for a single stage of consumers, without `--matrix`, `--sweep`, \
+                `--item-bytes`, `--mode taskqueue` or `--target-p99`".to_string());
+        }
+        if *histogram && (pipeline || !modes.is_empty()) {
+            return Err("`--histogram` is only for a single stage of consumers, without `--matrix`, `--sweep`, \
+                `--item-bytes`, `--mode taskqueue` or `--target-p99`".to_string());
+        }
+        if *echo && (pipeline || !modes.is_empty()) {
+            return Err("`--echo` is only for a single stage of consumers, without `--matrix`, `--sweep`, \
+                `--item-bytes`, `--mode taskqueue` or `--target-p99`".to_string());
+        }
+        if baseline.is_some() && (pipeline || !modes.is_empty()) {
+            return Err("`--baseline` is only for a single stage of consumers, without `--matrix`, `--sweep`, \
+                `--item-bytes`, `--mode taskqueue` or `--target-p99`".to_string());
+        }
+        if max_regression.is_some() && baseline.is_none() {
+            return Err("`--max-regression` is only for `--baseline`".to_string());
+        }
+        let reporting = [(report.is_some(), "--report-json"), (save_baseline.is_some(), "--save-baseline"),
+            (compare.is_some(), "--compare")];
+        let reported = reporting.into_iter().find(|&(on, _)| on).map(|(_, flag)| flag);
+        if journey.is_some() && (*runs > 1 || !modes.is_empty() || *queueing) {
+            return Err("`--journey` is only for a single run, without `--matrix`, `--sweep`, `--item-bytes`, \
+                `--mode taskqueue`, `--target-p99` or `--queueing`".to_string());
+        }
+        if let Some(flag) = reported.filter(|_| pipeline || *runs > 1 || !modes.is_empty()) {
+            return Err(format!("`{}` is only for a single run of a single stage of consumers, without `--matrix`, \
+                `--sweep`, `--item-bytes`, `--mode taskqueue` or `--target-p99`", flag));
+        }
+        // whatever the config file says, which is for `run`
+        config.echo = *echo;
+        for (_, buffer) in &mut config.buffers { buffer.echo = *echo; }
+        if config.tap.is_some() && (pipeline || !modes.is_empty()) {
+            return Err("`--tap` is only for a single stage of consumers, without `--matrix`, `--sweep`, \
+                `--item-bytes`, `--mode taskqueue` or `--target-p99`".to_string());
+        }
+        if *switches && cfg!(not(target_os = "linux")) {
+            return Err("`--switches` is only supported on Linux".to_string());
+        }
+    }
+    if matches!(command, Command::Simulate { replay: None, speed: Some(_), .. }) {
+        return Err("`--speed` is only for `--replay`".to_string());
+    }
+    if let Command::Simulate { n_steps, replay, rewind: Some(step), .. } = &command {
+        if replay.is_some() { return Err("`--rewind` is only for taking turns, not `--replay`".to_string()); }
+        if step >= n_steps { return Err(format!("can't rewind to step {} of {}", step, n_steps)); }
+    }
+    if let Some(Bench { runs, record, .. }) = bench {
+        if *runs == 0 { return Err("there must be at least 1 run".to_string()); }
+        if *runs > 1 && pipeline { return Err("`--runs` is only for a single stage of consumers".to_string()); }
+        if *runs > 1 && record.is_some() { return Err("only a single run can be recorded".to_string()); }
+    }
+    if matches!(command, Command::Worker { role: None, .. } | Command::Worker { connect: None, .. }) {
+        return Err("a worker needs a `--role` and an orchestrator to `--connect` to".to_string());
+    }
+    if matches!(&command, Command::Orchestrate { duration, .. } if duration.is_zero()) {
+        return Err("`orchestrate` needs a `--duration` of more than 0".to_string());
+    }
+    let recording = bench.is_some_and(|bench| bench.record.is_some())
+        || matches!(command, Command::Verify { .. } | Command::Soak { .. });
+    let instead = !modes.is_empty();
+    let single = matches!(
+        command,
+        Command::Simulate { .. } | Command::Orchestrate { .. } | Command::Worker { .. } | Command::Serve { .. },
+    );
+    if !config.buffers.is_empty() && (pipeline || recording || instead || single) {
+        return Err("only `run` and `bench` (without `--record`, `--stages`, `--occupancy-csv`, `--matrix`, `--sweep`, \
+            `--item-bytes`, `--mode taskqueue` or `--target-p99`) can run several buffers".to_string());
+    }
+    for (name, buffer) in config.instances() {
+        buffer.check(&command).map_err(|e| if name.is_empty() { e } else { format!("buffer `{}`: {}", name, e) })?;
+    }
+    Ok((config, command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<(Config, Command), String> {
+        parse(args.split_whitespace().map(str::to_string), |_| None)
+    }
+
+    // rates which aren't positive, or so slow that a producer's pacing would overflow, are turned down before running
+    #[test]
+    fn rejects_bad_rates() {
+        assert_eq!(parse_args("run --rate 2.5").unwrap().0.rate, Some(2.5));
+        for rate in ["0", "-1", "inf", "NaN", "1e-30"] {
+            assert!(parse_args(&format!("run --rate {}", rate)).is_err(), "{}", rate);
+        }
+        assert!(parse_args("run --rate fast").err().is_some_and(|e| e.contains("invalid value")));
+    }
+
+    // a soak reruns a single round with `--round`, and otherwise goes through them all
+    #[test]
+    fn parses_the_round_to_rerun() {
+        let round = |args| match parse_args(args).map(|(_, command)| command) {
+            Ok(Command::Soak { round, .. }) => round,
+            _ => panic!("`{}` isn't a soak", args),
+        };
+        assert_eq!((round("soak"), round("soak --round 41")), (None, Some(41)));
+        assert!(parse_args("soak --round -1").is_err());
+    }
+
+    // a flag which takes a duration, work time, tap or item size says so when its value is missing, and names itself
+    // when it's invalid
+    #[test]
+    fn reports_missing_values() {
+        for args in ["bench --warmup", "run --flush-interval", "run --work-time", "run --tap", "bench --item-bytes"] {
+            let flag = args.split_whitespace().last().unwrap();
+            assert_eq!(parse_args(args).err(), Some(format!("`{}` needs a value", flag)));
+        }
+        assert!(parse_args("bench --warmup 5q").err().is_some_and(|e| e.ends_with("for `--warmup`")));
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    sync::{Mutex, Condvar},
+    thread,
+    time::{Duration, Instant},
+};
+
+/* Where the buffers' timeouts, flush intervals and sampling rates, and `pc`'s pacing of producers to `--rate`, get the
+time from, so that tests can control it instead of sleeping for real: `SystemClock` is the real time, and `MockClock`
+only moves when told to.
+A thread timing out waits on a condvar for `wait_for(timeout)` of real time at a time, then checks `now` again.
+*/
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    // how long to block for at most, for `timeout` to pass by this clock
+    fn wait_for(&self, timeout: Duration) -> Duration;
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant { Instant::now() }
+    fn wait_for(&self, timeout: Duration) -> Duration { timeout }
+    fn sleep(&self, duration: Duration) { thread::sleep(duration); }
+}
+
+// how often a thread waiting for a `MockClock` checks whether it has moved on
+const MOCK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/* A clock which stands still until `advance`d, for testing timeouts deterministically: however long a test takes for
+real, an operation with a 10s timeout only times out once the test has advanced the clock by 10s, and then does so
+promptly. `sleep` blocks until the clock has been advanced far enough.
+*/
+pub struct MockClock {
+    now: Mutex<Instant>,
+    advanced: Condvar,
+}
+impl MockClock {
+
+    // starting at the real time
+    pub fn new() -> Self { MockClock { now: Mutex::new(Instant::now()), advanced: Condvar::new() } }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+        self.advanced.notify_all();
+    }
+}
+impl Default for MockClock {
+    fn default() -> Self { Self::new() }
+}
+impl Clock for MockClock {
+    fn now(&self) -> Instant { *self.now.lock().unwrap() }
+    fn wait_for(&self, timeout: Duration) -> Duration { timeout.min(MOCK_POLL_INTERVAL) }
+
+    fn sleep(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        let until = *now + duration;
+        while *now < until { now = self.advanced.wait(now).unwrap(); }
+    }
+}
@@ -0,0 +1,834 @@
+#[cfg(not(feature = "shuttle"))]
+use std::sync::{Mutex, MutexGuard, Condvar};
+#[cfg(feature = "shuttle")]
+use shuttle::sync::{Mutex, MutexGuard, Condvar};
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, PoisonError},
+    time::{Duration, Instant},
+};
+use crate::clock::{Clock, SystemClock};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PushError<T> {
+    // the buffer is closed; the item is given back
+    Closed(T),
+    // the buffer stayed full until the timeout; the item is given back
+    Timeout(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PopError {
+    // the buffer is closed and there are no items left
+    Closed,
+    // the buffer stayed empty until the timeout
+    Timeout,
+}
+
+// the outcome of one pass through an operation's wait loop, see `ClosableBuffer::produce_one`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pass<R, W> {
+    // the operation is over, with this result
+    Done(R),
+    // it would wait, and gives back what it needs to try again (the item, for a push)
+    Wait(W),
+}
+
+// a `ClosableBuffer`'s state at some point, to `restore` it to later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint<T> {
+    // oldest first
+    pub items: Vec<T>,
+    pub closed: bool,
+}
+
+// what closing does with the items still in the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnClose {
+    // leave them for the consumers to pop
+    Drain,
+    // take them out and give them to the closer, so that consumers find the buffer empty straight away
+    Discard,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    // how much of the capacity the items take, see `ClosableBuffer::size`, leased ones included
+    n_used: usize,
+    // the items leased out, and how much of the capacity they take
+    n_leased: usize,
+    n_used_leased: usize,
+    closed: bool,
+    // whether the closing discarded the items, so that those whose leases are aborted after it go to the consumer
+    discarded: bool,
+    // number of threads waiting to push and to pop
+    n_waiting_pushes: usize,
+    n_waiting_pops: usize,
+}
+
+/* Bounded buffer with a capacity chosen at runtime, which can be closed, and whose operations can time out.
+This is what the language bindings are built on, since they can't use const generics. It is synchronized the same way
+as `SyncedBoundedBuffer`. Closing wakes every blocked thread: pushes fail from then on (including those which were
+waiting, whose items are given back), and pops keep succeeding until the remaining items are gone, which with
+`OnClose::Discard` is at once. Timeouts and deadlines are by the real time, or the `Clock` given to `with_clock`.
+The capacity is a number of items, or with `with_byte_budget`, a number of bytes, which each item takes its size of.
+
+Rather than popping an item, a consumer can `lease` it, to work on it in place: it stays in the buffer, taking up its
+room, until the consumer commits it, or requeues it at the front by aborting (or dropping the lease, e.g. while
+unwinding from a panic), for another consumer to have a go. While leases are out, a closed and empty buffer isn't done
+yet, as an item may still come back, so pops wait for them; a consumer mustn't pop while holding a lease, then.
+*/
+pub struct ClosableBuffer<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    // how much of the capacity an item takes: 1, or its size in bytes with a byte budget
+    size: fn(&T) -> usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+    clock: Arc<dyn Clock>,
+}
+impl<T> ClosableBuffer<T> {
+
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        ClosableBuffer {
+            state: Mutex::new(State {
+                items: VecDeque::with_capacity(capacity), n_used: 0, n_leased: 0, n_used_leased: 0, closed: false,
+                discarded: false, n_waiting_pushes: 0, n_waiting_pops: 0,
+            }),
+            capacity,
+            size: |_| 1,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /* A buffer bounded by the total size of its items, `size_of` each, instead of their number, like a network buffer:
+    a push waits until its item fits in what's left of `n_bytes`. An item bigger than that on its own still goes in once
+    the buffer is empty, so that it can't wait forever, and the buffer then goes over its budget until it's popped.
+    */
+    pub fn with_byte_budget(n_bytes: usize, size_of: fn(&T) -> usize) -> Self {
+        assert!(n_bytes > 0, "a buffer with a budget of 0 bytes can never be pushed to");
+        ClosableBuffer {
+            state: Mutex::new(State {
+                items: VecDeque::new(), n_used: 0, n_leased: 0, n_used_leased: 0, closed: false, discarded: false,
+                n_waiting_pushes: 0, n_waiting_pops: 0,
+            }),
+            capacity: n_bytes,
+            size: size_of,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+    // not counting those leased out
+    pub fn n_items (&self) -> usize { self.state.lock().unwrap().items.len() }
+    pub fn n_leased(&self) -> usize { self.state.lock().unwrap().n_leased }
+    // how much of the capacity is taken: `n_items` and `n_leased`, or the items' total size with a byte budget
+    pub fn n_used  (&self) -> usize { self.state.lock().unwrap().n_used }
+    pub fn closed  (&self) -> bool  { self.state.lock().unwrap().closed }
+    // the threads blocked right now: [pushing, popping]
+    pub fn n_waiting(&self) -> [usize; 2] {
+        let state = self.state.lock().unwrap();
+        [state.n_waiting_pushes, state.n_waiting_pops]
+    }
+
+    // a copy of the items, oldest first
+    pub fn snapshot(&self) -> Vec<T> where T: Clone { self.state.lock().unwrap().items.iter().cloned().collect() }
+
+    /* The buffer's items and whether it's closed, for tests and simulations to rewind to with `restore`. Both hold the
+    lock throughout, which pauses every producer and consumer, so a checkpoint never catches an operation halfway.
+    */
+    pub fn checkpoint(&self) -> Checkpoint<T> where T: Clone {
+        let state = self.state.lock().unwrap();
+        Checkpoint { items: state.items.iter().cloned().collect(), closed: state.closed }
+    }
+
+    // replace the items with the checkpoint's, and reopen or close the buffer as it was; threads waiting for room or
+    // for an item check again, as there may be some now. Leases out are left alone
+    pub fn restore(&self, checkpoint: Checkpoint<T>) {
+        let mut state = self.state.lock().unwrap();
+        state.n_used = state.n_used_leased + checkpoint.items.iter().map(self.size).sum::<usize>();
+        state.items = checkpoint.items.into();
+        state.closed = checkpoint.closed;
+        state.discarded = false;
+        drop(state);
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn close(&self) { self.close_with(OnClose::Drain); }
+
+    // close, and return the items which were in the buffer if `on_close` discards them (and nothing otherwise); those
+    // leased out stay with their consumers, and if aborted, go back to them rather than into the buffer
+    pub fn close_with(&self, on_close: OnClose) -> Vec<T> {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let discarded = match on_close {
+            OnClose::Drain   => Vec::new(),
+            OnClose::Discard => {
+                state.n_used = state.n_used_leased;
+                state.discarded = true;
+                state.items.drain(..).collect()
+            },
+        };
+        drop(state);
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+        discarded
+    }
+
+    /* Wait on `condvar` while `blocked` holds, until `deadline` if there is one; returns whether we timed out.
+    Meanwhile we count ourselves in the counter `n_waiting` picks out of the state.
+    */
+    fn wait_while<'a>(
+        &self, mut state: MutexGuard<'a, State<T>>, condvar: &Condvar, deadline: Option<Instant>,
+        blocked: impl Fn(&State<T>) -> bool, n_waiting: impl Fn(&mut State<T>) -> &mut usize,
+    ) -> (MutexGuard<'a, State<T>>, bool) {
+        let mut timed_out = false;
+        *n_waiting(&mut state) += 1;
+        while blocked(&state) {
+            match deadline {
+                None => state = condvar.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = self.clock.now();
+                    if now >= deadline {
+                        timed_out = true;
+                        break;
+                    }
+                    state = condvar.wait_timeout(state, self.clock.wait_for(deadline - now)).unwrap().0;
+                },
+            }
+        }
+        *n_waiting(&mut state) -= 1;
+        (state, timed_out)
+    }
+
+    // whether a push of an item of `size` or a pop has to wait; an empty buffer takes any item, and once closed, a pop
+    // only waits for the leases out
+    fn push_blocked(&self, state: &State<T>, size: usize) -> bool {
+        state.n_used + size > self.capacity && (!state.items.is_empty() || state.n_leased > 0) && !state.closed
+    }
+    fn pop_blocked(state: &State<T>) -> bool { state.items.is_empty() && !Self::done(state) }
+    // closed, with nothing more to come
+    fn done(state: &State<T>) -> bool { state.closed && state.n_leased == 0 }
+
+    // what a push or a pop does once it's done waiting, or timed out
+    fn end_push(&self, mut state: MutexGuard<State<T>>, item: T, timed_out: bool) -> Result<(), PushError<T>> {
+        if state.closed { return Err(PushError::Closed(item)); }
+        if timed_out { return Err(PushError::Timeout(item)); }
+
+        state.n_used += (self.size)(&item);
+        state.items.push_back(item);
+        self.not_empty.notify_all();
+        Ok(())
+    }
+    fn end_pop(&self, mut state: MutexGuard<State<T>>, timed_out: bool) -> Result<T, PopError> {
+        match state.items.pop_front() {
+            Some(item) => {
+                state.n_used -= (self.size)(&item);
+                self.not_full.notify_all();
+                Ok(item)
+            },
+            None if timed_out => Err(PopError::Timeout),
+            None => Err(PopError::Closed),
+        }
+    }
+
+    fn push_until(&self, item: T, deadline: Option<Instant>) -> Result<(), PushError<T>> {
+        let size = (self.size)(&item);
+        let state = self.state.lock().unwrap();
+        let (state, timed_out) = self.wait_while(
+            state, &self.not_full, deadline, |s| self.push_blocked(s, size), |s| &mut s.n_waiting_pushes,
+        );
+        self.end_push(state, item, timed_out)
+    }
+
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, PopError> {
+        let state = self.state.lock().unwrap();
+        let (state, timed_out) = self.wait_while(
+            state, &self.not_empty, deadline, Self::pop_blocked, |s| &mut s.n_waiting_pops,
+        );
+        self.end_pop(state, timed_out)
+    }
+
+    /* One pass through `push`'s wait loop, without waiting: push the item, or fail if the buffer is closed, or if it's
+    full, give the item back where `push` would wait. With `consume_one`, this lets a test drive the buffer through an
+    exact interleaving of operations on a single thread (push, push, pop, close, ...), each retried by calling it again
+    as if a blocked thread had been woken, to check what the wait loops make of it.
+    */
+    pub fn produce_one(&self, item: T) -> Pass<Result<(), PushError<T>>, T> {
+        let state = self.state.lock().unwrap();
+        if self.push_blocked(&state, (self.size)(&item)) { return Pass::Wait(item); }
+        Pass::Done(self.end_push(state, item, false))
+    }
+    // one pass through `pop`'s wait loop, without waiting, like `produce_one`
+    pub fn consume_one(&self) -> Pass<Result<T, PopError>, ()> {
+        let state = self.state.lock().unwrap();
+        if Self::pop_blocked(&state) { return Pass::Wait(()); }
+        Pass::Done(self.end_pop(state, false))
+    }
+
+    // block while the buffer is full
+    pub fn push(&self, item: T) -> Result<(), PushError<T>> { self.push_until(item, None) }
+    // block while the buffer is empty
+    pub fn pop(&self) -> Result<T, PopError> { self.pop_until(None) }
+
+    pub fn push_timeout(&self, item: T, timeout: Duration) -> Result<(), PushError<T>> {
+        self.push_until(item, Some(self.clock.now() + timeout))
+    }
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<T, PopError> {
+        self.pop_until(Some(self.clock.now() + timeout))
+    }
+
+    // like `push_timeout` and `pop_timeout`, but against a fixed point in time (by the buffer's clock), so that several
+    // operations can share one deadline; a deadline which has already passed still succeeds if the operation needn't
+    // wait
+    pub fn push_deadline(&self, item: T, deadline: Instant) -> Result<(), PushError<T>> {
+        self.push_until(item, Some(deadline))
+    }
+    pub fn pop_deadline(&self, deadline: Instant) -> Result<T, PopError> { self.pop_until(Some(deadline)) }
+
+    /* Wait until the buffer holds at least `min` items, then pop up to `max` of them at once, oldest first: for
+    consumers with a cost per batch (e.g. a database insert) which want batches of a useful size without waiting
+    forever for them. If the buffer is closed or `timeout` passes first, pop whatever there is instead, failing only if
+    that's nothing. With a byte budget, whether `min` items can fit at once depends on their sizes.
+    */
+    pub fn pop_batch_min(&self, min: usize, max: usize, timeout: Duration) -> Result<Vec<T>, PopError> {
+        assert!(1 <= min && min <= max, "need 1 <= min <= max");
+        assert!(min <= self.capacity, "the buffer can never hold `min` items");
+        let state = self.state.lock().unwrap();
+        let (mut state, timed_out) = self.wait_while(
+            state, &self.not_empty, Some(self.clock.now() + timeout), |s| s.items.len() < min && !Self::done(s),
+            |s| &mut s.n_waiting_pops,
+        );
+        let n_popped = state.items.len().min(max);
+        if n_popped == 0 { return Err(if timed_out { PopError::Timeout } else { PopError::Closed }); }
+
+        let batch: Vec<T> = state.items.drain(..n_popped).collect();
+        state.n_used -= batch.iter().map(self.size).sum::<usize>();
+        self.not_full.notify_all();
+        Ok(batch)
+    }
+
+    /* Shut down gracefully: close the buffer, leaving its items for the consumers, and wait for them to pop them all,
+    and commit those leased, calling `progress` with the number left every `interval` meanwhile (not holding the lock).
+    If `deadline` (by the buffer's clock) comes first, take out what's left and return it, for the caller to log or
+    save, instead of silently dropping it; an empty result means the consumers got everything. Leases aborted after
+    that go back to their consumers, as with `OnClose::Discard`.
+    */
+    pub fn drain_with_progress(
+        &self, deadline: Instant, interval: Duration, mut progress: impl FnMut(usize),
+    ) -> Vec<T> {
+        assert!(!interval.is_zero(), "progress can't be reported continuously");
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+        let mut next_report = self.clock.now() + interval;
+        while !state.items.is_empty() || state.n_leased > 0 {
+            let now = self.clock.now();
+            if now >= deadline {
+                state.n_used = state.n_used_leased;
+                state.discarded = true;
+                return state.items.drain(..).collect();
+            }
+            if now >= next_report {
+                let n_left = state.items.len() + state.n_leased;
+                drop(state);
+                progress(n_left);
+                state = self.state.lock().unwrap();
+                next_report += interval;
+                continue;
+            }
+            // every pop and commit notifies `not_full`
+            let timeout = self.clock.wait_for(next_report.min(deadline) - now);
+            state = self.not_full.wait_timeout(state, timeout).unwrap().0;
+        }
+        Vec::new()
+    }
+
+    // wait for an item like `pop`, but lease it rather than taking it out of the buffer
+    pub fn lease(&self) -> Result<LeaseGuard<'_, T>, PopError> {
+        let state = self.state.lock().unwrap();
+        let (mut state, _) = self.wait_while(
+            state, &self.not_empty, None, Self::pop_blocked, |s| &mut s.n_waiting_pops,
+        );
+        let item = state.items.pop_front().ok_or(PopError::Closed)?;
+        let size = (self.size)(&item);
+        state.n_leased += 1;
+        state.n_used_leased += size;
+        Ok(LeaseGuard { buffer: self, item: Some(item), size })
+    }
+
+    // the lease on `item`, which took `size` when leased, is over, with it taken out of the buffer and given back, or
+    // requeued at the front, unless the items were discarded, which gives it back instead. A requeued item takes its
+    // size as it is now, which the consumer may have changed
+    fn end_lease(&self, item: T, size: usize, commit: bool) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        state.n_leased -= 1;
+        state.n_used_leased -= size;
+        if commit || state.discarded {
+            state.n_used -= size;
+            // the last lease out of a closed buffer ends pops waiting for it
+            if Self::done(&state) { self.not_empty.notify_all(); }
+            self.not_full.notify_all();
+            return Some(item);
+        }
+        state.n_used = state.n_used - size + (self.size)(&item);
+        state.items.push_front(item);
+        self.not_empty.notify_all();
+        None
+    }
+}
+
+/* An item leased from a `ClosableBuffer`, which the consumer has to itself, to read or change in place, while it stays
+in the buffer. `commit` takes it out for good, and `abort` (or dropping the lease) puts it back at the front.
+*/
+pub struct LeaseGuard<'a, T> {
+    buffer: &'a ClosableBuffer<T>,
+    // only `None` once the lease is over
+    item: Option<T>,
+    // how much of the capacity the item took when leased, whatever's been done to it since
+    size: usize,
+}
+impl<T> LeaseGuard<'_, T> {
+    // done with the item: take it out of the buffer, making room for another
+    pub fn commit(mut self) -> T { self.buffer.end_lease(self.item.take().unwrap(), self.size, true).unwrap() }
+
+    // put the item back at the front of the buffer, for the next pop or lease; after a close which discarded the items,
+    // it's given back instead
+    pub fn abort(mut self) -> Option<T> { self.buffer.end_lease(self.item.take().unwrap(), self.size, false) }
+}
+impl<T> Deref for LeaseGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T { self.item.as_ref().unwrap() }
+}
+impl<T> DerefMut for LeaseGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T { self.item.as_mut().unwrap() }
+}
+impl<T> Drop for LeaseGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(item) = self.item.take() { self.buffer.end_lease(item, self.size, false); }
+    }
+}
+
+// for dumping the state of a stalled program; takes the lock (even if a thread panicked while holding it)
+impl<T: fmt::Debug> fmt::Debug for ClosableBuffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("ClosableBuffer")
+            .field("items", &state.items)
+            .field("n_items", &state.items.len())
+            .field("n_leased", &state.n_leased)
+            .field("n_used", &state.n_used)
+            .field("capacity", &self.capacity)
+            .field("closed", &state.closed)
+            .field("n_waiting_pushes", &state.n_waiting_pushes)
+            .field("n_waiting_pops", &state.n_waiting_pops)
+            .finish()
+    }
+}
+
+// under shuttle, these would block outside of its scheduler
+#[cfg(all(test, not(feature = "shuttle")))]
+mod tests {
+    use std::{
+        sync::{Arc, mpsc},
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+    use crate::clock::MockClock;
+    use super::*;
+
+    const LONG: Duration = Duration::from_secs(10);
+
+    // a buffer of capacity 2 holding `items`
+    fn holding(items: &[i32]) -> Arc<ClosableBuffer<i32>> {
+        let buffer = Arc::new(ClosableBuffer::new(2));
+        for &item in items { buffer.push(item).unwrap(); }
+        buffer
+    }
+
+    // run `f` on another thread and return once it's blocked in the buffer
+    fn blocked<R: Send + 'static>(
+        buffer: &Arc<ClosableBuffer<i32>>, f: impl FnOnce(&ClosableBuffer<i32>) -> R + Send + 'static,
+    ) -> JoinHandle<R> {
+        let n_waiting = |buffer: &ClosableBuffer<i32>| {
+            let state = buffer.state.lock().unwrap();
+            state.n_waiting_pushes + state.n_waiting_pops
+        };
+        let before = n_waiting(buffer);
+        let thread = { let buffer = buffer.clone(); thread::spawn(move || f(&buffer)) };
+        while n_waiting(buffer) == before { thread::yield_now(); }
+        thread
+    }
+
+    #[test]
+    fn push_after_close() {
+        for on_close in [OnClose::Drain, OnClose::Discard] {
+            let buffer = holding(&[]);
+            buffer.close_with(on_close);
+            assert_eq!(buffer.push(1), Err(PushError::Closed(1)));
+            assert_eq!(buffer.push_timeout(2, LONG), Err(PushError::Closed(2)));
+            assert_eq!(buffer.push_deadline(3, Instant::now() + LONG), Err(PushError::Closed(3)));
+        }
+    }
+
+    #[test]
+    fn drain_leaves_items_to_pop() {
+        let buffer = holding(&[1, 2]);
+        assert!(buffer.close_with(OnClose::Drain).is_empty());
+        assert_eq!(buffer.pop(), Ok(1));
+        assert_eq!(buffer.pop_timeout(LONG), Ok(2));
+        assert_eq!(buffer.pop(), Err(PopError::Closed));
+        assert_eq!(buffer.pop_timeout(LONG), Err(PopError::Closed));
+    }
+
+    #[test]
+    fn discard_returns_items() {
+        let buffer = holding(&[1, 2]);
+        assert_eq!(buffer.close_with(OnClose::Discard), [1, 2]);
+        assert_eq!(buffer.n_items(), 0);
+        assert_eq!(buffer.pop(), Err(PopError::Closed));
+        assert_eq!(buffer.pop_batch_min(1, 2, LONG), Err(PopError::Closed));
+        // closing again discards nothing more
+        assert!(buffer.close_with(OnClose::Discard).is_empty());
+    }
+
+    #[test]
+    fn close_wakes_blocked_pushes() {
+        for on_close in [OnClose::Drain, OnClose::Discard] {
+            let buffer = holding(&[1, 2]);
+            let push = blocked(&buffer, |buffer| buffer.push(3));
+            let push_timeout = blocked(&buffer, |buffer| buffer.push_timeout(4, LONG));
+            let discarded = buffer.close_with(on_close);
+            assert_eq!(push.join().unwrap(), Err(PushError::Closed(3)));
+            assert_eq!(push_timeout.join().unwrap(), Err(PushError::Closed(4)));
+            // the waiting items never made it into the buffer
+            match on_close {
+                OnClose::Drain   => assert_eq!((discarded, buffer.snapshot()), (vec![], vec![1, 2])),
+                OnClose::Discard => assert_eq!((discarded, buffer.snapshot()), (vec![1, 2], vec![])),
+            }
+            assert_eq!(buffer.state.lock().unwrap().n_waiting_pushes, 0);
+        }
+    }
+
+    #[test]
+    fn close_wakes_blocked_pops() {
+        for on_close in [OnClose::Drain, OnClose::Discard] {
+            let buffer = holding(&[]);
+            let pop = blocked(&buffer, |buffer| buffer.pop());
+            let pop_timeout = blocked(&buffer, |buffer| buffer.pop_timeout(LONG));
+            let pop_batch = blocked(&buffer, |buffer| buffer.pop_batch_min(2, 2, LONG));
+            assert!(buffer.close_with(on_close).is_empty());
+            assert_eq!(pop.join().unwrap(), Err(PopError::Closed));
+            assert_eq!(pop_timeout.join().unwrap(), Err(PopError::Closed));
+            assert_eq!(pop_batch.join().unwrap(), Err(PopError::Closed));
+            assert_eq!(buffer.state.lock().unwrap().n_waiting_pops, 0);
+        }
+    }
+
+    // a batch pop waiting for more items than there are gets what there is when draining, and nothing when discarding
+    #[test]
+    fn close_ends_batch_wait() {
+        let buffer = holding(&[1]);
+        let pop_batch = blocked(&buffer, |buffer| buffer.pop_batch_min(2, 2, LONG));
+        buffer.close_with(OnClose::Drain);
+        assert_eq!(pop_batch.join().unwrap(), Ok(vec![1]));
+
+        let buffer = holding(&[1]);
+        let pop_batch = blocked(&buffer, |buffer| buffer.pop_batch_min(2, 2, LONG));
+        assert_eq!(buffer.close_with(OnClose::Discard), [1]);
+        assert_eq!(pop_batch.join().unwrap(), Err(PopError::Closed));
+    }
+
+    // a push waiting for room gets it from a pop, or fails once the buffer is closed, and a pop waiting for an item
+    // gets the next one pushed, or fails once the buffer is closed, unless items are left to drain
+    #[test]
+    fn steps_through_wait_loops() {
+        let buffer = holding(&[1]);
+        assert_eq!(buffer.produce_one(2), Pass::Done(Ok(())));
+        assert_eq!(buffer.produce_one(3), Pass::Wait(3));
+        assert_eq!(buffer.consume_one(), Pass::Done(Ok(1)));
+        assert_eq!(buffer.produce_one(3), Pass::Done(Ok(())));
+        assert_eq!(buffer.produce_one(4), Pass::Wait(4));
+        buffer.close();
+        assert_eq!(buffer.produce_one(4), Pass::Done(Err(PushError::Closed(4))));
+        assert_eq!(buffer.consume_one(), Pass::Done(Ok(2)));
+        assert_eq!(buffer.consume_one(), Pass::Done(Ok(3)));
+        assert_eq!(buffer.consume_one(), Pass::Done(Err(PopError::Closed)));
+
+        let buffer = holding(&[]);
+        assert_eq!(buffer.consume_one(), Pass::Wait(()));
+        assert_eq!(buffer.produce_one(1), Pass::Done(Ok(())));
+        assert_eq!(buffer.consume_one(), Pass::Done(Ok(1)));
+        assert_eq!(buffer.consume_one(), Pass::Wait(()));
+        assert!(buffer.close_with(OnClose::Discard).is_empty());
+        assert_eq!(buffer.consume_one(), Pass::Done(Err(PopError::Closed)));
+    }
+
+    // with a byte budget, a push waits until its item fits in what's left, however many items that leaves room for,
+    // except in an empty buffer, which takes an item bigger than the whole budget
+    #[test]
+    fn byte_budget() {
+        let buffer = ClosableBuffer::<Vec<u8>>::with_byte_budget(8, Vec::len);
+        assert_eq!(buffer.produce_one(vec![0; 5]), Pass::Done(Ok(())));
+        assert_eq!(buffer.produce_one(vec![1; 4]), Pass::Wait(vec![1; 4]));
+        assert_eq!(buffer.produce_one(vec![2; 3]), Pass::Done(Ok(())));
+        assert_eq!((buffer.n_items(), buffer.n_used()), (2, 8));
+        assert_eq!(buffer.consume_one(), Pass::Done(Ok(vec![0; 5])));
+        assert_eq!(buffer.produce_one(vec![1; 4]), Pass::Done(Ok(())));
+        assert_eq!(buffer.pop_batch_min(1, 2, LONG).unwrap().len(), 2);
+        assert_eq!(buffer.produce_one(vec![3; 20]), Pass::Done(Ok(())));
+        assert_eq!(buffer.produce_one(vec![4; 1]), Pass::Wait(vec![4; 1]));
+        assert_eq!(buffer.close_with(OnClose::Discard), [vec![3; 20]]);
+        assert_eq!(buffer.n_used(), 0);
+    }
+
+    // timeouts still work on an open buffer, and a waiter which timed out is no longer counted
+    #[test]
+    fn timeouts_before_close() {
+        let buffer = holding(&[1, 2]);
+        assert_eq!(buffer.push_timeout(3, Duration::from_millis(10)), Err(PushError::Timeout(3)));
+        let buffer = holding(&[]);
+        assert_eq!(buffer.pop_timeout(Duration::from_millis(10)), Err(PopError::Timeout));
+        let state = buffer.state.lock().unwrap();
+        assert_eq!((state.n_waiting_pushes, state.n_waiting_pops), (0, 0));
+    }
+
+    // by a mock clock, operations time out once it's advanced past their deadline, and not before, however long that
+    // takes for real
+    #[test]
+    fn timeouts_follow_the_clock() {
+        let clock = Arc::new(MockClock::new());
+        let buffer = Arc::new(ClosableBuffer::new(2).with_clock(clock.clone()));
+        let pop = blocked(&buffer, |buffer| buffer.pop_timeout(LONG));
+        clock.advance(LONG - Duration::from_nanos(1));
+        buffer.push(1).unwrap();
+        assert_eq!(pop.join().unwrap(), Ok(1));
+
+        let pop = blocked(&buffer, |buffer| buffer.pop_timeout(LONG));
+        let pop_batch = blocked(&buffer, |buffer| buffer.pop_batch_min(1, 2, LONG));
+        clock.advance(LONG);
+        assert_eq!(pop.join().unwrap(), Err(PopError::Timeout));
+        assert_eq!(pop_batch.join().unwrap(), Err(PopError::Timeout));
+
+        buffer.push(1).unwrap();
+        buffer.push(2).unwrap();
+        let deadline = clock.now() + LONG;
+        let push = blocked(&buffer, move |buffer| buffer.push_deadline(3, deadline));
+        clock.advance(LONG);
+        assert_eq!(push.join().unwrap(), Err(PushError::Timeout(3)));
+    }
+
+    // restoring rewinds the items and closing, and wakes a pop which was waiting for an item
+    #[test]
+    fn restore_rewinds_to_the_checkpoint() {
+        let buffer = holding(&[1, 2]);
+        let checkpoint = buffer.checkpoint();
+        assert_eq!(checkpoint, Checkpoint { items: vec![1, 2], closed: false });
+        assert_eq!((buffer.pop(), buffer.pop()), (Ok(1), Ok(2)));
+        let pop = blocked(&buffer, |buffer| buffer.pop());
+        buffer.restore(checkpoint.clone());
+        assert_eq!(pop.join().unwrap(), Ok(1));
+        buffer.close();
+        buffer.restore(checkpoint);
+        assert_eq!((buffer.snapshot(), buffer.n_used(), buffer.closed()), (vec![1, 2], 2, false));
+        // full again, and open
+        assert_eq!(buffer.push_timeout(3, Duration::ZERO), Err(PushError::Timeout(3)));
+    }
+
+    // draining reports how many items are left every interval, then gives back those still left at the deadline
+    #[test]
+    fn drain_with_progress_gives_back_leftovers() {
+        let clock = Arc::new(MockClock::new());
+        let buffer = Arc::new(ClosableBuffer::new(2).with_clock(clock.clone()));
+        for item in [1, 2] { buffer.push(item).unwrap(); }
+        let (deadline, (reports, reported)) = (clock.now() + LONG, mpsc::channel());
+        let drain = {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.drain_with_progress(deadline, LONG / 4, |n| reports.send(n).unwrap()))
+        };
+        while !buffer.closed() { thread::yield_now(); }
+        assert_eq!(buffer.pop(), Ok(1));
+        clock.advance(LONG / 4);
+        assert_eq!(reported.recv(), Ok(1));
+        clock.advance(LONG);
+        assert_eq!(drain.join().unwrap(), [2]);
+        assert!(reported.try_iter().next().is_none());
+        assert_eq!((buffer.n_items(), buffer.n_used()), (0, 0));
+
+        // once the consumers have popped everything, there's nothing left over
+        let buffer = holding(&[1]);
+        let drain = {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.drain_with_progress(Instant::now() + LONG, LONG, |_| {}))
+        };
+        while !buffer.closed() { thread::yield_now(); }
+        assert_eq!(buffer.pop(), Ok(1));
+        assert!(drain.join().unwrap().is_empty());
+    }
+
+    // a leased item keeps its room until committed, and an aborted or dropped lease puts it back, changes and all, at
+    // the front
+    #[test]
+    fn leases_commit_or_requeue() {
+        let buffer = holding(&[1, 2]);
+        let mut lease = buffer.lease().unwrap();
+        *lease += 10;
+        assert_eq!((buffer.n_items(), buffer.n_leased(), buffer.n_used()), (1, 1, 2));
+        assert_eq!(buffer.push_timeout(3, Duration::ZERO), Err(PushError::Timeout(3)));
+        assert_eq!(lease.abort(), None);
+        assert_eq!(buffer.snapshot(), [11, 2]);
+        drop(buffer.lease().unwrap());
+        let lease = buffer.lease().unwrap();
+        assert_eq!(*lease, 11);
+        assert_eq!(lease.commit(), 11);
+        assert_eq!((buffer.snapshot(), buffer.n_leased(), buffer.n_used()), (vec![2], 0, 1));
+        assert_eq!(buffer.push_timeout(3, Duration::ZERO), Ok(()));
+    }
+
+    // once closed, pops wait for the leases out, and get a requeued item, or fail once it's committed; after a discard,
+    // an aborted lease gives its item back instead
+    #[test]
+    fn close_waits_for_leases() {
+        let buffer = holding(&[1]);
+        let lease = buffer.lease().unwrap();
+        buffer.close();
+        let pop = blocked(&buffer, |buffer| buffer.pop());
+        drop(lease);
+        assert_eq!(pop.join().unwrap(), Ok(1));
+
+        let buffer = holding(&[1]);
+        let lease = buffer.lease().unwrap();
+        buffer.close();
+        let pop = blocked(&buffer, |buffer| buffer.pop());
+        let pop_batch = blocked(&buffer, |buffer| buffer.pop_batch_min(1, 2, LONG));
+        assert_eq!(lease.commit(), 1);
+        assert_eq!(pop.join().unwrap(), Err(PopError::Closed));
+        assert_eq!(pop_batch.join().unwrap(), Err(PopError::Closed));
+
+        let buffer = holding(&[1, 2]);
+        let lease = buffer.lease().unwrap();
+        assert_eq!(buffer.close_with(OnClose::Discard), [2]);
+        assert_eq!(buffer.n_used(), 1);
+        assert_eq!(lease.abort(), Some(1));
+        assert_eq!((buffer.n_used(), buffer.pop()), (0, Err(PopError::Closed)));
+    }
+
+    // with a byte budget, a leased item takes the room it took when leased until committed, however the consumer
+    // changes it, and one requeued takes its new size
+    #[test]
+    fn leases_within_a_byte_budget() {
+        let buffer = ClosableBuffer::<Vec<u8>>::with_byte_budget(8, Vec::len);
+        buffer.push(vec![0; 5]).unwrap();
+        buffer.push(vec![1; 2]).unwrap();
+        let mut lease = buffer.lease().unwrap();
+        lease.resize(15, 0);
+        assert_eq!(buffer.n_used(), 7);
+        assert_eq!(lease.commit().len(), 15);
+        assert_eq!(buffer.n_used(), 2);
+
+        let mut lease = buffer.lease().unwrap();
+        lease.push(1);
+        assert_eq!(lease.abort(), None);
+        assert_eq!((buffer.n_used(), buffer.pop()), (3, Ok(vec![1; 3])));
+        assert_eq!(buffer.n_used(), 0);
+    }
+}
+
+/* Run with `cargo test --features shuttle closable`. Each test runs many times, with the threads scheduled at random
+(but only where they synchronize), for interleavings of pushes, pops and closing which the threaded tests would rarely
+hit. A failing test prints the schedule it failed with; pass that to `shuttle::replay` in place of `check_random` to
+step through that same failure again. Shuttle's condvars never time out, so these leave timeouts alone.
+*/
+#[cfg(all(test, feature = "shuttle"))]
+mod shuttle_tests {
+    use std::sync::Arc;
+    use shuttle::thread::{self, JoinHandle};
+    use super::*;
+
+    const N_SCHEDULES: usize = 1000;
+    const N_THREADS: usize = 2;
+    const N_ITEMS: i32 = 3;
+
+    // `N_THREADS` producers each pushing `N_ITEMS` items, until the buffer is closed; returns what each pushed
+    fn producers(buffer: &Arc<ClosableBuffer<i32>>) -> Vec<JoinHandle<Vec<i32>>> {
+        (0..N_THREADS as i32).map(|p| {
+            let buffer = buffer.clone();
+            thread::spawn(move || (0..N_ITEMS).map(|k| p * N_ITEMS + k).map_while(|item| match buffer.push(item) {
+                Ok(()) => Some(item),
+                Err(PushError::Closed(rejected)) => {
+                    assert_eq!(rejected, item);
+                    None
+                },
+                Err(PushError::Timeout(_)) => unreachable!(),
+            }).collect())
+        }).collect()
+    }
+
+    // `N_THREADS` consumers each popping until the buffer is closed and empty; returns what each popped
+    fn consumers(buffer: &Arc<ClosableBuffer<i32>>) -> Vec<JoinHandle<Vec<i32>>> {
+        (0..N_THREADS).map(|_| {
+            let buffer = buffer.clone();
+            thread::spawn(move || std::iter::from_fn(|| buffer.pop().ok()).collect())
+        }).collect()
+    }
+
+    fn join(threads: Vec<JoinHandle<Vec<i32>>>) -> Vec<Vec<i32>> {
+        threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+    }
+
+    // closing once the producers are done, every item pushed is popped exactly once, and each producer's in order
+    #[test]
+    fn every_item_popped_once() {
+        shuttle::check_random(|| {
+            let buffer = Arc::new(ClosableBuffer::new(1));
+            let (producers, consumers) = (producers(&buffer), consumers(&buffer));
+            let pushed: Vec<_> = join(producers).concat();
+            buffer.close();
+            let popped = join(consumers);
+            for items in &popped {
+                for p in 0..N_THREADS as i32 {
+                    let own: Vec<_> = items.iter().filter(|&&item| item / N_ITEMS == p).collect();
+                    assert!(own.is_sorted(), "{:?}", items);
+                }
+            }
+            let mut popped = popped.concat();
+            popped.sort();
+            assert_eq!(popped, pushed);
+        }, N_SCHEDULES);
+    }
+
+    // closing while producers and consumers are at work wakes them all, and every item pushed before it is either
+    // popped or given back to the closer, never both
+    #[test]
+    fn close_during_traffic() {
+        for on_close in [OnClose::Drain, OnClose::Discard] {
+            shuttle::check_random(move || {
+                let buffer = Arc::new(ClosableBuffer::new(2));
+                let (producers, consumers) = (producers(&buffer), consumers(&buffer));
+                let discarded = buffer.close_with(on_close);
+                let mut pushed = join(producers).concat();
+                let mut popped = [join(consumers).concat(), discarded].concat();
+                pushed.sort();
+                popped.sort();
+                assert_eq!(popped, pushed);
+                let state = buffer.state.lock().unwrap();
+                assert_eq!((state.items.len(), state.n_waiting_pushes, state.n_waiting_pops), (0, 0, 0));
+            }, N_SCHEDULES);
+        }
+    }
+}
@@ -0,0 +1,685 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed}},
+    thread,
+};
+use crate::{
+    RingBuffer, Locker, Queue, Cancelled,
+    echo::{self, Contents, Echoed},
+    lock::{Guard, Held, HoldTimes, RawCondvar, RawLock},
+    stats::{DropReason, Side, Stats},
+    trace::{Op, Recorder},
+};
+
+// called with each item a buffer throws away; see `SyncedBoundedBufferBuilder::on_drop`
+type OnDrop = Box<dyn Fn(isize, DropReason) + Send + Sync>;
+
+// what a push does when the buffer is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    // wait for a consumer to make room
+    Block,
+    // throw away the oldest item to make room (without recording it, so a trace of the run can't be replayed)
+    DropOldest,
+    // throw away the item being pushed
+    DropNewest,
+}
+
+// how many waiting threads on the other side a push or pop wakes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wake {
+    // all of them; see `push`
+    NotifyAll,
+    // one, which is enough since each operation only frees one slot or adds one item; avoids waking threads which will
+    // just find that another thread got there first
+    NotifyOne,
+    /* None, unless the operation found the buffer empty (for a push) or full (for a pop), as only then can any thread
+    be waiting on the other side; then all of them, as later operations won't wake the rest. Saves a wake (a syscall,
+    if anyone's asleep) on most operations while the buffer is neither, but relies on threads waiting for nothing but
+    an empty or a full buffer, and wakes every waiter at once when it stops being one.
+    */
+    OnTransition,
+}
+impl FromStr for Wake {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all"        => Ok(Wake::NotifyAll),
+            "one"        => Ok(Wake::NotifyOne),
+            "transition" => Ok(Wake::OnTransition),
+            _ => Err(()),
+        }
+    }
+}
+
+// which side goes first when producers and consumers contend for the lock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bias {
+    // neither: whichever the lock lets in
+    None,
+    // consumers, so that the buffer holds fewer items, and each one waits less in it
+    Drain,
+    // producers, so that the buffer holds more items, and consumers are less often left without one
+    Fill,
+}
+impl FromStr for Bias {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none"  => Ok(Bias::None),
+            "drain" => Ok(Bias::Drain),
+            "fill"  => Ok(Bias::Fill),
+            _ => Err(()),
+        }
+    }
+}
+
+// how many times a thread on the side a `Bias` doesn't prefer yields to threads on the other side before it takes the
+// lock anyway, so that it can't starve
+const MAX_GIVE_WAY: usize = 8;
+
+// for a `fair` buffer: the threads waiting on each side, by ticket, oldest first
+#[derive(Default)]
+struct Turns {
+    queued: [VecDeque<u64>; 2],
+    n_tickets: u64,
+}
+impl Turns {
+    // a ticket for a thread on `side` which has to wait, or finds others there already waiting, to queue behind them
+    fn queue(&mut self, side: Side, blocked: bool) -> Option<u64> {
+        let queued = &mut self.queued[side as usize];
+        if !blocked && queued.is_empty() { return None; }
+        self.n_tickets += 1;
+        queued.push_back(self.n_tickets);
+        Some(self.n_tickets)
+    }
+
+    fn first(&self, side: Side) -> Option<u64> { self.queued[side as usize].front().copied() }
+
+    // whether there are others still waiting
+    fn leave(&mut self, side: Side, ticket: u64) -> bool {
+        let queued = &mut self.queued[side as usize];
+        queued.retain(|&queued| queued != ticket);
+        !queued.is_empty()
+    }
+}
+
+// the buffer's lock, held by a push or a pop (timed if the buffer keeps track of hold times)
+type Locked<'a, C> = Held<'a, Guard<'a, C, RingBuffer<isize>>>;
+
+/* Bounded buffer synchronized by a mutex and two condition variables, std's unless `C` is another `RawCondvar` (e.g.
+`lock::SpinCondvar`, or parking_lot's), whose own kind of mutex it then uses too: build it with `build_on::<C>()`.
+*/
+pub struct SyncedBoundedBuffer<C: RawCondvar = Condvar> {
+    buffer: C::Lock<RingBuffer<isize>>,
+    not_empty: C,
+    not_full: C,
+    stats: Stats,
+    // print the buffer's contents after every operation: a copy taken while holding the lock, printed once it's
+    // released, so that threads don't wait for the lock while another writes to stdout (but the lines from different
+    // threads may come out of order)
+    echo: bool,
+    recorder: Option<Arc<Recorder>>,
+    overflow: Overflow,
+    wake: Wake,
+    bias: Bias,
+    // how many threads on the side `bias` prefers are taking the lock right now, by `Side`
+    n_contending: [AtomicUsize; 2],
+    on_drop: Option<OnDrop>,
+    // the capacity as far as producers are concerned, which is less than the storage's while shrinking; see
+    // `set_capacity`. Only changed while holding the lock
+    limit: AtomicUsize,
+    // how long pushes and pops held the lock, by `Side`, if we're keeping track
+    hold_times: Option<Box<[HoldTimes; 2]>>,
+    // if `fair`; only ever locked while holding the buffer's lock, so never contended
+    turns: Option<Mutex<Turns>>,
+    // the high and low watermarks, if any, and whether producers are held back until the occupancy falls to the low
+    // one. Only changed while holding the lock
+    watermarks: Option<(usize, usize)>,
+    held_back: AtomicBool,
+}
+
+/* Configures a `SyncedBoundedBuffer`, e.g.
+    SyncedBoundedBuffer::builder().capacity(64).overflow(Overflow::DropOldest).wake(Wake::NotifyOne).build()
+Everything but the capacity has a default: no echo, no recorder, `Overflow::Block`, `Wake::NotifyAll`, `Bias::None`,
+no `on_drop`, no hold times, not fair, no watermarks.
+*/
+pub struct SyncedBoundedBufferBuilder {
+    capacity: usize,
+    echo: bool,
+    recorder: Option<Arc<Recorder>>,
+    overflow: Overflow,
+    wake: Wake,
+    bias: Bias,
+    on_drop: Option<OnDrop>,
+    hold_times: bool,
+    fair: bool,
+    watermarks: Option<(usize, usize)>,
+}
+impl SyncedBoundedBufferBuilder {
+
+    pub fn capacity(mut self, capacity: usize) -> Self { self.capacity = capacity; self }
+    pub fn echo    (mut self, echo: bool)      -> Self { self.echo     = echo;     self }
+    pub fn overflow(mut self, overflow: Overflow) -> Self { self.overflow = overflow; self }
+    pub fn wake    (mut self, wake: Wake)         -> Self { self.wake     = wake;     self }
+    pub fn bias    (mut self, bias: Bias)         -> Self { self.bias     = bias;     self }
+    // time how long every push and pop holds the lock; see `hold_times`
+    pub fn hold_times(mut self, hold_times: bool) -> Self { self.hold_times = hold_times; self }
+    // record every operation in `recorder`, if there is one
+    pub fn recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self { self.recorder = recorder; self }
+    /* Serve the threads waiting on each side in the order they came: a thread which has to wait, or finds others on its
+    side already waiting, queues behind them, and goes once it's first and there's room (or an item), so that none can
+    be overtaken for ever by threads which keep arriving just as there is. As the thread woken must be the first, every
+    wake then wakes every waiter, whatever `wake`.
+    */
+    pub fn fair(mut self, fair: bool) -> Self { self.fair = fair; self }
+    /* Once the occupancy reaches `high`, hold producers back until consumers have brought it down to `low`, so that
+    they push in bursts of `high - low` items rather than one at a time as room comes up, and consumers find more
+    items to pop at once. Only with `Overflow::Block`.
+    */
+    pub fn watermarks(mut self, high: usize, low: usize) -> Self { self.watermarks = Some((high, low)); self }
+    /* Call `on_drop` with every item the overflow policy throws away (which the stats count in any case), e.g. to log
+    or resubmit it. It's called after the buffer's lock is released, so it may use the buffer.
+    */
+    pub fn on_drop(mut self, on_drop: impl Fn(isize, DropReason) + Send + Sync + 'static) -> Self {
+        self.on_drop = Some(Box::new(on_drop));
+        self
+    }
+
+    pub fn build(self) -> SyncedBoundedBuffer { self.build_on() }
+
+    // on `C` and its mutex
+    pub fn build_on<C: RawCondvar>(self) -> SyncedBoundedBuffer<C> {
+        assert!(self.capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        if let Some((high, low)) = self.watermarks {
+            assert!(low < high && high <= self.capacity, "the watermarks must be low < high <= the capacity");
+            assert!(self.overflow == Overflow::Block, "watermarks only hold producers back with `Overflow::Block`");
+        }
+        SyncedBoundedBuffer {
+            buffer: RawLock::new(RingBuffer::new(self.capacity)),
+            not_empty: C::default(),
+            not_full: C::default(),
+            stats: Stats::default(),
+            echo: self.echo,
+            recorder: self.recorder,
+            overflow: self.overflow,
+            wake: if self.fair { Wake::NotifyAll } else { self.wake },
+            bias: self.bias,
+            n_contending: Default::default(),
+            on_drop: self.on_drop,
+            limit: AtomicUsize::new(self.capacity),
+            hold_times: self.hold_times.then(Default::default),
+            turns: self.fair.then(Default::default),
+            watermarks: self.watermarks,
+            held_back: AtomicBool::new(false),
+        }
+    }
+}
+
+impl SyncedBoundedBuffer {
+
+    pub fn builder() -> SyncedBoundedBufferBuilder {
+        SyncedBoundedBufferBuilder {
+            capacity: 0, echo: false, recorder: None, overflow: Overflow::Block, wake: Wake::NotifyAll,
+            bias: Bias::None, on_drop: None, hold_times: false, fair: false, watermarks: None,
+        }
+    }
+
+    pub fn new(capacity: usize, echo: bool) -> Self { Self::builder().capacity(capacity).echo(echo).build() }
+}
+impl<C: RawCondvar> SyncedBoundedBuffer<C> {
+
+    // record every operation in `recorder`, if there is one
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    // a copy of the items, oldest first
+    pub fn snapshot(&self) -> Vec<isize> { self.buffer.lock().iter().copied().collect() }
+
+    pub fn capacity(&self) -> usize { self.limit.load(Relaxed) }
+
+    // how long pushes and pops held the lock, by `Side`, if built with `hold_times`; the time spent waiting on a
+    // condvar doesn't count, since the lock is released meanwhile
+    pub fn hold_times(&self) -> Option<&[HoldTimes; 2]> { self.hold_times.as_deref() }
+
+    /* Change the capacity while the buffer is in use.
+    Growing takes effect at once. Shrinking takes effect at once for producers, which find the buffer full while it
+    holds `capacity` items or more, but blocks until consumers have brought the occupancy down to `capacity`, and only
+    then releases the extra storage. If another call changes the capacity meanwhile, this one gives way to it.
+    */
+    pub fn set_capacity(&self, capacity: usize) {
+        assert!(capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        let mut bbuf = self.buffer.lock();
+        self.limit.store(capacity, Relaxed);
+        while bbuf.n_items() > capacity {
+            bbuf = self.not_full.wait(bbuf);
+            if self.limit.load(Relaxed) != capacity { return; }
+            // with `Wake::NotifyOne` we may have taken the wakeup meant for a producer; pass it on
+            if self.wake == Wake::NotifyOne { self.not_full.notify_one(); }
+        }
+        bbuf.resize(capacity);
+        drop(bbuf);
+        // if we grew, there's room for every producer which was waiting, whatever the wake policy
+        self.stats.wake();
+        self.not_full.notify_all();
+    }
+
+    /* Take the lock as a thread on `side`: straight away on the side `bias` prefers (counting itself as contending
+    meanwhile), and on the other only once no thread on that side is contending, or after yielding to them
+    `MAX_GIVE_WAY` times.
+    */
+    fn lock<'a>(&'a self, side: Side, locker: &mut Locker) -> Locked<'a, C> {
+        let times = self.hold_times.as_ref().map(|times| &times[side as usize]);
+        let preferred = match self.bias {
+            Bias::None  => return Held::new(locker.lock(&self.buffer), times),
+            Bias::Drain => Side::Consumer,
+            Bias::Fill  => Side::Producer,
+        };
+        let n_contending = &self.n_contending[preferred as usize];
+        if side != preferred {
+            for _ in 0..MAX_GIVE_WAY {
+                if n_contending.load(Relaxed) == 0 { break; }
+                thread::yield_now();
+            }
+            return Held::new(locker.lock(&self.buffer), times);
+        }
+        n_contending.fetch_add(1, Relaxed);
+        let bbuf = locker.lock(&self.buffer);
+        n_contending.fetch_sub(1, Relaxed);
+        Held::new(bbuf, times)
+    }
+
+    // whether a producer has to wait
+    fn full(&self, bbuf: &RingBuffer<isize>) -> bool {
+        bbuf.n_items() >= self.limit.load(Relaxed) || self.held_back.load(Relaxed)
+    }
+
+    // how many items a producer can push before it has to wait
+    fn room(&self, bbuf: &RingBuffer<isize>) -> usize {
+        let ceiling = self.watermarks.map_or(usize::MAX, |(high, _)| high).min(self.limit.load(Relaxed));
+        ceiling.saturating_sub(bbuf.n_items())
+    }
+
+    // after a push or a pop, hold producers back at the high watermark, or stop at the low one
+    fn check_watermarks(&self, bbuf: &RingBuffer<isize>) {
+        let Some((high, low)) = self.watermarks else { return };
+        if bbuf.n_items() >= high { self.held_back.store(true, Relaxed); }
+        else if bbuf.n_items() <= low { self.held_back.store(false, Relaxed); }
+    }
+
+    /* Wait on `side`'s condvar until a thread on it isn't `blocked`, and with `fair`, until it's first of those
+    waiting. A thread cancelled while queued leaves the queue, waking the others in case it was first.
+    */
+    fn wait_while<'a>(
+        &'a self, mut bbuf: Locked<'a, C>, side: Side, locker: &mut Locker,
+        blocked: impl Fn(&RingBuffer<isize>) -> bool,
+    ) -> Result<Locked<'a, C>, Cancelled> {
+        let condvar = match side {
+            Side::Producer => &self.not_full,
+            Side::Consumer => &self.not_empty,
+        };
+        // with `fair`, this thread's place in the queue, if it has to queue
+        let turn = self.turns.as_ref()
+            .and_then(|turns| Some((turns, turns.lock().unwrap().queue(side, blocked(&bbuf))?)));
+        let first = |(turns, ticket): (&Mutex<Turns>, u64)| turns.lock().unwrap().first(side) == Some(ticket);
+        let mut n_waits = 0;
+        while blocked(&bbuf) || turn.is_some_and(|turn| !first(turn)) {
+            if let Err(cancelled) = locker.check() {
+                if let Some((turns, ticket)) = turn {
+                    turns.lock().unwrap().leave(side, ticket);
+                    self.stats.wake();
+                    condvar.notify_all();
+                }
+                return Err(cancelled);
+            }
+            if n_waits > 0 { locker.reblocked(); }
+            bbuf = self.wait(bbuf, condvar, side, locker);
+            n_waits += 1;
+        }
+        // the next in line may be able to go too
+        if turn.is_some_and(|(turns, ticket)| turns.lock().unwrap().leave(side, ticket)) {
+            self.stats.wake();
+            condvar.notify_all();
+        }
+        Ok(bbuf)
+    }
+
+    // release the lock and wait on `condvar`, counting ourselves as waiting on `side` meanwhile
+    fn wait<'a>(&'a self, bbuf: Locked<'a, C>, condvar: &C, side: Side, locker: &Locker) -> Locked<'a, C> {
+        self.stats.wait();
+        let waiting = self.stats.start_waiting(side);
+        let bbuf = bbuf.release();
+        let bbuf = match locker.poll_interval() {
+            None => condvar.wait(bbuf),
+            Some(interval) => condvar.wait_timeout(bbuf, interval),
+        };
+        self.stats.stop_waiting(side, waiting);
+        Held::new(bbuf, self.hold_times.as_ref().map(|times| &times[side as usize]))
+    }
+
+    // count an item the overflow policy threw away, and pass it to `on_drop`; call without holding the lock
+    fn dropped(&self, item: isize, reason: DropReason) {
+        self.stats.dropped(reason);
+        if let Some(on_drop) = &self.on_drop { on_drop(item, reason); }
+    }
+
+    // wake waiters on `condvar` after an operation; `transition` is whether it found the buffer empty (for a push) or
+    // full (for a pop)
+    fn notify(&self, condvar: &C, transition: bool) {
+        match self.wake {
+            Wake::NotifyAll => condvar.notify_all(),
+            Wake::NotifyOne => condvar.notify_one(),
+            Wake::OnTransition if transition => condvar.notify_all(),
+            Wake::OnTransition => return,
+        }
+        self.stats.wake();
+    }
+}
+impl<C: RawCondvar> Queue for SyncedBoundedBuffer<C> {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        locker.check()?;
+        // acquire the mutex so we can (at least) check if the buffer is full
+        let mut bbuf = self.lock(Side::Producer, locker);
+
+        // unless we're to block, deal with a full buffer straight away (more than one item may have to go, if the
+        // capacity was just reduced)
+        let mut evicted = Vec::new();
+        match self.overflow {
+            Overflow::Block => {},
+            Overflow::DropOldest => while self.full(&bbuf) { evicted.push(bbuf.pop()); },
+            Overflow::DropNewest => if self.full(&bbuf) {
+                drop(bbuf);
+                self.dropped(item, DropReason::Rejected);
+                return Ok(());
+            },
+        }
+
+        /* If the buffer is full, release the mutex until it isn't full.
+        `wait_while` waits until `not_full` is signalled, and checks again, as it is possible for the buffer to be full
+        when the wait returns, as follows:
+            1. the buffer becomes not full and `not_full` is signalled, waking all producers
+            2. another producer thread runs before this one, and fills the buffer
+            3. then this thread runs.
+        A thread with a cancellation token instead waits with a timeout, to check the token every so often.
+        */
+        bbuf = self.wait_while(bbuf, Side::Producer, locker, |bbuf| self.full(bbuf))?;
+
+        // add an item to the buffer
+        let was_empty = bbuf.empty();
+        bbuf.push(item);
+        self.check_watermarks(&bbuf);
+        self.stats.occupancy(bbuf.n_items());
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+        // copy the buffer state, to display once we've unlocked
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Producer, locker.id, Contents::items(bbuf.iter()), self.limit.load(Relaxed))
+        });
+
+        // since we just pushed an item, the buffer is definitely not empty.
+        // By default we use `notify_all` instead of `notify_one` because there may be space for multiple items, which
+        // may be filled by multiple threads.
+        self.notify(&self.not_empty, was_empty);
+        self.stats.op();
+        // we're done; unlock the Mutex before printing, or handing over any evicted items
+        drop(bbuf);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        for item in evicted { self.dropped(item, DropReason::Evicted); }
+        Ok(())
+    }
+
+    // see `push`; overflow policies other than `Block` push one item at a time
+    fn push_batch(&self, items: &mut VecDeque<isize>, locker: &mut Locker) -> Result<(), Cancelled> {
+        if self.overflow != Overflow::Block {
+            while let Some(&item) = items.front() {
+                self.push(item, locker)?;
+                items.pop_front();
+            }
+            return Ok(());
+        }
+
+        locker.check()?;
+        let mut bbuf = self.lock(Side::Producer, locker);
+        let mut echoed = Vec::new();
+        // push as many as fit, then wait for room for the rest
+        while !items.is_empty() {
+            bbuf = self.wait_while(bbuf, Side::Producer, locker, |bbuf| self.full(bbuf))?;
+
+            let was_empty = bbuf.empty();
+            let n_pushed = items.len().min(self.room(&bbuf));
+            for item in items.drain(..n_pushed) {
+                bbuf.push(item);
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+                self.stats.op();
+            }
+            self.check_watermarks(&bbuf);
+            self.stats.occupancy(bbuf.n_items());
+            if self.echo {
+                let capacity = self.limit.load(Relaxed);
+                echoed.push(Echoed::new(Side::Producer, locker.id, Contents::items(bbuf.iter()), capacity));
+            }
+
+            // with `Wake::NotifyOne`, there may be a consumer to wake for each item
+            let n_notifies = if self.wake == Wake::NotifyOne { n_pushed } else { 1 };
+            for _ in 0..n_notifies { self.notify(&self.not_empty, was_empty); }
+        }
+        drop(bbuf);
+        for echoed in echoed { echo::print(echoed); }
+        Ok(())
+    }
+
+    // see `push` for comments
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        locker.check()?;
+        let mut bbuf = self.lock(Side::Consumer, locker);
+        bbuf = self.wait_while(bbuf, Side::Consumer, locker, |bbuf| bbuf.empty())?;
+
+        let was_full = self.full(&bbuf);
+        let item = bbuf.pop();
+        self.check_watermarks(&bbuf);
+
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(bbuf.iter()), self.limit.load(Relaxed))
+        });
+
+        self.notify(&self.not_full, was_full);
+        self.stats.op();
+        drop(bbuf);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(item)
+    }
+
+    // see `pop`
+    fn pop_batch(&self, max_len: usize, locker: &mut Locker) -> Result<Vec<isize>, Cancelled> {
+        locker.check()?;
+        let mut bbuf = self.lock(Side::Consumer, locker);
+        bbuf = self.wait_while(bbuf, Side::Consumer, locker, |bbuf| bbuf.empty())?;
+
+        let was_full = self.full(&bbuf);
+        let batch: Vec<isize> = (0..max_len.min(bbuf.n_items())).map(|_| bbuf.pop()).collect();
+        self.check_watermarks(&bbuf);
+        for &item in &batch {
+            if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+            self.stats.op();
+        }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(bbuf.iter()), self.limit.load(Relaxed))
+        });
+
+        // each item freed a slot, so with `Wake::NotifyOne` there may be a producer to wake for each
+        let n_notifies = if self.wake == Wake::NotifyOne { batch.len() } else { 1 };
+        for _ in 0..n_notifies { self.notify(&self.not_full, was_full); }
+        drop(bbuf);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(batch)
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { SyncedBoundedBuffer::<C>::capacity(self) }
+    fn n_items(&self) -> usize { self.buffer.lock().n_items() }
+    fn snapshot(&self) -> Vec<isize> { SyncedBoundedBuffer::<C>::snapshot(self) }
+    fn hold_times(&self) -> Option<&[HoldTimes; 2]> { SyncedBoundedBuffer::<C>::hold_times(self) }
+    fn set_capacity(&self, capacity: usize) -> bool {
+        SyncedBoundedBuffer::<C>::set_capacity(self, capacity);
+        true
+    }
+}
+
+// for dumping the state of a stalled run; takes the lock (even if a thread panicked while holding it)
+impl<C: RawCondvar> fmt::Debug for SyncedBoundedBuffer<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bbuf = self.buffer.lock_anyway();
+        f.debug_struct("SyncedBoundedBuffer")
+            .field("items", &format_args!("{}", *bbuf))
+            .field("n_items", &bbuf.n_items())
+            .field("capacity", &self.limit.load(Relaxed))
+            .field("n_waiting_producers", &self.stats.n_waiting()[Side::Producer as usize])
+            .field("n_waiting_consumers", &self.stats.n_waiting()[Side::Consumer as usize])
+            .field("overflow", &self.overflow)
+            .field("wake", &self.wake)
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::{thread, time::Duration};
+    use crate::{CancellationToken, Strategy};
+    use super::*;
+
+    fn locker(id: usize) -> Locker { Locker::new(Strategy::Block, "thread", id) }
+
+    // wait until `n` producers are waiting on `buffer`
+    fn n_waiting(buffer: &SyncedBoundedBuffer, n: u64) {
+        while buffer.stats.n_waiting()[Side::Producer as usize] < n { thread::yield_now(); }
+    }
+
+    // at the high watermark, producers are held back until the occupancy is down to the low one, however much room
+    #[test]
+    fn watermarks_hold_producers_back() {
+        let buffer = Arc::new(SyncedBoundedBuffer::builder().capacity(8).watermarks(4, 1).build());
+        let mut main = locker(0);
+        let mut items = VecDeque::from([1, 2, 3, 4, 5, 6]);
+        let producer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                buffer.push_batch(&mut items, &mut locker(1)).unwrap();
+            })
+        };
+        n_waiting(&buffer, 1);
+        assert_eq!(buffer.snapshot(), [1, 2, 3, 4]);
+        assert_eq!(buffer.pop_batch(2, &mut main), Ok(vec![1, 2]));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(buffer.snapshot(), [3, 4]);
+        assert_eq!(buffer.pop(&mut main), Ok(3));
+        producer.join().unwrap();
+        assert_eq!(buffer.snapshot(), [4, 5, 6]);
+    }
+
+    // shrinking below the occupancy stops producers at once, but only returns once consumers are down to the new
+    // capacity
+    #[test]
+    fn shrinks_once_consumers_make_room() {
+        let buffer = Arc::new(SyncedBoundedBuffer::new(4, false));
+        let mut main = locker(0);
+        buffer.push_batch(&mut VecDeque::from([1, 2, 3, 4]), &mut main).unwrap();
+        let shrinking = {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.set_capacity(2))
+        };
+        while buffer.capacity() != 2 { thread::yield_now(); }
+        assert_eq!(buffer.pop(&mut main), Ok(1));
+        thread::sleep(Duration::from_millis(20));
+        assert!(!shrinking.is_finished());
+        assert_eq!(buffer.pop(&mut main), Ok(2));
+        shrinking.join().unwrap();
+        assert_eq!(buffer.snapshot(), [3, 4]);
+        assert_eq!(RawLock::lock(&buffer.buffer).capacity(), 2);
+
+        buffer.set_capacity(3);
+        buffer.push(5, &mut main).unwrap();
+        assert_eq!(buffer.snapshot(), [3, 4, 5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "only hold producers back")]
+    fn watermarks_need_blocking() {
+        SyncedBoundedBuffer::builder().capacity(8).watermarks(4, 1).overflow(Overflow::DropNewest).build();
+    }
+
+    // a producer arriving while another waits queues behind it, even if there's room by then
+    #[test]
+    fn fair_producers_go_in_order() {
+        let buffer = Arc::new(SyncedBoundedBuffer::builder().capacity(2).fair(true).wake(Wake::NotifyOne).build());
+        let mut main = locker(0);
+        buffer.push_batch(&mut VecDeque::from([10, 20]), &mut main).unwrap();
+        let push = |item, id| {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.push(item, &mut locker(id)).unwrap())
+        };
+        let first = push(1, 1);
+        n_waiting(&buffer, 1);
+        assert_eq!(buffer.pop(&mut main), Ok(10));
+        let second = push(2, 2);
+        let popped: Vec<_> = (0..3).map(|_| buffer.pop(&mut main).unwrap()).collect();
+        assert_eq!(popped, [20, 1, 2]);
+        first.join().unwrap();
+        second.join().unwrap();
+    }
+
+    // a producer cancelled while first in line doesn't hold up the one behind it
+    #[test]
+    fn fair_waiters_leave_when_cancelled() {
+        let buffer = Arc::new(SyncedBoundedBuffer::builder().capacity(1).fair(true).build());
+        let mut main = locker(0);
+        buffer.push(10, &mut main).unwrap();
+        let token = CancellationToken::new();
+        let cancelled = {
+            let (buffer, token) = (buffer.clone(), token.clone());
+            thread::spawn(move || buffer.push(1, &mut locker(1).with_cancellation(token)))
+        };
+        n_waiting(&buffer, 1);
+        let waiting = {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.push(2, &mut locker(2)))
+        };
+        n_waiting(&buffer, 2);
+        token.cancel();
+        assert_eq!(cancelled.join().unwrap(), Err(Cancelled));
+        assert_eq!(buffer.pop(&mut main), Ok(10));
+        assert_eq!(waiting.join().unwrap(), Ok(()));
+        assert_eq!(buffer.snapshot(), [2]);
+    }
+}
+
+// run with `RUSTFLAGS="--cfg loom" cargo test --release condvar`
+#[cfg(all(test, loom))]
+mod tests {
+    use loom::{sync::{Arc, Condvar}, thread};
+    use crate::{Locker, Queue, Strategy};
+    use super::{SyncedBoundedBuffer, Wake};
+
+    // with `Wake::OnTransition` only the first of the two pushes wakes anyone, so if it woke just one of two waiting
+    // consumers the other would wait forever, which loom reports as a deadlock
+    #[test]
+    fn transition_wakes_every_waiter() {
+        loom::model(|| {
+            let buffer =
+                Arc::new(SyncedBoundedBuffer::builder().capacity(2).wake(Wake::OnTransition).build_on::<Condvar>());
+            let consumers: Vec<_> = (0..2).map(|i| {
+                let buffer = buffer.clone();
+                thread::spawn(move || buffer.pop(&mut Locker::new(Strategy::Block, "consumer", i)).unwrap())
+            }).collect();
+            let mut locker = Locker::new(Strategy::Block, "producer", 0);
+            for item in 0..2 { buffer.push(item, &mut locker).unwrap(); }
+            let mut popped: Vec<_> = consumers.into_iter().map(|consumer| consumer.join().unwrap()).collect();
+            popped.sort();
+            assert_eq!(popped, [0, 1]);
+        });
+    }
+}
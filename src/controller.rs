@@ -0,0 +1,98 @@
+/* An experimental controller for `bench --target-p99`, which adjusts the buffer's capacity as it runs to hold the 99th
+percentile of the time items spend queued in it below a target, as a demonstration of tuning backpressure by latency:
+a smaller buffer queues items for less time, but holds the producers back sooner.
+
+Every interval it looks at the items popped during it, and like TCP's congestion control, backs off multiplicatively
+and probes additively: over the target, it shrinks the capacity by the factor the p99 is over by (as the time an item
+spends queued in a full buffer is in proportion to its capacity); well under it, it grows by an eighth, up to the
+capacity it was given; in between, it holds.
+*/
+
+use std::time::Duration;
+
+// how far under the target the p99 must be before the capacity grows, so that it doesn't flap around the target
+const HEADROOM: f64 = 0.8;
+
+pub struct Controller {
+    target: Duration,
+    max_capacity: usize,
+    capacity: usize,
+}
+
+// what the controller did with the capacity after an interval
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    Shrink,
+    Grow,
+    Hold,
+}
+
+impl Controller {
+
+    // starting at `max_capacity`
+    pub fn new(target: Duration, max_capacity: usize) -> Self {
+        Controller { target, max_capacity, capacity: max_capacity }
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    // adjust the capacity to go on with, given the p99 of the time items spent queued over the last interval
+    pub fn decide(&mut self, p99: Duration) -> Decision {
+        let ratio = self.target.as_secs_f64() / p99.as_secs_f64().max(1e-9);
+        if p99 > self.target && self.capacity > 1 {
+            self.capacity = ((self.capacity as f64 * ratio) as usize).clamp(1, self.capacity - 1);
+            Decision::Shrink
+        } else if ratio > 1.0 / HEADROOM && self.capacity < self.max_capacity {
+            self.capacity = (self.capacity + self.capacity.div_ceil(8)).min(self.max_capacity);
+            Decision::Grow
+        } else {
+            Decision::Hold
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration { Duration::from_millis(ms) }
+
+    // over the target, the capacity shrinks by the factor it's over by, by at least 1 and down to no less than 1
+    #[test]
+    fn shrinks_over_the_target() {
+        let mut controller = Controller::new(ms(10), 100);
+        assert_eq!(controller.decide(ms(40)), Decision::Shrink);
+        assert_eq!(controller.capacity(), 25);
+        assert_eq!(controller.decide(ms(11)), Decision::Shrink);
+        assert_eq!(controller.capacity(), 22);
+        assert_eq!(controller.decide(ms(10_000)), Decision::Shrink);
+        assert_eq!(controller.capacity(), 1);
+        assert_eq!(controller.decide(ms(10_000)), Decision::Hold);
+    }
+
+    // well under the target, the capacity grows by an eighth, rounded up, up to the capacity it started at
+    #[test]
+    fn grows_well_under_the_target() {
+        let mut controller = Controller::new(ms(10), 20);
+        controller.decide(ms(20));
+        assert_eq!(controller.capacity(), 10);
+        assert_eq!(controller.decide(ms(1)), Decision::Grow);
+        assert_eq!(controller.capacity(), 12);
+        // no items popped, so no time queued
+        assert_eq!(controller.decide(Duration::ZERO), Decision::Grow);
+        assert_eq!(controller.capacity(), 14);
+        for _ in 0..3 { controller.decide(ms(1)); }
+        assert_eq!((controller.capacity(), controller.decide(ms(1))), (20, Decision::Hold));
+    }
+
+    // between the headroom and the target, it holds
+    #[test]
+    fn holds_near_the_target() {
+        let mut controller = Controller::new(ms(10), 100);
+        controller.decide(ms(20));
+        for p99 in [ms(9), ms(10), Duration::from_micros(8_500)] {
+            assert_eq!(controller.decide(p99), Decision::Hold);
+            assert_eq!(controller.capacity(), 50);
+        }
+    }
+}
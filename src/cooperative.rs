@@ -0,0 +1,116 @@
+use alloc::vec::Vec;
+use crate::ring::RingBuffer;
+
+// what happened in one step of a `CooperativeDriver`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Step {
+    Produced        { producer: usize },
+    // the producer's turn came while the buffer was full, so it did nothing (where a thread would block)
+    ProducerBlocked { producer: usize },
+    Consumed        { consumer: usize },
+    ConsumerBlocked { consumer: usize },
+    // there are no producers and no consumers
+    Idle,
+}
+
+/* A `CooperativeDriver`'s state at some step, to `restore` it to later: the items in its buffer, oldest first, and
+whose turn is next. Whatever state `produce` and `consume` keep of their own isn't in it.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint<T> {
+    pub items: Vec<T>,
+    next_producer: usize,
+    next_consumer: usize,
+    producers_turn: bool,
+}
+
+/* Runs producers and consumers on a single thread by taking turns, for targets without threads (e.g. wasm32 in a
+browser, where each `step` can be driven from an animation frame or a timer).
+Turns alternate between the producer side and the consumer side, and round-robin within each side. Since no two steps
+ever overlap there is nothing to synchronize, so this wraps a plain `RingBuffer`; an actor whose turn comes when
+it can't make progress simply passes, which is where it would have blocked in the threaded version.
+`produce` is called with the index of the producer whose turn it is, and returns the item to push; `consume` is
+called with the index of the consumer and the item it popped.
+*/
+pub struct CooperativeDriver<T, P, C> {
+    buffer: RingBuffer<T>,
+    produce: P,
+    consume: C,
+    n_producers: usize,
+    n_consumers: usize,
+    next_producer: usize,
+    next_consumer: usize,
+    producers_turn: bool,
+}
+impl<T, P, C> CooperativeDriver<T, P, C>
+where T: Default, P: FnMut(usize) -> T, C: FnMut(usize, T) {
+
+    pub fn new(capacity: usize, n_producers: usize, n_consumers: usize, produce: P, consume: C) -> Self {
+        CooperativeDriver {
+            buffer: RingBuffer::new(capacity), produce, consume, n_producers, n_consumers,
+            next_producer: 0, next_consumer: 0, producers_turn: true,
+        }
+    }
+
+    pub fn buffer(&self) -> &RingBuffer<T> { &self.buffer }
+
+    // nothing else runs between steps, so this is always a consistent state
+    pub fn checkpoint(&self) -> Checkpoint<T> where T: Clone {
+        Checkpoint {
+            items: self.buffer.iter().cloned().collect(), next_producer: self.next_producer,
+            next_consumer: self.next_consumer, producers_turn: self.producers_turn,
+        }
+    }
+
+    // rewind to `checkpoint`, so that the steps from there happen again (given the same items from `produce`)
+    pub fn restore(&mut self, checkpoint: Checkpoint<T>) {
+        assert!(checkpoint.items.len() <= self.buffer.capacity(), "the checkpoint doesn't fit in the buffer");
+        self.buffer = RingBuffer::new(self.buffer.capacity());
+        for item in checkpoint.items { self.buffer.push(item); }
+        self.next_producer = checkpoint.next_producer;
+        self.next_consumer = checkpoint.next_consumer;
+        self.producers_turn = checkpoint.producers_turn;
+    }
+
+    pub fn step(&mut self) -> Step {
+        // if one side is empty the other gets every turn
+        let producers_turn = match (self.n_producers, self.n_consumers) {
+            (0, 0) => return Step::Idle,
+            (_, 0) => true,
+            (0, _) => false,
+            _ => self.producers_turn,
+        };
+        self.producers_turn = !producers_turn;
+
+        if producers_turn {
+            let producer = self.next_producer;
+            self.next_producer = (producer + 1) % self.n_producers;
+            self.step_producer(producer)
+        }
+        else {
+            let consumer = self.next_consumer;
+            self.next_consumer = (consumer + 1) % self.n_consumers;
+            self.step_consumer(consumer)
+        }
+    }
+
+    // give a turn to a particular producer, out of order; e.g. to replay a recorded schedule
+    pub fn step_producer(&mut self, producer: usize) -> Step {
+        if self.buffer.full() { return Step::ProducerBlocked { producer }; }
+        let item = (self.produce)(producer);
+        self.buffer.push(item);
+        Step::Produced { producer }
+    }
+
+    // give a turn to a particular consumer, out of order
+    pub fn step_consumer(&mut self, consumer: usize) -> Step {
+        if self.buffer.empty() { return Step::ConsumerBlocked { consumer }; }
+        let item = self.buffer.pop();
+        (self.consume)(consumer, item);
+        Step::Consumed { consumer }
+    }
+
+    pub fn run(&mut self, n_steps: usize) {
+        for _ in 0..n_steps { self.step(); }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{Locker, Cancelled, semaphore::Semaphore};
+
+/* Credit-based flow control: backpressure which needs nothing shared between producers and consumers but numbers sent
+from one to the other, unlike a buffer's condvars, so it works between processes or machines, over whatever carries
+the items (as `orchestrate --credit-window` does over TCP). The two sides agree on a window of items; the producers'
+`Credits`, a semaphore's permits, start with that many, and a producer spends one on every item it sends, waiting
+while it has none. The consumers' `Grants` count the items they're done with, and say when to send their credits
+back, which whatever receives them passes to `Credits::grant`. So there are never more than the window's worth of
+items sent but not yet done with, wherever they are.
+*/
+pub struct Credits {
+    semaphore: Semaphore,
+}
+impl Credits {
+
+    // `window` credits to start with
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0, "without credits, nothing can ever be sent");
+        Credits { semaphore: Semaphore::new(window) }
+    }
+
+    pub fn window(&self) -> usize { self.semaphore.permits() }
+    pub fn n_available(&self) -> usize { self.semaphore.permits() - self.semaphore.n_acquired() }
+
+    // spend a credit, waiting while there are none
+    pub fn acquire(&self, locker: &Locker) -> Result<(), Cancelled> { self.semaphore.acquire(locker) }
+
+    // spend a credit if there is one
+    pub fn try_acquire(&self) -> bool { self.semaphore.try_acquire() }
+
+    // add `n` credits sent back by the consumers; more than were spent is a bug on their side
+    pub fn grant(&self, n: usize) { self.semaphore.release_n(n); }
+}
+
+/* The consumers' side: counts the items they're done with, to grant them back as credits in batches of `batch`, so as
+not to send a message for every item. A batch bigger than the window would never fill, leaving the producers waiting
+for good, so it's refused, and one of more than half of it leaves them waiting while the consumers hold on to credits,
+so it's best kept well below that.
+*/
+pub struct Grants {
+    batch: usize,
+    n_owed: usize,
+}
+impl Grants {
+
+    // for producers with `window` credits
+    pub fn new(batch: usize, window: usize) -> Self {
+        assert!(batch > 0, "credits must be granted in batches of at least 1");
+        assert!(batch <= window, "a batch of {} credits would never fill with a window of {}", batch, window);
+        Grants { batch, n_owed: 0 }
+    }
+
+    // how many items are done with but not yet granted back
+    pub fn n_owed(&self) -> usize { self.n_owed }
+
+    // an item is done with; returns how many credits to send back, once there's a batch of them
+    pub fn done(&mut self) -> Option<usize> {
+        self.n_owed += 1;
+        if self.n_owed < self.batch { return None; }
+        self.flush()
+    }
+
+    // the credits owed, whatever their number, e.g. when the consumer is about to go idle; `None` if there are none
+    pub fn flush(&mut self) -> Option<usize> {
+        match std::mem::take(&mut self.n_owed) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, mpsc, atomic::{AtomicUsize, Ordering::SeqCst}},
+        thread,
+    };
+    use crate::Strategy;
+    use super::*;
+
+    // items sent one way and credits the other over channels, as they would be over a connection: there are never more
+    // than the window of items sent but not done with, and every credit comes back in the end
+    #[test]
+    fn window_bounds_items_in_flight() {
+        const WINDOW: usize = 4;
+        const N_ITEMS: usize = 100;
+        let (credits, n_in_flight) = (Arc::new(Credits::new(WINDOW)), Arc::new(AtomicUsize::new(0)));
+        let (items, received) = mpsc::channel();
+        let (grants, granted) = mpsc::channel();
+        let consumer = {
+            let n_in_flight = n_in_flight.clone();
+            thread::spawn(move || {
+                let mut owed = Grants::new(3, WINDOW);
+                for (n, item) in received.iter().enumerate() {
+                    assert_eq!(item, n);
+                    n_in_flight.fetch_sub(1, SeqCst);
+                    if let Some(n) = owed.done() { grants.send(n).unwrap(); }
+                }
+                if let Some(n) = owed.flush() { grants.send(n).unwrap(); }
+            })
+        };
+        let receiver = {
+            let credits = credits.clone();
+            thread::spawn(move || for n in granted { credits.grant(n); })
+        };
+
+        let locker = Locker::new(Strategy::Block, "producer", 0);
+        for item in 0..N_ITEMS {
+            credits.acquire(&locker).unwrap();
+            assert!(n_in_flight.fetch_add(1, SeqCst) < WINDOW);
+            items.send(item).unwrap();
+        }
+        drop(items);
+        consumer.join().unwrap();
+        receiver.join().unwrap();
+        assert_eq!(credits.n_available(), WINDOW);
+    }
+
+    #[test]
+    fn grants_in_batches() {
+        let credits = Credits::new(2);
+        assert!(credits.try_acquire() && credits.try_acquire());
+        assert!(!credits.try_acquire());
+        let mut owed = Grants::new(2, 2);
+        assert_eq!(owed.done(), None);
+        assert_eq!(owed.done(), Some(2));
+        assert_eq!((owed.n_owed(), owed.flush()), (0, None));
+        credits.grant(2);
+        assert!(credits.try_acquire());
+        assert_eq!(credits.n_available(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "would never fill")]
+    fn refuses_batches_bigger_than_the_window() { Grants::new(5, 4); }
+}
@@ -0,0 +1,73 @@
+use std::{collections::{HashSet, VecDeque}, sync::Mutex};
+
+struct Window {
+    seen: HashSet<isize>,
+    // the same items, oldest first, to know which to forget
+    order: VecDeque<isize>,
+}
+
+/* Suppresses duplicate items, for consumers of a buffer whose producers may deliver an item more than once (at least
+once delivery, e.g. retrying a push whose acknowledgement was lost): it remembers the last `window` distinct items, and
+reports any item among them as a duplicate. Duplicates further apart than that get through, so the window should span
+the longest a redelivery can take, in items. Items are their own ids, so they must be distinct apart from duplicates.
+Shared by all the consumers, as a duplicate may well go to a different one.
+*/
+pub struct Dedup {
+    window: Mutex<Window>,
+    size: usize,
+}
+impl Dedup {
+
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "a dedup window must hold at least 1 item");
+        Dedup {
+            window: Mutex::new(Window { seen: HashSet::with_capacity(size), order: VecDeque::with_capacity(size) }),
+            size,
+        }
+    }
+
+    pub fn size(&self) -> usize { self.size }
+
+    // whether `item` hasn't been seen within the window, in which case it's remembered from now on
+    pub fn is_new(&self, item: isize) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if !window.seen.insert(item) { return false; }
+        window.order.push_back(item);
+        if window.order.len() > self.size {
+            let oldest = window.order.pop_front().unwrap();
+            window.seen.remove(&oldest);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a duplicate within the window is suppressed, and only counts as seen once
+    #[test]
+    fn suppresses_duplicates_within_the_window() {
+        let dedup = Dedup::new(3);
+        assert!(dedup.is_new(1) && dedup.is_new(2));
+        assert!(!dedup.is_new(1) && !dedup.is_new(2));
+        assert!(dedup.is_new(3));
+        assert!(!dedup.is_new(1));
+    }
+
+    // a duplicate just outside the window, after `size` other distinct items, gets through
+    #[test]
+    fn forgets_items_which_slide_out_of_the_window() {
+        let dedup = Dedup::new(3);
+        for item in [1, 2, 3] { assert!(dedup.is_new(item)); }
+        assert!(!dedup.is_new(1));
+        assert!(dedup.is_new(4));
+        assert!(dedup.is_new(1));
+        assert!(!dedup.is_new(3) && !dedup.is_new(4));
+        assert!(dedup.is_new(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1 item")]
+    fn windows_must_hold_an_item() { Dedup::new(0); }
+}
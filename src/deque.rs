@@ -0,0 +1,184 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, Condvar},
+};
+use crate::{Locker, Queue, Cancelled, stats::{Side, Stats}, trace::{Op, Recorder}, echo::{self, Contents, Echoed}};
+
+/* The most straightforward bounded buffer: a `VecDeque` under a mutex, with a condvar for each side.
+It's the baseline for benchmarks and the reference the other backends are checked against, so it stays as plain as
+possible: no ring of its own, no policies, no counting of waiters.
+*/
+pub struct DequeBoundedBuffer {
+    items: Mutex<VecDeque<isize>>,
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+    stats: Stats,
+    // print the buffer's contents after every operation (once the lock is released; see `SyncedBoundedBuffer`)
+    echo: bool,
+    recorder: Option<Arc<Recorder>>,
+}
+impl DequeBoundedBuffer {
+
+    pub fn new(capacity: usize, echo: bool) -> Self {
+        assert!(capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        DequeBoundedBuffer {
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            stats: Stats::default(),
+            echo,
+            recorder: None,
+        }
+    }
+
+    // record every operation in `recorder`, if there is one
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+}
+// see `SyncedBoundedBuffer` for comments on the logic, which is the same
+impl Queue for DequeBoundedBuffer {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        locker.check()?;
+        let mut items = locker.lock(&self.items);
+        let mut n_waits = 0;
+        while items.len() == self.capacity {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            self.stats.wait();
+            let waiting = self.stats.start_waiting(Side::Producer);
+            items = match locker.poll_interval() {
+                None => self.not_full.wait(items).unwrap(),
+                Some(interval) => self.not_full.wait_timeout(items, interval).unwrap().0,
+            };
+            self.stats.stop_waiting(Side::Producer, waiting);
+            n_waits += 1;
+        }
+
+        items.push_back(item);
+        self.stats.occupancy(items.len());
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Producer, locker.id, Contents::items(items.iter()), self.capacity)
+        });
+
+        self.stats.wake();
+        self.not_empty.notify_all();
+        self.stats.op();
+        drop(items);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(())
+    }
+
+    fn push_batch(&self, items: &mut VecDeque<isize>, locker: &mut Locker) -> Result<(), Cancelled> {
+        locker.check()?;
+        let mut queued = locker.lock(&self.items);
+        let mut echoed = Vec::new();
+        while !items.is_empty() {
+            let mut n_waits = 0;
+            while queued.len() == self.capacity {
+                locker.check()?;
+                if n_waits > 0 { locker.reblocked(); }
+                self.stats.wait();
+                let waiting = self.stats.start_waiting(Side::Producer);
+                queued = match locker.poll_interval() {
+                    None => self.not_full.wait(queued).unwrap(),
+                    Some(interval) => self.not_full.wait_timeout(queued, interval).unwrap().0,
+                };
+                self.stats.stop_waiting(Side::Producer, waiting);
+                n_waits += 1;
+            }
+
+            let n_pushed = items.len().min(self.capacity - queued.len());
+            for item in items.drain(..n_pushed) {
+                queued.push_back(item);
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+                self.stats.op();
+            }
+            self.stats.occupancy(queued.len());
+            if self.echo {
+                echoed.push(Echoed::new(Side::Producer, locker.id, Contents::items(queued.iter()), self.capacity));
+            }
+
+            self.stats.wake();
+            self.not_empty.notify_all();
+        }
+        drop(queued);
+        for echoed in echoed { echo::print(echoed); }
+        Ok(())
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        locker.check()?;
+        let mut items = locker.lock(&self.items);
+        let mut n_waits = 0;
+        while items.is_empty() {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            self.stats.wait();
+            let waiting = self.stats.start_waiting(Side::Consumer);
+            items = match locker.poll_interval() {
+                None => self.not_empty.wait(items).unwrap(),
+                Some(interval) => self.not_empty.wait_timeout(items, interval).unwrap().0,
+            };
+            self.stats.stop_waiting(Side::Consumer, waiting);
+            n_waits += 1;
+        }
+
+        let item = items.pop_front().unwrap();
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(items.iter()), self.capacity)
+        });
+
+        self.stats.wake();
+        self.not_full.notify_all();
+        self.stats.op();
+        drop(items);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(item)
+    }
+
+    fn pop_batch(&self, max_len: usize, locker: &mut Locker) -> Result<Vec<isize>, Cancelled> {
+        locker.check()?;
+        let mut items = locker.lock(&self.items);
+        let mut n_waits = 0;
+        while items.is_empty() {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            self.stats.wait();
+            let waiting = self.stats.start_waiting(Side::Consumer);
+            items = match locker.poll_interval() {
+                None => self.not_empty.wait(items).unwrap(),
+                Some(interval) => self.not_empty.wait_timeout(items, interval).unwrap().0,
+            };
+            self.stats.stop_waiting(Side::Consumer, waiting);
+            n_waits += 1;
+        }
+
+        let n_popped = max_len.min(items.len());
+        let batch: Vec<isize> = items.drain(..n_popped).collect();
+        for &item in &batch {
+            if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+            self.stats.op();
+        }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(items.iter()), self.capacity)
+        });
+
+        self.stats.wake();
+        self.not_full.notify_all();
+        drop(items);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(batch)
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { self.capacity }
+    fn n_items(&self) -> usize { self.items.lock().unwrap().len() }
+    fn snapshot(&self) -> Vec<isize> { self.items.lock().unwrap().iter().copied().collect() }
+}
@@ -0,0 +1,282 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::{Arc, RwLock, mpsc::{self, Receiver, SyncSender, TrySendError}, atomic::{AtomicU64, Ordering::Relaxed}},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use crate::stats::Side;
+
+// the most lines the writer takes from its queue to write at once
+const MAX_BATCH: usize = 256;
+
+// what a buffer with echo prints after an operation: a copy of its items then, oldest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Contents {
+    Items(Vec<isize>),
+    // of one shard of a sharded buffer
+    Shard(usize, Vec<isize>),
+    // of each lane of a laned buffer, highest first
+    Lanes(Vec<Vec<isize>>),
+}
+impl Contents {
+
+    // a copy of `items`
+    pub fn items<'a>(items: impl IntoIterator<Item = &'a isize>) -> Self {
+        Contents::Items(items.into_iter().copied().collect())
+    }
+
+    fn n_items(&self) -> usize {
+        match self {
+            Contents::Items(items) | Contents::Shard(_, items) => items.len(),
+            Contents::Lanes(lanes) => lanes.iter().map(Vec::len).sum(),
+        }
+    }
+}
+impl fmt::Display for Contents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Contents::Items(items)        => write!(f, "{:?}", items),
+            Contents::Shard(shard, items) => write!(f, "{}: {:?}", shard, items),
+            Contents::Lanes(lanes)        => write!(f, "{:?}", lanes),
+        }
+    }
+}
+
+// a line of echo: the buffer's contents after a push (by a thread on `Side::Producer`) or a pop, by thread `thread`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Echoed {
+    pub side: Side,
+    pub thread: u32,
+    pub contents: Contents,
+    // of the buffer, or of the shard
+    pub capacity: usize,
+}
+impl Echoed {
+
+    pub fn new(side: Side, thread: u32, contents: Contents, capacity: usize) -> Self {
+        Echoed { side, thread, contents, capacity }
+    }
+
+    // whether the operation filled the buffer (as it's a push) or emptied it (a pop)
+    pub fn transition(&self) -> bool {
+        match self.side {
+            Side::Producer => self.contents.n_items() >= self.capacity,
+            Side::Consumer => self.contents.n_items() == 0,
+        }
+    }
+
+    // to display in the thread's color
+    pub fn colored(&self) -> Colored<'_> { Colored(self) }
+}
+impl fmt::Display for Echoed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.contents.fmt(f) }
+}
+
+// an `Echoed` displayed in its thread's color: warm for producers and cool for consumers, in reverse video when it
+// filled or emptied the buffer
+pub struct Colored<'a>(&'a Echoed);
+impl fmt::Display for Colored<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let colors = match self.0.side {
+            Side::Producer => PRODUCER_COLORS,
+            Side::Consumer => CONSUMER_COLORS,
+        };
+        let color = colors[self.0.thread as usize % colors.len()];
+        let reverse = if self.0.transition() { ";7" } else { "" };
+        write!(f, "\x1b[38;5;{}{}m{}\x1b[0m", color, reverse, self.0)
+    }
+}
+
+// 256-color palette entries for each side's threads, which take them in turn: reds to yellows, and blues to greens
+const PRODUCER_COLORS: &[u8] = &[196, 214, 201, 226, 160, 208, 205, 220];
+const CONSUMER_COLORS: &[u8] = &[39, 48, 63, 51, 33, 42, 105, 87];
+
+// where `print` sends lines while a writer is running
+static WRITER: RwLock<Option<EchoSender>> = RwLock::new(None);
+
+// print `echoed` as a line of its own: through the writer, if one is running, or else straight to stdout
+pub fn print(echoed: Echoed) {
+    match &*WRITER.read().unwrap() {
+        Some(sender) => sender.print(echoed),
+        None => println!("{}", echoed),
+    }
+}
+
+// the end of an `EchoWriter`'s queue which lines are sent to
+#[derive(Clone)]
+pub struct EchoSender {
+    lines: SyncSender<Echoed>,
+    counters: Arc<Counters>,
+}
+impl EchoSender {
+    // queue `echoed` for the writer, waiting while its backlog is full
+    pub fn print(&self, echoed: Echoed) {
+        let Err(TrySendError::Full(echoed)) = self.lines.try_send(echoed) else { return };
+        let start = Instant::now();
+        let _ = self.lines.send(echoed);
+        self.counters.n_blocked.fetch_add(1, Relaxed);
+        self.counters.blocked_nanos.fetch_add(start.elapsed().as_nanos() as u64, Relaxed);
+    }
+}
+
+// what an `EchoWriter` has done so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EchoStats {
+    pub n_lines: u64,
+    // how many writes (and flushes) the lines took
+    pub n_batches: u64,
+    // how many lines found the writer's queue full, so that whoever printed them waited, and for how long in all
+    pub n_blocked: u64,
+    pub blocked_time: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    n_lines: AtomicU64,
+    n_batches: AtomicU64,
+    n_blocked: AtomicU64,
+    blocked_nanos: AtomicU64,
+}
+impl Counters {
+    fn load(&self) -> EchoStats {
+        EchoStats {
+            n_lines: self.n_lines.load(Relaxed),
+            n_batches: self.n_batches.load(Relaxed),
+            n_blocked: self.n_blocked.load(Relaxed),
+            blocked_time: Duration::from_nanos(self.blocked_nanos.load(Relaxed)),
+        }
+    }
+}
+
+/* Writes what buffers with echo print from a thread of its own, so that the threads pushing and popping never wait for
+the terminal: `print` sends a copy of the items to a queue of up to `backlog` lines, from which the writer takes all
+that are waiting (up to `MAX_BATCH`) and writes them with one flush (`colored`, with `color`). Once it's `backlog` lines
+behind, `print` blocks until there's room, rather than dropping lines or letting them pile up, so a terminal which
+can't keep up still holds up the buffer's threads as printing to it would; but that's counted, to show what echoing
+costs. Lines from one thread come out in the order it printed them. While a writer `start`ed runs, every buffer's echo
+goes through it, so only one can run at once; one `spawn`ed only writes what's sent through its `sender`.
+*/
+pub struct EchoWriter {
+    sender: EchoSender,
+    // whether `print` sends lines to it
+    installed: bool,
+    thread: JoinHandle<io::Result<()>>,
+}
+impl EchoWriter {
+
+    pub fn start(backlog: usize, color: bool) -> Self { EchoWriter::start_to(io::stdout(), backlog, color) }
+
+    // writing to `out` instead of stdout
+    pub fn start_to(out: impl Write + Send + 'static, backlog: usize, color: bool) -> Self {
+        let mut writer = EchoWriter::spawn(out, backlog, color);
+        let mut installed = WRITER.write().unwrap();
+        assert!(installed.is_none(), "an echo writer is already running");
+        *installed = Some(writer.sender());
+        writer.installed = true;
+        writer
+    }
+
+    // a writer to `out` which only writes the lines sent through `sender`, not those of `print`
+    pub fn spawn(mut out: impl Write + Send + 'static, backlog: usize, color: bool) -> Self {
+        assert!(backlog > 0, "a writer without a backlog would hold up every line");
+        let (lines, receiver) = mpsc::sync_channel(backlog);
+        let counters = Arc::new(Counters::default());
+        let written = counters.clone();
+        let thread = thread::Builder::new()
+            .name("echo".to_string())
+            .spawn(move || write_lines(&mut out, receiver, color, &written))
+            .expect("failed to spawn a thread");
+        EchoWriter { sender: EchoSender { lines, counters }, installed: false, thread }
+    }
+
+    pub fn sender(&self) -> EchoSender { self.sender.clone() }
+
+    pub fn stats(&self) -> EchoStats { self.sender.counters.load() }
+
+    /* Write the lines still queued, and stop, failing if a write did; after this, echo prints straight to stdout
+    again. The writer's loop ends once it's written what's queued and every sender is gone, so the senders taken with
+    `sender` must be dropped first.
+    */
+    pub fn stop(self) -> io::Result<EchoStats> {
+        let EchoWriter { sender: EchoSender { lines, counters }, installed, thread } = self;
+        if installed { WRITER.write().unwrap().take(); }
+        drop(lines);
+        thread.join().unwrap()?;
+        Ok(counters.load())
+    }
+}
+
+// the writer's thread: wait for a line, then write it and any others waiting, all at once
+fn write_lines(out: &mut impl Write, lines: Receiver<Echoed>, color: bool, counters: &Counters) -> io::Result<()> {
+    let mut batch = Vec::new();
+    let write = |batch: &mut Vec<u8>, echoed: Echoed| match color {
+        true  => writeln!(batch, "{}", echoed.colored()),
+        false => writeln!(batch, "{}", echoed),
+    };
+    while let Ok(echoed) = lines.recv() {
+        let mut n_lines = 1;
+        write(&mut batch, echoed)?;
+        while n_lines < MAX_BATCH {
+            let Ok(echoed) = lines.try_recv() else { break };
+            write(&mut batch, echoed)?;
+            n_lines += 1;
+        }
+        out.write_all(&batch)?;
+        out.flush()?;
+        batch.clear();
+        counters.n_lines.fetch_add(n_lines as u64, Relaxed);
+        counters.n_batches.fetch_add(1, Relaxed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    // stands in for stdout
+    #[derive(Clone, Default)]
+    struct Written(Arc<Mutex<Vec<u8>>>);
+    impl Write for Written {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(bytes) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    // every line sent is written once the writer stops, each thread's in order, while a full backlog holds senders up
+    // instead of losing lines
+    #[test]
+    fn writes_every_line_in_order() {
+        let written = Written::default();
+        let writer = EchoWriter::spawn(written.clone(), 2, false);
+        thread::scope(|scope| for shard in 0..4 {
+            let sender = writer.sender();
+            scope.spawn(move || for item in 0..100 {
+                sender.print(Echoed::new(Side::Producer, 0, Contents::Shard(shard, vec![item]), 1));
+            });
+        });
+        let stats = writer.stop().unwrap();
+        assert_eq!(stats.n_lines, 400);
+        assert!(stats.n_batches <= stats.n_lines);
+        let text = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+        for shard in 0..4 {
+            let prefix = format!("{}: ", shard);
+            let lines: Vec<_> = text.lines().filter_map(|line| line.strip_prefix(&prefix)).collect();
+            let expected: Vec<_> = (0..100).map(|item| format!("[{}]", item)).collect();
+            assert_eq!(lines, expected);
+        }
+    }
+
+    // a push which fills the buffer, or a pop which empties it, stands out in reverse video
+    #[test]
+    fn colors_by_thread_and_transition() {
+        let pushed = Echoed::new(Side::Producer, 1, Contents::Items(vec![1, 2]), 3);
+        let filled = Echoed::new(Side::Producer, 1, Contents::Items(vec![1, 2, 3]), 3);
+        let emptied = Echoed::new(Side::Consumer, 0, Contents::Items(vec![]), 3);
+        assert_eq!(pushed.colored().to_string(), "\x1b[38;5;214m[1, 2]\x1b[0m");
+        assert_eq!(filled.colored().to_string(), "\x1b[38;5;214;7m[1, 2, 3]\x1b[0m");
+        assert_eq!(emptied.colored().to_string(), "\x1b[38;5;39;7m[]\x1b[0m");
+        assert_eq!(filled.to_string(), "[1, 2, 3]");
+    }
+}
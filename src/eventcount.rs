@@ -0,0 +1,250 @@
+#[cfg(not(loom))]
+use std::sync::{Mutex, Condvar, atomic::{AtomicU32, Ordering::SeqCst}};
+#[cfg(loom)]
+use loom::sync::{Mutex, Condvar, atomic::{AtomicU32, Ordering::SeqCst}};
+
+use std::{sync::Arc, time::Duration};
+use crate::{
+    RingBuffer, Locker, Queue, Cancelled, stats::{Side, Stats}, trace::{Op, Recorder},
+    echo::{self, Contents, Echoed},
+};
+
+/* An eventcount: a monotonic generation number which is bumped on every notification.
+A waiter first reads the generation (`prepare_wait`), then checks its condition, and only if the condition is false
+sleeps until the generation differs from what it read (`wait`). A notification that lands anywhere between the read
+and the sleep changes the generation, so the waiter doesn't sleep at all; that is, wakeups can't be lost even though
+the condition is checked without holding the lock that `wait` uses. This also means the condition can be checked
+under a different lock from the one used for sleeping, or with no lock at all.
+*/
+pub struct EventCount {
+    generation: AtomicU32,
+    // number of threads between `prepare_wait` and the end of `wait`, so notifiers can skip locking when it's 0
+    n_waiters: AtomicU32,
+    lock: Mutex<()>,
+    changed: Condvar,
+}
+impl EventCount {
+
+    pub fn new() -> Self {
+        EventCount {
+            generation: AtomicU32::new(0), n_waiters: AtomicU32::new(0), lock: Mutex::new(()), changed: Condvar::new(),
+        }
+    }
+
+    /* Must be followed by exactly one `wait` or `cancel_wait`.
+    Here and in `notify_all`, the second access is a read-modify-write rather than a load: this is a store-buffering
+    pattern, and read-modify-writes (which always read the latest value) make it correct even under acquire-release
+    ordering, which is all loom models of `SeqCst`.
+    */
+    pub fn prepare_wait(&self) -> u32 {
+        self.n_waiters.fetch_add(1, SeqCst);
+        self.generation.fetch_add(0, SeqCst)
+    }
+
+    pub fn cancel_wait(&self) { self.n_waiters.fetch_sub(1, SeqCst); }
+
+    // block until the generation differs from `key`, the value returned by `prepare_wait`, or until `timeout` if given
+    pub fn wait(&self, key: u32, timeout: Option<Duration>, stats: &Stats) {
+        let mut guard = self.lock.lock().unwrap();
+        // the generation is bumped before notifiers take the lock, so checking it under the lock can't miss one
+        while self.generation.load(SeqCst) == key {
+            stats.wait();
+            match timeout {
+                None => guard = self.changed.wait(guard).unwrap(),
+                Some(timeout) => {
+                    let (g, result) = self.changed.wait_timeout(guard, timeout).unwrap();
+                    guard = g;
+                    if result.timed_out() { break; }
+                },
+            }
+        }
+        drop(guard);
+        self.n_waiters.fetch_sub(1, SeqCst);
+    }
+
+    pub fn notify_all(&self, stats: &Stats) {
+        // either a waiter's `prepare_wait` sees the new generation, or we see the waiter
+        self.generation.fetch_add(1, SeqCst);
+        if self.n_waiters.fetch_add(0, SeqCst) == 0 { return; }
+
+        // taking the lock ensures no waiter is between checking the generation and sleeping
+        drop(self.lock.lock().unwrap());
+        stats.wake();
+        self.changed.notify_all();
+    }
+}
+impl Default for EventCount {
+    fn default() -> Self { Self::new() }
+}
+
+/* Bounded buffer which checks for space/items under its own mutex, but sleeps on eventcounts.
+Unlike with condvars, threads release the buffer's mutex before sleeping, and notify after releasing it, so a woken
+thread never immediately blocks on a mutex still held by its waker.
+*/
+pub struct EventCountBoundedBuffer {
+    // not loom's, since it's only the eventcounts being modelled
+    buffer: std::sync::Mutex<RingBuffer<isize>>,
+    not_empty: EventCount,
+    not_full: EventCount,
+    stats: Stats,
+    // print the buffer's contents after every operation (once the lock is released; see `SyncedBoundedBuffer`)
+    echo: bool,
+    recorder: Option<Arc<Recorder>>,
+}
+
+impl EventCountBoundedBuffer {
+    pub fn new(capacity: usize, echo: bool) -> Self {
+        EventCountBoundedBuffer {
+            buffer: std::sync::Mutex::new(RingBuffer::new(capacity)),
+            not_empty: EventCount::new(),
+            not_full: EventCount::new(),
+            stats: Stats::default(),
+            echo,
+            recorder: None,
+        }
+    }
+
+    // record every operation in `recorder`, if there is one
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+}
+
+// see `SyncedBoundedBuffer` for comments on the logic, which is the same
+impl Queue for EventCountBoundedBuffer {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        let mut n_waits = 0;
+        loop {
+            locker.check()?;
+            let key = self.not_full.prepare_wait();
+            let mut bbuf = locker.lock(&self.buffer);
+            if !bbuf.full() {
+                self.not_full.cancel_wait();
+                bbuf.push(item);
+                self.stats.occupancy(bbuf.n_items());
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+                let echoed = self.echo.then(|| {
+                    Echoed::new(Side::Producer, locker.id, Contents::items(bbuf.iter()), bbuf.capacity())
+                });
+                drop(bbuf);
+                if let Some(echoed) = echoed { echo::print(echoed); }
+
+                self.not_empty.notify_all(&self.stats);
+                self.stats.op();
+                return Ok(());
+            }
+            drop(bbuf);
+
+            if n_waits > 0 { locker.reblocked(); }
+            let waiting = self.stats.start_waiting(Side::Producer);
+            self.not_full.wait(key, locker.poll_interval(), &self.stats);
+            self.stats.stop_waiting(Side::Producer, waiting);
+            n_waits += 1;
+        }
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        let mut n_waits = 0;
+        loop {
+            locker.check()?;
+            let key = self.not_empty.prepare_wait();
+            let mut bbuf = locker.lock(&self.buffer);
+            if !bbuf.empty() {
+                self.not_empty.cancel_wait();
+                let item = bbuf.pop();
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+                let echoed = self.echo.then(|| {
+                    Echoed::new(Side::Consumer, locker.id, Contents::items(bbuf.iter()), bbuf.capacity())
+                });
+                drop(bbuf);
+                if let Some(echoed) = echoed { echo::print(echoed); }
+
+                self.not_full.notify_all(&self.stats);
+                self.stats.op();
+                return Ok(item);
+            }
+            drop(bbuf);
+
+            if n_waits > 0 { locker.reblocked(); }
+            let waiting = self.stats.start_waiting(Side::Consumer);
+            self.not_empty.wait(key, locker.poll_interval(), &self.stats);
+            self.stats.stop_waiting(Side::Consumer, waiting);
+            n_waits += 1;
+        }
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { self.buffer.lock().unwrap().capacity() }
+    fn n_items(&self) -> usize { self.buffer.lock().unwrap().n_items() }
+    fn snapshot(&self) -> Vec<isize> { self.buffer.lock().unwrap().iter().copied().collect() }
+}
+
+// run with `RUSTFLAGS="--cfg loom" cargo test --release eventcount`
+#[cfg(all(test, loom))]
+mod tests {
+    use loom::{
+        sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst}},
+        thread,
+    };
+    use super::EventCount;
+    use crate::stats::Stats;
+
+    // the waiter checks the flag, and the notifier sets it and notifies, between the waiter's check and its sleep;
+    // with a lost wakeup the waiter sleeps forever, which loom reports as a deadlock
+    #[test]
+    fn no_lost_wakeup() {
+        loom::model(|| {
+            let ec = Arc::new(EventCount::new());
+            let flag = Arc::new(AtomicBool::new(false));
+
+            let waiter = {
+                let (ec, flag) = (ec.clone(), flag.clone());
+                thread::spawn(move || loop {
+                    let key = ec.prepare_wait();
+                    if flag.load(SeqCst) { ec.cancel_wait(); break; }
+                    ec.wait(key, None, &Stats::default());
+                })
+            };
+
+            flag.store(true, SeqCst);
+            ec.notify_all(&Stats::default());
+            waiter.join().unwrap();
+        });
+    }
+
+    // a one-slot buffer passed back and forth between a producer and a consumer, each waiting on its own eventcount
+    #[test]
+    fn handoff() {
+        loom::model(|| {
+            let (not_empty, not_full) = (Arc::new(EventCount::new()), Arc::new(EventCount::new()));
+            let n_items = Arc::new(AtomicUsize::new(0));
+            const N_ITEMS: usize = 2;
+
+            let consumer = {
+                let (not_empty, not_full, n_items) = (not_empty.clone(), not_full.clone(), n_items.clone());
+                thread::spawn(move || for _ in 0..N_ITEMS {
+                    loop {
+                        let key = not_empty.prepare_wait();
+                        if n_items.load(SeqCst) == 1 { not_empty.cancel_wait(); break; }
+                        not_empty.wait(key, None, &Stats::default());
+                    }
+                    n_items.store(0, SeqCst);
+                    not_full.notify_all(&Stats::default());
+                })
+            };
+
+            for _ in 0..N_ITEMS {
+                loop {
+                    let key = not_full.prepare_wait();
+                    if n_items.load(SeqCst) == 0 { not_full.cancel_wait(); break; }
+                    not_full.wait(key, None, &Stats::default());
+                }
+                n_items.store(1, SeqCst);
+                not_empty.notify_all(&Stats::default());
+            }
+            consumer.join().unwrap();
+        });
+    }
+}
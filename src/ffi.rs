@@ -0,0 +1,75 @@
+/* C API over a bounded queue of `intptr_t`s (which can hold pointers as well as integers).
+The header, `include/pcq.h`, is generated by cbindgen when building with the `ffi` feature. To get a library C can
+link against, build with e.g. `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+*/
+use std::ptr;
+use crate::closable::{ClosableBuffer, PopError};
+
+/// Success.
+pub const PCQ_OK: i32 = 0;
+/// The queue has been closed (and, for `pcq_pop`, drained).
+pub const PCQ_CLOSED: i32 = 1;
+/// A null pointer was passed.
+pub const PCQ_INVALID: i32 = -1;
+
+/// Opaque to C.
+pub struct PcqQueue { buffer: ClosableBuffer<isize> }
+
+/// Returns null if `capacity` is 0. The queue must be freed with `pcq_destroy`.
+#[no_mangle]
+pub extern "C" fn pcq_create(capacity: usize) -> *mut PcqQueue {
+    if capacity == 0 { return ptr::null_mut(); }
+    Box::into_raw(Box::new(PcqQueue { buffer: ClosableBuffer::new(capacity) }))
+}
+
+/// Blocks while the queue is full. Returns `PCQ_CLOSED` (without pushing) if the queue is closed.
+///
+/// # Safety
+/// `queue` must be null or a pointer returned by `pcq_create` which hasn't been passed to `pcq_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn pcq_push(queue: *mut PcqQueue, item: isize) -> i32 {
+    let Some(queue) = (unsafe { queue.as_ref() }) else { return PCQ_INVALID };
+    match queue.buffer.push(item) {
+        Ok(()) => PCQ_OK,
+        Err(_) => PCQ_CLOSED,
+    }
+}
+
+/// Blocks while the queue is empty. Items pushed before closing are still popped after it; once the queue is closed
+/// and empty, returns `PCQ_CLOSED` and leaves `*item` untouched.
+///
+/// # Safety
+/// `queue` must be as for `pcq_push`, and `item` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn pcq_pop(queue: *mut PcqQueue, item: *mut isize) -> i32 {
+    let Some(queue) = (unsafe { queue.as_ref() }) else { return PCQ_INVALID };
+    if item.is_null() { return PCQ_INVALID; }
+
+    match queue.buffer.pop() {
+        Ok(popped) => {
+            unsafe { *item = popped; }
+            PCQ_OK
+        },
+        Err(PopError::Closed | PopError::Timeout) => PCQ_CLOSED,
+    }
+}
+
+/// Wakes all blocked threads; subsequent pushes fail, and pops fail once the remaining items are gone.
+///
+/// # Safety
+/// As for `pcq_push`.
+#[no_mangle]
+pub unsafe extern "C" fn pcq_close(queue: *mut PcqQueue) -> i32 {
+    let Some(queue) = (unsafe { queue.as_ref() }) else { return PCQ_INVALID };
+    queue.buffer.close();
+    PCQ_OK
+}
+
+/// Frees the queue and any items still in it. No other thread may be using it, or use it afterwards.
+///
+/// # Safety
+/// As for `pcq_push`.
+#[no_mangle]
+pub unsafe extern "C" fn pcq_destroy(queue: *mut PcqQueue) {
+    if !queue.is_null() { drop(unsafe { Box::from_raw(queue) }); }
+}
@@ -0,0 +1,227 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::{Arc, atomic::{AtomicU32, Ordering::{Acquire, Release, Relaxed, SeqCst}}},
+    time::Duration,
+};
+use crate::{
+    RingBuffer, Locker, Queue, Cancelled, stats::{Side, Stats}, trace::{Op, Recorder},
+    echo::{self, Contents, Echoed},
+};
+
+// sleep for at most `timeout`, if given
+fn futex_wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>, stats: &Stats) {
+    stats.wait();
+    let timespec = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t, tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    // returns immediately (with EAGAIN) if the value is no longer `expected`, which is what makes this race-free
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex, atomic.as_ptr(), libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG, expected,
+            timespec.as_ref().map_or(ptr::null(), |t| t as *const libc::timespec),
+        );
+    }
+}
+fn futex_wake(atomic: &AtomicU32, n_threads: i32, stats: &Stats) {
+    stats.wake();
+    unsafe { libc::syscall(libc::SYS_futex, atomic.as_ptr(), libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG, n_threads); }
+}
+
+// states of `FutexBoundedBuffer::lock`
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const LOCKED_CONTENDED: u32 = 2; // locked, and there may be threads sleeping on it
+
+/* Bounded buffer synchronized by futexes instead of a Mutex and Condvars.
+The lock is a futex-based mutex (see Drepper, "Futexes Are Tricky"). Instead of condition variables, threads sleep
+directly on `n_items`, a copy of the buffer's occupancy which is only written while holding the lock: a producer which
+finds the buffer full waits for `n_items` to change from the capacity, a consumer which finds it empty waits for it to
+change from 0. Since the kernel rechecks the value before sleeping, a change between releasing the lock and sleeping
+can't be missed.
+*/
+pub struct FutexBoundedBuffer {
+    lock: AtomicU32,
+    buffer: UnsafeCell<RingBuffer<isize>>,
+    n_items: AtomicU32,
+    // number of threads sleeping on (or about to sleep on) `n_items`, so that we can skip the wake syscall if there
+    // are none
+    n_waiters: AtomicU32,
+    stats: Stats,
+    // print the buffer's contents after every operation (once the lock is released; see `SyncedBoundedBuffer`)
+    echo: bool,
+    recorder: Option<Arc<Recorder>>,
+}
+// the buffer is only accessed while holding `lock`
+unsafe impl Sync for FutexBoundedBuffer {}
+
+struct FutexGuard<'a> { fbbuf: &'a FutexBoundedBuffer }
+impl Deref for FutexGuard<'_> {
+    type Target = RingBuffer<isize>;
+    fn deref(&self) -> &Self::Target { unsafe { &*self.fbbuf.buffer.get() } }
+}
+impl DerefMut for FutexGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target { unsafe { &mut *self.fbbuf.buffer.get() } }
+}
+impl Drop for FutexGuard<'_> {
+    fn drop(&mut self) {
+        let fbbuf = self.fbbuf;
+        if fbbuf.lock.swap(UNLOCKED, Release) == LOCKED_CONTENDED { futex_wake(&fbbuf.lock, 1, &fbbuf.stats); }
+    }
+}
+
+impl FutexBoundedBuffer {
+
+    pub fn new(capacity: usize, echo: bool) -> Self {
+        FutexBoundedBuffer {
+            lock: AtomicU32::new(UNLOCKED),
+            buffer: UnsafeCell::new(RingBuffer::new(capacity)),
+            n_items: AtomicU32::new(0),
+            n_waiters: AtomicU32::new(0),
+            stats: Stats::default(),
+            echo,
+            recorder: None,
+        }
+    }
+
+    // record every operation in `recorder`, if there is one
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    fn try_lock(&self) -> Option<FutexGuard<'_>> {
+        self.lock.compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed).ok().map(|_| FutexGuard { fbbuf: self })
+    }
+    fn lock_contended(&self) -> FutexGuard<'_> {
+        // once we've had to wait we can't know whether anyone else is waiting, so assume they are
+        while self.lock.swap(LOCKED_CONTENDED, Acquire) != UNLOCKED {
+            futex_wait(&self.lock, LOCKED_CONTENDED, None, &self.stats);
+        }
+        FutexGuard { fbbuf: self }
+    }
+    fn lock<'a>(&'a self, locker: &mut Locker) -> FutexGuard<'a> {
+        locker.acquire(|| self.try_lock(), || self.lock_contended())
+    }
+
+    // sleep on `side` until `n_items` is no longer `n_items_now`, or until `timeout` if given; must be called without
+    // holding the lock
+    fn wait_for_change(&self, side: Side, n_items_now: u32, timeout: Option<Duration>) {
+        self.n_waiters.fetch_add(1, SeqCst);
+        let waiting = self.stats.start_waiting(side);
+        futex_wait(&self.n_items, n_items_now, timeout, &self.stats);
+        self.stats.stop_waiting(side, waiting);
+        self.n_waiters.fetch_sub(1, SeqCst);
+    }
+
+    // publish the new occupancy and wake everyone waiting for it to change; must be called while holding the lock
+    fn publish(&self, n_items: usize) {
+        // `SeqCst` on both sides: either a waiter sees the new value (and doesn't sleep), or we see the waiter
+        self.n_items.store(n_items as u32, SeqCst);
+        if self.n_waiters.load(SeqCst) > 0 { futex_wake(&self.n_items, i32::MAX, &self.stats); }
+    }
+}
+// see `SyncedBoundedBuffer` for comments on the logic, which is the same
+impl Queue for FutexBoundedBuffer {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        let mut n_waits = 0;
+        loop {
+            locker.check()?;
+            let mut bbuf = self.lock(locker);
+            if !bbuf.full() {
+                bbuf.push(item);
+                self.stats.occupancy(bbuf.n_items());
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+                let echoed = self.echo.then(|| {
+                    Echoed::new(Side::Producer, locker.id, Contents::items(bbuf.iter()), bbuf.capacity())
+                });
+                self.publish(bbuf.n_items());
+                self.stats.op();
+                drop(bbuf);
+                if let Some(echoed) = echoed { echo::print(echoed); }
+                return Ok(());
+            }
+            let capacity = bbuf.capacity();
+            drop(bbuf);
+
+            if n_waits > 0 { locker.reblocked(); }
+            self.wait_for_change(Side::Producer, capacity as u32, locker.poll_interval());
+            n_waits += 1;
+        }
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        let mut n_waits = 0;
+        loop {
+            locker.check()?;
+            let mut bbuf = self.lock(locker);
+            if !bbuf.empty() {
+                let item = bbuf.pop();
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+                let echoed = self.echo.then(|| {
+                    Echoed::new(Side::Consumer, locker.id, Contents::items(bbuf.iter()), bbuf.capacity())
+                });
+                self.publish(bbuf.n_items());
+                self.stats.op();
+                drop(bbuf);
+                if let Some(echoed) = echoed { echo::print(echoed); }
+                return Ok(item);
+            }
+            drop(bbuf);
+
+            if n_waits > 0 { locker.reblocked(); }
+            self.wait_for_change(Side::Consumer, 0, locker.poll_interval());
+            n_waits += 1;
+        }
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { self.try_lock().unwrap_or_else(|| self.lock_contended()).capacity() }
+    fn n_items(&self) -> usize { self.n_items.load(Acquire) as usize }
+    fn snapshot(&self) -> Vec<isize> {
+        let bbuf = self.try_lock().unwrap_or_else(|| self.lock_contended());
+        bbuf.iter().copied().collect()
+    }
+}
+
+// like `spin`'s, these also run under Miri, which emulates the futex syscalls: `cargo +nightly miri test --lib futex`
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use crate::Strategy;
+    use super::*;
+
+    #[test]
+    fn push_pop_wrapping_around() {
+        let buffer = FutexBoundedBuffer::new(2, false);
+        let mut locker = Locker::new(Strategy::Block, "test", 0);
+        for item in 0..5 {
+            buffer.push(item, &mut locker).unwrap();
+            buffer.push(item + 100, &mut locker).unwrap();
+            assert_eq!(buffer.n_items(), 2);
+            assert_eq!(buffer.snapshot(), [item, item + 100]);
+            assert_eq!(buffer.pop(&mut locker), Ok(item));
+            assert_eq!(buffer.pop(&mut locker), Ok(item + 100));
+        }
+        assert_eq!(buffer.n_items(), 0);
+    }
+
+    // with a capacity of 1, each side keeps sleeping on `n_items` until the other wakes it
+    #[test]
+    fn handoff_between_threads() {
+        const N_ITEMS: isize = 20;
+        let buffer = Arc::new(FutexBoundedBuffer::new(1, false));
+        let consumer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                let mut locker = Locker::new(Strategy::Block, "consumer", 0);
+                (0..N_ITEMS).map(|_| buffer.pop(&mut locker).unwrap()).collect::<Vec<_>>()
+            })
+        };
+        let mut locker = Locker::new(Strategy::Block, "producer", 0);
+        for item in 0..N_ITEMS { buffer.push(item, &mut locker).unwrap(); }
+        assert_eq!(consumer.join().unwrap(), (0..N_ITEMS).collect::<Vec<_>>());
+    }
+}
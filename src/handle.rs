@@ -0,0 +1,338 @@
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    mem,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+use crate::{Cancelled, Locker, Queue, Strategy, SyncedBoundedBuffer, clock::{Clock, SystemClock}, stats::Stats};
+
+// a handle's buffer: borrowed, or shared by a producer `collect`ed from an iterator and the handles it gives out
+enum Buffer<'a> {
+    Borrowed(&'a dyn Queue),
+    Shared(Arc<dyn Queue>),
+}
+impl<'a> Deref for Buffer<'a> {
+    type Target = dyn Queue + 'a;
+    fn deref(&self) -> &(dyn Queue + 'a) {
+        match self {
+            Buffer::Borrowed(queue) => *queue,
+            Buffer::Shared(queue) => &**queue,
+        }
+    }
+}
+
+// a producer's locker: borrowed, or its own if it was `collect`ed from an iterator
+enum Held<'a> {
+    Borrowed(&'a mut Locker),
+    Owned(Locker),
+}
+impl Deref for Held<'_> {
+    type Target = Locker;
+    fn deref(&self) -> &Locker {
+        match self {
+            Held::Borrowed(locker) => locker,
+            Held::Owned(locker) => locker,
+        }
+    }
+}
+impl DerefMut for Held<'_> {
+    fn deref_mut(&mut self) -> &mut Locker {
+        match self {
+            Held::Borrowed(locker) => locker,
+            Held::Owned(locker) => locker,
+        }
+    }
+}
+
+/* One thread's end of a buffer for pushing, so that it composes with iterators: `producer.extend(items)` pushes each
+item in turn (blocking while the buffer is full) and stops early if the locker's token is cancelled.
+With `with_batch(n, flush_interval)`, pushed items are held back until there are `n` of them, or the oldest has waited
+`flush_interval`, and then pushed together (see `Queue::push_batch`), which takes the buffer's lock less often at the
+cost of latency. The interval is only checked on each push, so a thread which pushes rarely should call `flush` itself
+by `flush_deadline`. Held-back items are flushed at the end of `extend`; otherwise call `flush` before dropping the
+handle, or take them back with `into_pending`, as dropping it discards them (with a warning) rather than risk blocking
+forever on a full buffer. The interval is by the real time, or the `Clock` given to `with_clock`.
+`items.collect::<Producer>()` gives a producer with a buffer of its own, holding `items` and exactly full (or empty with
+capacity 1, if there are none), and a locker of its own which blocks; `consumer` gives the other end.
+*/
+pub struct Producer<'a> {
+    queue: Buffer<'a>,
+    locker: Held<'a>,
+    n_pushed: usize,
+    batch_size: usize,
+    flush_interval: Duration,
+    pending: VecDeque<isize>,
+    // when the oldest of `pending` was pushed
+    pending_since: Instant,
+    clock: &'a dyn Clock,
+}
+impl<'a> Producer<'a> {
+
+    pub fn new(queue: &'a dyn Queue, locker: &'a mut Locker) -> Self {
+        Producer {
+            queue: Buffer::Borrowed(queue), locker: Held::Borrowed(locker), n_pushed: 0,
+            batch_size: 1, flush_interval: Duration::ZERO, pending: VecDeque::new(), pending_since: Instant::now(),
+            clock: &SystemClock,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: &'a dyn Clock) -> Self {
+        self.clock = clock;
+        self.pending_since = clock.now();
+        self
+    }
+
+    pub fn with_batch(mut self, batch_size: usize, flush_interval: Duration) -> Self {
+        assert!(batch_size > 0, "a producer must push at least one item at a time");
+        self.batch_size = batch_size;
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn push(&mut self, item: isize) -> Result<(), Cancelled> {
+        if self.batch_size == 1 {
+            self.queue.push(item, &mut self.locker)?;
+            self.n_pushed += 1;
+            return Ok(());
+        }
+        if self.pending.is_empty() { self.pending_since = self.clock.now(); }
+        self.pending.push_back(item);
+        let waited = self.clock.now().saturating_duration_since(self.pending_since);
+        if self.pending.len() >= self.batch_size || waited >= self.flush_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // push the held-back items; if cancelled, the ones not pushed yet stay held back
+    pub fn flush(&mut self) -> Result<(), Cancelled> {
+        if self.pending.is_empty() { return Ok(()); }
+        let n_pending = self.pending.len();
+        let result = self.queue.push_batch(&mut self.pending, &mut self.locker);
+        self.n_pushed += n_pending - self.pending.len();
+        result
+    }
+
+    // when the held-back items are due to be flushed (by the handle's clock), if there are any
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        (!self.pending.is_empty()).then(|| self.pending_since + self.flush_interval)
+    }
+
+    // how many items this handle has pushed into the buffer (not counting held-back ones), e.g. to find out how far
+    // `extend` got before being cancelled
+    pub fn n_pushed(&self) -> usize { self.n_pushed }
+
+    // e.g. to wait for something else in a way which can be cancelled along with the pushes
+    pub fn locker(&self) -> &Locker { &self.locker }
+
+    // the held-back items, not pushed, e.g. when cancelled and there's no waiting to push them
+    pub fn into_pending(mut self) -> VecDeque<isize> { mem::take(&mut self.pending) }
+
+    pub fn downgrade(&self) -> Observer<'a> {
+        let queue = match &self.queue {
+            Buffer::Borrowed(queue) => Watched::Borrowed(*queue),
+            Buffer::Shared(queue) => Watched::Shared(Arc::downgrade(queue)),
+        };
+        Observer { queue }
+    }
+
+    // a consumer popping from this producer's buffer
+    pub fn consumer<'b>(&'b self, locker: &'b mut Locker) -> Consumer<'b> { Consumer::new(&*self.queue, locker) }
+}
+impl FromIterator<isize> for Producer<'static> {
+    fn from_iter<I: IntoIterator<Item = isize>>(items: I) -> Self {
+        let items: VecDeque<isize> = items.into_iter().collect();
+        let buffer = SyncedBoundedBuffer::builder().capacity(items.len().max(1)).build();
+        let mut locker = Locker::new(Strategy::Block, "producer", 0);
+        if !items.is_empty() {
+            buffer.push_batch(&mut items.clone(), &mut locker).expect("the locker has no cancellation token");
+        }
+        Producer {
+            queue: Buffer::Shared(Arc::new(buffer)), locker: Held::Owned(locker), n_pushed: items.len(),
+            batch_size: 1, flush_interval: Duration::ZERO, pending: VecDeque::new(), pending_since: Instant::now(),
+            clock: &SystemClock,
+        }
+    }
+}
+impl Drop for Producer<'_> {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() && !thread::panicking() {
+            eprintln!("warning: dropped a producer holding back {} items, which weren't pushed", self.pending.len());
+        }
+    }
+}
+impl Extend<isize> for Producer<'_> {
+    fn extend<I: IntoIterator<Item = isize>>(&mut self, items: I) {
+        for item in items {
+            if self.push(item).is_err() { return; }
+        }
+        let _ = self.flush();
+    }
+}
+
+/* One thread's end of a buffer for popping, as an iterator: `for item in consumer` pops items (blocking while the
+buffer is empty) until the locker's token is cancelled, and never ends otherwise.
+With `with_prefetch(n)`, it pops up to `n` items at a time (see `Queue::pop_batch`) into a local stash and hands them
+out from there, which takes the buffer's lock less often, at the cost of items waiting in the stash while the thread
+processes the ones before them, where no other consumer can get at them. `stash_time` measures that cost.
+*/
+pub struct Consumer<'a> {
+    queue: &'a dyn Queue,
+    locker: &'a mut Locker,
+    prefetch: usize,
+    stash: VecDeque<isize>,
+    // when the items in `stash` were popped
+    popped_at: Instant,
+    stash_time: Duration,
+}
+impl<'a> Consumer<'a> {
+
+    pub fn new(queue: &'a dyn Queue, locker: &'a mut Locker) -> Self {
+        Consumer {
+            queue, locker, prefetch: 1, stash: VecDeque::new(), popped_at: Instant::now(), stash_time: Duration::ZERO,
+        }
+    }
+
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        assert!(prefetch > 0, "a consumer must pop at least one item at a time");
+        self.prefetch = prefetch;
+        self
+    }
+
+    // the total time the items handed out so far spent in the stash between being popped and handed out
+    pub fn stash_time(&self) -> Duration { self.stash_time }
+
+    pub fn downgrade(&self) -> Observer<'a> { Observer::new(self.queue) }
+
+    // up to `max_len` items: from the stash if there are any there, or else popped with `Queue::pop_batch`; `None` once
+    // cancelled
+    pub fn next_batch(&mut self, max_len: usize) -> Option<Vec<isize>> {
+        if self.stash.is_empty() { return self.queue.pop_batch(max_len, self.locker).ok(); }
+        let n_stashed = max_len.min(self.stash.len());
+        self.stash_time += self.popped_at.elapsed() * n_stashed as u32;
+        Some(self.stash.drain(..n_stashed).collect())
+    }
+}
+impl Iterator for Consumer<'_> {
+    type Item = isize;
+    fn next(&mut self) -> Option<isize> {
+        if self.prefetch == 1 { return self.queue.pop(self.locker).ok(); }
+        if self.stash.is_empty() {
+            self.stash = self.queue.pop_batch(self.prefetch, self.locker).ok()?.into();
+            self.popped_at = Instant::now();
+        }
+        self.stash_time += self.popped_at.elapsed();
+        self.stash.pop_front()
+    }
+}
+
+// an observer's buffer: borrowed, or a collected producer's, which it doesn't keep alive
+#[derive(Clone)]
+enum Watched<'a> {
+    Borrowed(&'a dyn Queue),
+    Shared(Weak<dyn Queue>),
+}
+
+/* A handle which can look at a buffer but not push or pop, e.g. for a monitoring thread. It's not tied to the handle it
+was downgraded from, so it can outlive it, and since it holds no locker it's never blocked or cancelled. Nor does it
+keep a collected producer's buffer alive: once the producer is dropped, it reads `None`.
+*/
+#[derive(Clone)]
+pub struct Observer<'a> { queue: Watched<'a> }
+impl<'a> Observer<'a> {
+
+    pub fn new(queue: &'a dyn Queue) -> Self { Observer { queue: Watched::Borrowed(queue) } }
+
+    pub fn n_items (&self) -> Option<usize>      { self.queue().map(|queue| queue.n_items()) }
+    pub fn snapshot(&self) -> Option<Vec<isize>> { self.queue().map(|queue| queue.snapshot()) }
+
+    // what `read` makes of the buffer's statistics
+    pub fn stats<R>(&self, read: impl FnOnce(&Stats) -> R) -> Option<R> {
+        self.queue().map(|queue| read(queue.stats()))
+    }
+
+    // the buffer, unless it was a collected producer's which has been dropped since
+    fn queue(&self) -> Option<Buffer<'a>> {
+        match &self.queue {
+            Watched::Borrowed(queue) => Some(Buffer::Borrowed(*queue)),
+            Watched::Shared(queue) => queue.upgrade().map(Buffer::Shared),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+    use crate::SyncedBoundedBuffer;
+    use super::*;
+
+    fn locker() -> Locker { Locker::new(Strategy::Block, "consumer", 0) }
+
+    // a producer collected from an iterator holds the items in a buffer of its own, which its consumer pops in order
+    #[test]
+    fn collects_into_a_producer() {
+        let producer: Producer = (1..=3).collect();
+        assert_eq!((producer.n_pushed(), producer.downgrade().snapshot()), (3, Some(vec![1, 2, 3])));
+        let mut locker = locker();
+        assert_eq!(producer.consumer(&mut locker).take(3).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(producer.downgrade().n_items(), Some(0));
+    }
+
+    // the collected buffer is exactly full, so pushing more blocks until the consumer makes room
+    #[test]
+    fn collected_producers_block_when_full() {
+        let mut producer: Producer = [7].into_iter().collect();
+        let observer = producer.downgrade();
+        thread::scope(|scope| {
+            scope.spawn(|| producer.extend([8]));
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(observer.snapshot(), Some(vec![7]));
+            let (buffer, mut locker) = (observer.queue().unwrap(), locker());
+            assert_eq!(buffer.pop(&mut locker), Ok(7));
+        });
+        assert_eq!(observer.snapshot(), Some(vec![8]));
+    }
+
+    // an observer of a collected producer's buffer doesn't keep it alive once the producer is dropped
+    #[test]
+    fn observers_dont_outlive_collected_buffers() {
+        let producer: Producer = (1..=3).collect();
+        let observer = producer.downgrade();
+        assert_eq!((observer.n_items(), observer.stats(|stats| stats.peak_items())), (Some(3), Some(3)));
+        drop(producer);
+        assert_eq!((observer.n_items(), observer.snapshot()), (None, None));
+        assert!(observer.stats(|_| ()).is_none());
+    }
+
+    // a producer gives back what it holds back instead of pushing it, which would block on a full buffer
+    #[test]
+    fn gives_back_what_is_held_back() {
+        let buffer = SyncedBoundedBuffer::new(1, false);
+        let mut locker = locker();
+        let mut producer = Producer::new(&buffer, &mut locker).with_batch(2, Duration::from_secs(60));
+        producer.extend([1]);
+        producer.push(2).unwrap();
+        assert!(producer.flush_deadline().is_some());
+        assert_eq!(producer.into_pending(), [2]);
+        assert_eq!(buffer.snapshot(), [1]);
+    }
+
+    // extending a borrowing producer pushes into the borrowed buffer, and a consumer iterates over it
+    #[test]
+    fn extends_and_iterates() {
+        let buffer = SyncedBoundedBuffer::new(4, false);
+        let (mut producer_locker, mut consumer_locker) = (locker(), locker());
+        let mut producer = Producer::new(&buffer, &mut producer_locker);
+        producer.extend([4, 5, 6]);
+        assert_eq!(producer.n_pushed(), 3);
+        drop(producer);
+        let mut items = Vec::new();
+        for item in Consumer::new(&buffer, &mut consumer_locker) {
+            items.push(item);
+            if items.len() == 3 { break; }
+        }
+        assert_eq!(items, [4, 5, 6]);
+    }
+}
@@ -0,0 +1,131 @@
+// `/healthz` and `/readyz` for `run --health`, so that it can be deployed behind a container orchestrator's liveness
+// and readiness probes. Just enough HTTP for those (and `--ws-port`'s page): one request per connection, and no body.
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// how long a probe gets to send its request, so that a stuck one can't hold up the others
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+// the most of a request read, which is plenty for the request line
+const MAX_REQUEST: usize = 4096;
+
+/* Whether the run is ready for work, and if not, why, which `/readyz` answers with; `/healthz` only says that the
+process is up. It starts out not ready, until the run has started its threads.
+*/
+pub struct Health {
+    unready: Mutex<Option<String>>,
+}
+impl Health {
+
+    pub fn set_ready(&self) { *self.unready.lock().unwrap() = None; }
+    pub fn set_unready(&self, reason: String) { *self.unready.lock().unwrap() = Some(reason); }
+
+    fn unready(&self) -> Option<String> { self.unready.lock().unwrap().clone() }
+}
+
+// listen on `address`, answering probes on a thread of its own for as long as the process runs
+pub fn serve(address: SocketAddr) -> io::Result<Arc<Health>> {
+    let listener = TcpListener::bind(address)?;
+    let health = Arc::new(Health { unready: Mutex::new(Some("starting".to_string())) });
+    let served = health.clone();
+    crate::spawn("health".to_string(), move || for stream in listener.incoming().flatten() {
+        // a probe which hangs up early is its own problem
+        let _ = answer(stream, &served);
+    });
+    Ok(health)
+}
+
+// a request's line and headers
+pub fn read_request(stream: &mut TcpStream) -> io::Result<String> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buffer = [0; 512];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 { break; }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+// the method and path of `request`, without any query string, e.g. `/readyz?verbose`
+pub fn method_and_path(request: &str) -> (&str, &str) {
+    let mut words = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (words.next().unwrap_or_default(), words.next().unwrap_or_default());
+    (method, path.split('?').next().unwrap_or_default())
+}
+
+// `body` is left out for a `HEAD`
+pub fn respond(stream: &mut TcpStream, method: &str, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len(),
+    );
+    stream.write_all(head.as_bytes())?;
+    if method != "HEAD" { stream.write_all(body)?; }
+    Ok(())
+}
+
+fn answer(mut stream: TcpStream, health: &Health) -> io::Result<()> {
+    let request = read_request(&mut stream)?;
+    let (method, path) = method_and_path(&request);
+    let (status, body) = match (method, path) {
+        ("GET" | "HEAD", "/healthz") => ("200 OK", "ok".to_string()),
+        ("GET" | "HEAD", "/readyz") => match health.unready() {
+            None => ("200 OK", "ready".to_string()),
+            Some(reason) => ("503 Service Unavailable", format!("not ready: {}", reason)),
+        },
+        ("GET" | "HEAD", _) => ("404 Not Found", "not found".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed".to_string()),
+    };
+    respond(&mut stream, method, status, "text/plain; charset=utf-8", (body + "\n").as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    // send `request` to a probe answered by `health`, returning the response
+    fn probe(health: Arc<Health>, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = thread::spawn(move || answer(listener.accept().unwrap().0, &health));
+        client.write_all(request.as_bytes()).unwrap();
+        server.join().unwrap().unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn parses_the_request_line() {
+        assert_eq!(method_and_path("GET /readyz?verbose HTTP/1.1\r\nHost: x\r\n\r\n"), ("GET", "/readyz"));
+        assert_eq!(method_and_path("HEAD / HTTP/1.1\r\n\r\n"), ("HEAD", "/"));
+        assert_eq!(method_and_path(""), ("", ""));
+    }
+
+    // ready only once told so, with the reason until then; a `HEAD` gets no body, and anything else an error status
+    #[test]
+    fn answers_probes() {
+        let health = Arc::new(Health { unready: Mutex::new(Some("starting".to_string())) });
+        let response = probe(health.clone(), "GET /healthz HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 3\r\n") && response.ends_with("\r\n\r\nok\n"));
+
+        let response = probe(health.clone(), "GET /readyz HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.ends_with("not ready: starting\n"));
+        health.set_ready();
+        assert!(probe(health.clone(), "GET /readyz HTTP/1.1\r\n\r\n").ends_with("\r\n\r\nready\n"));
+        let response = probe(health.clone(), "HEAD /readyz HTTP/1.1\r\n\r\n");
+        assert!(response.contains("Content-Length: 6\r\n") && response.ends_with("\r\n\r\n"));
+
+        assert!(probe(health.clone(), "GET /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(probe(health, "POST /readyz HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
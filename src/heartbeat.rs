@@ -0,0 +1,154 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, atomic::{AtomicU64, AtomicU8, Ordering::Relaxed}},
+    time::{Duration, Instant},
+};
+use crate::{Hooked, Hooks, Queue};
+
+// what a worker was doing at its last heartbeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    // outside the buffer: making an item, or working on one
+    Working,
+    // in a push or a pop, maybe waiting for room or for an item
+    InQueue,
+    // done: its closure has returned
+    Finished,
+}
+
+/* A worker's heartbeat: when it last went into or came out of the buffer, and which, so that one which hasn't for a
+while can be told apart as stuck working on an item or blocked on the buffer. The two are stored separately, so a
+reader can see one beat's time with the other's activity, which is close enough for spotting a stall.
+*/
+pub struct Heartbeat {
+    name: String,
+    epoch: Instant,
+    // in nanoseconds since `epoch`
+    last: AtomicU64,
+    activity: AtomicU8,
+}
+impl Heartbeat {
+
+    pub fn beat(&self, activity: Activity) {
+        self.last.store(self.epoch.elapsed().as_nanos() as u64, Relaxed);
+        self.activity.store(activity as u8, Relaxed);
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+    pub fn activity(&self) -> Activity {
+        match self.activity.load(Relaxed) {
+            0 => Activity::Working,
+            1 => Activity::InQueue,
+            _ => Activity::Finished,
+        }
+    }
+    // how long ago the last heartbeat was
+    pub fn since(&self) -> Duration {
+        self.epoch.elapsed().saturating_sub(Duration::from_nanos(self.last.load(Relaxed)))
+    }
+}
+// e.g. "producer 3: in the buffer for 2.50s"
+impl fmt::Display for Heartbeat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.activity() {
+            Activity::Working  => write!(f, "{}: working for {:.2}s", self.name, self.since().as_secs_f64()),
+            Activity::InQueue  => write!(f, "{}: in the buffer for {:.2}s", self.name, self.since().as_secs_f64()),
+            Activity::Finished => write!(f, "{}: finished", self.name),
+        }
+    }
+}
+
+// the heartbeats of a set of workers, e.g. a `Runner`'s threads, for a monitor to check on
+pub struct Heartbeats {
+    epoch: Instant,
+    workers: Mutex<Vec<Arc<Heartbeat>>>,
+}
+impl Default for Heartbeats {
+    fn default() -> Self { Heartbeats { epoch: Instant::now(), workers: Mutex::new(Vec::new()) } }
+}
+impl Heartbeats {
+
+    // a heartbeat for a new worker called `name`, which starts out working
+    pub fn register(&self, name: String) -> Arc<Heartbeat> {
+        let heartbeat = Arc::new(Heartbeat {
+            name, epoch: self.epoch, last: AtomicU64::new(0), activity: AtomicU8::new(Activity::Working as u8),
+        });
+        heartbeat.beat(Activity::Working);
+        self.workers.lock().unwrap().push(heartbeat.clone());
+        heartbeat
+    }
+
+    // in the order they were registered
+    pub fn workers(&self) -> Vec<Arc<Heartbeat>> { self.workers.lock().unwrap().clone() }
+
+    // the workers which haven't finished, and haven't gone into or out of the buffer for longer than `threshold`
+    pub fn stalled(&self, threshold: Duration) -> Vec<Arc<Heartbeat>> {
+        self.workers().into_iter()
+            .filter(|worker| worker.activity() != Activity::Finished && worker.since() > threshold)
+            .collect()
+    }
+}
+
+// `queue`, with `heartbeat` beating on the way into and out of every push and pop
+pub type Beating<'a> = Hooked<&'a dyn Queue, &'a Heartbeat>;
+impl Hooks for &Heartbeat {
+    fn around<R>(&self, op: impl FnOnce() -> R) -> R {
+        self.beat(Activity::InQueue);
+        let result = op();
+        self.beat(Activity::Working);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use crate::{Locker, Strategy, SyncedBoundedBuffer};
+    use super::*;
+
+    const STALL: Duration = Duration::from_millis(20);
+
+    fn locker() -> Locker { Locker::new(Strategy::Block, "producer", 0) }
+
+    // a worker blocked in a push on a full buffer has stalled in the buffer, and goes back to working once it's through
+    #[test]
+    fn blocked_workers_are_in_the_queue() {
+        let (buffer, heartbeats) = (SyncedBoundedBuffer::new(1, false), Heartbeats::default());
+        buffer.push(1, &mut locker()).unwrap();
+        let heartbeat = heartbeats.register("producer 0".to_string());
+        let beating: Beating = Hooked::new(&buffer, &*heartbeat);
+        thread::scope(|scope| {
+            scope.spawn(|| beating.push(2, &mut locker()).unwrap());
+            thread::sleep(STALL * 3);
+            let stalled = heartbeats.stalled(STALL);
+            assert_eq!(stalled.iter().map(|worker| (worker.name(), worker.activity())).collect::<Vec<_>>(),
+                [("producer 0", Activity::InQueue)]);
+            assert_eq!(buffer.pop(&mut locker()), Ok(1));
+        });
+        assert_eq!(heartbeat.activity(), Activity::Working);
+        assert!(heartbeats.stalled(STALL).is_empty());
+    }
+
+    // a worker which stays outside the buffer, e.g. on a slow item, has stalled working, once past the threshold
+    #[test]
+    fn slow_workers_are_working() {
+        let heartbeats = Heartbeats::default();
+        let heartbeat = heartbeats.register("consumer 0".to_string());
+        thread::sleep(STALL * 3);
+        assert!(heartbeats.stalled(Duration::from_secs(60)).is_empty());
+        let stalled = heartbeats.stalled(STALL);
+        assert_eq!((stalled.len(), heartbeat.activity()), (1, Activity::Working));
+        assert!(stalled[0].since() > STALL);
+    }
+
+    // finished workers never stall, however long ago they finished
+    #[test]
+    fn finished_workers_never_stall() {
+        let heartbeats = Heartbeats::default();
+        heartbeats.register("producer 0".to_string()).beat(Activity::Finished);
+        let working = heartbeats.register("producer 1".to_string());
+        thread::sleep(STALL * 3);
+        let stalled = heartbeats.stalled(Duration::ZERO);
+        assert_eq!(stalled.iter().map(|worker| worker.name()).collect::<Vec<_>>(), [working.name()]);
+    }
+}
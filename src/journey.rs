@@ -0,0 +1,113 @@
+/* Each item's journey, for `--journey`: a line for every push and pop, through every buffer of a pipeline, with the
+item's trace id, so that one item's path through the buffers and the threads which handled it can be found with
+`grep -w <id>`. With `--generator tagged`, every item is its own trace id: producer p's nth is p000000000 + n, as echo,
+`--tap`, `--ws-port` and recorded traces show it too.
+
+    0.000184 2000000017 buffer 1: pushed by producer 2
+    0.000190 2000000017 buffer 1: popped by stage 2 worker 0
+    0.010262 2000000017 buffer 2: pushed by stage 2 worker 0
+
+A line is sent just after its push or pop returns, so a pop can come just before the push of the same item. The
+threads hand the lines over to a writer thread of their own, through a channel holding up to `BACKLOG`; one which finds
+it full waits, so that no line is lost.
+*/
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex, mpsc::{self, Receiver, SyncSender}},
+    thread::JoinHandle,
+    time::Instant,
+};
+use rpc::{Hooked, Hooks, Locker, Queue};
+
+const BACKLOG: usize = 4096;
+
+// a push or pop of `item` on buffer number `buffer`, by thread `thread` of the side which did it
+struct Step {
+    nanos: u64,
+    item: isize,
+    buffer: usize,
+    pushed: bool,
+    thread: u32,
+}
+
+// a buffer which is followed, and what its producers and consumers are called, e.g. "producer" or "stage 2 worker"
+struct Followed {
+    name: String,
+    pushers: String,
+    poppers: String,
+}
+
+pub struct Journal {
+    start: Instant,
+    buffers: Arc<Mutex<Vec<Followed>>>,
+    // `None` tells the writer to finish
+    steps: SyncSender<Option<Step>>,
+    writer: JoinHandle<io::Result<u64>>,
+}
+impl Journal {
+
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let out = BufWriter::new(File::create(path)?);
+        let buffers = Arc::new(Mutex::new(Vec::new()));
+        let (steps, receiver) = mpsc::sync_channel(BACKLOG);
+        let written = buffers.clone();
+        let writer = crate::spawn("journey".to_string(), move || write(out, receiver, &written));
+        Ok(Journal { start: Instant::now(), buffers, steps, writer })
+    }
+
+    // `queue`, writing its pushes and pops to the journal as those of the buffer called `name`
+    pub fn follow(&self, queue: Arc<dyn Queue>, name: String, pushers: &str, poppers: &str) -> Arc<dyn Queue> {
+        let mut buffers = self.buffers.lock().unwrap();
+        buffers.push(Followed { name, pushers: pushers.to_string(), poppers: poppers.to_string() });
+        let journeying = Journeying { start: self.start, buffer: buffers.len() - 1, steps: self.steps.clone() };
+        Arc::new(Hooked::new(queue, journeying))
+    }
+
+    // write whatever's left, once the threads are done, and return the number of lines written
+    pub fn finish(self) -> io::Result<u64> {
+        let _ = self.steps.send(None);
+        self.writer.join().expect("the journey writer panicked")
+    }
+}
+
+fn write(mut out: BufWriter<File>, steps: Receiver<Option<Step>>, buffers: &Mutex<Vec<Followed>>) -> io::Result<u64> {
+    let mut n_lines = 0;
+    while let Ok(Some(step)) = steps.recv() {
+        let buffers = buffers.lock().unwrap();
+        let buffer = &buffers[step.buffer];
+        let (done, thread) = if step.pushed { ("pushed", &buffer.pushers) } else { ("popped", &buffer.poppers) };
+        writeln!(
+            out, "{:.6} {} {}: {} by {} {}",
+            step.nanos as f64 / 1e9, step.item, buffer.name, done, thread, step.thread,
+        )?;
+        n_lines += 1;
+    }
+    out.flush()?;
+    Ok(n_lines)
+}
+
+// writing a buffer's pushes and pops to a journal
+struct Journeying {
+    start: Instant,
+    buffer: usize,
+    steps: SyncSender<Option<Step>>,
+}
+impl Journeying {
+    fn send(&self, pushed: bool, thread: u32, item: isize) {
+        let nanos = self.start.elapsed().as_nanos() as u64;
+        // once the journal has finished, there's nowhere for it to go
+        let _ = self.steps.send(Some(Step { nanos, item, buffer: self.buffer, pushed, thread }));
+    }
+}
+impl Hooks for Journeying {
+    // if cancelled partway, those pushed so far are written
+    fn after_push(&self, pushed: &[isize], _: &[isize], locker: &Locker) {
+        for &item in pushed { self.send(true, locker.id, item); }
+    }
+    fn after_pop(&self, popped: &[isize], locker: &Locker) {
+        for &item in popped { self.send(false, locker.id, item); }
+    }
+}
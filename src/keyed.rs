@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Condvar, Mutex},
+};
+use crate::closable::{OnClose, PopError, PushError};
+
+struct State<T> {
+    // oldest first, with their keys
+    items: VecDeque<(Option<u64>, T)>,
+    // how many items of each key are popped but not done with; keys without any are left out
+    in_flight: HashMap<u64, usize>,
+    closed: bool,
+}
+impl<T> State<T> {
+    // the oldest item whose key has room for another in flight
+    fn first_eligible(&self, per_key: usize) -> Option<usize> {
+        self.items.iter().position(|(key, _)| match key {
+            None => true,
+            Some(key) => self.in_flight.get(key).copied().unwrap_or(0) < per_key,
+        })
+    }
+}
+
+/* Bounded buffer whose items can have a key, of which at most `per_key` items are in flight at once: from when one is
+popped until the consumer says it's `done` with it. A pop takes the oldest item whose key has room, passing over those
+whose key doesn't, so that a busy key holds up only its own items and not the others. With `per_key` 1, the items of a
+key are handled one at a time in the order they were pushed, e.g. the updates to one account, while different keys are
+handled in parallel. Items without a key are never held back. The capacity bounds the items waiting in the buffer,
+not those in flight. Closing makes pushes fail, and pops once there are no items left, though an item held back
+behind its key still waits for it.
+*/
+pub struct KeyedBuffer<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    per_key: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+impl<T> KeyedBuffer<T> {
+
+    pub fn new(capacity: usize, per_key: usize) -> Self {
+        assert!(capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        assert!(per_key > 0, "a key with no room in flight can never be popped");
+        KeyedBuffer {
+            state: Mutex::new(State { items: VecDeque::new(), in_flight: HashMap::new(), closed: false }),
+            capacity,
+            per_key,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+    pub fn per_key (&self) -> usize { self.per_key }
+    pub fn n_items (&self) -> usize { self.state.lock().unwrap().items.len() }
+    // how many items of `key` are popped but not done with
+    pub fn n_in_flight(&self, key: u64) -> usize {
+        self.state.lock().unwrap().in_flight.get(&key).copied().unwrap_or(0)
+    }
+
+    // block while the buffer is full
+    pub fn push(&self, key: Option<u64>, item: T) -> Result<(), PushError<T>> {
+        let mut state = self.state.lock().unwrap();
+        while state.items.len() >= self.capacity && !state.closed { state = self.not_full.wait(state).unwrap(); }
+        if state.closed { return Err(PushError::Closed(item)); }
+        state.items.push_back((key, item));
+        self.not_empty.notify_all();
+        Ok(())
+    }
+
+    // the oldest item whose key has room, with its key, blocking while there isn't one; call `done` with the key once
+    // the item is dealt with
+    pub fn pop(&self) -> Result<(Option<u64>, T), PopError> {
+        let mut state = self.state.lock().unwrap();
+        let i = loop {
+            if let Some(i) = state.first_eligible(self.per_key) { break i; }
+            if state.items.is_empty() && state.closed { return Err(PopError::Closed); }
+            state = self.not_empty.wait(state).unwrap();
+        };
+        let (key, item) = state.items.remove(i).unwrap();
+        if let Some(key) = key { *state.in_flight.entry(key).or_insert(0) += 1; }
+        self.not_full.notify_all();
+        Ok((key, item))
+    }
+
+    // an item of `key` popped is dealt with, making room for the next of that key
+    pub fn done(&self, key: Option<u64>) {
+        let Some(key) = key else { return };
+        let mut state = self.state.lock().unwrap();
+        let n_in_flight = state.in_flight.get_mut(&key).expect("`done` with a key which has nothing in flight");
+        *n_in_flight -= 1;
+        if *n_in_flight == 0 { state.in_flight.remove(&key); }
+        // any pop might be waiting for this key
+        self.not_empty.notify_all();
+    }
+
+    pub fn close(&self) { self.close_with(OnClose::Drain); }
+
+    // close, and return the items which were in the buffer if `on_close` discards them (and nothing otherwise)
+    pub fn close_with(&self, on_close: OnClose) -> Vec<T> {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let discarded = match on_close {
+            OnClose::Drain   => Vec::new(),
+            OnClose::Discard => state.items.drain(..).map(|(_, item)| item).collect(),
+        };
+        drop(state);
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+        discarded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::{AtomicUsize, Ordering::SeqCst}, thread, time::Duration};
+    use super::*;
+
+    // a pop passes over the items of a key with no room left, and takes them once it has some again
+    #[test]
+    fn busy_key_holds_up_only_its_own_items() {
+        let buffer = KeyedBuffer::new(8, 1);
+        for (key, item) in [(Some(1), 'a'), (Some(1), 'b'), (Some(2), 'c'), (None, 'd')] {
+            buffer.push(key, item).unwrap();
+        }
+        assert_eq!(buffer.pop(), Ok((Some(1), 'a')));
+        assert_eq!(buffer.pop(), Ok((Some(2), 'c')));
+        assert_eq!(buffer.pop(), Ok((None, 'd')));
+        assert_eq!((buffer.n_items(), buffer.n_in_flight(1)), (1, 1));
+        buffer.done(Some(1));
+        assert_eq!(buffer.pop(), Ok((Some(1), 'b')));
+        buffer.close();
+        assert_eq!(buffer.pop(), Err(PopError::Closed));
+    }
+
+    // with several consumers, each key's items are still handled one at a time, in the order they were pushed
+    #[test]
+    fn one_in_flight_per_key() {
+        const N_KEYS: u64 = 3;
+        const N_ITEMS: u64 = 60;
+        let buffer = KeyedBuffer::new(4, 1);
+        let in_flight: Vec<_> = (0..N_KEYS).map(|_| AtomicUsize::new(0)).collect();
+        let handled: Vec<_> = (0..N_KEYS).map(|_| Mutex::new(Vec::new())).collect();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| while let Ok((key, item)) = buffer.pop() {
+                    let key = key.unwrap() as usize;
+                    assert_eq!(in_flight[key].fetch_add(1, SeqCst), 0);
+                    thread::sleep(Duration::from_micros(100));
+                    handled[key].lock().unwrap().push(item);
+                    in_flight[key].fetch_sub(1, SeqCst);
+                    buffer.done(Some(key as u64));
+                });
+            }
+            for item in 0..N_ITEMS { buffer.push(Some(item % N_KEYS), item).unwrap(); }
+            buffer.close();
+        });
+        for (key, handled) in handled.into_iter().enumerate() {
+            let expected: Vec<_> = (0..N_ITEMS).filter(|item| item % N_KEYS == key as u64).collect();
+            assert_eq!(handled.into_inner().unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, Condvar},
+};
+use crate::{Locker, Queue, Cancelled, stats::{Side, Stats}, echo::{self, Contents, Echoed}};
+
+// how urgent an item is; consumers take items from higher lanes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane { High, Normal, Low }
+
+const N_LANES: usize = 3;
+
+struct Lanes {
+    items: [VecDeque<isize>; N_LANES],
+    // how many pops in a row have taken an item from a higher lane while this one had items
+    n_passed_over: [usize; N_LANES],
+}
+impl Lanes {
+
+    fn n_items(&self) -> usize { self.items.iter().map(VecDeque::len).sum() }
+
+    // must not be empty
+    fn pop(&mut self, quota: Option<usize>) -> isize {
+        // a lane which has been passed over `quota` times has items, since only popping from it resets its count
+        let starved = quota.and_then(|quota| (0..N_LANES).find(|&lane| self.n_passed_over[lane] >= quota));
+        let lane = starved.unwrap_or_else(|| (0..N_LANES).find(|&lane| !self.items[lane].is_empty()).unwrap());
+        for lower in lane + 1..N_LANES {
+            if !self.items[lower].is_empty() { self.n_passed_over[lower] += 1; }
+        }
+        self.n_passed_over[lane] = 0;
+        self.items[lane].pop_front().unwrap()
+    }
+}
+
+/* Bounded buffer with a few priority lanes instead of a single FIFO: `push_with_priority(item, Lane::High, ...)`, and
+pops always take the oldest item of the highest lane which has any. `Queue::push` pushes to `Lane::Normal`.
+The lanes share the capacity, so a buffer full of low-priority items blocks high-priority pushes too.
+With only the lanes' order, a steady stream of high-priority items starves the lower lanes completely; with a quota
+(`with_quota(n)`), a lane which has been passed over `n` times in a row while it had items gets the next pop (the
+highest such lane, if there are several), so no lane with items waits forever.
+Otherwise synchronized like `DequeBoundedBuffer`.
+*/
+pub struct LanedBoundedBuffer {
+    lanes: Mutex<Lanes>,
+    capacity: usize,
+    quota: Option<usize>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    stats: Stats,
+    // print the lanes' contents after every operation (once the lock is released; see `SyncedBoundedBuffer`)
+    echo: bool,
+}
+impl LanedBoundedBuffer {
+
+    pub fn new(capacity: usize, echo: bool) -> Self {
+        assert!(capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        LanedBoundedBuffer {
+            lanes: Mutex::new(Lanes { items: Default::default(), n_passed_over: [0; N_LANES] }),
+            capacity,
+            quota: None,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            stats: Stats::default(),
+            echo,
+        }
+    }
+
+    pub fn with_quota(mut self, quota: usize) -> Self {
+        assert!(quota > 0, "a quota of 0 would always pop from the lowest lane with items");
+        self.quota = Some(quota);
+        self
+    }
+
+    pub fn push_with_priority(&self, item: isize, lane: Lane, locker: &mut Locker) -> Result<(), Cancelled> {
+        locker.check()?;
+        let mut lanes = locker.lock(&self.lanes);
+        let mut n_waits = 0;
+        while lanes.n_items() == self.capacity {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            self.stats.wait();
+            let waiting = self.stats.start_waiting(Side::Producer);
+            lanes = match locker.poll_interval() {
+                None => self.not_full.wait(lanes).unwrap(),
+                Some(interval) => self.not_full.wait_timeout(lanes, interval).unwrap().0,
+            };
+            self.stats.stop_waiting(Side::Producer, waiting);
+            n_waits += 1;
+        }
+
+        lanes.items[lane as usize].push_back(item);
+        self.stats.occupancy(lanes.n_items());
+        let echoed = self.echo.then(|| {
+            let items = lanes.items.iter().map(|lane| lane.iter().copied().collect()).collect();
+            Echoed::new(Side::Producer, locker.id, Contents::Lanes(items), self.capacity)
+        });
+
+        self.stats.wake();
+        self.not_empty.notify_all();
+        self.stats.op();
+        drop(lanes);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(())
+    }
+}
+impl Queue for LanedBoundedBuffer {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        self.push_with_priority(item, Lane::Normal, locker)
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        locker.check()?;
+        let mut lanes = locker.lock(&self.lanes);
+        let mut n_waits = 0;
+        while lanes.n_items() == 0 {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            self.stats.wait();
+            let waiting = self.stats.start_waiting(Side::Consumer);
+            lanes = match locker.poll_interval() {
+                None => self.not_empty.wait(lanes).unwrap(),
+                Some(interval) => self.not_empty.wait_timeout(lanes, interval).unwrap().0,
+            };
+            self.stats.stop_waiting(Side::Consumer, waiting);
+            n_waits += 1;
+        }
+
+        let item = lanes.pop(self.quota);
+        let echoed = self.echo.then(|| {
+            let items = lanes.items.iter().map(|lane| lane.iter().copied().collect()).collect();
+            Echoed::new(Side::Consumer, locker.id, Contents::Lanes(items), self.capacity)
+        });
+
+        self.stats.wake();
+        self.not_full.notify_all();
+        self.stats.op();
+        drop(lanes);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(item)
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { self.capacity }
+    fn n_items(&self) -> usize { self.lanes.lock().unwrap().n_items() }
+    // highest lane first, oldest first within each lane
+    fn snapshot(&self) -> Vec<isize> { self.lanes.lock().unwrap().items.iter().flatten().copied().collect() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+    use crate::Strategy;
+    use super::*;
+
+    fn locker() -> Locker { Locker::new(Strategy::Block, "thread", 0) }
+
+    fn fill(buffer: &LanedBoundedBuffer, items: &[(isize, Lane)], locker: &mut Locker) {
+        for &(item, lane) in items { buffer.push_with_priority(item, lane, locker).unwrap(); }
+    }
+
+    // pops take the oldest item of the highest lane which has any, and `push` goes to the normal lane
+    #[test]
+    fn pops_higher_lanes_first() {
+        let (buffer, mut locker) = (LanedBoundedBuffer::new(8, false), locker());
+        let items = [(1, Lane::Low), (2, Lane::Normal), (3, Lane::High), (4, Lane::Low), (5, Lane::High)];
+        fill(&buffer, &items, &mut locker);
+        buffer.push(6, &mut locker).unwrap();
+        assert_eq!(buffer.snapshot(), [3, 5, 2, 6, 1, 4]);
+        let popped: Vec<isize> = (0..6).map(|_| buffer.pop(&mut locker).unwrap()).collect();
+        assert_eq!(popped, [3, 5, 2, 6, 1, 4]);
+    }
+
+    // without a quota, the low lane waits for the higher ones to empty
+    #[test]
+    fn starves_lower_lanes_without_a_quota() {
+        let (buffer, mut locker) = (LanedBoundedBuffer::new(8, false), locker());
+        fill(&buffer, &[(9, Lane::Low), (1, Lane::High), (2, Lane::High), (3, Lane::High)], &mut locker);
+        let popped: Vec<isize> = (0..4).map(|_| buffer.pop(&mut locker).unwrap()).collect();
+        assert_eq!(popped, [1, 2, 3, 9]);
+    }
+
+    // with a quota of 2, a lane passed over twice in a row gets the next pop, and then its count starts again
+    #[test]
+    fn quotas_let_passed_over_lanes_through() {
+        let (buffer, mut locker) = (LanedBoundedBuffer::new(8, false).with_quota(2), locker());
+        fill(&buffer, &[(8, Lane::Low), (9, Lane::Low)], &mut locker);
+        fill(&buffer, &(1..=5).map(|item| (item, Lane::High)).collect::<Vec<_>>(), &mut locker);
+        let popped: Vec<isize> = (0..7).map(|_| buffer.pop(&mut locker).unwrap()).collect();
+        assert_eq!(popped, [1, 2, 8, 3, 4, 9, 5]);
+    }
+
+    // a lane only counts as passed over while it has items
+    #[test]
+    fn quotas_only_count_lanes_with_items() {
+        let (buffer, mut locker) = (LanedBoundedBuffer::new(8, false).with_quota(2), locker());
+        fill(&buffer, &[(1, Lane::High), (2, Lane::High), (3, Lane::High)], &mut locker);
+        assert_eq!(buffer.pop(&mut locker), Ok(1));
+        assert_eq!(buffer.pop(&mut locker), Ok(2));
+        fill(&buffer, &[(9, Lane::Low), (4, Lane::High)], &mut locker);
+        let popped: Vec<isize> = (0..3).map(|_| buffer.pop(&mut locker).unwrap()).collect();
+        assert_eq!(popped, [3, 4, 9]);
+    }
+
+    #[test]
+    #[should_panic(expected = "a quota of 0")]
+    fn quotas_must_be_positive() { let _ = LanedBoundedBuffer::new(8, false).with_quota(0); }
+
+    // the lanes share the capacity, so a buffer full of low-priority items blocks a high-priority push
+    #[test]
+    fn lanes_share_the_capacity() {
+        let (buffer, mut locker) = (LanedBoundedBuffer::new(2, false), locker());
+        fill(&buffer, &[(1, Lane::Low), (2, Lane::Low)], &mut locker);
+        thread::scope(|scope| {
+            scope.spawn(|| buffer.push_with_priority(3, Lane::High, &mut Locker::new(Strategy::Block, "thread", 1)));
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(buffer.snapshot(), [1, 2]);
+            assert_eq!(buffer.pop(&mut locker), Ok(1));
+        });
+        assert_eq!(buffer.snapshot(), [3, 2]);
+    }
+}
@@ -0,0 +1,96 @@
+// Without the `std` feature only the ring buffers, the spinlock-based buffer, and the cooperative driver are built,
+// which need nothing but `core` and `alloc`; everything that needs threads or an OS to sleep on is behind it.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod ring;
+pub mod spin;
+pub mod cooperative;
+
+#[cfg(feature = "std")]
+pub mod backoff;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod lock;
+#[cfg(feature = "std")]
+mod queue;
+#[cfg(feature = "std")]
+mod cancel;
+#[cfg(feature = "std")]
+mod handle;
+#[cfg(feature = "std")]
+mod condvar;
+#[cfg(feature = "std")]
+pub mod eventcount;
+#[cfg(feature = "std")]
+pub mod deque;
+#[cfg(feature = "std")]
+pub mod sharded;
+#[cfg(feature = "std")]
+pub mod lanes;
+#[cfg(feature = "std")]
+pub mod waiters;
+#[cfg(feature = "std")]
+pub mod swap;
+#[cfg(feature = "std")]
+pub mod closable;
+#[cfg(feature = "std")]
+pub mod broadcast;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod runner;
+#[cfg(feature = "std")]
+pub mod heartbeat;
+#[cfg(feature = "std")]
+pub mod dedup;
+#[cfg(feature = "std")]
+pub mod reorder;
+#[cfg(feature = "std")]
+pub mod semaphore;
+#[cfg(feature = "std")]
+pub mod credit;
+#[cfg(feature = "std")]
+pub mod keyed;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod recycle;
+#[cfg(feature = "std")]
+pub mod arena;
+#[cfg(feature = "std")]
+pub mod slots;
+#[cfg(feature = "std")]
+pub mod tap;
+#[cfg(feature = "std")]
+pub mod echo;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod futex;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "rayon")]
+mod par;
+#[cfg(feature = "tokio")]
+pub mod bridge;
+
+pub use ring::{BoundedBuffer, RingBuffer};
+pub use spin::{SpinLock, SpinBoundedBuffer};
+pub use cooperative::{CooperativeDriver, Step};
+#[cfg(feature = "std")]
+pub use queue::{Queue, Strategy, Locker, Hooks, Hooked};
+#[cfg(feature = "std")]
+pub use cancel::{CancellationToken, Cancelled};
+#[cfg(feature = "std")]
+pub use handle::{Producer, Consumer, Observer};
+#[cfg(feature = "std")]
+pub use stats::{DropReason, Side};
+#[cfg(feature = "std")]
+pub use condvar::{SyncedBoundedBuffer, SyncedBoundedBufferBuilder, Overflow, Wake, Bias};
+#[cfg(feature = "std")]
+pub use closable::{ClosableBuffer, PushError, PopError, OnClose, Pass, Checkpoint, LeaseGuard};
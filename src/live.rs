@@ -0,0 +1,285 @@
+/* Live events for `run --ws-port`, for a browser to draw the run as it goes: a page at `/`, and a WebSocket which sends
+it, every `INTERVAL`, a JSON message with each buffer's occupancy and the pushes and pops since the last one:
+
+    {"secs": 1.2, "buffers": [{"name": "condvar", "capacity": 30, "n_items": 12, "n_missed": 0,
+        "events": [{"op": "push", "id": 0, "item": 5, "secs": 1.19}, ...]}]}
+
+Like a tap, the buffers send their events through a channel holding up to `BACKLOG` of them, so at most that many go
+in a message; the rest are missed (and counted) rather than holding up the producers and consumers. An event is sent
+just after its push or pop returns, so a pop can come just before the push of the same item.
+*/
+
+use std::{
+    io::{self, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{Arc, Mutex, mpsc::{self, Receiver, SyncSender}, atomic::{AtomicU64, Ordering::Relaxed}},
+    thread,
+    time::{Duration, Instant},
+};
+use serde::Serialize;
+use rpc::{Hooked, Hooks, Locker, Queue};
+use crate::health;
+
+// how often a message goes out, and the most events each buffer's can hold
+const INTERVAL: Duration = Duration::from_millis(100);
+const BACKLOG: usize = 1000;
+// how long a browser gets to take a message, before it's dropped as gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+const PAGE: &str = include_str!("live.html");
+// what the key a browser sends is hashed with, to show the server speaks WebSocket (RFC 6455)
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    Push,
+    Pop,
+}
+
+#[derive(Clone, Copy, Serialize)]
+struct Event {
+    op: Op,
+    // the producer's index for a push, the consumer's for a pop
+    id: u32,
+    item: isize,
+    // since the run started
+    secs: f64,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    secs: f64,
+    buffers: Vec<BufferMessage<'a>>,
+}
+
+#[derive(Serialize)]
+struct BufferMessage<'a> {
+    name: &'a str,
+    capacity: usize,
+    n_items: usize,
+    // the events left out since the last message, for want of room
+    n_missed: u64,
+    events: Vec<Event>,
+}
+
+// a buffer the page is shown, and where its events come from
+struct Watched {
+    name: String,
+    queue: Arc<dyn Queue>,
+    events: Receiver<Event>,
+    n_missed: Arc<AtomicU64>,
+}
+
+pub struct Live {
+    start: Instant,
+    buffers: Mutex<Vec<Watched>>,
+    clients: Mutex<Vec<TcpStream>>,
+}
+impl Live {
+
+    // `queue`, sending its pushes and pops to the browsers as the buffer called `name`
+    pub fn watch(&self, name: String, queue: Arc<dyn Queue>) -> Arc<dyn Queue> {
+        let (events, receiver) = mpsc::sync_channel(BACKLOG);
+        let n_missed = Arc::new(AtomicU64::new(0));
+        self.buffers.lock().unwrap().push(Watched {
+            name, queue: queue.clone(), events: receiver, n_missed: n_missed.clone(),
+        });
+        Arc::new(Hooked::new(queue, Streaming { start: self.start, events, n_missed }))
+    }
+
+    // send every browser what's happened since the last time
+    fn broadcast(&self) {
+        let buffers = self.buffers.lock().unwrap();
+        let message = Message {
+            secs: self.start.elapsed().as_secs_f64(),
+            buffers: buffers.iter().map(|buffer| BufferMessage {
+                name: &buffer.name, capacity: buffer.queue.capacity(), n_items: buffer.queue.n_items(),
+                n_missed: buffer.n_missed.swap(0, Relaxed), events: buffer.events.try_iter().collect(),
+            }).collect(),
+        };
+        let frame = text_frame(&serde_json::to_string(&message).expect("events serialize"));
+        self.clients.lock().unwrap().retain_mut(|client| client.write_all(&frame).is_ok());
+    }
+}
+
+// listen on `port` on the loopback, serving the page and its events for as long as the process runs
+pub fn serve(port: u16) -> io::Result<Arc<Live>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let live = Arc::new(Live { start: Instant::now(), buffers: Mutex::default(), clients: Mutex::default() });
+    let accepting = live.clone();
+    crate::spawn("live".to_string(), move || for stream in listener.incoming().flatten() {
+        // a browser which hangs up early is its own problem
+        let _ = accept(stream, &accepting);
+    });
+    let broadcasting = live.clone();
+    crate::spawn("live broadcast".to_string(), move || loop {
+        thread::sleep(INTERVAL);
+        broadcasting.broadcast();
+    });
+    Ok(live)
+}
+
+// serve the page, or take the browser on as a client if it's asking for the events
+fn accept(mut stream: TcpStream, live: &Live) -> io::Result<()> {
+    let request = health::read_request(&mut stream)?;
+    let (method, path) = health::method_and_path(&request);
+    let key = request.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("sec-websocket-key").then(|| value.trim().to_string())
+    });
+    match (method, path, key) {
+        ("GET", "/events", Some(key)) => {
+            let accept = base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept,
+            )?;
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            live.clients.lock().unwrap().push(stream);
+            Ok(())
+        },
+        ("GET" | "HEAD", "/", _) =>
+            health::respond(&mut stream, method, "200 OK", "text/html; charset=utf-8", PAGE.as_bytes()),
+        ("GET" | "HEAD", ..) => health::respond(&mut stream, method, "404 Not Found", "text/plain", b"not found\n"),
+        _ => health::respond(&mut stream, method, "405 Method Not Allowed", "text/plain", b"method not allowed\n"),
+    }
+}
+
+// a final, unmasked text frame, as a server sends
+fn text_frame(text: &str) -> Vec<u8> {
+    let mut frame = vec![0x81];
+    match text.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(n as u16).to_be_bytes());
+        },
+        n => {
+            frame.push(127);
+            frame.extend_from_slice(&(n as u64).to_be_bytes());
+        },
+    }
+    frame.extend_from_slice(text.as_bytes());
+    frame
+}
+
+// just for the handshake, which doesn't need it to be secure
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut padded = bytes.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 { padded.push(0); }
+    padded.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() { w[i] = u32::from_be_bytes(word.try_into().unwrap()); }
+        for i in 16..80 { w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1); }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20  => ((b & c) | (!b & d), 0x5A827999),
+                20..40 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _      => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) { *h = h.wrapping_add(x); }
+    }
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) { bytes.copy_from_slice(&h.to_be_bytes()); }
+    digest
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            let c = if i <= chunk.len() { ALPHABET[(n >> (18 - 6 * i) & 63) as usize] } else { b'=' };
+            text.push(c as char);
+        }
+    }
+    text
+}
+
+// sending a buffer's pushes and pops to `live`
+struct Streaming {
+    start: Instant,
+    events: SyncSender<Event>,
+    n_missed: Arc<AtomicU64>,
+}
+impl Streaming {
+    fn send(&self, op: Op, id: u32, item: isize) {
+        let event = Event { op, id, item, secs: self.start.elapsed().as_secs_f64() };
+        if self.events.try_send(event).is_err() { self.n_missed.fetch_add(1, Relaxed); }
+    }
+}
+impl Hooks for Streaming {
+    // if cancelled partway, those pushed so far are sent
+    fn after_push(&self, pushed: &[isize], _: &[isize], locker: &Locker) {
+        for &item in pushed { self.send(Op::Push, locker.id, item); }
+    }
+    fn after_pop(&self, popped: &[isize], locker: &Locker) {
+        for &item in popped { self.send(Op::Pop, locker.id, item); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String { bytes.iter().map(|byte| format!("{:02x}", byte)).collect() }
+
+    // FIPS 180's examples, including one spanning two blocks
+    #[test]
+    fn hashes_sha1() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            hex(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+        );
+    }
+
+    // RFC 4648's examples, padded
+    #[test]
+    fn encodes_base64() {
+        let encoded = ["", "f", "fo", "foo", "foob", "fooba", "foobar"].map(|text| base64(text.as_bytes()));
+        assert_eq!(encoded, ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "Zm9vYmE=", "Zm9vYmFy"]);
+    }
+
+    // a length under 126 in the second byte, then up to 16 bits after a 126, then 64 after a 127
+    #[test]
+    fn frames_text() {
+        assert_eq!(text_frame("hi"), [0x81, 2, b'h', b'i']);
+        let frame = text_frame(&"x".repeat(126));
+        assert_eq!((&frame[..4], frame.len()), (&[0x81, 126, 0, 126][..], 4 + 126));
+        let frame = text_frame(&"x".repeat(1 << 16));
+        assert_eq!((&frame[..10], frame.len()), (&[0x81, 127, 0, 0, 0, 0, 0, 1, 0, 0][..], 10 + (1 << 16)));
+    }
+
+    // the handshake in RFC 6455's example is accepted with the key it gives
+    #[test]
+    fn accepts_the_handshake() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(
+            b"GET /events HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        ).unwrap();
+        let live = Live { start: Instant::now(), buffers: Mutex::default(), clients: Mutex::default() };
+        accept(listener.accept().unwrap().0, &live).unwrap();
+        assert_eq!(live.clients.lock().unwrap().len(), 1);
+
+        let head: Vec<_> = BufReader::new(client).lines().map(Result::unwrap).take_while(|line| !line.is_empty())
+            .collect();
+        assert_eq!(head[0], "HTTP/1.1 101 Switching Protocols");
+        assert!(head.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()));
+    }
+}
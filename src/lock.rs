@@ -0,0 +1,323 @@
+use std::{
+    hint,
+    ops::{Deref, DerefMut},
+    sync::{
+        Condvar, Mutex, MutexGuard, PoisonError, TryLockError,
+        atomic::{AtomicU32, AtomicU64, Ordering::{Acquire, Relaxed, Release}},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use crate::spin::{SpinLock, SpinLockGuard};
+
+/* The locks and condition variables a buffer can be synchronized with, so that the same buffer can be built on std's,
+parking_lot's (with the `parking_lot` feature), `SpinLock` and `SpinCondvar`, or loom's (under `--cfg loom`), instead
+of being written out again for each. `RawLock<T>` is a mutex holding a `T`, and `RawCondvar` waits on one of its
+guards; a condvar names the lock it works with, `Lock<T>`, so that a buffer generic over `C: RawCondvar` needs no
+other parameter. Poisoning is left out: a thread which panics while holding a lock ends the program anyway, so taking a
+poisoned lock panics.
+*/
+pub trait RawLock<T>: Send + Sync {
+    type Guard<'a>: DerefMut<Target = T> where Self: 'a;
+
+    fn new(data: T) -> Self;
+    fn lock(&self) -> Self::Guard<'_>;
+    // `None` if it's held
+    fn try_lock(&self) -> Option<Self::Guard<'_>>;
+    // for dumping the state of a stalled program, even if a thread panicked while holding the lock
+    fn lock_anyway(&self) -> Self::Guard<'_> { self.lock() }
+}
+
+pub trait RawCondvar: Default + Send + Sync + 'static {
+    type Lock<T: Send>: RawLock<T>;
+
+    // release the lock while waiting for a notification, then take it again; may also return spuriously
+    fn wait<'a, T: Send + 'a>(&self, guard: Guard<'a, Self, T>) -> Guard<'a, Self, T>;
+    // `wait`, but for at most `timeout`
+    fn wait_timeout<'a, T: Send + 'a>(&self, guard: Guard<'a, Self, T>, timeout: Duration) -> Guard<'a, Self, T>;
+    fn notify_one(&self);
+    fn notify_all(&self);
+}
+
+// the guard of the lock condvar `C` works with
+pub type Guard<'a, C, T> = <<C as RawCondvar>::Lock<T> as RawLock<T>>::Guard<'a>;
+
+impl<T: Send> RawLock<T> for Mutex<T> {
+    type Guard<'a> = MutexGuard<'a, T> where T: 'a;
+
+    fn new(data: T) -> Self { Mutex::new(data) }
+    fn lock(&self) -> MutexGuard<'_, T> { Mutex::lock(self).unwrap() }
+    fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        match Mutex::try_lock(self) {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::WouldBlock) => None,
+            Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+        }
+    }
+    fn lock_anyway(&self) -> MutexGuard<'_, T> { Mutex::lock(self).unwrap_or_else(PoisonError::into_inner) }
+}
+impl RawCondvar for Condvar {
+    type Lock<T: Send> = Mutex<T>;
+
+    fn wait<'a, T: Send + 'a>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        Condvar::wait(self, guard).unwrap()
+    }
+    fn wait_timeout<'a, T: Send + 'a>(&self, guard: MutexGuard<'a, T>, timeout: Duration) -> MutexGuard<'a, T> {
+        Condvar::wait_timeout(self, guard, timeout).unwrap().0
+    }
+    fn notify_one(&self) { Condvar::notify_one(self); }
+    fn notify_all(&self) { Condvar::notify_all(self); }
+}
+
+impl<T: Send> RawLock<T> for SpinLock<T> {
+    type Guard<'a> = SpinLockGuard<'a, T> where T: 'a;
+
+    fn new(data: T) -> Self { SpinLock::new(data) }
+    fn lock(&self) -> SpinLockGuard<'_, T> { SpinLock::lock(self) }
+    fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> { SpinLock::try_lock(self) }
+}
+
+/* A condition variable for `SpinLock` which never parks the thread either: a waiter releases the lock, and busy-waits
+until the generation number it read while holding it is bumped by a notification (or the timeout passes), then takes
+the lock again. After `SPINS_BEFORE_YIELD` spins, it yields the CPU between checks instead, so that a long wait
+doesn't starve the thread it's waiting on when there are more threads than cores. As waiters read the generation
+under the lock, and the condition they wait for only changes under it, no notification can come between their check
+and their wait unseen. Every notification wakes every waiter.
+*/
+#[derive(Default)]
+pub struct SpinCondvar { generation: AtomicU32 }
+const SPINS_BEFORE_YIELD: u32 = 1 << 7;
+impl SpinCondvar {
+    // wait for a notification after `generation`, or until `deadline`
+    fn wait_until(&self, generation: u32, deadline: Option<Instant>) {
+        let mut n_spins = 0;
+        while self.generation.load(Acquire) == generation && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+            if n_spins < SPINS_BEFORE_YIELD {
+                n_spins += 1;
+                hint::spin_loop();
+            }
+            else { thread::yield_now(); }
+        }
+    }
+}
+impl RawCondvar for SpinCondvar {
+    type Lock<T: Send> = SpinLock<T>;
+
+    fn wait<'a, T: Send + 'a>(&self, guard: SpinLockGuard<'a, T>) -> SpinLockGuard<'a, T> {
+        let generation = self.generation.load(Acquire);
+        let lock = guard.unlock();
+        self.wait_until(generation, None);
+        lock.lock()
+    }
+    fn wait_timeout<'a, T: Send + 'a>(&self, guard: SpinLockGuard<'a, T>, timeout: Duration) -> SpinLockGuard<'a, T> {
+        let (generation, deadline) = (self.generation.load(Acquire), Instant::now() + timeout);
+        let lock = guard.unlock();
+        self.wait_until(generation, Some(deadline));
+        lock.lock()
+    }
+    fn notify_one(&self) { self.notify_all(); }
+    fn notify_all(&self) { self.generation.fetch_add(1, Release); }
+}
+
+#[cfg(feature = "parking_lot")]
+impl<T: Send> RawLock<T> for parking_lot::Mutex<T> {
+    type Guard<'a> = parking_lot::MutexGuard<'a, T> where T: 'a;
+
+    fn new(data: T) -> Self { parking_lot::Mutex::new(data) }
+    fn lock(&self) -> parking_lot::MutexGuard<'_, T> { parking_lot::Mutex::lock(self) }
+    fn try_lock(&self) -> Option<parking_lot::MutexGuard<'_, T>> { parking_lot::Mutex::try_lock(self) }
+}
+#[cfg(feature = "parking_lot")]
+impl RawCondvar for parking_lot::Condvar {
+    type Lock<T: Send> = parking_lot::Mutex<T>;
+
+    fn wait<'a, T: Send + 'a>(&self, mut guard: parking_lot::MutexGuard<'a, T>) -> parking_lot::MutexGuard<'a, T> {
+        parking_lot::Condvar::wait(self, &mut guard);
+        guard
+    }
+    fn wait_timeout<'a, T: Send + 'a>(
+        &self, mut guard: parking_lot::MutexGuard<'a, T>, timeout: Duration,
+    ) -> parking_lot::MutexGuard<'a, T> {
+        parking_lot::Condvar::wait_for(self, &mut guard, timeout);
+        guard
+    }
+    fn notify_one(&self) { parking_lot::Condvar::notify_one(self); }
+    fn notify_all(&self) { parking_lot::Condvar::notify_all(self); }
+}
+
+#[cfg(loom)]
+impl<T: Send> RawLock<T> for loom::sync::Mutex<T> {
+    type Guard<'a> = loom::sync::MutexGuard<'a, T> where T: 'a;
+
+    fn new(data: T) -> Self { loom::sync::Mutex::new(data) }
+    fn lock(&self) -> loom::sync::MutexGuard<'_, T> { loom::sync::Mutex::lock(self).unwrap() }
+    fn try_lock(&self) -> Option<loom::sync::MutexGuard<'_, T>> { loom::sync::Mutex::try_lock(self).ok() }
+}
+#[cfg(loom)]
+impl RawCondvar for loom::sync::Condvar {
+    type Lock<T: Send> = loom::sync::Mutex<T>;
+
+    fn wait<'a, T: Send + 'a>(&self, guard: loom::sync::MutexGuard<'a, T>) -> loom::sync::MutexGuard<'a, T> {
+        loom::sync::Condvar::wait(self, guard).unwrap()
+    }
+    fn wait_timeout<'a, T: Send + 'a>(
+        &self, guard: loom::sync::MutexGuard<'a, T>, timeout: Duration,
+    ) -> loom::sync::MutexGuard<'a, T> {
+        loom::sync::Condvar::wait_timeout(self, guard, timeout).unwrap().0
+    }
+    fn notify_one(&self) { loom::sync::Condvar::notify_one(self); }
+    fn notify_all(&self) { loom::sync::Condvar::notify_all(self); }
+}
+
+// how many buckets `HoldTimes` has: the last holds every time of 2^(N - 2) ns (about 4.6 minutes) or more
+const N_HOLD_BUCKETS: usize = 40;
+
+/* How long a lock was held each time, for finding the critical sections which take too long (e.g. printing while
+holding it), in power-of-two buckets of nanoseconds: bucket `b` counts the holds of 2^(b - 1) ns up to 2^b ns. So
+quantiles are only known to within a factor of 2, but recording one is just two atomic operations, without a lock.
+*/
+pub struct HoldTimes {
+    buckets: [AtomicU64; N_HOLD_BUCKETS],
+    max_nanos: AtomicU64,
+}
+impl Default for HoldTimes {
+    fn default() -> Self {
+        HoldTimes { buckets: std::array::from_fn(|_| AtomicU64::new(0)), max_nanos: AtomicU64::new(0) }
+    }
+}
+impl HoldTimes {
+
+    pub fn record(&self, held: Duration) {
+        let nanos = held.as_nanos().min(u64::MAX as u128) as u64;
+        let bucket = ((u64::BITS - nanos.leading_zeros()) as usize).min(N_HOLD_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Relaxed);
+        self.max_nanos.fetch_max(nanos, Relaxed);
+    }
+
+    pub fn n_holds(&self) -> u64 { self.buckets.iter().map(|n| n.load(Relaxed)).sum() }
+    pub fn max(&self) -> Duration { Duration::from_nanos(self.max_nanos.load(Relaxed)) }
+
+    // how long the lock was held at most, for the fraction `q` of the holds: the top of the bucket that falls in, but
+    // no more than the longest
+    pub fn quantile(&self, q: f64) -> Duration {
+        let n_below = (q * self.n_holds() as f64).ceil() as u64;
+        let mut n_so_far = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            n_so_far += n.load(Relaxed);
+            if n_so_far >= n_below.max(1) { return Duration::from_nanos(1 << bucket).min(self.max()); }
+        }
+        self.max()
+    }
+}
+
+/* A lock's guard which records how long the lock was held in `times`, once dropped, or `release`d to wait on a
+condvar (which releases the lock), after which `Held::new` takes up the guard the wait gives back. Without `times`,
+it's just the guard, and doesn't look at the clock.
+*/
+pub struct Held<'a, G> {
+    // only `None` once released
+    guard: Option<G>,
+    since: Option<Instant>,
+    times: Option<&'a HoldTimes>,
+}
+impl<'a, G> Held<'a, G> {
+
+    pub fn new(guard: G, times: Option<&'a HoldTimes>) -> Self {
+        Held { guard: Some(guard), since: times.map(|_| Instant::now()), times }
+    }
+
+    pub fn release(mut self) -> G {
+        self.record();
+        self.guard.take().unwrap()
+    }
+
+    fn record(&self) {
+        if let (Some(times), Some(since)) = (self.times, self.since) { times.record(since.elapsed()); }
+    }
+}
+impl<G: Deref> Deref for Held<'_, G> {
+    type Target = G::Target;
+    fn deref(&self) -> &G::Target { self.guard.as_ref().unwrap() }
+}
+impl<G: DerefMut> DerefMut for Held<'_, G> {
+    fn deref_mut(&mut self) -> &mut G::Target { self.guard.as_mut().unwrap() }
+}
+impl<G> Drop for Held<'_, G> {
+    fn drop(&mut self) { if self.guard.is_some() { self.record(); } }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::{sync::Arc, thread};
+    use crate::{Locker, Queue, Strategy, SyncedBoundedBuffer};
+    use super::*;
+
+    // items passed one at a time through a buffer built on `C`, so that both sides keep waiting on its condvars
+    fn handoff<C: RawCondvar>() {
+        const N_ITEMS: isize = 100;
+        let buffer = Arc::new(SyncedBoundedBuffer::builder().capacity(1).build_on::<C>());
+        let consumer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                let mut locker = Locker::new(Strategy::Block, "consumer", 0);
+                (0..N_ITEMS).map(|_| buffer.pop(&mut locker).unwrap()).collect::<Vec<_>>()
+            })
+        };
+        let mut locker = Locker::new(Strategy::Block, "producer", 0);
+        for item in 0..N_ITEMS { buffer.push(item, &mut locker).unwrap(); }
+        assert_eq!(consumer.join().unwrap(), (0..N_ITEMS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn std_handoff() { handoff::<Condvar>(); }
+
+    #[test]
+    fn spin_handoff() { handoff::<SpinCondvar>(); }
+
+    #[cfg(feature = "parking_lot")]
+    #[test]
+    fn parking_lot_handoff() { handoff::<parking_lot::Condvar>(); }
+
+    // a hold counts from taking the guard until dropping or releasing it, and quantiles are the tops of their buckets
+    #[test]
+    fn hold_times() {
+        let (times, mutex) = (HoldTimes::default(), Mutex::new(0));
+        for _ in 0..9 { drop(Held::new(RawLock::lock(&mutex), Some(&times))); }
+        let mut held = Held::new(RawLock::lock(&mutex), Some(&times));
+        *held += 1;
+        thread::sleep(Duration::from_millis(2));
+        drop(held.release());
+        assert_eq!((times.n_holds(), *RawLock::lock(&mutex)), (10, 1));
+        assert!(times.quantile(0.5) < Duration::from_millis(1));
+        assert!(times.max() >= Duration::from_millis(2) && times.quantile(1.0) == times.max());
+        drop(Held::new(RawLock::lock(&mutex), None));
+        assert_eq!(times.n_holds(), 10);
+    }
+}
+
+// run with `RUSTFLAGS="--cfg loom" cargo test --release lock`
+#[cfg(all(test, loom))]
+mod tests {
+    use loom::{sync::{Arc, Condvar}, thread};
+    use crate::{Locker, Queue, Strategy, SyncedBoundedBuffer};
+
+    // the condvar buffer on loom's mutex and condvar, passing items through one slot; a lost wakeup leaves one side
+    // waiting forever, which loom reports as a deadlock
+    #[test]
+    fn handoff() {
+        loom::model(|| {
+            const N_ITEMS: isize = 2;
+            let buffer = Arc::new(SyncedBoundedBuffer::builder().capacity(1).build_on::<Condvar>());
+            let consumer = {
+                let buffer = buffer.clone();
+                thread::spawn(move || {
+                    let mut locker = Locker::new(Strategy::Block, "consumer", 0);
+                    for item in 0..N_ITEMS { assert_eq!(buffer.pop(&mut locker).unwrap(), item); }
+                })
+            };
+            let mut locker = Locker::new(Strategy::Block, "producer", 0);
+            for item in 0..N_ITEMS { buffer.push(item, &mut locker).unwrap(); }
+            consumer.join().unwrap();
+        });
+    }
+}
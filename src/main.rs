@@ -1,121 +1,2224 @@
+mod aggregate;
+#[cfg(feature = "alloc-audit")]
+mod audit;
+mod breakdown;
+mod cli;
+mod controller;
+mod health;
+mod journey;
+mod live;
+mod numa;
+mod orchestrate;
+mod pacer;
+#[cfg(all(feature = "perf", target_os = "linux"))]
+mod perf;
+mod progress;
+mod queueing;
+mod report;
+#[cfg(feature = "server")]
+mod server;
+mod switches;
+
 use std::{
-    sync::{Mutex, Condvar, Arc},
+    cell::Cell,
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, IsTerminal},
+    iter,
+    mem,
+    net::{Ipv4Addr, Shutdown, TcpListener},
+    ops::Deref,
+    sync::{Arc, Mutex, mpsc::{self, Receiver}, atomic::{AtomicIsize, AtomicU64, AtomicUsize, Ordering::Relaxed}},
     env,
-    thread,
-    fmt::{self, Display},
+    panic,
+    process::{self, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use rpc::{
+    CancellationToken, ClosableBuffer, Consumer, CooperativeDriver, Hooked, OnClose, Producer, Queue, Side, Step,
+    SyncedBoundedBuffer,
+    eventcount::EventCountBoundedBuffer,
+    dedup::Dedup,
+    heartbeat::{Activity, Heartbeat, Heartbeats},
+    pool::{self, TaskPool, TaskStats},
+    recycle::Recycler,
+    arena::{ArenaStats, Arenas},
+    slots::SlotBuffer,
+    lock::SpinCondvar,
+    deque::DequeBoundedBuffer,
+    sharded::ShardedBoundedBuffer,
+    reorder::Reorder,
+    runner::Runner,
+    semaphore::Semaphore,
+    tap::{Sampling, Tap, Tapped},
+    echo::EchoWriter,
+    trace::{Op, Recorder, Trace},
+    swap::SwapBoundedBuffer,
+    waiters::WaiterQueueBoundedBuffer,
 };
+#[cfg(target_os = "linux")]
+use rpc::futex::FutexBoundedBuffer;
+use breakdown::Breakdown;
+use controller::{Controller, Decision};
+use journey::Journal;
+use pacer::Pacer;
+use progress::{Bars, Counted, Counts};
+use serde_json::Value;
+use report::{BufferReport, ConsumerReport, ProducerReport, Report, Verification};
+use cli::{Backend, Bench, Color, Command, Config, ItemBytes, Mode, OnStarved, Role, Run, Work};
+
+// allocated on `config.numa_node`, if any
+fn make_queue(config: &Config, echo: bool, recorder: Option<Arc<Recorder>>) -> Arc<dyn Queue> {
+    let capacity = config.capacity;
+    numa::on(config.numa_node, || -> Arc<dyn Queue> { match config.backend {
+        Backend::Condvar    => Arc::new(
+            SyncedBoundedBuffer::builder().capacity(capacity).echo(echo).recorder(recorder).wake(config.wake)
+                .bias(config.bias).hold_times(config.hold_times).build(),
+        ),
+        #[cfg(target_os = "linux")]
+        Backend::Futex      => Arc::new(FutexBoundedBuffer::new(capacity, echo).with_recorder(recorder)),
+        Backend::EventCount => Arc::new(EventCountBoundedBuffer::new(capacity, echo).with_recorder(recorder)),
+        Backend::Deque      => Arc::new(DequeBoundedBuffer::new(capacity, echo).with_recorder(recorder)),
+        Backend::Sharded    =>
+            Arc::new(ShardedBoundedBuffer::new(capacity, config.n_shards, echo).with_stealing(config.steal)),
+        Backend::Spin       => Arc::new(
+            SyncedBoundedBuffer::builder().capacity(capacity).echo(echo).recorder(recorder).wake(config.wake)
+                .bias(config.bias).hold_times(config.hold_times)
+                .build_on::<SpinCondvar>(),
+        ),
+        Backend::Waiters    => Arc::new(WaiterQueueBoundedBuffer::new(capacity, echo).with_recorder(recorder)),
+        Backend::Swap       => Arc::new(SwapBoundedBuffer::new(capacity, echo).with_recorder(recorder)),
+    } })
+}
+
+// how many lines echo's writer can fall behind by, before holding up the threads printing them
+const ECHO_BACKLOG: usize = 256;
+
+// with echo on for any of the buffers, print what they echo from a thread of its own, rather than the threads pushing
+// and popping, in color if `config.color` says so
+fn start_echo(config: &Config) -> Option<EchoWriter> {
+    let color = match config.color {
+        Color::Auto   => io::stdout().is_terminal(),
+        Color::Always => true,
+        Color::Never  => false,
+    };
+    config.instances().iter().any(|(_, config)| config.echo).then(|| EchoWriter::start(ECHO_BACKLOG, color))
+}
+
+// start a journal of every push and pop to `path`, if given
+fn start_journal(path: Option<&str>) -> Option<Journal> {
+    path.map(|path| Journal::create(path).unwrap_or_else(|e| {
+        eprintln!("error: can't write `{}`: {}", path, e);
+        process::exit(1);
+    }))
+}
+
+// once every thread is done: write what's left of the journal to `path`
+fn finish_journal(journal: Option<Journal>, path: Option<&str>) {
+    let (Some(journal), Some(path)) = (journal, path) else { return };
+    match journal.finish() {
+        Ok(n_lines) => eprintln!("wrote {} pushes and pops to `{}`", n_lines, path),
+        Err(e) => {
+            eprintln!("error: can't write `{}`: {}", path, e);
+            process::exit(1);
+        },
+    }
+}
+
+// write what's left to echo, and say how often the writer fell so far behind that it held threads up
+fn stop_echo(writer: Option<EchoWriter>) {
+    let Some(writer) = writer else { return };
+    match writer.stop() {
+        Ok(stats) => println!(
+            "echo: {} lines in {} writes; a full backlog held up {} of them, for {:?} in all",
+            stats.n_lines, stats.n_batches, stats.n_blocked, stats.blocked_time,
+        ),
+        Err(e) => eprintln!("error: failed to echo: {}", e),
+    }
+}
+
+// how many samples a tap holds for the thread watching it, before missing any
+const TAP_BACKLOG: usize = 1024;
+
+/* With `config.tap`, put a tap on `queue`, and start a thread taking its samples: printing each one to stderr, after
+`label`, with `print`, or else (for `bench`, where printing would skew the results) only taking them.
+*/
+fn tap(label: String, config: &Config, queue: Arc<dyn Queue>, print: bool) -> (Arc<dyn Queue>, Option<Arc<Tapped>>) {
+    let Some(sampling) = config.tap else { return (queue, None) };
+    let (tap, samples) = Tap::new(sampling, TAP_BACKLOG);
+    spawn("tap".to_string(), move || for item in samples {
+        if print { eprintln!("[{}] tap: {}", label, item); }
+    });
+    let tapped = Arc::new(Hooked::new(queue, tap));
+    (tapped.clone(), Some(tapped))
+}
+
+// write everything `recorder` has recorded so far to the trace file `path`
+fn save_trace(config: &Config, recorder: &Recorder, path: &str) {
+    let trace = Trace { capacity: config.capacity, events: recorder.take(), timed: true };
+    match File::create(path).and_then(|file| trace.write_to(BufWriter::new(file))) {
+        Ok(()) => eprintln!("recorded {} operations to `{}`", trace.events.len(), path),
+        Err(e) => {
+            eprintln!("error: can't write `{}`: {}", path, e);
+            process::exit(1);
+        },
+    }
+}
 
-struct BoundedBuffer<const BOUND: usize> {
-    array: [isize; BOUND],
-    n_items: usize,
+fn sleep_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now { thread::sleep(deadline - now); }
+}
+
+// spawn a thread with a name, which panic messages include
+fn spawn<T: Send + 'static>(name: String, f: impl FnOnce() -> T + Send + 'static) -> JoinHandle<T> {
+    thread::Builder::new().name(name).spawn(f).expect("failed to spawn a thread")
+}
+
+fn print_totals(totals: impl Iterator<Item = usize>) {
+    for (i, n) in totals.enumerate() { println!("    producer {:>3}: {:>12} items", i, n); }
+}
+
+// the most items the buffer held at once, and how much memory it takes up
+fn occupancy(queue: &dyn Queue) -> String {
+    format!(
+        "peak {} of {} items, {:.1} KiB", queue.stats().peak_items(), queue.capacity(), queue.memory() as f64 / 1024.0,
+    )
+}
+
+// above this fraction of its time spent blocked on the buffer, a side is taken to be waiting for the other; below
+// `BLOCKED_RARELY`, to hardly wait at all
+const BLOCKED_MOSTLY: f64 = 0.5;
+const BLOCKED_RARELY: f64 = 0.1;
+
+/* A one-line diagnosis of what limits the throughput, with the options to change, from how much of their time the
+producers and the consumers spent blocked on the buffer, `blocked` ([producers, consumers], summed over each side's
+threads) of `secs` seconds: one side waiting most of the time means the other is the bottleneck, both waiting now and
+then means bursts which a bigger buffer would absorb, and neither waiting means the buffer itself, or the CPUs. A wait
+is only counted once it's over, so one still going on at the end of the run (at most the poll interval, as runners'
+threads can be cancelled) isn't.
+*/
+fn diagnose(config: &Config, blocked: [Duration; 2], secs: f64) -> String {
+    let fraction = |side: Side, n_threads: usize| blocked[side as usize].as_secs_f64() / (n_threads as f64 * secs);
+    let producers = fraction(Side::Producer, config.n_producers);
+    let consumers = fraction(Side::Consumer, config.n_consumers);
+    let diagnosis = match (producers >= BLOCKED_MOSTLY, consumers >= BLOCKED_MOSTLY) {
+        (true, false) => "the consumers are the bottleneck; try more of them (`--consumers`), or making them faster",
+        (false, true) if config.rate.is_some() =>
+            "the producers are the bottleneck; try a higher `--rate`, more producers, or fewer consumers",
+        (false, true) => "the producers are the bottleneck; try more of them (`--producers`), or fewer consumers",
+        (true, true) => "both sides mostly wait for each other, taking turns with the buffer (with a small buffer, or \
+            more threads than CPUs); try batching (`--producer-batch`, `--prefetch`) or a bigger buffer (`--capacity`)",
+        _ if producers < BLOCKED_RARELY && consumers < BLOCKED_RARELY =>
+            "neither side waits for the other, so the limit is the buffer's lock or the CPUs; try batching \
+            (`--producer-batch`, `--prefetch`) or another `--backend`",
+        _ => "both sides wait for each other now and then, as bursts fill and empty the buffer; try a bigger buffer \
+            (`--capacity`)",
+    };
+    format!(
+        "producers blocked {:.0}% of the time, consumers {:.0}%: {}", producers * 100.0, consumers * 100.0, diagnosis,
+    )
+}
+
+// which NUMA nodes the buffer and the threads using it are on, if they've been placed
+fn placement(config: &Config) -> Option<String> {
+    Some(match (config.numa_node, config.consumer_node.or(config.numa_node)) {
+        (Some(node), Some(consumer_node)) if node == consumer_node =>
+            format!("buffer and threads on NUMA node {}", node),
+        (Some(node), Some(consumer_node)) =>
+            format!("buffer and producers on NUMA node {}, consumers on node {}", node, consumer_node),
+        (None, Some(consumer_node)) => format!("consumers on NUMA node {}", consumer_node),
+        _ => return None,
+    })
+}
+
+// how to refer to one of the buffers in a run: by its backend, and its name if it has one
+fn label(name: &str, config: &Config) -> String {
+    if name.is_empty() { config.backend.name().to_string() } else { format!("{} ({})", name, config.backend.name()) }
+}
+
+// what a consumer returns
+#[derive(Default)]
+struct Consumed {
+    // not counting the items popped before measuring started, which only count towards `n_excluded`
+    n_popped: usize,
+    n_excluded: usize,
+    // the total time items waited in its prefetch stash, and it spent working on them
+    stash_time: Duration,
+    busy_time: Duration,
+    // the total time from each item being pushed to its being popped, if items are their push times
+    wait_time: Duration,
+    // the items popped which were duplicates of recent ones (with `--dedup-window`), and so skipped
+    n_duplicates: usize,
+}
+impl Consumed {
+    fn add(self, other: Consumed) -> Consumed {
+        Consumed {
+            n_popped: self.n_popped + other.n_popped, n_excluded: self.n_excluded + other.n_excluded,
+            stash_time: self.stash_time + other.stash_time,
+            busy_time: self.busy_time + other.busy_time, wait_time: self.wait_time + other.wait_time,
+            n_duplicates: self.n_duplicates + other.n_duplicates,
+        }
+    }
+}
+
+/* For measuring how long items wait between being pushed and popped: producers push the time since `epoch`, in
+nanoseconds, instead of the generator's items, and `n_in_flight` counts the items which have been pushed (or are being
+pushed, or held back in a batch) but not popped yet.
+*/
+#[derive(Clone)]
+struct Timing {
+    epoch: Instant,
+    n_in_flight: Arc<AtomicIsize>,
+}
+impl Timing {
+    fn new() -> Self { Timing { epoch: Instant::now(), n_in_flight: Arc::new(AtomicIsize::new(0)) } }
+}
+
+/* Start producers and consumers which run until they're stopped, or for the producers, until they've pushed `n_items`
+items each if given. Each producer returns how many items it pushed, and each consumer what it measured from
+`measure_from` on. With `heartbeats`, every thread beats one (which costs a clock reading on each push and pop).
+*/
+fn start(
+    config: &Config, queue: Arc<dyn Queue>, timing: Option<Timing>, n_items: Option<usize>, measure_from: Instant,
+    heartbeats: Option<Arc<Heartbeats>>,
+) -> Runner<usize, Consumed> {
+    let mut runner = Runner::new(queue, config.strategy);
+    if let Some(heartbeats) = heartbeats { runner = runner.with_heartbeats(heartbeats); }
+    spawn_producers(&mut runner, config, timing.clone(), None, n_items, measure_from);
+    spawn_consumers(&mut runner, config, config.work_time, 2, timing, None, measure_from);
+    runner
+}
+
+// from measuring on, the threads' allocations count against them (with the `alloc-audit` feature)
+fn audit() {
+    #[cfg(feature = "alloc-audit")]
+    audit::start();
+}
+
+// count the calling thread's events for `bench` (with the `perf` feature, on Linux)
+fn count_events() {
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    perf::count_thread();
+}
+
+// taking a permit from `in_flight`, if given, before pushing each item, including those redelivered; with `n_items`,
+// each finishes after that many (not counting redeliveries)
+fn spawn_producers(
+    runner: &mut Runner<usize, Consumed>, config: &Config, timing: Option<Timing>, in_flight: Option<Arc<Semaphore>>,
+    n_items: Option<usize>, measure_from: Instant,
+) {
+    for i in 0..config.n_producers {
+        let mut pacer = Pacer::new(config.rate, config.arrivals, config.seed, i);
+        let (generator, seed) = (config.generator, config.seed);
+        let (batch_size, flush_interval) = (config.producer_batch, config.flush_interval);
+        let (timing, in_flight, node) = (timing.clone(), in_flight.clone(), config.numa_node);
+        let redeliver = config.redeliver;
+        runner.spawn_producer(move |queue, locker| {
+            numa::pin(node);
+            count_events();
+            let mut producer = Producer::new(queue, locker).with_batch(batch_size, flush_interval);
+            let mut measuring = false;
+            // the items to push again, in order, each after the `n`th item
+            let mut redeliveries = VecDeque::new();
+            'items: for n in 0..n_items.unwrap_or(usize::MAX) {
+                if !measuring && Instant::now() >= measure_from {
+                    measuring = true;
+                    audit();
+                }
+                // don't hold back a batch past its deadline while waiting for the next item
+                pacer.tick(producer.flush_deadline(), || { let _ = producer.flush(); });
+                if let Some(in_flight) = &in_flight {
+                    if in_flight.acquire(producer.locker()).is_err() { break; }
+                }
+                let item = match &timing {
+                    Some(timing) => {
+                        timing.n_in_flight.fetch_add(1, Relaxed);
+                        timing.epoch.elapsed().as_nanos() as isize
+                    },
+                    None => generator.item(seed, i, n),
+                };
+                if producer.push(item).is_err() { break; }
+
+                if let Some(lag) = cli::redelivery(redeliver, seed, i, n) {
+                    let due = n + lag;
+                    let k = redeliveries.partition_point(|&(other, _)| other <= due);
+                    redeliveries.insert(k, (due, item));
+                }
+                while redeliveries.front().is_some_and(|&(due, _)| due <= n) {
+                    let (_, item) = redeliveries.pop_front().unwrap();
+                    if let Some(in_flight) = &in_flight {
+                        if in_flight.acquire(producer.locker()).is_err() { break 'items; }
+                    }
+                    if producer.push(item).is_err() { break 'items; }
+                }
+            }
+            // push what's held back, which gives up once cancelled like the pushes do; then what's still held back was
+            // never pushed, like the items not made yet
+            let _ = producer.flush();
+            let n_pushed = producer.n_pushed();
+            drop(producer.into_pending());
+            n_pushed
+        });
+    }
+}
+
+// spending `work` on each item, as `stage` of a pipeline, then releasing a permit to `in_flight`, if given
+fn spawn_consumers(
+    runner: &mut Runner<usize, Consumed>, config: &Config, work: Work, stage: usize, timing: Option<Timing>,
+    in_flight: Option<Arc<Semaphore>>, measure_from: Instant,
+) {
+    // shared, as a duplicate may be popped by any consumer
+    let dedup = config.dedup_window.map(|size| Arc::new(Dedup::new(size)));
+    for i in 0..config.n_consumers {
+        let (seed, prefetch, timing) = (config.seed, config.prefetch, timing.clone());
+        let (in_flight, node) = (in_flight.clone(), config.consumer_node.or(config.numa_node));
+        let dedup = dedup.clone();
+        runner.spawn_consumer(move |queue, locker| {
+            numa::pin(node);
+            count_events();
+            let mut consumer = Consumer::new(queue, locker).with_prefetch(prefetch);
+            let (mut consumed, mut measuring) = (Consumed::default(), false);
+            // how long items had waited in the stash when measuring started
+            let mut excluded_stash_time = Duration::ZERO;
+            while let Some(item) = consumer.next() {
+                if !measuring && Instant::now() >= measure_from {
+                    measuring = true;
+                    excluded_stash_time = consumer.stash_time();
+                    audit();
+                }
+                let n = consumed.n_popped + consumed.n_excluded;
+                if let Some(timing) = &timing {
+                    timing.n_in_flight.fetch_sub(1, Relaxed);
+                    let wait = timing.epoch.elapsed().saturating_sub(Duration::from_nanos(item as u64));
+                    if measuring { consumed.wait_time += wait; }
+                }
+                if dedup.as_ref().is_some_and(|dedup| !dedup.is_new(item)) {
+                    if let Some(in_flight) = &in_flight { in_flight.release(); }
+                    if measuring { consumed.n_duplicates += 1; } else { consumed.n_excluded += 1; }
+                    continue;
+                }
+                if !work.is_zero() {
+                    let started = Instant::now();
+                    thread::sleep(work.sample(seed, stage, i, n));
+                    if measuring { consumed.busy_time += started.elapsed(); }
+                }
+                if let Some(in_flight) = &in_flight { in_flight.release(); }
+                if measuring { consumed.n_popped += 1; } else { consumed.n_excluded += 1; }
+            }
+            Consumed { stash_time: consumer.stash_time() - excluded_stash_time, ..consumed }
+        });
+    }
 }
-impl<const BOUND: usize> BoundedBuffer<BOUND> {
 
-    fn new() -> Self { BoundedBuffer { array: [0; BOUND], n_items: 0 } }
+/* Start a pipeline with a buffer like `config`'s between each stage and the next, and return a runner for each buffer.
+The producers are the first stage and the consumers the last; each stage in between has `config.n_consumers` workers,
+which pop an item from the buffer before them (as consumers of its runner), work on it, then push it to the buffer after
+them. `work[k]` is how long stage `k + 2` spends on each item, so there are `work.len() + 1` stages. With `in_flight`,
+the producers take one of its permits for each item, and the last stage gives it back once done with the item. With
+`reorders`, one for each stage between the producers and the consumers, those stages pass items on in the order they
+popped them (see `Reorder`). With `journal`, every buffer's pushes and pops are written to it, and with `breakdown`,
+every item is stamped as it passes through.
+*/
+fn start_pipeline(
+    config: &Config, work: &[Work], in_flight: Option<Arc<Semaphore>>, reorders: &[Arc<Reorder>], measure_from: Instant,
+    journal: Option<&Journal>, breakdown: Option<&Arc<Breakdown>>,
+) -> Vec<Runner<usize, Consumed>> {
+    let n_buffers = work.len();
+    let mut runners: Vec<_> = (0..n_buffers).map(|k| {
+        let mut queue = make_queue(config, false, None);
+        if let Some(breakdown) = breakdown { queue = breakdown.stamp(queue, k); }
+        if let Some(journal) = journal {
+            let pushers = if k == 0 { "producer".to_string() } else { format!("stage {} worker", k + 1) };
+            let poppers = if k == n_buffers - 1 { "consumer".to_string() } else { format!("stage {} worker", k + 2) };
+            queue = journal.follow(queue, format!("buffer {}", k + 1), &pushers, &poppers);
+        }
+        Runner::new(queue, config.strategy)
+    }).collect();
+    spawn_producers(&mut runners[0], config, None, in_flight.clone(), None, measure_from);
+    for k in 0..runners.len() - 1 {
+        let next = runners[k + 1].queue().clone();
+        for i in 0..config.n_consumers {
+            let (seed, work, next, node) = (config.seed, work[k], next.clone(), config.numa_node);
+            let reorder = reorders.get(k).cloned();
+            runners[k].spawn_consumer(move |queue, locker| {
+                numa::pin(node);
+                let mut n_popped = 0;
+                loop {
+                    let popped = match &reorder {
+                        Some(reorder) => reorder.pop(queue, locker),
+                        None => queue.pop(locker).map(|item| (0, item)),
+                    };
+                    let Ok((seq, item)) = popped else { break };
+                    if !work.is_zero() { thread::sleep(work.sample(seed, k + 2, i, n_popped)); }
+                    n_popped += 1;
+                    let pushed = match &reorder {
+                        Some(reorder) => reorder.complete(seq, item, locker, |item, locker| next.push(item, locker)),
+                        None => next.push(item, locker),
+                    };
+                    if pushed.is_err() { break; }
+                }
+                Consumed { n_popped, ..Consumed::default() }
+            });
+        }
+    }
+    let (last_work, n_stages) = (work[work.len() - 1], work.len() + 1);
+    spawn_consumers(runners.last_mut().unwrap(), config, last_work, n_stages, None, in_flight, measure_from);
+    runners
+}
 
-    fn empty(&self) -> bool { self.n_items == 0     }
-    fn full (&self) -> bool { self.n_items == BOUND }
+// how often `run` checks whether its consumers are starved for good
+const STARVED_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// how often `run` reports how many items are left while it empties the buffers
+const DRAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+// how many of its producers' intervals between pushes at `--rate` a thread may go without a push or pop before it's
+// taken for stalled, if that's longer than `--stall-after`; even Poisson arrivals only go that long once in e^10 gaps
+const STALL_INTERVALS: f64 = 10.0;
 
-    fn push(&mut self, item: isize) {
-        assert!(!self.full());
-        self.array[self.n_items] = item;
-        self.n_items += 1;
+// how long a thread of a buffer with `config` may go without going into or out of it before it's taken for stalled
+fn stall_threshold(config: &Config, stall_after: Duration) -> Duration {
+    match config.rate {
+        Some(rate) => stall_after.max(Duration::from_secs_f64(STALL_INTERVALS * config.producer_batch as f64 / rate)),
+        None => stall_after,
     }
-    fn pop(&mut self) -> isize {
-        assert!(!self.empty());
-        self.n_items -= 1;
-        self.array[self.n_items]
+}
+
+// the signals `run` handles
+enum Signal {
+    // SIGINT or SIGTERM: stop
+    Stop(&'static str),
+    // SIGUSR1: report the state of the run
+    Dump,
+}
+
+/* Block SIGINT, SIGTERM and SIGUSR1 in this thread, and so in every thread it spawns from now on, and return a channel
+which receives them instead. Must be called before spawning any other threads, or they could still be killed by them.
+*/
+#[cfg(target_os = "linux")]
+fn signals() -> Receiver<Signal> {
+    let (sender, receiver) = mpsc::channel();
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        libc::sigaddset(&mut set, libc::SIGUSR1);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        spawn("signals".to_string(), move || loop {
+            let mut signal = 0;
+            libc::sigwait(&set, &mut signal);
+            let signal = match signal {
+                libc::SIGINT  => Signal::Stop("SIGINT"),
+                libc::SIGTERM => Signal::Stop("SIGTERM"),
+                _ => Signal::Dump,
+            };
+            if sender.send(signal).is_err() { break; }
+        });
     }
+    receiver
 }
-impl<const BOUND: usize> Default for BoundedBuffer<BOUND> {
-    fn default() -> Self { Self::new() }
+// elsewhere the signals keep their default behaviour, and this never receives anything
+#[cfg(not(target_os = "linux"))]
+fn signals() -> Receiver<Signal> {
+    let (sender, receiver) = mpsc::channel();
+    std::mem::forget(sender);
+    receiver
 }
-impl<const BOUND: usize> Display for BoundedBuffer<BOUND> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 
-        write!(f, "[")?;
+// print the statistics so far, the buffer's contents, and what each thread is doing (from its heartbeat), to stderr
+fn dump(label: &str, runner: &Runner<usize, Consumed>, start: Instant) {
+    let [n_ops, n_waits, n_wakes, n_steals] = runner.queue().stats().load();
+    eprintln!(
+        "{} after {:.1}s: {} ops, {} waits, {} wakes, {} steals",
+        label, start.elapsed().as_secs_f64(), n_ops, n_waits, n_wakes, n_steals,
+    );
+    eprintln!("    {}", occupancy(&**runner.queue()));
+    let [n_producers, n_consumers] = runner.queue().stats().n_waiting();
+    eprintln!("    waiting: {} producers for room, {} consumers for an item", n_producers, n_consumers);
+    let items = runner.queue().snapshot();
+    eprintln!("    buffer ({} items): {:?}", items.len(), items);
+    match runner.heartbeats() {
+        Some(heartbeats) => for worker in heartbeats.workers() { eprintln!("    {}", worker); },
+        None => for (name, finished) in runner.threads() {
+            eprintln!("    {:<14} {}", name, if finished { "finished" } else { "running" });
+        },
+    }
+}
 
-        if self.n_items > 0 {
-            write!(f, "{}", self.array[0])?;
-            for i in 1..self.n_items { write!(f, ", {}", self.array[i])?; };
+// warn about each of `stalled` which wasn't stalled last time (in `warned`), which becomes those
+fn warn_stalled(label: &str, stalled: Vec<Arc<Heartbeat>>, warned: &mut Vec<String>) {
+    for worker in &stalled {
+        if warned.iter().any(|name| name == worker.name()) { continue; }
+        let doing = match worker.activity() {
+            Activity::InQueue => "blocked on the buffer",
+            _ => "stuck outside the buffer (working on an item, or making one)",
         };
+        eprintln!("warning: {}: {} for {:.1}s: {}", label, worker.name(), worker.since().as_secs_f64(), doing);
+    }
+    *warned = stalled.iter().map(|worker| worker.name().to_string()).collect();
+}
+
+/* Run until SIGINT or SIGTERM, then stop the producers and wait for the consumers to empty the buffers
+(`Runner::drain_with_progress`), for at most the grace period, reporting how many items are left every second meanwhile.
+A second signal (noticed at the next report), or the grace period running out, exits straight away, with a nonzero
+status, printing the items left over in each buffer first, and writing the journey and the report so far, without the
+threads' own results. SIGUSR1 dumps the state of the run at any time. With `n_items`, each producer finishes after
+pushing that many items; once they all have and a buffer's consumers are all waiting on it empty, they're starved for
+good, which `on_starved` warns about, or treats like SIGINT. A thread which hasn't gone into or out of its buffer for
+`stall_after` is warned about too, as blocked on the buffer or stuck outside it, unless its buffer is starved; with a
+`--rate` slow enough that its producers push less often than that, after a few of their intervals instead. With
+`n_items` and `progress` (by default, if stderr is a terminal), bars for the items pushed and popped so far replace echo
+and the rates. With `report`, the results are also written to that JSON file. With `health`, probes on that address are
+answered: the run is ready once its threads have started, until it's stopping or one of them stalls. With `ws_port`, a
+page on that port draws every buffer's pushes, pops and occupancy live. With `journey`, every push and pop is written to
+that file.
+*/
+fn run(config: &Config, options: &Run) {
+    let Run { n_items, on_starved, stall_after, progress, ref report, health, ws_port, ref journey } = *options;
+    let (report, journey) = (report.as_deref(), journey.as_deref());
+    let signals = signals();
+    let health = health.map(|address| health::serve(address).unwrap_or_else(|e| {
+        eprintln!("error: can't answer health probes on `{}`: {}", address, e);
+        process::exit(1);
+    }));
+    let live = ws_port.map(|port| live::serve(port).unwrap_or_else(|e| {
+        eprintln!("error: can't serve live events on port {}: {}", port, e);
+        process::exit(1);
+    }));
+    let progress = n_items.is_some() && progress.unwrap_or_else(|| io::stderr().is_terminal());
+    let mut echo = if progress { None } else { start_echo(config) };
+    let mut journal = start_journal(journey);
+    let start_time = Instant::now();
+    let mut counted = Vec::new();
+    let runners: Vec<_> = config.instances().into_iter().map(|(name, config)| {
+        let mut queue = make_queue(config, config.echo && !progress, None);
+        if let Some(journal) = &journal { queue = journal.follow(queue, label(name, config), "producer", "consumer"); }
+        let (mut queue, _) = tap(label(name, config), config, queue, true);
+        if let Some(live) = &live { queue = live.watch(label(name, config), queue); }
+        if progress {
+            let counter = Arc::new(Counted::new(queue, Counts::default()));
+            counted.push(counter.clone());
+            queue = counter;
+        }
+        let runner = start(config, queue, None, n_items, start_time, Some(Arc::default()));
+        // report throughput and syscall counts, so that the backends can be compared
+        let (label, interval, monitored) = (label(name, config), config.stats_interval, runner.queue().clone());
+        if !progress { spawn("monitor".to_string(), move || monitored.stats().monitor(&label, interval)); }
+        (name, config, runner)
+    }).collect();
+    let n_producers: u64 = runners.iter().map(|(_, config, _)| config.n_producers as u64).sum();
+    let total = n_items.unwrap_or(0) as u64 * n_producers;
+    let mut bars = progress.then(|| Bars::new(total));
+    let draw = |bars: &mut Bars| bars.draw(
+        counted.iter().map(|counter| counter.hooks().n_pushed()).sum(),
+        counted.iter().map(|counter| counter.hooks().n_popped()).sum(),
+    );
+    let dump_all = || for (name, config, runner) in &runners { dump(&label(name, config), runner, start_time); };
+    let n_items = || runners.iter().map(|(_, _, runner)| runner.queue().n_items()).sum::<usize>();
+    let starved = |(_, config, runner): &(&str, &Config, Runner<usize, Consumed>)| {
+        let n_waiting = runner.queue().stats().n_waiting()[Side::Consumer as usize];
+        runner.producers_finished() && runner.queue().n_items() == 0 && n_waiting == config.n_consumers as u64
+    };
+    // the first thread stalled, if any, except in buffers which are starved
+    let stall = || runners.iter().filter(|instance| !starved(instance)).find_map(|instance| {
+        let threshold = stall_threshold(instance.1, stall_after);
+        let worker = instance.2.heartbeats().unwrap().stalled(threshold).into_iter().next()?;
+        Some(format!("{}: {}", label(instance.0, instance.1), worker))
+    });
+    let check_health = || if let Some(health) = &health {
+        match stall() { Some(reason) => health.set_unready(reason), None => health.set_ready() }
+    };
+    check_health();
+
+    let mut warned = vec![false; runners.len()];
+    let mut stalled = vec![Vec::new(); runners.len()];
+    let signal = loop {
+        let received = signals.recv_timeout(STARVED_CHECK_INTERVAL);
+        // the bars come off while anything else is printed, then go back on below it
+        if let Some(bars) = &mut bars { bars.clear(); }
+        match received {
+            Ok(Signal::Stop(signal)) => break Some(signal),
+            Ok(Signal::Dump) => dump_all(),
+            Err(_) if matches!(on_starved, OnStarved::Exit) && runners.iter().all(starved) => break None,
+            Err(_) => for ((warned, stalled), instance) in warned.iter_mut().zip(&mut stalled).zip(&runners) {
+                if !starved(instance) {
+                    let heartbeats = instance.2.heartbeats().unwrap();
+                    let threshold = stall_threshold(instance.1, stall_after);
+                    warn_stalled(&label(instance.0, instance.1), heartbeats.stalled(threshold), stalled);
+                    continue;
+                }
+                if *warned { continue; }
+                *warned = true;
+                eprintln!(
+                    "warning: {}: every producer has finished, so the consumers are waiting for items which will \
+                    never come; stop with SIGINT, or use `--on-starved exit`",
+                    label(instance.0, instance.1),
+                );
+            },
+        }
+        check_health();
+        if let Some(bars) = &mut bars { draw(bars); }
+    };
+    if let Some(health) = &health { health.set_unready("stopping".to_string()); }
+    if let Some(bars) = &mut bars {
+        draw(bars);
+        bars.finish();
+    }
+    match signal {
+        Some(signal) => eprintln!("{}: stopping the producers and emptying the buffer", signal),
+        None => eprintln!("every producer has finished, and the consumers have emptied the buffer"),
+    }
+    for (_, _, runner) in &runners { runner.stop_producers(); }
 
-        write!(f, "]")
+    // what's left in each buffer, so that it isn't lost without a trace
+    let print_leftovers = || for (name, config, runner) in &runners {
+        let items = runner.queue().snapshot();
+        if !items.is_empty() { eprintln!("    {}: left over: {:?}", label(name, config), items); }
+    };
+    let deadline = Instant::now() + config.grace_period;
+    for (i, (name, config, runner)) in runners.iter().enumerate() {
+        let drained = runner.drain_with_progress(deadline, DRAIN_PROGRESS_INTERVAL, |n_left| {
+            match signals.try_recv() {
+                Ok(Signal::Stop(signal)) => {
+                    eprintln!("{} again: exiting with {} items left", signal, n_items());
+                    print_leftovers();
+                    abandon(&runners, start_time, report, journal.take(), journey, echo.take());
+                    process::exit(130);
+                },
+                Ok(Signal::Dump) => dump_all(),
+                Err(_) => {},
+            }
+            eprintln!("emptying {}: {} items left, {:.1}s of the grace period to go", label(name, config), n_left,
+                deadline.saturating_duration_since(Instant::now()).as_secs_f64());
+        });
+        if let Err(leftovers) = drained {
+            eprintln!("error: the grace period ran out with {} items left in {}", leftovers.len(), label(name, config));
+            eprintln!("    {}: left over: {:?}", label(name, config), leftovers);
+            // and those of the buffers not drained yet
+            for (name, config, runner) in &runners[i + 1..] {
+                let items = runner.queue().snapshot();
+                if !items.is_empty() { eprintln!("    {}: left over: {:?}", label(name, config), items); }
+            }
+            abandon(&runners, start_time, report, journal.take(), journey, echo.take());
+            process::exit(1);
+        }
+    }
+    eprintln!("buffer empty; exiting");
+    for (_, _, runner) in &runners { runner.shutdown(); }
+    let secs = start_time.elapsed().as_secs_f64();
+    let mut buffers = Vec::new();
+    for (name, config, runner) in runners {
+        let queue = runner.queue().clone();
+        if !name.is_empty() { println!("{}:", label(name, config)); }
+        println!("    {}", occupancy(&*queue));
+        println!("    {}", diagnose(config, queue.stats().blocked(), secs));
+        print_hold_times(&*queue);
+        let results = runner.join();
+        let n_pushed: Vec<_> = results.producers.into_iter().map(Result::unwrap).collect();
+        let mut buffer = BufferReport::new(name, config, &*queue, queue.stats().load(), queue.stats().blocked(), secs);
+        buffer.producers = n_pushed.iter().map(|&n_pushed| ProducerReport { n_pushed }).collect();
+        buffer.consumers = results.consumers.into_iter().map(|consumed| (&consumed.unwrap()).into()).collect();
+        buffers.push(buffer);
+        print_totals(n_pushed.into_iter());
+    }
+    finish_journal(journal, journey);
+    stop_echo(echo);
+    if let Some(path) = report {
+        report::write(&Report { command: "run".to_string(), secs, buffers, verification: None }, path);
     }
 }
 
-#[derive(Default)]
-struct SyncedBoundedBuffer<const BOUND: usize> {
-    buffer: Mutex<BoundedBuffer<BOUND>>,
-    not_empty: Condvar,
-    not_full: Condvar,
+/* Before `run` exits without waiting for its threads to finish: write the rest of the journal and of the echo, and
+with `report`, the results so far, without what the threads would have returned, so that a run which can't stop
+cleanly still leaves its results behind.
+*/
+fn abandon(
+    runners: &[(&str, &Config, Runner<usize, Consumed>)], start_time: Instant, report: Option<&str>,
+    journal: Option<Journal>, journey: Option<&str>, echo: Option<EchoWriter>,
+) {
+    let secs = start_time.elapsed().as_secs_f64();
+    finish_journal(journal, journey);
+    stop_echo(echo);
+    let Some(path) = report else { return };
+    let buffers = runners.iter().map(|(name, config, runner)| {
+        let queue = runner.queue();
+        BufferReport::new(name, config, &**queue, queue.stats().load(), queue.stats().blocked(), secs)
+    }).collect();
+    report::write(&Report { command: "run".to_string(), secs, buffers, verification: None }, path);
+}
+
+/* At the end of a warm-up: forget the buffers' peak occupancy so far, and return their counters' values, for `since`
+to subtract from their values at the end of the run.
+*/
+fn warm_up<'a>(queues: impl Iterator<Item = &'a dyn Queue>) -> Vec<[u64; 4]> {
+    queues.map(|queue| {
+        queue.stats().reset_peak(queue.n_items());
+        queue.stats().load()
+    }).collect()
+}
+
+// how much each buffer's counters have gone up since `warm_up` returned `baselines`
+fn since<'a>(queues: impl Iterator<Item = &'a dyn Queue>, baselines: &[[u64; 4]]) -> Vec<[u64; 4]> {
+    queues.zip(baselines).map(|(queue, baseline)| {
+        let load = queue.stats().load();
+        std::array::from_fn(|i| load[i] - baseline[i])
+    }).collect()
 }
 
-fn producer_routine<const BOUND: usize>(sbbuf: Arc<SyncedBoundedBuffer<BOUND>>, item: isize) {
-    loop {
-        // acquire the mutex so we can (at least) check if the buffer is full
-        let mut bbuf = sbbuf.buffer.lock().unwrap();
+// how often `bench` samples how many items are queued, for `--queueing` and `--histogram`
+const QUEUE_SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+// how many bars `--histogram` divides the capacity into, and how wide the longest is
+const HISTOGRAM_BINS: usize = 10;
+const HISTOGRAM_WIDTH: usize = 50;
 
-        /* If the buffer is full, release the mutex until it isn't full.
-        `wait` blocks until `not_full` is signalled; the `while` instead of `if` is because it is possible for
-        the buffer to be full when `wait` returns, as follows:
-            1. the buffer becomes not full and `not_full` is signalled, waking all producers
-            2. another producer thread runs before this one, and fills the buffer
-            3. then this thread runs.
-        */
-        while bbuf.full() { bbuf = sbbuf.not_full.wait(bbuf).unwrap(); }
+/* With several buffers, each one's results are reported separately, followed by the total throughput. With `queueing`,
+each one is also compared with an M/M/c queue with the same arrival and service rates. With `histogram`, each one's
+occupancy is charted. With `switches`, how often each producer and consumer was switched out is reported last. Returns
+the results, for `--report-json`. With `journey`, every push and pop is written to that file.
+*/
+fn bench(config: &Config, options: &Bench) -> Report {
+    let Bench { duration, warmup, ref record, queueing, switches, histogram, ref journey, .. } = *options;
+    let (record, journey) = (record.as_deref(), journey.as_deref());
+    let recorder = record.map(|_| Arc::new(Recorder::default()));
+    let echo = start_echo(config);
+    let journal = start_journal(journey);
+    let measure_from = Instant::now() + warmup;
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    perf::begin();
+    let runners: Vec<_> = config.instances().into_iter().map(|(name, config)| {
+        let mut queue = make_queue(config, config.echo, recorder.clone());
+        if let Some(journal) = &journal { queue = journal.follow(queue, label(name, config), "producer", "consumer"); }
+        let (queue, tapped) = tap(label(name, config), config, queue, false);
+        let timing = queueing.then(Timing::new);
+        (name, config, start(config, queue, timing.clone(), None, measure_from, None), timing, tapped)
+    }).collect();
 
-        // add an item to the buffer
-        bbuf.push(item);
-        // display the buffer state
-        println!("{}", bbuf);
+    sleep_until(measure_from);
+    #[cfg(feature = "alloc-audit")]
+    audit::reset();
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    let events = perf::read();
+    let switched = if switches { switches::threads() } else { BTreeMap::new() };
+    let baselines = warm_up(runners.iter().map(|(_, _, runner, ..)| &**runner.queue()));
+    let blocked_baselines: Vec<_> = runners.iter().map(|(_, _, runner, ..)| runner.queue().stats().blocked()).collect();
+    let start = Instant::now();
+    // the number of items each buffer held, and that were in flight through it, summed over `n_samples` samples, and
+    // how many of those samples found it holding each number of items up to its capacity
+    let (mut n_samples, mut n_queued) = (0, vec![(0, 0); runners.len()]);
+    let mut histograms: Vec<_> =
+        runners.iter().map(|(_, _, runner, ..)| vec![0; runner.queue().capacity() + 1]).collect();
+    while (queueing || histogram) && start.elapsed() + QUEUE_SAMPLE_INTERVAL < duration {
+        thread::sleep(QUEUE_SAMPLE_INTERVAL);
+        let sampled = n_queued.iter_mut().zip(&mut histograms).zip(&runners);
+        for (((n_items, n_in_flight), n_times), (_, _, runner, timing, _)) in sampled {
+            let n = runner.queue().n_items();
+            *n_items += n;
+            *n_in_flight += timing.as_ref().map_or(0, |timing| timing.n_in_flight.load(Relaxed));
+            if let Some(n_times) = n_times.get_mut(n) { *n_times += 1; }
+        }
+        n_samples += 1;
+    }
+    sleep_until(start + duration);
+    let loads = since(runners.iter().map(|(_, _, runner, ..)| &**runner.queue()), &baselines);
+    let blocked: Vec<_> = runners.iter().zip(blocked_baselines).map(|((_, _, runner, ..), baseline)| {
+        let blocked = runner.queue().stats().blocked();
+        [0, 1].map(|side| blocked[side] - baseline[side])
+    }).collect();
+    let secs = start.elapsed().as_secs_f64();
+    // before shutting down, which may allocate
+    #[cfg(feature = "alloc-audit")]
+    let n_allocs = audit::n_allocs();
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    let events = (events, perf::read());
+    let switched = if switches { Some((switched, switches::threads())) } else { None };
+    for (_, _, runner, ..) in &runners { runner.shutdown(); }
+
+    let mut buffers = Vec::new();
+    let reports = runners.into_iter().zip(&loads).zip(n_queued).zip(histograms).zip(blocked);
+    for (
+        ((((name, config, runner, _, tapped), [n_ops, n_waits, n_wakes, n_steals]), (n_items, n_in_flight)), n_times),
+        blocked,
+    ) in reports {
+        println!(
+            "{}: {} producers, {} consumers, {:.1}s",
+            label(name, config), config.n_producers, config.n_consumers, secs,
+        );
+        if let Some(placement) = placement(config) { println!("    {}", placement); }
+        println!("    {:>12.0} ops/s", *n_ops as f64 / secs);
+        println!("    {:>12.0} waits/s", *n_waits as f64 / secs);
+        println!("    {:>12.0} wakes/s", *n_wakes as f64 / secs);
+        if matches!(config.backend, Backend::Sharded) { println!("    {:>12.0} steals/s", *n_steals as f64 / secs); }
+        println!("    {}", occupancy(&**runner.queue()));
+        if histogram { print_histogram(&n_times); }
+        let queue = runner.queue().clone();
 
-        // since we just pushed an item, the buffer is definitely not empty.
-        // We use `notify_all` instead of `notify_one` because there may be space for multiple items, which
-        // may be filled by multiple threads.
-        sbbuf.not_empty.notify_all();
-        // we're done; now the MutexGuard goes out of scope, unlocking the Mutex
+        let counts = [*n_ops, *n_waits, *n_wakes, *n_steals];
+        let mut buffer = BufferReport::new(name, config, &*queue, counts, blocked, secs);
+        buffer.occupancy_histogram = histogram.then_some(n_times);
+        let results = runner.join();
+        let consumers: Vec<_> = results.consumers.into_iter().map(Result::unwrap).collect();
+        buffer.consumers = consumers.iter().map(ConsumerReport::from).collect();
+        let consumed = consumers.into_iter().fold(Consumed::default(), Consumed::add);
+        if !warmup.is_zero() {
+            println!("    excluding a {:?} warm-up, and the {} items popped in it", warmup, consumed.n_excluded);
+        }
+        if config.prefetch > 1 {
+            let mean = consumed.stash_time.as_secs_f64() / consumed.n_popped.max(1) as f64;
+            println!("    {:>12.1} µs mean wait in a consumer's stash", mean * 1e6);
+        }
+        print_duplicates(config, &consumed);
+        if let Some(tapped) = tapped { print_tap(tapped.hooks()); }
+        print_hold_times(&*queue);
+        let n_pushed: Vec<_> = results.producers.into_iter().map(Result::unwrap).collect();
+        if queueing {
+            let n_samples = n_samples.max(1) as f64;
+            compare_mmc(config, &*queue, secs, &consumed, n_items as f64 / n_samples);
+            check_littles_law(secs, &consumed, n_in_flight as f64 / n_samples);
+        }
+        buffer.producers = n_pushed.iter().map(|&n_pushed| ProducerReport { n_pushed }).collect();
+        buffers.push(buffer);
+        print_totals(n_pushed.into_iter());
+        println!("    {}", diagnose(config, blocked, secs));
     }
+    if loads.len() > 1 {
+        println!("total: {:.0} ops/s", loads.iter().map(|load| load[0]).sum::<u64>() as f64 / secs);
+    }
+    finish_journal(journal, journey);
+    stop_echo(echo);
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    report_events(events.0, events.1, loads.iter().map(|load| load[0]).sum(), secs);
+    if let Some((start, end)) = switched { report_switches(start, end, loads.iter().map(|load| load[0]).sum()); }
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    perf::end();
+    if let (Some(recorder), Some(path)) = (&recorder, record) { save_trace(config, recorder, path); }
+    #[cfg(feature = "alloc-audit")]
+    check_allocations(n_allocs, record.is_some());
+    Report { command: "bench".to_string(), secs, buffers, verification: None }
 }
 
-// see the producer routine for comments
-fn consumer_routine<const BOUND: usize>(sbbuf: Arc<SyncedBoundedBuffer<BOUND>>) {
-    loop {
-        let mut bbuf = sbbuf.buffer.lock().unwrap();
-        while bbuf.empty() { bbuf = sbbuf.not_empty.wait(bbuf).unwrap(); }
+/* Chart how often the buffer was empty, full, or held each tenth (by `HISTOGRAM_BINS`) of the numbers of items in
+between, given how many samples found it holding each number from 0 to the capacity; empty and full get bars of their
+own, as they're where a consumer or a producer has to wait.
+*/
+fn print_histogram(n_times: &[u64]) {
+    let capacity = n_times.len() - 1;
+    let n_samples: u64 = n_times.iter().sum();
+    let mean = n_times.iter().enumerate().map(|(n, &n_times)| n as u64 * n_times).sum::<u64>() as f64;
+    println!("    occupancy over {} samples, mean {:.1} items:", n_samples, mean / n_samples.max(1) as f64);
+    let mut bars = vec![("empty".to_string(), n_times[0])];
+    if capacity > 1 {
+        let n_bins = HISTOGRAM_BINS.min(capacity - 1);
+        // bin k holds the partly full occupancies from `1 + k * (capacity - 1) / n_bins` on
+        let first = |k: usize| 1 + k * (capacity - 1) / n_bins;
+        for k in 0..n_bins {
+            let range = first(k)..first(k + 1);
+            let label = if range.len() == 1 { format!("{}", range.start) } else {
+                format!("{}-{}", range.start, range.end - 1)
+            };
+            bars.push((label, n_times[range].iter().sum()));
+        }
+    }
+    bars.push(("full".to_string(), n_times[capacity]));
+    let most = bars.iter().map(|&(_, n)| n).max().unwrap_or(0).max(1);
+    for (label, n) in bars {
+        let width = (n as usize * HISTOGRAM_WIDTH).div_ceil(most as usize);
+        println!(
+            "    {:>11} {:>5.1}% {}", label, n as f64 / n_samples.max(1) as f64 * 100.0, "#".repeat(width),
+        );
+    }
+}
 
-        bbuf.pop();
-        println!("{}", bbuf);
+/* Print how often each producer and consumer (by its name at the `end` snapshot, e.g. "producer 3") was switched out
+between the `start` and `end` snapshots, leaving out any which started or finished in between, and in total, per
+operation.
+*/
+fn report_switches(
+    start: BTreeMap<u64, (String, switches::Switches)>, end: BTreeMap<u64, (String, switches::Switches)>, n_ops: u64,
+) {
+    println!("context switches while measuring:");
+    println!("    {:<14} {:>12} {:>12}", "", "voluntary", "involuntary");
+    let mut total = switches::Switches::default();
+    for (id, (name, after)) in end {
+        if !(name.starts_with("producer ") || name.starts_with("consumer ")) { continue; }
+        let Some((_, before)) = start.get(&id) else { continue };
+        let (voluntary, involuntary) = (after.voluntary - before.voluntary, after.involuntary - before.involuntary);
+        println!("    {:<14} {:>12} {:>12}", name, voluntary, involuntary);
+        total.voluntary += voluntary;
+        total.involuntary += involuntary;
+    }
+    println!("    {:<14} {:>12} {:>12}", "total", total.voluntary, total.involuntary);
+    println!(
+        "    {:<14} {:>12.3} {:>12.3}",
+        "per op", total.voluntary as f64 / n_ops.max(1) as f64, total.involuntary as f64 / n_ops.max(1) as f64,
+    );
+}
 
-        sbbuf.not_full.notify_all();
+// print the events `perf` counted for the producers and consumers between `start` and `end`, per op and per second
+#[cfg(all(feature = "perf", target_os = "linux"))]
+fn report_events(start: perf::Counts, end: perf::Counts, n_ops: u64, secs: f64) {
+    println!("producers and consumers:");
+    for ((start, end), (_, _, name)) in start.into_iter().zip(end).zip(perf::EVENTS) {
+        match end.and_then(|end| Ok(end - start?)) {
+            Ok(n) => println!("    {:>12.3} {}/op, {:.0}/s", n as f64 / n_ops.max(1) as f64, name, n as f64 / secs),
+            Err(e) => println!("    {} unavailable: {}", name, e),
+        }
     }
 }
 
-fn main() {
-    const INVALID_ARGS_MSG: &str = "Invalid arguments. Correct usage: `rpc <n_producers> <n_consumers>`";
-    const BUF_SIZE: usize = 30; // arbitary choice
+// fail if producers or consumers allocated while measuring, unless recording, which allocates as the trace grows
+#[cfg(feature = "alloc-audit")]
+fn check_allocations(n_allocs: u64, recording: bool) {
+    match n_allocs {
+        0 => println!("no allocations by producers or consumers while measuring"),
+        _ if recording => println!("{} allocations while measuring, recording the trace", n_allocs),
+        _ => {
+            eprintln!("error: producers and consumers allocated {} times while measuring", n_allocs);
+            process::exit(1);
+        },
+    }
+}
+
+/* Run `bench` `n_runs` times, then summarize each buffer's throughput (and the total, with several buffers) over the
+runs, pointing out outliers, so that comparisons don't rest on a single noisy run. Returns each buffer's mean.
+*/
+fn bench_runs(config: &Config, options: &Bench) -> Vec<f64> {
+    let n_runs = options.runs;
+    let mut throughputs = Vec::new();
+    for run in 1..=n_runs {
+        println!("run {} of {}:", run, n_runs);
+        // `cli::parse` checks several runs aren't recorded or journaled
+        let report = bench(config, options);
+        throughputs.push(report.buffers.iter().map(|buffer| buffer.ops_per_sec).collect::<Vec<_>>());
+    }
+
+    let mut labels: Vec<_> = config.instances().into_iter().map(|(name, config)| label(name, config)).collect();
+    if labels.len() > 1 {
+        labels.push("total".to_string());
+        for run in &mut throughputs { run.push(run.iter().sum()); }
+    }
+    println!("over {} runs:", n_runs);
+    let mut means = Vec::new();
+    for (k, label) in labels.iter().enumerate() {
+        let summary = aggregate::summarize(&throughputs.iter().map(|run| run[k]).collect::<Vec<_>>());
+        means.push(summary.mean);
+        println!(
+            "    {}: mean {:.0} ops/s, sd {:.0} ({:.1}%), min {:.0}, max {:.0}",
+            label, summary.mean, summary.sd, summary.sd / summary.mean * 100.0, summary.min, summary.max,
+        );
+        for i in summary.outliers {
+            println!("        outlier: run {} at {:.0} ops/s", i + 1, throughputs[i][k]);
+        }
+    }
+    // not the total
+    means.truncate(config.instances().len());
+    means
+}
+
+/* Compare each buffer's throughput with that in `baseline`, for the buffer of the same name, and return whether none
+fell by more than `max_regression`, a fraction; a buffer missing from the baseline fails too, as it can't be checked.
+*/
+fn check_regression(config: &Config, throughputs: &[f64], baseline: &Report, max_regression: f64) -> bool {
+    println!("against the baseline (at most {:.1}% slower):", max_regression * 100.0);
+    let mut passed = true;
+    for ((name, config), &throughput) in config.instances().into_iter().zip(throughputs) {
+        let Some(before) = baseline.buffers.iter().find(|buffer| buffer.name == name).map(|buffer| buffer.ops_per_sec)
+        else {
+            eprintln!("error: {}: the baseline has no buffer named `{}`", label(name, config), name);
+            passed = false;
+            continue;
+        };
+        let (label, change) = (label(name, config), throughput / before - 1.0);
+        println!("    {}: {:.0} ops/s, against {:.0}: {:+.1}%", label, throughput, before, change * 100.0);
+        if -change > max_regression {
+            eprintln!("error: {}: the throughput fell by more than {:.1}%", label, max_regression * 100.0);
+            passed = false;
+        }
+    }
+    passed
+}
+
+// keep `report` as the baseline called `name`, replacing any kept before
+fn keep_baseline(name: &str, report: &Report) {
+    let path = report::baseline_path(name);
+    if let Err(e) = path.parent().map_or(Ok(()), fs::create_dir_all) {
+        eprintln!("error: can't create `{}`: {}", path.display(), e);
+        process::exit(1);
+    }
+    report::write(report, &path);
+    println!("saved the results as the baseline `{}`, in `{}`", name, path.display());
+}
+
+// one of the metrics `--compare` compares, from a report and one of its buffers, if the buffer has it
+type Metric = fn(&Report, &BufferReport) -> Option<f64>;
+
+/* Print how each buffer's metrics changed from the baseline `before`, called `name`, to `now`, for the buffer of the
+same name in each, and which of the buffer's options were different then, as they may explain the changes.
+*/
+fn compare_reports(name: &str, before: &Report, now: &Report) {
+    let metrics: [(&str, Metric); 11] = [
+        ("ops/s",                  |_, buffer| Some(buffer.ops_per_sec)),
+        ("waits/s",                |report, buffer| Some(buffer.n_waits as f64 / report.secs)),
+        ("wakes/s",                |report, buffer| Some(buffer.n_wakes as f64 / report.secs)),
+        ("steals/s",               |report, buffer| {
+            (buffer.config.backend == "sharded").then(|| buffer.n_steals as f64 / report.secs)
+        }),
+        ("peak items",             |_, buffer| Some(buffer.peak_items as f64)),
+        ("producers blocked, s/s", |report, buffer| Some(buffer.producers_blocked_secs / report.secs)),
+        ("consumers blocked, s/s", |report, buffer| Some(buffer.consumers_blocked_secs / report.secs)),
+        ("push hold p50, ns",      |_, buffer| buffer.hold_times.as_ref().map(|times| times.pushes.p50_nanos as f64)),
+        ("push hold p99, ns",      |_, buffer| buffer.hold_times.as_ref().map(|times| times.pushes.p99_nanos as f64)),
+        ("pop hold p50, ns",       |_, buffer| buffer.hold_times.as_ref().map(|times| times.pops.p50_nanos as f64)),
+        ("pop hold p99, ns",       |_, buffer| buffer.hold_times.as_ref().map(|times| times.pops.p99_nanos as f64)),
+    ];
+    println!("compared with the baseline `{}`:", name);
+    println!("    {:<28} {:>14} {:>14} {:>9}", "", "then", "now", "change");
+    for buffer in &now.buffers {
+        let label = if buffer.name.is_empty() { buffer.config.backend.clone() } else {
+            format!("{} ({})", buffer.name, buffer.config.backend)
+        };
+        let Some(then) = before.buffers.iter().find(|then| then.name == buffer.name) else {
+            println!("    {}: not in the baseline", label);
+            continue;
+        };
+        println!("    {}:", label);
+        let options = |buffer: &BufferReport| serde_json::to_value(&buffer.config).unwrap();
+        if let (Value::Object(was), Value::Object(is)) = (options(then), options(buffer)) {
+            for (option, value) in is.iter().filter(|&(option, value)| was.get(option) != Some(value)) {
+                println!("        {} was {}, now {}", option, was.get(option).unwrap_or(&Value::Null), value);
+            }
+        }
+        for (metric, value) in metrics {
+            let (Some(was), Some(is)) = (value(before, then), value(now, buffer)) else { continue };
+            let change = if was > 0.0 { format!("{:+.1}%", (is / was - 1.0) * 100.0) } else { String::new() };
+            println!("        {:<24} {:>14.precision$} {:>14.precision$} {:>9}", metric, was, is, change,
+                precision = if metric.ends_with(", s/s") { 3 } else { 0 });
+        }
+    }
+}
+
+// how far below `--baseline` the throughput may fall, if `--max-regression` isn't given
+const DEFAULT_MAX_REGRESSION: f64 = 0.1;
+
+// the numbers of producers (and of consumers) and the capacities which `bench_matrix` tries
+const MATRIX_THREADS: [usize; 4] = [1, 2, 4, 8];
+const MATRIX_CAPACITIES: [usize; 3] = [1, 16, 256];
+
+// run a buffer for `duration` after `warmup`, without reporting anything, and return its throughput in ops/s
+fn throughput(config: &Config, duration: Duration, warmup: Duration) -> f64 {
+    let runner = start(config, make_queue(config, false, None), None, None, Instant::now() + warmup, None);
+    thread::sleep(warmup);
+    let [baseline, ..] = runner.queue().stats().load();
+    let start = Instant::now();
+    thread::sleep(duration);
+    let [n_ops, ..] = runner.queue().stats().load();
+    let secs = start.elapsed().as_secs_f64();
+    runner.shutdown();
+    runner.join();
+    (n_ops - baseline) as f64 / secs
+}
 
-    let mut args = env::args();
-    args.next(); // ignore program name
-    let n_producers = args.next().expect(INVALID_ARGS_MSG).parse::<usize>().expect(INVALID_ARGS_MSG);
-    let n_consumers = args.next().expect(INVALID_ARGS_MSG).parse::<usize>().expect(INVALID_ARGS_MSG);
+/* Compare every backend's throughput with each of `MATRIX_THREADS` producers and as many consumers, and each of
+`MATRIX_CAPACITIES` it can be built with, in a table with a row for each combination, marking the fastest backend; with
+`csv`, also write the results to that file.
+*/
+fn bench_matrix(config: &Config, duration: Duration, warmup: Duration, csv: Option<&str>) {
+    let backends: Vec<_> = Backend::all().collect();
+    let mut text = String::from("backend,producers,consumers,capacity,ops_per_sec\n");
+    print!("{:>8} {:>9}", "threads", "capacity");
+    for backend in &backends { print!(" {:>13}", backend.name()); }
+    println!("  (ops/s)");
 
-    let mut producers = Vec::with_capacity(n_producers);
-    let mut consumers = Vec::with_capacity(n_consumers);
+    for n_threads in MATRIX_THREADS {
+        for capacity in MATRIX_CAPACITIES {
+            let results: Vec<_> = backends.iter().map(|&backend| {
+                if capacity < backend.min_capacity() { return None; }
+                let config =
+                    Config { backend, n_producers: n_threads, n_consumers: n_threads, capacity, ..config.clone() };
+                let ops_per_sec = throughput(&config, duration, warmup);
+                text += &format!("{},{},{},{},{:.0}\n", backend.name(), n_threads, n_threads, capacity, ops_per_sec);
+                Some(ops_per_sec)
+            }).collect();
+            let best = results.iter().flatten().copied().fold(0.0, f64::max);
+            print!("{:>8} {:>9}", n_threads, capacity);
+            for ops_per_sec in results {
+                match ops_per_sec {
+                    Some(ops_per_sec) =>
+                        print!(" {:>12.0}{}", ops_per_sec, if ops_per_sec == best { "*" } else { " " }),
+                    None => print!(" {:>12} ", "-"),
+                }
+            }
+            println!();
+        }
+    }
+    println!("(* the fastest backend for each number of threads and capacity)");
+
+    if let Some(path) = csv {
+        if let Err(e) = fs::write(path, text) {
+            eprintln!("error: can't write `{}`: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+// how efficient `bench_sweep` considers scaling to be worth it
+const SWEEP_MIN_EFFICIENCY: f64 = 0.5;
+
+/* Measure the throughput with 1, 2, 4, ... producers and as many consumers, up to `max_threads` (and including it,
+even if it isn't a power of 2), and how well it scales: the speedup compared with 1 of each, and the efficiency, the
+speedup per thread, which is 100% if the buffer doesn't limit the throughput at all.
+*/
+fn bench_sweep(config: &Config, max_threads: usize, duration: Duration, warmup: Duration) {
+    let mut counts: Vec<_> = (0..).map(|i| 1 << i).take_while(|&n| n < max_threads).collect();
+    counts.push(max_threads);
+
+    println!("{}: up to {} producers and as many consumers, {:.1}s each", config.backend.name(), max_threads,
+        duration.as_secs_f64());
+    println!("    {:>7} {:>12} {:>8} {:>11}", "threads", "ops/s", "speedup", "efficiency");
+    let mut base = None;
+    let mut stopped_scaling = None;
+    for n_threads in counts {
+        let config = Config { n_producers: n_threads, n_consumers: n_threads, ..config.clone() };
+        let ops_per_sec = throughput(&config, duration, warmup);
+        let speedup = ops_per_sec / *base.get_or_insert(ops_per_sec);
+        let efficiency = speedup / n_threads as f64;
+        println!("    {:>7} {:>12.0} {:>7.2}x {:>10.0}%", n_threads, ops_per_sec, speedup, efficiency * 100.0);
+        if efficiency < SWEEP_MIN_EFFICIENCY { stopped_scaling.get_or_insert(n_threads); }
+    }
+    match stopped_scaling {
+        Some(n_threads) => println!(
+            "    the efficiency falls below {:.0}% with {} threads", SWEEP_MIN_EFFICIENCY * 100.0, n_threads,
+        ),
+        None => println!("    the efficiency stays above {:.0}% throughout", SWEEP_MIN_EFFICIENCY * 100.0),
+    }
+}
+
+// how often `bench --target-p99` adjusts the capacity
+const CONTROL_INTERVAL: Duration = Duration::from_millis(100);
+
+/* Run for `duration` (after `warmup`) with a `Controller` adjusting the buffer's capacity every `CONTROL_INTERVAL`, up
+to `--capacity`, to hold the 99th percentile of the time items spend queued in it below `target`, logging each decision
+to stderr, then report the throughput, how the capacity moved and the time items spent queued over the whole run.
+Items are stamped by their trace ids as they're pushed and popped, as for a pipeline's breakdown.
+*/
+fn bench_target(config: &Config, target: Duration, duration: Duration, warmup: Duration) {
+    let measure_from = Instant::now() + warmup;
+    let buffer = Arc::new(
+        SyncedBoundedBuffer::builder().capacity(config.capacity).wake(config.wake).bias(config.bias)
+            .hold_times(config.hold_times).build(),
+    );
+    let breakdown = Breakdown::new(1, measure_from);
+    let runner = start(config, breakdown.stamp(buffer.clone(), 0), None, None, measure_from, None);
+
+    sleep_until(measure_from);
+    let baselines = warm_up(iter::once(&**runner.queue()));
+    let start = Instant::now();
+    let mut controller = Controller::new(target, config.capacity);
+    // every item's time queued, and the capacity after each interval
+    let (mut queued, mut capacities) = (Vec::new(), Vec::new());
+    let mut n_over = 0;
+    for i in 1..=duration.div_duration_f64(CONTROL_INTERVAL).floor().max(1.0) as u32 {
+        sleep_until(start + CONTROL_INTERVAL * i);
+        let interval = breakdown.take().swap_remove(0);
+        let from = controller.capacity();
+        let secs = start.elapsed().as_secs_f64();
+        if interval.is_empty() {
+            eprintln!("{:>7.1}s: no items popped, held the capacity at {}", secs, from);
+        } else {
+            let [_, _, p99, _] = breakdown::percentiles(interval.clone());
+            if p99 > target { n_over += 1; }
+            let done = match controller.decide(p99) {
+                Decision::Shrink => format!("shrank the capacity from {} to {}", from, controller.capacity()),
+                Decision::Grow   => format!("grew the capacity from {} to {}", from, controller.capacity()),
+                Decision::Hold   => format!("held the capacity at {}", from),
+            };
+            eprintln!("{:>7.1}s: p99 {:>10.3?} over {:>6} items, {}", secs, p99, interval.len(), done);
+            queued.extend(interval);
+        }
+        // shrinking waits for the consumers to bring the occupancy down
+        if controller.capacity() != from { buffer.set_capacity(controller.capacity()); }
+        capacities.push(controller.capacity());
+    }
+    let [n_ops, ..] = since(iter::once(&**runner.queue()), &baselines)[0];
+    let secs = start.elapsed().as_secs_f64();
+    runner.shutdown();
+
+    println!(
+        "{}: target p99 {:?}, {} producers, {} consumers, {:.1}s",
+        config.backend.name(), target, config.n_producers, config.n_consumers, secs,
+    );
+    println!("    {:>12.0} ops/s", n_ops as f64 / secs);
+    println!(
+        "    capacity {} at the end, between {} and {}, {:.1} on average over the {} intervals of {:?}",
+        controller.capacity(), capacities.iter().min().unwrap(), capacities.iter().max().unwrap(),
+        capacities.iter().sum::<usize>() as f64 / capacities.len() as f64, capacities.len(), CONTROL_INTERVAL,
+    );
+    println!("    p99 over the target in {} of the {} intervals", n_over, capacities.len());
+    let n_queued = queued.len();
+    let [p50, p90, p99, max] = breakdown::percentiles(queued);
+    println!(
+        "    queued: p50 {:.3?}, p90 {:.3?}, p99 {:.3?}, max {:.3?}, over {} items", p50, p90, p99, max, n_queued,
+    );
+    print_totals(runner.join().producers.into_iter().map(Result::unwrap));
+}
+
+/* Pass items of `item_bytes` bytes each from producers to consumers for `duration` (after `warmup`), and report the
+throughput in items and bytes. The backends only hold `isize`s, so this goes through a `ClosableBuffer`, whose items
+can be anything. Producers allocate each payload and fill it, and consumers read every byte before freeing it, so that
+the cost of moving that much memory between threads is included, however small the item. With `byte_budget`, the
+buffer holds at most that many bytes of payloads rather than `--capacity` of them, so bigger items leave room for fewer.
+With `recycle`, consumers give the payloads back to a `Recycler` keeping up to that many, made up front with room for
+the biggest, and producers fill those again rather than allocating, which is reported on too. With `arena_batch`,
+producers write that many payloads at a time into an arena instead, and push a handle to each one's slice of it. With
+`zero_copy`, the payloads go through a `SlotBuffer` instead, of `--capacity` slots of the biggest size, which
+producers write each one straight into, and consumers read it from in place.
+*/
+fn bench_payload(config: &Config, item_bytes: ItemBytes, options: &Bench) {
+    let Bench { byte_budget, recycle, arena_batch, zero_copy, .. } = *options;
+    let max_bytes = item_bytes.max();
+    let sample = |producer, n| item_bytes.sample(config.seed, producer, n);
+    // and what became of the payloads' memory, if anything special
+    let ((n_ops, n_bytes, secs), memory) = if zero_copy {
+        let slots = SlotBuffer::new(config.capacity, max_bytes);
+        let result = pass_payloads(config, options, |producer, push| {
+            for n in 0.. {
+                let Some(mut slot) = slots.reserve() else { break };
+                let len = sample(producer, n);
+                slot[..len].fill(producer as u8);
+                if !push((slot, len)) { break; }
+            }
+        }, |(slot, len)| slot.commit(len).is_ok(), || slots.read(), drop, || slots.close());
+        (result, Some(format!("{} slots of {} bytes, written and read in place", slots.n_slots(), max_bytes)))
+    } else if let Some(batch_len) = arena_batch {
+        let buffer = payload_buffer(config, byte_budget);
+        let stats = Mutex::new(ArenaStats::default());
+        let result = pass_payloads(config, options, |producer, push| {
+            let mut arenas = Arenas::new(batch_len * max_bytes);
+            'batches: for first in (0..).step_by(batch_len) {
+                let mut batch = arenas.batch();
+                for n in first..first + batch_len {
+                    batch.push_with(sample(producer, n), |bytes| bytes.fill(producer as u8));
+                }
+                for payload in batch.finish() {
+                    if !push(payload) { break 'batches; }
+                }
+            }
+            let mut stats = stats.lock().unwrap();
+            *stats = *stats + arenas.stats();
+        }, |payload| buffer.push(payload).is_ok(), || buffer.pop().ok(), drop, || {
+            buffer.close_with(OnClose::Discard);
+        });
+        let stats = stats.into_inner().unwrap();
+        let memory = format!(
+            "arenas of {} payloads: {} made, {} reused ({:.1}%), {} still held by payloads at the end",
+            batch_len, stats.n_made, stats.n_reused,
+            stats.n_reused as f64 * 100.0 / (stats.n_made + stats.n_reused).max(1) as f64, stats.n_held,
+        );
+        (result, Some(memory))
+    } else {
+        let buffer = payload_buffer(config, byte_budget);
+        let recycler = recycle.map(|max_free| Recycler::preallocated(max_free, || Vec::with_capacity(max_bytes)));
+        let result = pass_payloads(config, options, |producer, push| {
+            for n in 0.. {
+                let payload = match &recycler {
+                    Some(recycler) => {
+                        let mut payload = recycler.take(|| Vec::with_capacity(max_bytes));
+                        payload.clear();
+                        payload.resize(sample(producer, n), producer as u8);
+                        payload
+                    },
+                    None => vec![producer as u8; sample(producer, n)],
+                };
+                if !push(payload) { break; }
+            }
+        }, |payload| buffer.push(payload).is_ok(), || buffer.pop().ok(), |payload| {
+            if let Some(recycler) = &recycler { recycler.give(payload); }
+        }, || {
+            buffer.close_with(OnClose::Discard);
+        });
+        let memory = recycler.map(|recycler| {
+            let stats = recycler.stats();
+            format!(
+                "recycled payloads: {} hits, {} misses ({:.1}% hits), {} of up to {} free at the end, {} dropped",
+                stats.n_hits, stats.n_misses,
+                stats.n_hits as f64 * 100.0 / (stats.n_hits + stats.n_misses).max(1) as f64,
+                stats.n_free, recycler.max_free(), stats.n_dropped,
+            )
+        });
+        (result, memory)
+    };
+    let bound = match byte_budget {
+        None => format!("{} items", config.capacity),
+        Some(n_bytes) => format!("{} bytes", n_bytes),
+    };
+    println!("{} items of {} in {:.1}s, through a buffer of {}: {:.0} items/s, {:.1} MB/s", n_ops, item_bytes, secs,
+        bound, n_ops as f64 / secs, n_bytes as f64 / secs / 1e6);
+    if let Some(memory) = memory { println!("    {}", memory); }
+}
+
+// `bench_payload`'s buffer, bounded as `byte_budget` says
+fn payload_buffer<T: Deref<Target = [u8]>>(config: &Config, byte_budget: Option<usize>) -> ClosableBuffer<T> {
+    match byte_budget {
+        None => ClosableBuffer::new(config.capacity),
+        Some(n_bytes) => ClosableBuffer::with_byte_budget(n_bytes, |item| item.len()),
+    }
+}
+
+/* `bench_payload`'s producers and consumers, passing payloads through a buffer, and returning how many were popped
+while measuring, their total size, and how long that took in seconds. Each producer runs `produce` with its index and a
+function handing a payload to `push`, which says whether to go on; consumers take payloads from `pop` until it's out of
+them, read each one, then hand it to `consume`. Once done measuring, `close` has the buffer stop them all.
+*/
+fn pass_payloads<P, R: Deref<Target = [u8]>>(
+    config: &Config, options: &Bench,
+    produce: impl Fn(usize, &mut dyn FnMut(P) -> bool) + Sync, push: impl Fn(P) -> bool + Sync,
+    pop: impl Fn() -> Option<R> + Sync, consume: impl Fn(R) + Sync, close: impl FnOnce(),
+) -> (u64, u64, f64) {
+    let measure_from = Instant::now() + options.warmup;
+    let (n_popped, n_bytes_popped) = (AtomicU64::new(0), AtomicU64::new(0));
+    thread::scope(|scope| {
+        for producer in 0..config.n_producers {
+            let (produce, push) = (&produce, &push);
+            scope.spawn(move || {
+                let mut measuring = false;
+                produce(producer, &mut |payload| {
+                    if !measuring && Instant::now() >= measure_from {
+                        measuring = true;
+                        audit();
+                    }
+                    push(payload)
+                });
+            });
+        }
+        for consumer in 0..config.n_consumers {
+            let (pop, consume, n_popped, n_bytes_popped) = (&pop, &consume, &n_popped, &n_bytes_popped);
+            scope.spawn(move || {
+                let (mut n, mut measuring) = (0, false);
+                while let Some(item) = pop() {
+                    if !measuring && Instant::now() >= measure_from {
+                        measuring = true;
+                        audit();
+                    }
+                    std::hint::black_box(item.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+                    let work = config.work_time;
+                    if !work.is_zero() { thread::sleep(work.sample(config.seed, 2, consumer, n)); }
+                    n += 1;
+                    n_popped.fetch_add(1, Relaxed);
+                    n_bytes_popped.fetch_add(item.len() as u64, Relaxed);
+                    consume(item);
+                }
+            });
+        }
+        sleep_until(measure_from);
+        #[cfg(feature = "alloc-audit")]
+        audit::reset();
+        let (baseline, bytes_baseline, start) = (n_popped.load(Relaxed), n_bytes_popped.load(Relaxed), Instant::now());
+        thread::sleep(options.duration);
+        let result = (
+            n_popped.load(Relaxed) - baseline, n_bytes_popped.load(Relaxed) - bytes_baseline,
+            start.elapsed().as_secs_f64(),
+        );
+        #[cfg(feature = "alloc-audit")]
+        println!("{} allocations by producers and consumers while measuring", audit::n_allocs());
+        close();
+        result
+    })
+}
+
+/* Run a bounded thread pool for `duration` (after `warmup`): producers submit tasks, each sleeping for a `--work-time`
+and made afresh for every submit, as a boxed closure, and `--consumers` workers run them, through a queue of
+`--capacity` tasks. Then report how many ran, how long they took, and how many panicked, with the first messages. With
+no work time, the tasks do nothing, so this measures what the pool itself costs per task. With `keys`, the number of
+keys and the most tasks of a key to run at once, each task gets a key drawn by the seed; fewer keys leave fewer tasks
+which can run, so the workers end up waiting for them.
+*/
+fn bench_tasks(config: &Config, keys: Option<(usize, usize)>, duration: Duration, warmup: Duration) {
+    let pool = match keys {
+        None => TaskPool::new(config.n_consumers, config.capacity),
+        Some((_, per_key)) => TaskPool::keyed(config.n_consumers, config.capacity, per_key),
+    };
+    let (stats, secs) = thread::scope(|scope| {
+        for producer in 0..config.n_producers {
+            let (pool, work, seed) = (&pool, config.work_time, config.seed);
+            scope.spawn(move || {
+                for n in 0.. {
+                    let task = move || match work.sample(seed, 2, producer, n) {
+                        time if time.is_zero() => { std::hint::black_box((producer, n)); },
+                        time => thread::sleep(time),
+                    };
+                    let submitted = match keys {
+                        None => pool.submit(task),
+                        Some((n_keys, _)) => {
+                            let key = cli::mix(cli::mix(seed ^ producer as u64) ^ n as u64) % n_keys as u64;
+                            pool.submit_keyed(key, task)
+                        },
+                    };
+                    if submitted.is_err() { break; }
+                }
+            });
+        }
+        thread::sleep(warmup);
+        let (baseline, start) = (pool.stats(), Instant::now());
+        thread::sleep(duration);
+        let (stats, secs) = (pool.stats(), start.elapsed().as_secs_f64());
+        pool.shutdown(OnClose::Discard);
+        (TaskStats {
+            n_run: stats.n_run - baseline.n_run,
+            n_panicked: stats.n_panicked - baseline.n_panicked,
+            busy_time: stats.busy_time - baseline.busy_time,
+            ..stats
+        }, secs)
+    });
+    println!("{} tasks in {:.1}s on {} workers, through a queue of {}: {:.0} tasks/s", stats.n_run, secs,
+        config.n_consumers, config.capacity, stats.n_run as f64 / secs);
+    if let Some((n_keys, per_key)) = keys {
+        println!("    of {} keys, with at most {} of each key running at once", n_keys, per_key);
+    }
+    // the longest is over the warm-up too
+    println!("    each ran for {:?} on average, {:?} at most; {:.0}% of the workers' time was spent running them",
+        stats.busy_time.div_f64(stats.n_run.max(1) as f64), stats.max_time,
+        stats.busy_time.as_secs_f64() / (secs * config.n_consumers as f64) * 100.0);
+    println!("    {} panicked", stats.n_panicked);
+    for message in pool.panics() { println!("        {}", message); }
+}
+
+/* Print what an M/M/c queue would do with the arrival and service rates measured in a run of `secs` seconds, next to
+what `queue` did: how busy the consumers were, how many items it held on average, and how long items waited in it.
+The theory assumes Poisson arrivals, exponential service times (e.g. `--work-time exp:1ms`) and an unbounded queue, so
+it only matches a run which is like that: `--rate`'s arrivals are evenly spaced, and a buffer can fill up.
+*/
+fn compare_mmc(config: &Config, queue: &dyn Queue, secs: f64, consumed: &Consumed, queue_length: f64) {
+    if consumed.busy_time.is_zero() || consumed.n_popped == 0 {
+        println!("    no M/M/c comparison: the consumers didn't spend any time on items (see `--work-time`)");
+        return;
+    }
+    let c = config.n_consumers;
+    // in a steady state, items are pushed as fast as they're popped
+    let arrival_rate = consumed.n_popped as f64 / secs;
+    let service_rate = consumed.n_popped as f64 / consumed.busy_time.as_secs_f64();
+    println!("    M/M/c with λ = {:.1}/s, μ = {:.1}/s per consumer, c = {}:", arrival_rate, service_rate, c);
+
+    let utilization = consumed.busy_time.as_secs_f64() / (c as f64 * secs);
+    let wait = consumed.wait_time.as_secs_f64() / consumed.n_popped as f64;
+    match queueing::mmc(arrival_rate, service_rate, c) {
+        Some(theory) => {
+            println!("        {:<14} {:>10} {:>10}", "", "theory", "observed");
+            println!("        {:<14} {:>9.1}% {:>9.1}%", "utilization", theory.utilization * 1e2, utilization * 1e2);
+            println!("        {:<14} {:>10.2} {:>10.2}", "queue length", theory.queue_length, queue_length);
+            println!("        {:<14} {:>8.3}ms {:>8.3}ms", "wait", theory.wait * 1e3, wait * 1e3);
+        },
+        None => println!(
+            "        the consumers can't keep up (ρ ≥ 1), so the queue would grow without bound; observed: {:.1}% \
+            utilization, {:.2} items queued, {:.3}ms wait", utilization * 100.0, queue_length, wait * 1e3,
+        ),
+    }
+    if queue.stats().peak_items() >= queue.capacity() as u64 {
+        println!("        (the buffer filled up and held back the producers, which an unbounded queue never does)");
+    }
+}
+
+// how far apart L and λW can be before `check_littles_law` calls it a measurement problem
+const LITTLES_LAW_TOLERANCE: f64 = 0.1;
+
+/* Little's law says that in a steady state, the mean number of items in a system is the rate they go through it times
+the mean time each one spends in it, L = λW, whatever the distributions. For the items in flight through a buffer, L
+(`n_in_flight`, sampled), λ (the items popped per second) and W (each item's wait, from its timestamp) are all measured
+separately, so if they don't agree, at least one of the measurements must be wrong.
+*/
+fn check_littles_law(secs: f64, consumed: &Consumed, n_in_flight: f64) {
+    let throughput = consumed.n_popped as f64 / secs;
+    let wait = consumed.wait_time.as_secs_f64() / consumed.n_popped.max(1) as f64;
+    let (l, lambda_w) = (n_in_flight, throughput * wait);
+    // (both are 0 if nothing was popped)
+    let error = if l == lambda_w { 0.0 } else { (l - lambda_w).abs() / l.max(lambda_w) };
+    let warning = if error > LITTLES_LAW_TOLERANCE {
+        ", which suggests a measurement problem, e.g. a run too short or too few CPUs to sample it evenly"
+    } else {
+        ""
+    };
+    println!(
+        "    Little's law: L = {:.2} items in flight, λW = {:.1}/s × {:.3}ms = {:.2} ({:.1}% apart{})",
+        l, throughput, wait * 1e3, lambda_w, error * 100.0, warning,
+    );
+}
+
+// how many times `bench_pipeline` samples the buffers' occupancy, one column of the chart each
+const CHART_WIDTH: usize = 60;
+// from empty to full
+const SHADES: &[u8] = b" .:-=+*#";
+
+/* Like `bench`, for a pipeline whose stages after the producers spend `work` on each item: report each buffer's
+throughput and occupancy, and chart how full each one was over time, so that one can see a slow stage's buffer fill up,
+then the one before it, and so on upstream. With `csv`, also write the samples the chart is drawn from to that file.
+With `max_in_flight`, at most that many items are in the pipeline at once, and how many were is reported too. With
+`reorder_window`, the stages between the producers and the consumers pass items on in order.
+*/
+fn bench_pipeline(config: &Config, options: &Bench) {
+    let Bench { duration, warmup, ref work, ref csv, max_in_flight, reorder_window, ref journey, breakdown, .. } =
+        *options;
+    let (csv, journey) = (csv.as_deref(), journey.as_deref());
+    let measure_from = Instant::now() + warmup;
+    let in_flight = max_in_flight.map(|permits| Arc::new(Semaphore::new(permits)));
+    let reorders: Vec<_> = match reorder_window {
+        Some(window) => work[1..].iter().map(|_| Arc::new(Reorder::new(window))).collect(),
+        None => Vec::new(),
+    };
+    let journal = start_journal(journey);
+    let breakdown = breakdown.then(|| Breakdown::new(work.len(), measure_from));
+    let runners = start_pipeline(
+        config, work, in_flight.clone(), &reorders, measure_from, journal.as_ref(), breakdown.as_ref(),
+    );
+
+    sleep_until(measure_from);
+    let baselines = warm_up(runners.iter().map(|runner| &**runner.queue()));
+    let start = Instant::now();
+    // when each sample was taken, in seconds since the start, and how many items each buffer held
+    let mut samples = Vec::new();
+    // how many items were in flight across the whole pipeline, summed over the samples, and the most at once
+    let (mut n_in_flight, mut peak_in_flight) = (0, 0);
+    for i in 1..=CHART_WIDTH {
+        sleep_until(start + duration.mul_f64(i as f64 / CHART_WIDTH as f64));
+        let n_items: Vec<_> = runners.iter().map(|runner| runner.queue().n_items()).collect();
+        samples.push((start.elapsed().as_secs_f64(), n_items));
+        if let Some(in_flight) = &in_flight {
+            let n = in_flight.n_acquired();
+            (n_in_flight, peak_in_flight) = (n_in_flight + n, peak_in_flight.max(n));
+        }
+    }
+    let loads = since(runners.iter().map(|runner| &**runner.queue()), &baselines);
+    let secs = start.elapsed().as_secs_f64();
+    for runner in &runners { runner.shutdown(); }
+
+    println!(
+        "{}: {} stages, {} producers, {} consumers per later stage, {:.1}s",
+        config.backend.name(), work.len() + 1, config.n_producers, config.n_consumers, secs,
+    );
+    for (k, (runner, [n_ops, ..])) in runners.iter().zip(&loads).enumerate() {
+        println!(
+            "    buffer {} (stage {} to {}): {:>12.0} ops/s, {}; stage {} works {}",
+            k + 1, k + 1, k + 2, *n_ops as f64 / secs, occupancy(&**runner.queue()), k + 2, work[k],
+        );
+    }
+    println!(
+        "    occupancy over time (each column is {:.2}s, from empty `{}` to full `{}`):",
+        secs / CHART_WIDTH as f64, SHADES[0] as char, SHADES[SHADES.len() - 1] as char,
+    );
+    // how full each buffer was on average
+    let mut fill = Vec::new();
+    for (k, runner) in runners.iter().enumerate() {
+        let capacity = runner.queue().capacity();
+        let row: String = samples.iter().map(|(_, n_items)| {
+            SHADES[(n_items[k] * (SHADES.len() - 1) + capacity / 2) / capacity] as char
+        }).collect();
+        println!("    buffer {:<3} |{}|", k + 1, row);
+        let n_items: usize = samples.iter().map(|(_, n_items)| n_items[k]).sum();
+        fill.push(n_items as f64 / (samples.len() * capacity) as f64);
+    }
+    // the stages before a bottleneck back up behind it, so it's the one after the last mostly full buffer
+    match fill.iter().rposition(|&fill| fill >= 0.5) {
+        Some(k) => println!("    bottleneck: stage {} (its buffer was {:.0}% full on average)", k + 2, fill[k] * 100.0),
+        None => println!("    bottleneck: the producers (no buffer was more than half full on average)"),
+    }
+    for (k, reorder) in reorders.iter().enumerate() {
+        println!(
+            "    stage {} held back {} items finished out of order, at most {} at once (window {})",
+            k + 2, reorder.n_held_back(), reorder.peak_held(), reorder.window(),
+        );
+    }
+    if let Some(in_flight) = &in_flight {
+        println!(
+            "    in flight across the pipeline: {:.1} items on average, at most {} sampled, of {} allowed",
+            n_in_flight as f64 / CHART_WIDTH as f64, peak_in_flight, in_flight.permits(),
+        );
+    }
+
+    if let Some(path) = csv {
+        let mut text = String::from("seconds");
+        for k in 0..runners.len() { text += &format!(",buffer {}", k + 1); }
+        for (time, n_items) in &samples {
+            text += &format!("\n{:.3}", time);
+            for n in n_items { text += &format!(",{}", n); }
+        }
+        text += "\n";
+        if let Err(e) = fs::write(path, text) {
+            eprintln!("error: can't write `{}`: {}", path, e);
+            process::exit(1);
+        }
+    }
+    // with a single stage after the producers, the first runner is also the last
+    let mut results: Vec<_> = runners.into_iter().map(Runner::join).collect();
+    let consumers = mem::take(&mut results.last_mut().unwrap().consumers).into_iter().map(Result::unwrap);
+    let consumed = consumers.fold(Consumed::default(), Consumed::add);
+    if !warmup.is_zero() {
+        println!(
+            "    excluding a {:?} warm-up, and the {} items the last stage popped in it", warmup, consumed.n_excluded,
+        );
+    }
+    print_duplicates(config, &consumed);
+    if let Some(breakdown) = &breakdown {
+        let (rows, n_items) = breakdown.report();
+        println!("    where the {} items which went all the way through spent their time:", n_items);
+        for (name, [p50, p90, p99, max]) in rows {
+            println!(
+                "        {:<24} p50 {:>10.3?}, p90 {:>10.3?}, p99 {:>10.3?}, max {:>10.3?}", name, p50, p90, p99, max,
+            );
+        }
+    }
+    print_totals(mem::take(&mut results[0].producers).into_iter().map(Result::unwrap));
+    finish_journal(journal, journey);
+}
+
+// how many samples a tap passed on over the whole run, including any warm-up
+fn print_tap(tap: &Tap) {
+    let sampling = match tap.sampling() {
+        Sampling::OneIn(n)        => format!("1 in {}", n),
+        Sampling::PerSecond(rate) => format!("up to {}/s", rate),
+    };
+    println!(
+        "    tap ({}): {} items sampled, {} missed as it fell behind", sampling, tap.n_sampled(), tap.n_missed(),
+    );
+}
+
+// with `--hold-times`, how long pushes and pops held the lock over the whole run, including any warm-up; the quantiles
+// are rounded up to a power of two nanoseconds
+fn print_hold_times(queue: &dyn Queue) {
+    let Some(hold_times) = queue.hold_times() else { return };
+    let [pushes, pops] = [Side::Producer, Side::Consumer].map(|side| &hold_times[side as usize]);
+    for (side, times) in [("pushes", pushes), ("pops", pops)] {
+        println!(
+            "    lock held by {}: p50 {:?}, p99 {:?}, max {:?}, over {} holds",
+            side, times.quantile(0.5), times.quantile(0.99), times.max(), times.n_holds(),
+        );
+    }
+}
 
-    let bounded_buffer = Arc::from(SyncedBoundedBuffer::<BUF_SIZE>::default());
+// with `--dedup-window`, how many of the items popped the consumers skipped as duplicates
+fn print_duplicates(config: &Config, consumed: &Consumed) {
+    if let Some(window) = config.dedup_window {
+        let n_popped = consumed.n_popped + consumed.n_duplicates;
+        println!(
+            "    {} of {} items popped were duplicates, and skipped (window of {})",
+            consumed.n_duplicates, n_popped, window,
+        );
+    }
+}
+
+/* Each producer pushes `n_items` distinct items through `queue`, its threads beating `heartbeats` if given; returns how
+many items weren't popped, how many were popped more than once, and how many a consumer popped after a later one from
+the same producer. Every backend is FIFO, or at least FIFO for each producer's items, so no consumer should see any of
+a producer's items out of order (although a duplicate counts as one, as it comes after the original).
+*/
+fn pass_items(
+    config: &Config, queue: Arc<dyn Queue>, n_items: usize, heartbeats: Option<Arc<Heartbeats>>,
+) -> (usize, usize, usize) {
+    let mut runner = Runner::new(queue, config.strategy);
+    if let Some(heartbeats) = heartbeats { runner = runner.with_heartbeats(heartbeats); }
+    let n_total = config.n_producers * n_items;
+
+    let (batch_size, flush_interval, node) = (config.producer_batch, config.flush_interval, config.numa_node);
+    for i in 0..config.n_producers {
+        runner.spawn_producer(move |queue, locker| {
+            numa::pin(node);
+            Producer::new(queue, locker).with_batch(batch_size, flush_interval)
+                .extend((0..n_items).map(|k| (i * n_items + k) as isize));
+        });
+    }
+
+    // consumers claim an item before popping it, so that they stop once every item has been claimed instead of
+    // blocking forever on an empty buffer
+    let n_unclaimed = Arc::new(AtomicUsize::new(n_total));
+    for _ in 0..config.n_consumers {
+        let (n_unclaimed, node) = (n_unclaimed.clone(), config.consumer_node.or(config.numa_node));
+        runner.spawn_consumer(move |queue, locker| {
+            numa::pin(node);
+            let mut popped = Vec::new();
+            while n_unclaimed.fetch_update(Relaxed, Relaxed, |n| n.checked_sub(1)).is_ok() {
+                let Ok(item) = queue.pop(locker) else { break };
+                popped.push(item);
+            }
+            popped
+        });
+    }
+
+    let (mut n_times_popped, mut n_reordered) = (vec![0usize; n_total], 0);
+    for popped in runner.join().consumers {
+        // the next of each producer's items this consumer may pop, by its index among them
+        let mut next = vec![0; config.n_producers];
+        for item in popped.unwrap() {
+            let item = item as usize;
+            n_times_popped[item] += 1;
+            let (producer, k) = (item / n_items, item % n_items);
+            if k < next[producer] { n_reordered += 1; } else { next[producer] = k + 1; }
+        }
+    }
+    let n_lost       = n_times_popped.iter().filter(|&&n| n == 0).count();
+    let n_duplicated = n_times_popped.iter().filter(|&&n| n > 1).count();
+    (n_lost, n_duplicated, n_reordered)
+}
+
+// each producer pushes `n_items` distinct items; returns whether every item was popped exactly once, and in order,
+// writing that to `report` too if given
+fn verify(config: &Config, n_items: usize, record: Option<&str>, report: Option<&str>) -> bool {
+    let recorder = record.map(|_| Arc::new(Recorder::default()));
+    let queue = make_queue(config, false, recorder.clone());
+    let start = Instant::now();
+    let (n_lost, n_duplicated, n_reordered) = pass_items(config, queue.clone(), n_items, None);
+    let secs = start.elapsed().as_secs_f64();
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    perf::end();
+    if let (Some(recorder), Some(path)) = (&recorder, record) { save_trace(config, recorder, path); }
+
+    let n_total = config.n_producers * n_items;
+    println!(
+        "{}: {} items through {} producers and {} consumers; {} lost, {} duplicated, {} out of order",
+        config.backend.name(), n_total, config.n_producers, config.n_consumers, n_lost, n_duplicated, n_reordered,
+    );
+    let passed = n_lost == 0 && n_duplicated == 0 && n_reordered == 0;
+    if let Some(path) = report {
+        let buffer = BufferReport::new("", config, &*queue, queue.stats().load(), queue.stats().blocked(), secs);
+        let verification = Verification { n_items: n_total, n_lost, n_duplicated, n_reordered, passed };
+        let buffers = vec![buffer];
+        report::write(&Report { command: "verify".to_string(), secs, buffers, verification: Some(verification) }, path);
+    }
+    passed
+}
 
-    // spawn the threads
-    for i in 0..n_producers {
-        let buf = bounded_buffer.clone();
-        producers.push( thread::spawn(move || producer_routine(buf, i as isize)) );
+// the numbers of producers (and, separately, of consumers), capacities and producer batch sizes `soak` cycles through
+const SOAK_THREADS: [usize; 3] = [1, 2, 4];
+const SOAK_CAPACITIES: [usize; 3] = [1, 16, 256];
+const SOAK_BATCHES: [usize; 2] = [1, 8];
+// how long a round of `soak` may take before it's taken to have hung
+const SOAK_ROUND_TIMEOUT: Duration = Duration::from_secs(60);
+// how much the memory in use may grow over a soak, as a fraction of that at the first checkpoint, before it's reported
+// as a likely leak
+const SOAK_MAX_GROWTH: f64 = 0.5;
+
+// round `round` of `soak`: every backend in turn, then each with the next combination of the rest, so that a failing
+// round can be rerun from its number alone; except the spinning one, which with more threads than CPUs can take longer
+// than the watchdog allows without being stuck
+fn soak_config(config: &Config, round: usize) -> Config {
+    let backends: Vec<_> = Backend::all().filter(|backend| !matches!(backend, Backend::Spin)).collect();
+    let mut rest = round;
+    let mut next = |n: usize| {
+        let i = rest % n;
+        rest /= n;
+        i
+    };
+    let backend = backends[next(backends.len())];
+    let (n_producers, n_consumers) = (SOAK_THREADS[next(SOAK_THREADS.len())], SOAK_THREADS[next(SOAK_THREADS.len())]);
+    let capacity = SOAK_CAPACITIES[next(SOAK_CAPACITIES.len())].max(backend.min_capacity());
+    let producer_batch = SOAK_BATCHES[next(SOAK_BATCHES.len())];
+    // `verify`'s consumers stop after popping their share of the items, wherever those are
+    Config { backend, n_producers, n_consumers, capacity, producer_batch, steal: true, ..config.clone() }
+}
+
+// the memory this process has in use (its resident set), on Linux
+fn resident_memory() -> Option<usize> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?.trim().strip_suffix("kB")?.trim();
+    Some(kib.parse::<usize>().ok()? * 1024)
+}
+
+fn mib(bytes: usize) -> f64 { bytes as f64 / (1 << 20) as f64 }
+
+/* Describe a failed round of `soak` in `soak-failure-<name>.txt`: its options, the state the buffer was left in, and
+what each thread was last doing and for how long, which for a round which hung shows who's waiting for what, and who's
+stuck outside the buffer. With `recorder`, the round's pushes and pops so far also go to `soak-failure-<name>.bin`, to
+replay with `simulate --replay`. `name` is the round's number, and for a rerun, which time it was.
+*/
+fn dump_failure(
+    config: &Config, round: usize, name: &str, failure: &str, queue: &dyn Queue, heartbeats: &Heartbeats,
+    recorder: Option<&Recorder>,
+) {
+    let [n_ops, n_waits, n_wakes, n_steals] = queue.stats().load();
+    let [n_producers, n_consumers] = queue.stats().n_waiting();
+    let items = queue.snapshot();
+    let mut text = format!(
+        "round {}: {}\n\
+        backend {}, {} producers, {} consumers, capacity {}, producer batch {}\n\
+        {} ops, {} waits, {} wakes, {} steals\n\
+        {}\n\
+        waiting: {} producers for room, {} consumers for an item\n\
+        buffer ({} items): {:?}\n",
+        round, failure, config.backend.name(), config.n_producers, config.n_consumers, config.capacity,
+        config.producer_batch, n_ops, n_waits, n_wakes, n_steals, occupancy(queue),
+        n_producers, n_consumers, items.len(), items,
+    );
+    for worker in heartbeats.workers() { text += &format!("{}\n", worker); }
+    let path = format!("soak-failure-{}.txt", name);
+    match fs::write(&path, text) {
+        Ok(()) => eprintln!("wrote the state of round {} to `{}`", round, path),
+        Err(e) => eprintln!("error: can't write `{}`: {}", path, e),
+    }
+    if let Some(recorder) = recorder { save_trace(config, recorder, &format!("soak-failure-{}.bin", name)); }
+}
+
+/* Pass items through buffers over and over for `duration`, checking every time that every item was popped exactly once,
+and in order, as `verify` does, with each round's options from `soak_config`. Every `checkpoint`, report how many rounds
+have passed, and how much memory is in use, which shouldn't keep growing. A round which loses, duplicates or reorders
+items, or which hangs for `SOAK_ROUND_TIMEOUT`, is dumped with `dump_failure`, and ends the soak; returns whether none
+did. Rounds are recorded, where the backend can be, so that a failure can be replayed. With `only`, every round is
+that round, to rerun a failure which only shows up now and then.
+*/
+fn soak(config: &Config, duration: Duration, n_items: usize, checkpoint: Duration, only: Option<usize>) -> bool {
+    let start = Instant::now();
+    let (mut n_rounds, mut n_total, mut next_checkpoint, mut baseline) = (0, 0, start + checkpoint, None);
+    if let Some(only) = only {
+        let round = soak_config(config, only);
+        println!(
+            "rerunning round {}: backend {}, {} producers, {} consumers, capacity {}, producer batch {}",
+            only, round.backend.name(), round.n_producers, round.n_consumers, round.capacity, round.producer_batch,
+        );
     }
-    for _ in 0..n_consumers {
-        let buf = bounded_buffer.clone();
-        consumers.push( thread::spawn(move || consumer_routine(buf)) );
+    while start.elapsed() < duration {
+        let number = only.unwrap_or(n_rounds);
+        let round = soak_config(config, number);
+        let recorder = (!matches!(round.backend, Backend::Sharded)).then(|| Arc::new(Recorder::default()));
+        let queue = make_queue(&round, false, recorder.clone());
+        let (done, outcome) = mpsc::channel();
+        let heartbeats = Arc::new(Heartbeats::default());
+        let (round_config, round_queue, round_heartbeats) = (round.clone(), queue.clone(), heartbeats.clone());
+        let handle = spawn(format!("round {}", number), move || {
+            let _ = done.send(pass_items(&round_config, round_queue, n_items, Some(round_heartbeats)));
+        });
+        let failure = match outcome.recv_timeout(SOAK_ROUND_TIMEOUT) {
+            Ok((0, 0, 0)) => None,
+            Ok((n_lost, n_duplicated, n_reordered)) =>
+                Some(format!("{} lost, {} duplicated, {} out of order", n_lost, n_duplicated, n_reordered)),
+            Err(_) => Some(format!("hung for {}s", SOAK_ROUND_TIMEOUT.as_secs())),
+        };
+        if let Some(failure) = failure {
+            eprintln!("round {} failed after {:.0}s: {}", number, start.elapsed().as_secs_f64(), failure);
+            // a rerun's dump doesn't replace the one of the run it reproduces
+            let name = match only {
+                Some(_) => format!("{}-rerun-{}", number, n_rounds),
+                None => number.to_string(),
+            };
+            // a round which hung is left running, as there's no stopping it from here; exiting ends it
+            dump_failure(&round, number, &name, &failure, &*queue, &heartbeats, recorder.as_deref());
+            return false;
+        }
+        handle.join().unwrap();
+        n_rounds += 1;
+        n_total += round.n_producers * n_items;
+
+        if Instant::now() >= next_checkpoint {
+            next_checkpoint += checkpoint;
+            let memory = resident_memory();
+            // the first checkpoint is the baseline, once the allocator has settled
+            let baseline = *baseline.get_or_insert(memory);
+            print!("{:.0}s: {} rounds, {} items, all passed", start.elapsed().as_secs_f64(), n_rounds, n_total);
+            match (memory, baseline) {
+                (Some(memory), Some(baseline)) => println!(
+                    "; {:.1} MiB in use ({:+.1} MiB since the first checkpoint)",
+                    mib(memory), mib(memory) - mib(baseline),
+                ),
+                _ => println!(),
+            }
+        }
     }
 
-    // wait for all threads to complete (which will never happen since they're infinite loops)
-    for thread in producers { thread.join().unwrap(); };
-    for thread in consumers { thread.join().unwrap(); };
+    let through = only.map_or("every backend".to_string(), |only| format!("round {}'s options", only));
+    println!("{} rounds, {} items through {}; none lost, duplicated or reordered", n_rounds, n_total, through);
+    if let (Some(memory), Some(Some(baseline))) = (resident_memory(), baseline) {
+        if memory as f64 > baseline as f64 * (1.0 + SOAK_MAX_GROWTH) {
+            println!(
+                "warning: the memory in use grew from {:.1} MiB to {:.1} MiB, which suggests a leak",
+                mib(baseline), mib(memory),
+            );
+        }
+    }
+    true
+}
+
+/* Take turns between producers and consumers on a single thread for `n_steps`, printing every step. With `rewind`, then
+go back to how the buffer and the producers were after that step, and run the steps from there again, which should go
+exactly the same way; returns whether they did, and false if it went idle (with nobody to take a turn) before that
+step.
+*/
+fn simulate(config: &Config, n_steps: usize, rewind: Option<usize>) -> bool {
+    let (generator, seed) = (config.generator, config.seed);
+    // in cells, so that a checkpoint can include them
+    let n_pushed: Vec<_> = (0..config.n_producers).map(|_| Cell::new(0)).collect();
+    let mut driver = CooperativeDriver::new(
+        config.capacity, config.n_producers, config.n_consumers,
+        |producer| {
+            n_pushed[producer].set(n_pushed[producer].get() + 1);
+            generator.item(seed, producer, n_pushed[producer].get() - 1)
+        },
+        |_, _| {},
+    );
+    let (mut checkpoint, mut steps) = (None, Vec::new());
+    for n in 0..n_steps {
+        if rewind == Some(n) {
+            checkpoint = Some((driver.checkpoint(), n_pushed.iter().map(Cell::get).collect::<Vec<_>>()));
+        }
+        let step = driver.step();
+        if step == Step::Idle { break; }
+        let line = format!("{:<40} {}", format!("{:?}", step), driver.buffer());
+        println!("{}", line);
+        steps.push(line);
+    }
+    print_totals(n_pushed.iter().map(Cell::get));
+    let Some(rewound_to) = rewind else { return true };
+    // with nobody to take a turn, it stopped before reaching the step, so there's nothing to go back to
+    let Some((checkpoint, pushed)) = checkpoint else {
+        eprintln!("error: can't rewind to step {}: the simulation went idle after {} steps", rewound_to, steps.len());
+        return false;
+    };
+
+    driver.restore(checkpoint);
+    for (cell, n) in n_pushed.iter().zip(pushed) { cell.set(n); }
+    println!("rewound to step {}", rewound_to);
+    for (n, expected) in steps.iter().enumerate().skip(rewound_to) {
+        let line = format!("{:<40} {}", format!("{:?}", driver.step()), driver.buffer());
+        println!("{}", line);
+        if line != *expected {
+            eprintln!("error: step {} went differently after rewinding: it was `{}`", n, expected);
+            return false;
+        }
+    }
+    true
+}
+
+/* Replay a trace file on a single thread, one recorded operation per step, printing every step.
+The capacity and the numbers of producers and consumers come from the trace. Since the trace is in the order the
+operations took effect, every step should succeed and every pop should get the item it got when recorded; returns
+whether they all did. With `speed`, each step is taken when it was in the recorded run, with the time between them
+divided by `speed`; otherwise as soon as the last is printed.
+*/
+fn replay(path: &str, speed: Option<f64>) -> bool {
+    let trace = File::open(path).and_then(|file| Trace::read_from(BufReader::new(file))).unwrap_or_else(|e| {
+        eprintln!("error: can't read `{}`: {}", path, e);
+        process::exit(2);
+    });
+    if speed.is_some() && !trace.timed {
+        eprintln!("error: `{}` was recorded without times, so it can only be replayed at `--speed max`", path);
+        process::exit(2);
+    }
+    let (start, first) = (Instant::now(), trace.events.first().map_or(0, |event| event.nanos));
+    let n_threads = |op| trace.events.iter().filter(|e| e.op == op).map(|e| e.thread as usize + 1).max().unwrap_or(0);
+
+    // the driver asks for items and hands them back through these
+    let (next_item, popped) = (Cell::new(0), Cell::new(None));
+    let mut driver = CooperativeDriver::new(
+        trace.capacity, n_threads(Op::Push), n_threads(Op::Pop),
+        |_| next_item.get(),
+        |_, item| popped.set(Some(item)),
+    );
+    for (n, event) in trace.events.iter().enumerate() {
+        if let Some(speed) = speed { sleep_until(start + Duration::from_nanos(event.nanos - first).div_f64(speed)); }
+        let step = match event.op {
+            Op::Push => {
+                next_item.set(event.item);
+                driver.step_producer(event.thread as usize)
+            },
+            Op::Pop => driver.step_consumer(event.thread as usize),
+        };
+        println!("{:<40} {}", format!("{:?}", step), driver.buffer());
+
+        let diverged = match step {
+            Step::Produced { .. } => false,
+            Step::Consumed { .. } => popped.take() != Some(event.item),
+            _ => true,
+        };
+        if diverged {
+            eprintln!("error: operation {} doesn't match the trace, which has {:?}", n, event);
+            return false;
+        }
+    }
+    true
+}
+
+// how long `orchestrate` waits for its workers to connect, before giving up on them
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/* Measure what crossing processes costs: run the buffer with producer and consumer threads for `duration`, as `bench`
+would, then with each producer and consumer a process of its own (`pc worker`), which this process pushes and pops for
+over TCP on the loopback, and compare the two. Only the numbers of producers and consumers, the capacity, the backend
+and its options and the strategy are taken from `config`, as the workers don't pace themselves or work on items.
+Returns whether every worker connected, and exited cleanly.
+*/
+fn orchestrate(config: &Config, duration: Duration, credit_window: usize) -> bool {
+    let config = &Config {
+        n_producers: config.n_producers, n_consumers: config.n_consumers, capacity: config.capacity,
+        strategy: config.strategy, backend: config.backend, wake: config.wake, bias: config.bias,
+        n_shards: config.n_shards, steal: config.steal, ..Config::default()
+    };
+    let threaded = throughput(config, duration, Duration::ZERO);
+    println!("{}: {} producers, {} consumers", config.backend.name(), config.n_producers, config.n_consumers);
+    println!("    {:>12.0} ops/s with threads", threaded);
+
+    let fail = |what: &str, e: io::Error| -> ! {
+        eprintln!("error: can't {}: {}", what, e);
+        process::exit(1);
+    };
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap_or_else(|e| fail("listen", e));
+    let address = listener.local_addr().unwrap_or_else(|e| fail("listen", e)).to_string();
+    let exe = env::current_exe().unwrap_or_else(|e| fail("find `pc`", e));
+    let roles =
+        iter::repeat_n(Role::Producer, config.n_producers).chain(iter::repeat_n(Role::Consumer, config.n_consumers));
+    let workers: Vec<_> = roles.map(|role| {
+        let worker = process::Command::new(&exe)
+            .args(["worker", "--role", role.name(), "--connect", &address])
+            .args(["--credit-window", &credit_window.to_string()])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| fail("start a worker", e));
+        (role, worker)
+    }).collect();
+
+    // every worker connects before any is served, so that they all run for the whole measurement
+    listener.set_nonblocking(true).unwrap_or_else(|e| fail("listen", e));
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    let mut streams = Vec::new();
+    while streams.len() < workers.len() {
+        match listener.accept() {
+            Ok((stream, _)) => streams.push(stream),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline =>
+                thread::sleep(Duration::from_millis(1)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                eprintln!("error: only {} of {} workers connected", streams.len(), workers.len());
+                for (_, mut worker) in workers { let _ = worker.kill(); }
+                return false;
+            },
+            Err(e) => fail("accept a worker", e),
+        }
+    }
+    let queue = make_queue(config, false, None);
+    let cancellation = CancellationToken::new();
+    let servers: Vec<_> = streams.iter().enumerate().map(|(i, stream)| {
+        let stream = stream.try_clone().unwrap_or_else(|e| fail("serve a worker", e));
+        let (queue, strategy, cancellation) = (queue.clone(), config.strategy, cancellation.clone());
+        stream.set_nonblocking(false).unwrap_or_else(|e| fail("serve a worker", e));
+        spawn(format!("serving {}", i), move || orchestrate::serve(stream, &*queue, strategy, i, cancellation))
+    }).collect();
+
+    let [baseline, ..] = queue.stats().load();
+    let start = Instant::now();
+    thread::sleep(duration);
+    let [n_ops, ..] = queue.stats().load();
+    let secs = start.elapsed().as_secs_f64();
+    // servers blocked on the buffer give up, and hanging up on the workers stops them
+    cancellation.cancel();
+    for stream in &streams { let _ = stream.shutdown(Shutdown::Both); }
+    for server in servers { let _ = server.join(); }
+    let processes = (n_ops - baseline) as f64 / secs;
+    match credit_window {
+        0 => println!("    {:>12.0} ops/s with processes, over TCP", processes),
+        n => println!("    {:>12.0} ops/s with processes, over TCP with a window of {} credits", processes, n),
+    }
+
+    let mut clean = true;
+    let mut ids = [0, 0];
+    for (role, worker) in workers {
+        let id = worker.id();
+        let output = worker.wait_with_output().unwrap_or_else(|e| fail("wait for a worker", e));
+        let text = String::from_utf8_lossy(&output.stdout);
+        let reported: Option<(u64, u64)> = text.trim().split_once(' ').and_then(|(n_items, nanos)| {
+            Some((n_items.parse().ok()?, nanos.parse().ok()?))
+        });
+        let k = &mut ids[role as usize];
+        match reported {
+            Some((n_items, nanos)) if output.status.success() => println!(
+                "        {} {} (pid {}): {} items, {:.1} µs a {}",
+                role.name(), k, id, n_items, nanos as f64 / n_items.max(1) as f64 / 1e3,
+                if role == Role::Producer { "push" } else { "pop" },
+            ),
+            _ => {
+                eprintln!("error: {} {} (pid {}) failed: {}", role.name(), k, id, output.status);
+                clean = false;
+            },
+        }
+        *k += 1;
+    }
+    println!(
+        "    {:.2} µs an op with processes, against {:.2} with threads: {:.1} times as long",
+        1e6 / processes, 1e6 / threaded, threaded / processes,
+    );
+    clean
+}
+
+fn main() {
+    /* A panic in any thread ends the process. Otherwise the threads on the other side of the buffer could block forever
+    waiting for the one which panicked, and `main` would never get to join it. The default hook has already said which
+    thread it was (by name) and why. A task panicking on a `TaskPool`'s worker is caught and counted instead, so that
+    one unwinds as usual.
+    */
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if pool::catches_panics() { return; }
+        eprintln!("error: thread `{}` panicked; stopping", thread::current().name().unwrap_or("<unnamed>"));
+        process::exit(101);
+    }));
+
+    let (config, command) = cli::parse(env::args().skip(1), |name| env::var(name).ok()).unwrap_or_else(|e| {
+        eprintln!("error: {}\n\n{}", e, cli::USAGE);
+        process::exit(2);
+    });
+
+    match command {
+        Command::Run(options) => run(&config, &options),
+        Command::Bench(options) => match *options {
+            Bench { stages, ref csv, .. } if stages > 2 || csv.is_some() => bench_pipeline(&config, &options),
+            Bench { duration, warmup, matrix: true, ref matrix_csv, .. } =>
+                bench_matrix(&config, duration, warmup, matrix_csv.as_deref()),
+            Bench { item_bytes: Some(item_bytes), .. } => bench_payload(&config, item_bytes, &options),
+            Bench { duration, warmup, mode: Mode::TaskQueue, n_keys, per_key, .. } =>
+                bench_tasks(&config, n_keys.map(|n_keys| (n_keys, per_key)), duration, warmup),
+            Bench { duration, warmup, sweep: Some(max_threads), .. } =>
+                bench_sweep(&config, max_threads, duration, warmup),
+            Bench { duration, warmup, target_p99: Some(target), .. } =>
+                bench_target(&config, target, duration, warmup),
+            Bench { runs, report: ref path, ref baseline, max_regression, ref save_baseline, ref compare, .. } => {
+                // read before running, so that a missing or bad file fails straight away
+                let fail = |e: String| -> Report {
+                    eprintln!("error: {}", e);
+                    process::exit(2);
+                };
+                let baseline = baseline.as_ref().map(|path| report::read_baseline(path).unwrap_or_else(fail));
+                let compared = compare.as_ref().map(|name| (report::saved_baseline(name).unwrap_or_else(fail), name));
+                let throughputs = if runs > 1 {
+                    bench_runs(&config, &options)
+                }
+                else {
+                    let report = bench(&config, &options);
+                    if let Some(path) = path { report::write(&report, path); }
+                    if let Some((before, name)) = compared { compare_reports(name, &before, &report); }
+                    if let Some(name) = save_baseline { keep_baseline(name, &report); }
+                    report.buffers.iter().map(|buffer| buffer.ops_per_sec).collect()
+                };
+                let max_regression = max_regression.unwrap_or(DEFAULT_MAX_REGRESSION);
+                let regressed = |baseline| !check_regression(&config, &throughputs, &baseline, max_regression);
+                if baseline.is_some_and(regressed) { process::exit(1); }
+            },
+        },
+        Command::Verify   { n_items, record, report } =>
+            if !verify(&config, n_items, record.as_deref(), report.as_deref()) { process::exit(1); },
+        Command::Soak     { duration, n_items, checkpoint, round } =>
+            if !soak(&config, duration, n_items, checkpoint, round) { process::exit(1); },
+        Command::Simulate { replay: Some(path), speed, .. } => if !replay(&path, speed) { process::exit(1); },
+        Command::Simulate { n_steps, replay: None, rewind, .. } =>
+            if !simulate(&config, n_steps, rewind) { process::exit(1); },
+        Command::Orchestrate { duration, credit_window } =>
+            if !orchestrate(&config, duration, credit_window) { process::exit(1); },
+        Command::Worker   { role: Some(role), connect: Some(address), credit_window } =>
+            if let Err(e) = orchestrate::work(role, &address, credit_window) {
+                eprintln!("error: worker: {}", e);
+                process::exit(1);
+            },
+        Command::Worker   { .. } => unreachable!("`cli::parse` checks a worker has a role and an address"),
+        #[cfg(feature = "server")]
+        Command::Serve    { listen, allow_shutdown } =>
+            if let Err(e) = server::serve(make_queue(&config, false, None), config.strategy, listen, allow_shutdown) {
+                eprintln!("error: can't serve on `{}`: {}", listen, e);
+                process::exit(1);
+            },
+        #[cfg(not(feature = "server"))]
+        Command::Serve    { .. } => {
+            eprintln!("error: `serve` needs `pc` built with the `server` feature");
+            process::exit(2);
+        },
+    }
 }
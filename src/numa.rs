@@ -0,0 +1,97 @@
+// Placing the buffer and the threads using it on NUMA nodes, to compare running on one node with running across them.
+
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+// the CPUs of NUMA node `node`, which must have some
+#[cfg(target_os = "linux")]
+pub fn cpus(node: usize) -> Result<Vec<usize>, String> {
+    let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+    let list = std::fs::read_to_string(&path).map_err(|_| format!("there is no NUMA node {}", node))?;
+    // e.g. `0-3,8-11`
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let parse =
+            |cpu: &str| cpu.parse::<usize>().map_err(|_| format!("invalid CPU list `{}` in `{}`", list.trim(), path));
+        cpus.extend(parse(first)?..=parse(last)?);
+    }
+    if cpus.is_empty() { return Err(format!("NUMA node {} has no CPUs", node)); }
+    Ok(cpus)
+}
+#[cfg(not(target_os = "linux"))]
+pub fn cpus(_node: usize) -> Result<Vec<usize>, String> { Err("NUMA placement is only supported on Linux".to_string()) }
+
+// whether `pin` has warned that it couldn't pin a thread, which it only does once, as every thread would fail alike
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+// restrict the calling thread to `node`'s CPUs, if there is one, or else warn and leave it unpinned; `node` must have
+// been checked with `cpus`
+pub fn pin(node: Option<usize>) {
+    let Some(node) = node else { return };
+    if let Err(e) = try_pin(node) {
+        if !WARNED.swap(true, Relaxed) { eprintln!("warning: {}; running unpinned", e); }
+    }
+}
+
+// restrict the calling thread to `node`'s CPUs, leaving it as it was if that can't be done
+#[cfg(target_os = "linux")]
+pub fn try_pin(node: usize) -> Result<(), String> {
+    let set = cpu_set(&cpus(node)?).map_err(|e| format!("can't pin a thread to NUMA node {}: {}", node, e))?;
+    if unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(format!("can't pin a thread to NUMA node {}: {}", node, std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+// `cpus` as a set for `sched_setaffinity`, if it can hold them
+#[cfg(target_os = "linux")]
+fn cpu_set(cpus: &[usize]) -> Result<libc::cpu_set_t, String> {
+    let max = libc::CPU_SETSIZE as usize;
+    if let Some(cpu) = cpus.iter().find(|&&cpu| cpu >= max) {
+        return Err(format!("CPU {} is beyond the {} a CPU set holds", cpu, max));
+    }
+    // `CPU_SET` would panic on a CPU out of range, which is ruled out above
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus { libc::CPU_SET(cpu, &mut set); }
+        Ok(set)
+    }
+}
+#[cfg(not(target_os = "linux"))]
+pub fn try_pin(_node: usize) -> Result<(), String> { Err("NUMA placement is only supported on Linux".to_string()) }
+
+/* Call `f` on `node`'s CPUs, if there is one, then put the calling thread back on the CPUs it was on. Linux puts the
+memory a thread touches first on the node it's running on, so what `f` allocates and initializes ends up on `node`, as
+long as it doesn't share pages with memory allocated before.
+*/
+pub fn on<T>(node: Option<usize>, f: impl FnOnce() -> T) -> T {
+    #[cfg(target_os = "linux")]
+    if node.is_some() {
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set);
+            pin(node);
+            let result = f();
+            libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set);
+            return result;
+        }
+    }
+    let _ = node;
+    f()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    // a CPU past what a CPU set holds is an error rather than a panic, and a missing node too
+    #[test]
+    fn pinning_fails_without_panicking() {
+        let set = cpu_set(&[0, 3]).unwrap();
+        assert!(unsafe { libc::CPU_ISSET(3, &set) && !libc::CPU_ISSET(1, &set) });
+        let too_far = libc::CPU_SETSIZE as usize;
+        let beyond = format!("CPU {} is beyond the {} a CPU set holds", too_far, too_far);
+        assert_eq!(cpu_set(&[0, too_far]).err(), Some(beyond));
+        assert_eq!(try_pin(1 << 20), Err(format!("there is no NUMA node {}", 1 << 20)));
+    }
+}
@@ -0,0 +1,165 @@
+// Producers and consumers in processes of their own, for `orchestrate`: the buffer stays in the orchestrating process,
+// which pushes and pops for the others as they ask it to over TCP, one round trip per item, or with credit-based flow
+// control, as many items in flight on each connection as its window of credits allows.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    thread,
+    time::Instant,
+};
+use rpc::{CancellationToken, Locker, Queue, Strategy, credit::{Credits, Grants}};
+use crate::cli::Role;
+
+// what a worker sends first, to say which side it's on
+const PRODUCER: u8 = b'p';
+const CONSUMER: u8 = b'c';
+
+// how many credits to grant back at once, out of a window of `window`
+fn grant_batch(window: usize) -> usize { (window / 4).max(1) }
+
+/* Push or pop on behalf of the worker on `stream`, as the `id`th on its side, until it hangs up or `cancellation` is
+cancelled. A worker first sends its role, and its window of credits as a `u32`, 0 for none.
+Without credits, a producer sends each item, and waits for a byte back once it's been pushed; a consumer sends a byte
+for each item it wants, and waits for the item. So, as with threads, a worker waits while the buffer is full or empty.
+With a window, the side sending the items spends a credit on each, and the other grants them back, as a `u32` count,
+in batches of a quarter of the window once it has pushed the items (for a producer) or received them (for a consumer):
+so there's no round trip per item, but never more than the window's worth of items on the wire, or waiting to be
+pushed, and the buffer still holds up a producer while it's full.
+*/
+pub fn serve(
+    mut stream: TcpStream, queue: &dyn Queue, strategy: Strategy, id: usize, cancellation: CancellationToken,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut hello = [0; 5];
+    stream.read_exact(&mut hello)?;
+    let window = u32::from_le_bytes(hello[1..].try_into().unwrap()) as usize;
+    match (hello[0], window) {
+        (PRODUCER, 0) => {
+            let mut locker = Locker::new(strategy, "producer", id).with_cancellation(cancellation);
+            let mut item = [0; 8];
+            while receive(&mut stream, &mut item)? {
+                if queue.push(i64::from_le_bytes(item) as isize, &mut locker).is_err() { break; }
+                stream.write_all(&[1])?;
+            }
+        },
+        (PRODUCER, window) => {
+            let mut locker = Locker::new(strategy, "producer", id).with_cancellation(cancellation);
+            let mut grants = Grants::new(grant_batch(window), window);
+            let mut item = [0; 8];
+            while receive(&mut stream, &mut item)? {
+                if queue.push(i64::from_le_bytes(item) as isize, &mut locker).is_err() { break; }
+                if let Some(n) = grants.done() { stream.write_all(&(n as u32).to_le_bytes())?; }
+            }
+        },
+        (CONSUMER, 0) => {
+            let mut locker = Locker::new(strategy, "consumer", id).with_cancellation(cancellation);
+            while receive(&mut stream, &mut [0])? {
+                let Ok(item) = queue.pop(&mut locker) else { break };
+                stream.write_all(&(item as i64).to_le_bytes())?;
+            }
+        },
+        (CONSUMER, window) => {
+            let mut locker = Locker::new(strategy, "consumer", id).with_cancellation(cancellation);
+            let credits = Credits::new(window);
+            let granting = stream.try_clone()?;
+            // once the worker hangs up, no more credits will come
+            let hung_up = CancellationToken::new();
+            let waiting = Locker::new(strategy, "consumer", id).with_cancellation(hung_up.clone());
+            thread::scope(|scope| {
+                let granted = scope.spawn(|| {
+                    let granted = receive_grants(granting, &credits);
+                    hung_up.cancel();
+                    granted
+                });
+                while credits.acquire(&waiting).is_ok() {
+                    let Ok(item) = queue.pop(&mut locker) else { break };
+                    if stream.write_all(&(item as i64).to_le_bytes()).is_err() { break; }
+                }
+                // the worker hangs up once this does, if it hasn't already
+                let _ = stream.shutdown(Shutdown::Both);
+                granted.join().unwrap().map(drop)
+            })?;
+        },
+        (other, _) => return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown role `{}`", other))),
+    }
+    Ok(())
+}
+
+// pass the credits granted over `stream` to `credits`, until the other end hangs up; returns how many there were
+fn receive_grants(mut stream: TcpStream, credits: &Credits) -> io::Result<u64> {
+    let mut n_granted = 0;
+    let mut n = [0; 4];
+    while receive(&mut stream, &mut n)? {
+        let n = u32::from_le_bytes(n) as usize;
+        // only this thread adds credits, so there are at least as many spent by the time they're granted
+        if n > credits.window() - credits.n_available() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "granted more credits than were spent"));
+        }
+        credits.grant(n);
+        n_granted += n as u64;
+    }
+    Ok(n_granted)
+}
+
+// fill `bytes`, or return false if the other end has hung up
+fn receive(stream: &mut TcpStream, bytes: &mut [u8]) -> io::Result<bool> {
+    match stream.read_exact(bytes) {
+        Ok(()) => Ok(true),
+        Err(e) if matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/* A worker process: connect to the orchestrator at `address`, and push (or pop) as `role` until it hangs up, with a
+window of `credit_window` credits if it isn't 0; then print how many items it passed, and in how many nanoseconds from
+connecting, for the orchestrator to read.
+*/
+pub fn work(role: Role, address: &str, credit_window: usize) -> io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let start = Instant::now();
+    let mut n_items: u64 = 0;
+    let side = match role { Role::Producer => PRODUCER, Role::Consumer => CONSUMER };
+    // `cli::parse` checks the window fits
+    stream.write_all(&[[side].as_slice(), &(credit_window as u32).to_le_bytes()].concat())?;
+    match (role, credit_window) {
+        (Role::Producer, 0) =>
+            while stream.write_all(&(n_items as i64).to_le_bytes()).is_ok() && receive(&mut stream, &mut [0])? {
+                n_items += 1;
+            },
+        // the items pushed are those granted back
+        (Role::Producer, window) => {
+            let credits = Credits::new(window);
+            let granting = stream.try_clone()?;
+            // once the orchestrator hangs up, no more credits will come
+            let hung_up = CancellationToken::new();
+            let waiting = Locker::new(Strategy::Block, "producer", 0).with_cancellation(hung_up.clone());
+            n_items = thread::scope(|scope| {
+                let granted = scope.spawn(|| {
+                    let granted = receive_grants(granting, &credits);
+                    hung_up.cancel();
+                    granted
+                });
+                let mut n_sent: u64 = 0;
+                while credits.acquire(&waiting).is_ok() && stream.write_all(&(n_sent as i64).to_le_bytes()).is_ok() {
+                    n_sent += 1;
+                }
+                granted.join().unwrap()
+            })?;
+        },
+        (Role::Consumer, 0) => while stream.write_all(&[1]).is_ok() && receive(&mut stream, &mut [0; 8])? {
+            n_items += 1;
+        },
+        (Role::Consumer, window) => {
+            let mut grants = Grants::new(grant_batch(window), window);
+            while receive(&mut stream, &mut [0; 8])? {
+                n_items += 1;
+                let Some(n) = grants.done() else { continue };
+                if stream.write_all(&(n as u32).to_le_bytes()).is_err() { break; }
+            }
+        },
+    }
+    println!("{} {}", n_items, start.elapsed().as_nanos());
+    Ok(())
+}
@@ -0,0 +1,118 @@
+// Pacing producers to `--rate`.
+
+use std::{sync::Arc, time::{Duration, Instant}};
+use rpc::clock::{Clock, SystemClock};
+use crate::cli::{Arrivals, Work};
+
+/* Sleeps as needed to keep calls to `tick` at a fixed rate, on average. The time is by the real time, or in tests, the
+`Clock` given to `with_clock`.
+*/
+pub struct Pacer {
+    // the time between calls, or the distribution to draw it from for each call
+    gap: Option<Work>,
+    // what `Work::sample` draws each gap from: the seed, the producer calling `tick`, and its number of calls
+    seed: u64,
+    producer: usize,
+    n: usize,
+    next: Instant,
+    clock: Arc<dyn Clock>,
+}
+impl Pacer {
+
+    // `rate` is in calls per second; `None` never sleeps
+    pub fn new(rate: Option<f64>, arrivals: Arrivals, seed: u64, producer: usize) -> Self {
+        let gap = rate.map(|rate| {
+            let period = Duration::from_secs_f64(1.0 / rate);
+            match arrivals {
+                Arrivals::Even    => Work::Fixed(period),
+                Arrivals::Poisson => Work::Exponential(period),
+            }
+        });
+        Pacer { gap, seed, producer, n: 0, next: Instant::now(), clock: Arc::new(SystemClock) }
+    }
+
+    // only tests pace by another clock
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.next = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    // if the sleep would last past `deadline`, wake up then to call `at_deadline` before sleeping the rest
+    pub fn tick(&mut self, deadline: Option<Instant>, at_deadline: impl FnOnce()) {
+        let Some(gap) = self.gap else { return };
+        // stage 1 of a pipeline is the producers
+        self.next += gap.sample(self.seed, 1, self.producer, self.n);
+        self.n += 1;
+        if let Some(deadline) = deadline.filter(|&deadline| deadline < self.next) {
+            self.sleep_until(deadline);
+            at_deadline();
+        }
+        let now = self.clock.now();
+        // if we've fallen behind, don't try to catch up with a burst
+        if self.next > now { self.clock.sleep(self.next - now); } else { self.next = now; }
+    }
+
+    fn sleep_until(&self, deadline: Instant) {
+        let now = self.clock.now();
+        if deadline > now { self.clock.sleep(deadline - now); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+    use rpc::clock::MockClock;
+    use super::*;
+
+    const GAP: Duration = Duration::from_millis(100);
+
+    // ticks wait for the clock to move on by the gap, and a producer which has fallen behind goes on at once, without
+    // a burst to catch up
+    #[test]
+    fn ticks_at_the_rate() {
+        let clock = Arc::new(MockClock::new());
+        let mut pacer = Pacer::new(Some(10.0), Arrivals::Even, 0, 0).with_clock(clock.clone());
+        let (ticked, ticks) = mpsc::channel();
+        let ticker = thread::spawn(move || for _ in 0..4 {
+            pacer.tick(None, || {});
+            ticked.send(()).unwrap();
+        });
+        assert!(ticks.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(GAP);
+        ticks.recv().unwrap();
+        assert!(ticks.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(GAP * 5);
+        ticks.recv().unwrap();
+        ticks.recv().unwrap();
+        assert!(ticks.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(GAP);
+        ticks.recv().unwrap();
+        ticker.join().unwrap();
+    }
+
+    // a deadline before the next tick is kept, then the rest of the gap slept
+    #[test]
+    fn wakes_for_deadlines() {
+        let clock = Arc::new(MockClock::new());
+        let mut pacer = Pacer::new(Some(10.0), Arrivals::Even, 0, 0).with_clock(clock.clone());
+        let (deadline, (reached, reach)) = (clock.now() + GAP / 2, mpsc::channel());
+        let ticker = thread::spawn(move || pacer.tick(Some(deadline), || reached.send(()).unwrap()));
+        assert!(reach.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(GAP / 2);
+        reach.recv().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!ticker.is_finished());
+        clock.advance(GAP / 2);
+        ticker.join().unwrap();
+    }
+
+    // without a rate, ticks never wait
+    #[test]
+    fn unlimited_never_waits() {
+        let clock = Arc::new(MockClock::new());
+        let mut pacer = Pacer::new(None, Arrivals::Poisson, 0, 0).with_clock(clock);
+        for _ in 0..1000 { pacer.tick(None, || unreachable!()); }
+    }
+}
@@ -0,0 +1,23 @@
+use rayon::{prelude::*, vec::IntoIter};
+use crate::Consumer;
+
+/* Adapters which move the work done on popped items onto rayon's thread pool, so that CPU-heavy processing doesn't
+need as many dedicated consumer threads as there are cores: one thread pops batches (see `Consumer::next_batch`), and
+the pool processes each batch in parallel while the buffer refills.
+Each batch is finished before the next is popped, which bounds the number of items in flight to `batch_size`; items
+within a batch are processed in no particular order.
+*/
+impl<'a> Consumer<'a> {
+
+    // each batch as a parallel iterator, until the consumer is cancelled
+    pub fn par_batches(mut self, batch_size: usize) -> impl Iterator<Item = IntoIter<isize>> + 'a {
+        assert!(batch_size > 0, "batches must hold at least one item");
+        std::iter::from_fn(move || self.next_batch(batch_size)).map(IntoParallelIterator::into_par_iter)
+    }
+
+    // call `f` on every item, in parallel within each batch, until the consumer is cancelled; returns how many items
+    // were processed
+    pub fn par_consume(self, batch_size: usize, f: impl Fn(isize) + Sync + Send) -> usize {
+        self.par_batches(batch_size).map(|batch| batch.map(&f).count()).sum()
+    }
+}
@@ -0,0 +1,80 @@
+// Linux performance counters (see perf_event_open(2)) for the producers' and consumers' threads.
+
+use std::sync::{Mutex, atomic::{AtomicBool, Ordering::Relaxed}};
+
+// the events counted, as perf_event_attr's `type` and `config`, and what to call them
+pub const EVENTS: [(u32, u64, &str); 3] = [
+    // PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_MISSES: in user space only, as that's all unprivileged users may count
+    (0, 3, "cache misses"),
+    // PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CONTEXT_SWITCHES and PERF_COUNT_SW_CPU_MIGRATIONS
+    (1, 3, "context switches"),
+    (1, 4, "CPU migrations"),
+];
+
+// the first version of perf_event_attr, which every kernel since it accepts
+#[repr(C)]
+#[derive(Default)]
+struct Attr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    // bit 5 is exclude_kernel, bit 6 exclude_hv
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+// a thread's counters, one for each of `EVENTS`, or the error opening it (the hardware ones aren't always available,
+// e.g. in virtual machines)
+struct Counters([Result<libc::c_int, String>; EVENTS.len()]);
+impl Drop for Counters {
+    fn drop(&mut self) {
+        for fd in self.0.iter().flatten() { unsafe { libc::close(*fd); } }
+    }
+}
+
+// whether threads calling `count_thread` should be counted
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static THREADS: Mutex<Vec<Counters>> = Mutex::new(Vec::new());
+
+// count the events of the threads which call `count_thread` from now on, until `end`
+pub fn begin() { COUNTING.store(true, Relaxed); }
+
+pub fn end() {
+    COUNTING.store(false, Relaxed);
+    THREADS.lock().unwrap().clear();
+}
+
+// count the calling thread's events, between `begin` and `end`
+pub fn count_thread() {
+    if !COUNTING.load(Relaxed) { return; }
+    let counters = EVENTS.map(|(kind, config, _)| {
+        let attr = Attr {
+            kind, size: size_of::<Attr>() as u32, config, flags: if kind == 0 { 1 << 5 | 1 << 6 } else { 0 },
+            ..Attr::default()
+        };
+        let fd = unsafe { libc::syscall(libc::SYS_perf_event_open, &attr, 0, -1, -1, 0) };
+        if fd < 0 { Err(std::io::Error::last_os_error().to_string()) } else { Ok(fd as libc::c_int) }
+    });
+    THREADS.lock().unwrap().push(Counters(counters));
+}
+
+// each of `EVENTS` counted so far, summed over the threads, or why it couldn't be counted
+pub type Counts = [Result<u64, String>; EVENTS.len()];
+
+pub fn read() -> Counts {
+    let threads = THREADS.lock().unwrap();
+    std::array::from_fn(|i| {
+        threads.iter().try_fold(0, |sum, counters| {
+            let fd = counters.0[i].clone()?;
+            let mut count = 0u64;
+            let n_read = unsafe { libc::read(fd, &mut count as *mut u64 as *mut libc::c_void, size_of::<u64>()) };
+            if n_read != size_of::<u64>() as isize { return Err(std::io::Error::last_os_error().to_string()); }
+            Ok(sum + count)
+        })
+    })
+}
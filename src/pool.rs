@@ -0,0 +1,166 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, atomic::{AtomicU64, Ordering::Relaxed}},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use crate::{closable::{OnClose, PushError}, keyed::KeyedBuffer};
+
+pub type Task = Box<dyn FnOnce() + Send>;
+
+// how many panic messages a pool keeps, of the first tasks to panic
+const MAX_PANIC_MESSAGES: usize = 10;
+
+thread_local! {
+    // whether this thread is a pool's worker
+    static IS_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+// whether a panic on this thread will be caught by a pool, as it's one of its workers, for a panic hook which would
+// otherwise end the process to let it unwind instead
+pub fn catches_panics() -> bool { IS_WORKER.with(Cell::get) }
+
+// what a pool's tasks have done so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    // counting those which panicked
+    pub n_run: u64,
+    pub n_panicked: u64,
+    // the total and longest time a task ran for
+    pub busy_time: Duration,
+    pub max_time: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    n_run: AtomicU64,
+    n_panicked: AtomicU64,
+    busy_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+    panics: Mutex<Vec<String>>,
+}
+impl Counters {
+    fn ran(&self, time: Duration, panic: Option<Box<dyn Any + Send>>) {
+        let nanos = time.as_nanos() as u64;
+        self.n_run.fetch_add(1, Relaxed);
+        self.busy_nanos.fetch_add(nanos, Relaxed);
+        self.max_nanos.fetch_max(nanos, Relaxed);
+        let Some(payload) = panic else { return };
+        self.n_panicked.fetch_add(1, Relaxed);
+        let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "<not a string>".to_string(),
+        };
+        let mut panics = self.panics.lock().unwrap();
+        if panics.len() < MAX_PANIC_MESSAGES { panics.push(message); }
+    }
+}
+
+/* A minimal bounded thread pool: tasks (boxed closures) go into a `KeyedBuffer`, from which `n_workers` threads pop
+and run them, so a submit waits while `capacity` tasks are queued, which holds back whoever submits faster than the
+workers can keep up. A task which panics is caught and counted, keeping its message (for the first few), instead of
+taking its worker down with it; the panic hook still prints it as usual. A task submitted with a key runs only while
+fewer than `per_key` others of that key are running (see `keyed`), so with 1, the tasks of a key run one at a time, in
+the order they were submitted.
+*/
+pub struct TaskPool {
+    buffer: Arc<KeyedBuffer<Task>>,
+    counters: Arc<Counters>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+impl TaskPool {
+
+    // with no limit on the tasks of a key running at once
+    pub fn new(n_workers: usize, capacity: usize) -> Self { TaskPool::keyed(n_workers, capacity, usize::MAX) }
+
+    // running at most `per_key` of the tasks submitted with the same key at once
+    pub fn keyed(n_workers: usize, capacity: usize, per_key: usize) -> Self {
+        assert!(n_workers > 0, "a pool without workers never runs anything");
+        let (buffer, counters) = (Arc::new(KeyedBuffer::<Task>::new(capacity, per_key)), Arc::new(Counters::default()));
+        let workers = (0..n_workers).map(|i| {
+            let (buffer, counters) = (buffer.clone(), counters.clone());
+            thread::Builder::new()
+                .name(format!("worker {}", i))
+                .spawn(move || {
+                    IS_WORKER.with(|is_worker| is_worker.set(true));
+                    while let Ok((key, task)) = buffer.pop() {
+                        let start = Instant::now();
+                        let panic = panic::catch_unwind(AssertUnwindSafe(task)).err();
+                        counters.ran(start.elapsed(), panic);
+                        buffer.done(key);
+                    }
+                })
+                .expect("failed to spawn a thread")
+        }).collect();
+        TaskPool { buffer, counters, workers: Mutex::new(workers) }
+    }
+
+    // queue `task`, waiting while the pool has a full queue; fails once the pool is shut down, giving the task back
+    pub fn submit(&self, task: impl FnOnce() + Send + 'static) -> Result<(), PushError<Task>> {
+        self.buffer.push(None, Box::new(task))
+    }
+    // the same, for a task of `key`
+    pub fn submit_keyed(&self, key: u64, task: impl FnOnce() + Send + 'static) -> Result<(), PushError<Task>> {
+        self.buffer.push(Some(key), Box::new(task))
+    }
+
+    pub fn n_queued(&self) -> usize { self.buffer.n_items() }
+
+    pub fn stats(&self) -> TaskStats {
+        TaskStats {
+            n_run: self.counters.n_run.load(Relaxed),
+            n_panicked: self.counters.n_panicked.load(Relaxed),
+            busy_time: Duration::from_nanos(self.counters.busy_nanos.load(Relaxed)),
+            max_time: Duration::from_nanos(self.counters.max_nanos.load(Relaxed)),
+        }
+    }
+
+    // the messages of the first tasks to panic, up to `MAX_PANIC_MESSAGES`
+    pub fn panics(&self) -> Vec<String> { self.counters.panics.lock().unwrap().clone() }
+
+    // stop taking tasks, let the workers run those queued (or drop them with `OnClose::Discard`), and wait for them to
+    // finish; submits waiting for room fail, and so do any after; returns how many tasks were dropped
+    pub fn shutdown(&self, on_close: OnClose) -> usize {
+        let n_dropped = self.buffer.close_with(on_close).len();
+        for worker in self.workers.lock().unwrap().drain(..) { worker.join().unwrap(); }
+        n_dropped
+    }
+}
+// waits for the queued tasks to run
+impl Drop for TaskPool {
+    fn drop(&mut self) { self.shutdown(OnClose::Drain); }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use super::*;
+
+    // every task submitted runs once, on a worker known to catch its panics, and those which panic are counted without
+    // stopping the others
+    #[test]
+    fn runs_every_task_and_counts_panics() {
+        let n_ran = Arc::new(AtomicUsize::new(0));
+        let pool = TaskPool::new(2, 4);
+        assert!(!catches_panics());
+        for i in 0..20 {
+            let n_ran = n_ran.clone();
+            let submitted = pool.submit(move || {
+                if catches_panics() { n_ran.fetch_add(1, Relaxed); }
+                if i % 5 == 0 { panic!("task {} failed", i); }
+            });
+            assert!(submitted.is_ok());
+        }
+        assert_eq!(pool.shutdown(OnClose::Drain), 0);
+        assert_eq!(n_ran.load(Relaxed), 20);
+        let stats = pool.stats();
+        assert_eq!((stats.n_run, stats.n_panicked), (20, 4));
+        let mut panics = pool.panics();
+        panics.sort();
+        assert_eq!(panics, ["task 0 failed", "task 10 failed", "task 15 failed", "task 5 failed"]);
+        assert!(pool.submit(|| {}).is_err());
+    }
+}
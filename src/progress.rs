@@ -0,0 +1,81 @@
+// Progress bars for `run --items`, drawn on stderr in place of echo and the monitor's rates.
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, atomic::{AtomicU64, Ordering::Relaxed}},
+    time::{Duration, Instant},
+};
+use rpc::{Hooked, Hooks, Locker, Queue};
+
+// how many cells wide each bar is
+const WIDTH: usize = 40;
+
+// counting the items pushed into a buffer and popped out of it, for the bars to show
+#[derive(Default)]
+pub struct Counts {
+    n_pushed: AtomicU64,
+    n_popped: AtomicU64,
+}
+impl Counts {
+    pub fn n_pushed(&self) -> u64 { self.n_pushed.load(Relaxed) }
+    pub fn n_popped(&self) -> u64 { self.n_popped.load(Relaxed) }
+}
+impl Hooks for Counts {
+    // if cancelled partway, the items pushed so far still count
+    fn after_push(&self, pushed: &[isize], _: &[isize], _: &Locker) {
+        self.n_pushed.fetch_add(pushed.len() as u64, Relaxed);
+    }
+    fn after_pop(&self, popped: &[isize], _: &Locker) { self.n_popped.fetch_add(popped.len() as u64, Relaxed); }
+}
+// a buffer with its pushes and pops counted
+pub type Counted = Hooked<Arc<dyn Queue>, Counts>;
+
+/* A bar each for the items pushed and popped so far, out of `total`, with the rate since `start` and how long the rest
+should take at that rate. They're redrawn in place, two lines on stderr, so anything else printed to stderr should go
+between a `clear` and the next `draw`. Redelivered items count too, so a bar can go past `total` (but stays full).
+*/
+pub struct Bars {
+    total: u64,
+    start: Instant,
+    drawn: bool,
+}
+impl Bars {
+
+    pub fn new(total: u64) -> Self { Bars { total, start: Instant::now(), drawn: false } }
+
+    pub fn draw(&mut self, n_pushed: u64, n_popped: u64) {
+        let secs = self.start.elapsed().as_secs_f64();
+        let (pushed, popped) = (self.bar("pushed", n_pushed, secs), self.bar("popped", n_popped, secs));
+        let text = format!("{}{}\n{}\n", self.erase(), pushed, popped);
+        let _ = io::stderr().write_all(text.as_bytes());
+        self.drawn = true;
+    }
+
+    // take the bars off the screen, if they're on it
+    pub fn clear(&mut self) {
+        let _ = io::stderr().write_all(self.erase().as_bytes());
+        self.drawn = false;
+    }
+
+    // leave the bars as they're drawn, so that whatever's printed next goes below them
+    pub fn finish(&mut self) { self.drawn = false; }
+
+    // back up to the first bar and clear to the end of the screen
+    fn erase(&self) -> &'static str { if self.drawn { "\x1b[2A\x1b[J" } else { "" } }
+
+    fn bar(&self, name: &str, n: u64, secs: f64) -> String {
+        let fraction = (n as f64 / self.total as f64).min(1.0);
+        let filled = (fraction * WIDTH as f64) as usize;
+        let cells = match filled {
+            WIDTH => "=".repeat(WIDTH),
+            _ => format!("{}>{}", "=".repeat(filled), " ".repeat(WIDTH - filled - 1)),
+        };
+        let rate = n as f64 / secs;
+        let eta = match self.total.saturating_sub(n) {
+            0 => "done".to_string(),
+            _ if rate > 0.0 => format!("ETA {:.0?}", Duration::from_secs_f64((self.total - n) as f64 / rate)),
+            _ => "ETA ?".to_string(),
+        };
+        format!("{} [{}] {}/{} ({:.0}%) {:.0}/s {}", name, cells, n, self.total, fraction * 100.0, rate, eta)
+    }
+}
@@ -0,0 +1,82 @@
+/* Python bindings: `import rpc; buf = rpc.BoundedBuffer(capacity)`.
+Items are arbitrary Python objects. Blocking calls release the GIL while they wait, so other Python threads (and native
+threads which need the GIL) keep running. Build with e.g. `maturin build --features python`, or
+`cargo rustc --lib --release --features python --crate-type cdylib` and rename the library to `rpc.so`.
+*/
+use std::time::Duration;
+use pyo3::{
+    prelude::*,
+    create_exception,
+    exceptions::{PyException, PyTimeoutError, PyValueError},
+};
+use crate::closable::{ClosableBuffer, PushError, PopError, OnClose};
+
+create_exception!(rpc, Closed, PyException, "The buffer has been closed (and, when popping, drained).");
+
+fn to_duration(timeout: Option<f64>) -> PyResult<Option<Duration>> {
+    timeout.map(|secs| Duration::try_from_secs_f64(secs).map_err(|e| PyValueError::new_err(e.to_string()))).transpose()
+}
+
+#[pyclass(name = "BoundedBuffer", module = "rpc", frozen)]
+struct PyBoundedBuffer { buffer: ClosableBuffer<PyObject> }
+
+#[pymethods]
+impl PyBoundedBuffer {
+
+    #[new]
+    fn new(capacity: usize) -> PyResult<Self> {
+        if capacity == 0 { return Err(PyValueError::new_err("capacity must be positive")); }
+        Ok(PyBoundedBuffer { buffer: ClosableBuffer::new(capacity) })
+    }
+
+    // Blocks while the buffer is full, for at most `timeout` seconds if given (raising `TimeoutError`).
+    // Raises `Closed` if the buffer is closed.
+    #[pyo3(signature = (item, timeout=None))]
+    fn push(&self, py: Python<'_>, item: PyObject, timeout: Option<f64>) -> PyResult<()> {
+        let timeout = to_duration(timeout)?;
+        let result = py.allow_threads(|| match timeout {
+            None          => self.buffer.push(item),
+            Some(timeout) => self.buffer.push_timeout(item, timeout),
+        });
+        match result {
+            Ok(()) => Ok(()),
+            Err(PushError::Closed(_))  => Err(Closed::new_err("push to a closed buffer")),
+            Err(PushError::Timeout(_)) => Err(PyTimeoutError::new_err("buffer stayed full")),
+        }
+    }
+
+    // Blocks while the buffer is empty, for at most `timeout` seconds if given (raising `TimeoutError`).
+    // Raises `Closed` once the buffer is closed and empty.
+    #[pyo3(signature = (timeout=None))]
+    fn pop(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<PyObject> {
+        let timeout = to_duration(timeout)?;
+        let result = py.allow_threads(|| match timeout {
+            None          => self.buffer.pop(),
+            Some(timeout) => self.buffer.pop_timeout(timeout),
+        });
+        match result {
+            Ok(item) => Ok(item),
+            Err(PopError::Closed)  => Err(Closed::new_err("pop from a closed, empty buffer")),
+            Err(PopError::Timeout) => Err(PyTimeoutError::new_err("buffer stayed empty")),
+        }
+    }
+
+    // With `discard=True`, the items still in the buffer are taken out and returned, rather than left to be popped.
+    #[pyo3(signature = (discard=false))]
+    fn close(&self, discard: bool) -> Vec<PyObject> {
+        self.buffer.close_with(if discard { OnClose::Discard } else { OnClose::Drain })
+    }
+
+    #[getter]
+    fn capacity(&self) -> usize { self.buffer.capacity() }
+    #[getter]
+    fn closed(&self) -> bool { self.buffer.closed() }
+    fn __len__(&self) -> usize { self.buffer.n_items() }
+}
+
+#[pymodule]
+fn rpc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyBoundedBuffer>()?;
+    m.add("Closed", m.py().get_type::<Closed>())?;
+    Ok(())
+}
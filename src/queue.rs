@@ -0,0 +1,248 @@
+use std::{
+    collections::VecDeque,
+    ops::Deref,
+    str::FromStr,
+    time::Duration,
+};
+use crate::{
+    backoff::AdaptiveBackoff, stats::Stats, cancel::{self, CancellationToken}, lock::{HoldTimes, RawLock}, Cancelled,
+};
+
+// a bounded buffer which can be shared between threads
+pub trait Queue: Send + Sync {
+    // add an item, blocking while the buffer is full; fails only once the locker's cancellation token is cancelled
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled>;
+    /* Push all of `items`, front first, taking each out once it's pushed and blocking while the buffer is full; if
+    cancelled, the ones not pushed yet are left. Backends which can push as many as fit under one acquisition of their
+    lock do; by default it's a `push` for each.
+    */
+    fn push_batch(&self, items: &mut VecDeque<isize>, locker: &mut Locker) -> Result<(), Cancelled> {
+        while let Some(&item) = items.front() {
+            self.push(item, locker)?;
+            items.pop_front();
+        }
+        Ok(())
+    }
+    // remove an item, blocking while the buffer is empty; fails only once the locker's cancellation token is cancelled
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled>;
+    /* Remove up to `max_len` items, oldest first: block while the buffer is empty, then take whatever else it holds
+    without waiting for more. Fails only if cancelled before popping anything. Backends which can take the items under
+    one acquisition of their lock do; by default it's a `pop` for each (so another consumer may take an item which
+    seemed to be there, in which case we wait for the next).
+    */
+    fn pop_batch(&self, max_len: usize, locker: &mut Locker) -> Result<Vec<isize>, Cancelled> {
+        let mut batch = vec![self.pop(locker)?];
+        while batch.len() < max_len && self.n_items() > 0 {
+            match self.pop(locker) {
+                Ok(item) => batch.push(item),
+                Err(_) => break,
+            }
+        }
+        Ok(batch)
+    }
+    fn stats(&self) -> &Stats;
+    // how many items the buffer can hold
+    fn capacity(&self) -> usize;
+    // roughly how many bytes the buffer's storage takes up, for capacity planning
+    fn memory(&self) -> usize { self.capacity() * std::mem::size_of::<isize>() }
+    // how many items the buffer holds right now
+    fn n_items(&self) -> usize;
+    // a copy of the items, oldest first
+    fn snapshot(&self) -> Vec<isize>;
+    // how long pushes and pops held the buffer's lock, by `Side`, if it keeps track
+    fn hold_times(&self) -> Option<&[HoldTimes; 2]> { None }
+    // change the capacity while the buffer is in use, if the backend can (see `SyncedBoundedBuffer::set_capacity`)
+    fn set_capacity(&self, _capacity: usize) -> bool { false }
+}
+
+/* What a `Hooked` buffer does besides passing each push and pop on, for wrappers which watch the traffic through a
+buffer without changing it: counting it, logging it, sampling it, and so on. The hooks run on the thread whose push or
+pop it is, and do nothing unless overridden.
+*/
+pub trait Hooks: Send + Sync {
+    // run a push or a pop, waiting included
+    fn around<R>(&self, op: impl FnOnce() -> R) -> R { op() }
+    // before `items` are pushed
+    fn before_push(&self, _items: &[isize]) {}
+    // after `pushed` were, and `left` weren't, as the push was cancelled partway
+    fn after_push(&self, _pushed: &[isize], _left: &[isize], _locker: &Locker) {}
+    // after `popped` were
+    fn after_pop(&self, _popped: &[isize], _locker: &Locker) {}
+}
+
+// `queue` (an `Arc<dyn Queue>`, a `&dyn Queue`, ...), with `hooks` called on every push and pop
+pub struct Hooked<Q, H> {
+    queue: Q,
+    hooks: H,
+}
+impl<Q, H> Hooked<Q, H> {
+    pub fn new(queue: Q, hooks: H) -> Self { Hooked { queue, hooks } }
+    pub fn queue(&self) -> &Q { &self.queue }
+    pub fn hooks(&self) -> &H { &self.hooks }
+}
+impl<Q, H> Queue for Hooked<Q, H> where Q: Deref + Send + Sync, Q::Target: Queue, H: Hooks {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        self.hooks.before_push(&[item]);
+        let pushed = self.hooks.around(|| self.queue.push(item, locker));
+        match pushed {
+            Ok(()) => self.hooks.after_push(&[item], &[], locker),
+            Err(_) => self.hooks.after_push(&[], &[item], locker),
+        }
+        pushed
+    }
+    fn push_batch(&self, items: &mut VecDeque<isize>, locker: &mut Locker) -> Result<(), Cancelled> {
+        let batch: Vec<_> = items.iter().copied().collect();
+        self.hooks.before_push(&batch);
+        let pushed = self.hooks.around(|| self.queue.push_batch(items, locker));
+        let (done, left) = batch.split_at(batch.len() - items.len());
+        self.hooks.after_push(done, left, locker);
+        pushed
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        let item = self.hooks.around(|| self.queue.pop(locker))?;
+        self.hooks.after_pop(&[item], locker);
+        Ok(item)
+    }
+    fn pop_batch(&self, max_len: usize, locker: &mut Locker) -> Result<Vec<isize>, Cancelled> {
+        let batch = self.hooks.around(|| self.queue.pop_batch(max_len, locker))?;
+        self.hooks.after_pop(&batch, locker);
+        Ok(batch)
+    }
+
+    fn stats(&self) -> &Stats { self.queue.stats() }
+    fn capacity(&self) -> usize { self.queue.capacity() }
+    fn memory(&self) -> usize { self.queue.memory() }
+    fn n_items(&self) -> usize { self.queue.n_items() }
+    fn snapshot(&self) -> Vec<isize> { self.queue.snapshot() }
+    fn hold_times(&self) -> Option<&[HoldTimes; 2]> { self.queue.hold_times() }
+    fn set_capacity(&self, capacity: usize) -> bool { self.queue.set_capacity(capacity) }
+}
+
+// how a thread acquires the buffer's lock
+#[derive(Clone, Copy)]
+pub enum Strategy {
+    // just block on the lock
+    Block,
+    // spin and back off depending on recent contention; see `AdaptiveBackoff`
+    Adaptive,
+}
+impl FromStr for Strategy {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block"    => Ok(Strategy::Block),
+            "adaptive" => Ok(Strategy::Adaptive),
+            _ => Err(()),
+        }
+    }
+}
+
+// how many operations a thread performs between reports of its backoff parameters
+const REPORT_INTERVAL: usize = 1 << 12;
+
+// per-thread state for taking a buffer's lock according to a `Strategy`
+pub struct Locker {
+    backoff: Option<AdaptiveBackoff>,
+    name: String,
+    // the thread's index among the producers or the consumers
+    pub id: u32,
+    n_ops: usize,
+    cancellation: Option<CancellationToken>,
+}
+impl Locker {
+
+    // `role` is e.g. "producer", for reports
+    pub fn new(strategy: Strategy, role: &str, id: usize) -> Self {
+        let backoff = match strategy {
+            Strategy::Block    => None,
+            Strategy::Adaptive => Some(AdaptiveBackoff::new()),
+        };
+        Locker { backoff, name: format!("{} {}", role, id), id: id as u32, n_ops: 0, cancellation: None }
+    }
+
+    // make this thread's pushes and pops fail once `token` is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    // whether the thread's operations should fail, checked by backends before each attempt
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match &self.cancellation {
+            Some(token) if token.is_cancelled() => Err(Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    // how long backends should wait at a time before calling `check` again; `None` is indefinitely
+    pub fn poll_interval(&self) -> Option<Duration> { self.cancellation.as_ref().map(|_| cancel::POLL_INTERVAL) }
+
+    // take a lock given a non-blocking and a blocking way to take it
+    pub fn acquire<G>(&mut self, try_lock: impl FnMut() -> Option<G>, lock: impl FnOnce() -> G) -> G {
+        self.n_ops += 1;
+        match &mut self.backoff {
+            Some(backoff) => {
+                if self.n_ops.is_multiple_of(REPORT_INTERVAL) { backoff.report(&self.name); }
+                backoff.acquire(try_lock, lock)
+            },
+            None => lock(),
+        }
+    }
+
+    pub fn lock<'a, T, L: RawLock<T>>(&mut self, mutex: &'a L) -> L::Guard<'a> {
+        self.acquire(|| mutex.try_lock(), || mutex.lock())
+    }
+
+    // to be called when a thread woken from waiting for the buffer has to wait again straight away
+    pub fn reblocked(&mut self) {
+        if let Some(backoff) = &mut self.backoff { backoff.reblocked(); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::{CancellationToken, SyncedBoundedBuffer};
+    use super::*;
+
+    // what the hooks were called with
+    #[derive(Default)]
+    struct Calls(Mutex<Vec<String>>);
+    impl Hooks for Calls {
+        fn around<R>(&self, op: impl FnOnce() -> R) -> R {
+            self.0.lock().unwrap().push("around".to_string());
+            op()
+        }
+        fn before_push(&self, items: &[isize]) { self.0.lock().unwrap().push(format!("before {:?}", items)); }
+        fn after_push(&self, pushed: &[isize], left: &[isize], _: &Locker) {
+            self.0.lock().unwrap().push(format!("pushed {:?}, left {:?}", pushed, left));
+        }
+        fn after_pop(&self, popped: &[isize], _: &Locker) {
+            self.0.lock().unwrap().push(format!("popped {:?}", popped));
+        }
+    }
+
+    // the hooks see every push and pop around the buffer's own, including the items a cancelled push left over, and
+    // nothing of a cancelled pop
+    #[test]
+    fn hooks_see_every_push_and_pop() {
+        let hooked = Hooked::new(Arc::new(SyncedBoundedBuffer::new(2, false)), Calls::default());
+        let mut locker = Locker::new(Strategy::Block, "producer", 0);
+        hooked.push(1, &mut locker).unwrap();
+        assert_eq!(hooked.pop_batch(2, &mut locker), Ok(vec![1]));
+        let token = CancellationToken::new();
+        let mut cancellable = Locker::new(Strategy::Block, "producer", 1).with_cancellation(token.clone());
+        hooked.push(2, &mut cancellable).unwrap();
+        token.cancel();
+        assert_eq!(hooked.push_batch(&mut VecDeque::from([3, 4]), &mut cancellable), Err(Cancelled));
+        assert_eq!(hooked.pop(&mut cancellable), Err(Cancelled));
+        assert_eq!(hooked.queue().snapshot(), [2]);
+        assert_eq!(*hooked.hooks().0.lock().unwrap(), [
+            "before [1]", "around", "pushed [1], left []", "around", "popped [1]",
+            "before [2]", "around", "pushed [2], left []",
+            "before [3, 4]", "around", "pushed [], left [3, 4]", "around",
+        ]);
+    }
+}
@@ -0,0 +1,34 @@
+// What queueing theory predicts for a buffer, to compare with what `bench` measures.
+
+/* The steady state of an M/M/c queue: arrivals at random (a Poisson process) at `arrival_rate` per second, `c` servers,
+each taking a random (exponentially distributed) time with mean `1 / service_rate` seconds per item, and an unbounded
+queue. For the buffer, the servers are the consumers, and the queue is the buffer itself.
+*/
+pub struct MMc {
+    // the fraction of the time each server is busy, ρ = λ / (c μ)
+    pub utilization: f64,
+    // the mean number of items queued, Lq
+    pub queue_length: f64,
+    // the mean time an item is queued for, in seconds, Wq = Lq / λ
+    pub wait: f64,
+}
+
+// `None` if the servers can't keep up (ρ ≥ 1), so that the queue grows without bound
+pub fn mmc(arrival_rate: f64, service_rate: f64, c: usize) -> Option<MMc> {
+    let load = arrival_rate / service_rate;
+    let utilization = load / c as f64;
+    if c == 0 || !utilization.is_finite() || utilization >= 1.0 { return None; }
+
+    // sum the terms load^k / k! for k < c, and end up with term = load^c / c!, without overflowing
+    let (mut sum, mut term) = (0.0, 1.0);
+    for k in 0..c {
+        sum += term;
+        term *= load / (k + 1) as f64;
+    }
+    // the probability that an item has to queue at all (Erlang's C formula)
+    let last = term / (1.0 - utilization);
+    let p_wait = last / (sum + last);
+
+    let queue_length = p_wait * utilization / (1.0 - utilization);
+    Some(MMc { utilization, queue_length, wait: queue_length / arrival_rate })
+}
@@ -0,0 +1,99 @@
+use std::sync::{Mutex, atomic::{AtomicU64, Ordering::Relaxed}};
+
+// what a recycler has handed out and taken back so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecycleStats {
+    // takes which found an object free, and those which had to make one
+    pub n_hits: u64,
+    pub n_misses: u64,
+    // the objects free right now, and those given back while it was full, which were dropped
+    pub n_free: usize,
+    pub n_dropped: u64,
+}
+
+/* An object pool, for items which own an allocation (e.g. boxed payload buffers): consumers give each one back once
+they're done with it, to a free list of up to `max_free`, and producers take one from there to fill instead of
+allocating a new one, so that once there are enough going round, passing items allocates nothing. The list's storage
+is allocated up front, so giving back never allocates either; `preallocated` also makes the objects up front.
+*/
+pub struct Recycler<T> {
+    free: Mutex<Vec<T>>,
+    max_free: usize,
+    n_hits: AtomicU64,
+    n_misses: AtomicU64,
+    n_dropped: AtomicU64,
+}
+impl<T> Recycler<T> {
+
+    pub fn new(max_free: usize) -> Self {
+        Recycler {
+            free: Mutex::new(Vec::with_capacity(max_free)), max_free,
+            n_hits: AtomicU64::new(0), n_misses: AtomicU64::new(0), n_dropped: AtomicU64::new(0),
+        }
+    }
+
+    // with `max_free` objects from `make` free from the start
+    pub fn preallocated(max_free: usize, make: impl FnMut() -> T) -> Self {
+        let recycler = Self::new(max_free);
+        recycler.free.lock().unwrap().extend(std::iter::repeat_with(make).take(max_free));
+        recycler
+    }
+
+    pub fn max_free(&self) -> usize { self.max_free }
+
+    // a free object if there is one, or one from `make` otherwise; either way, it's as it was given back, so clear it
+    pub fn take(&self, make: impl FnOnce() -> T) -> T {
+        let free = self.free.lock().unwrap().pop();
+        match free {
+            Some(object) => {
+                self.n_hits.fetch_add(1, Relaxed);
+                object
+            },
+            None => {
+                self.n_misses.fetch_add(1, Relaxed);
+                make()
+            },
+        }
+    }
+
+    // keep `object` for a later `take`, or drop it (outside the lock) if the free list is full
+    pub fn give(&self, object: T) {
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.max_free {
+            free.push(object);
+            return;
+        }
+        drop(free);
+        self.n_dropped.fetch_add(1, Relaxed);
+    }
+
+    pub fn stats(&self) -> RecycleStats {
+        RecycleStats {
+            n_hits: self.n_hits.load(Relaxed), n_misses: self.n_misses.load(Relaxed),
+            n_free: self.free.lock().unwrap().len(), n_dropped: self.n_dropped.load(Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // objects given back are taken again before any new ones are made, and those beyond `max_free` are dropped
+    #[test]
+    fn reuses_objects_given_back() {
+        let recycler = Recycler::preallocated(2, || Vec::<u8>::with_capacity(16));
+        let (mut a, b) = (recycler.take(Vec::new), recycler.take(Vec::new));
+        let c = recycler.take(|| Vec::with_capacity(1));
+        assert_eq!((a.capacity(), b.capacity(), c.capacity()), (16, 16, 1));
+        a.extend_from_slice(b"payload");
+        let pointer = a.as_ptr();
+        for object in [a, b, c] { recycler.give(object); }
+        assert_eq!(recycler.stats(), RecycleStats { n_hits: 2, n_misses: 1, n_free: 2, n_dropped: 1 });
+        let b = recycler.take(Vec::new);
+        let a = recycler.take(Vec::new);
+        assert_eq!((a.as_ptr(), &a[..]), (pointer, &b"payload"[..]));
+        assert_eq!(b.capacity(), 16);
+        assert_eq!(recycler.stats().n_free, 0);
+    }
+}
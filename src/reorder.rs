@@ -0,0 +1,143 @@
+use std::{collections::BTreeMap, sync::{Mutex, Condvar}};
+use crate::{Locker, Queue, Cancelled};
+
+struct State {
+    // the sequence number of the next item to pass on
+    next: u64,
+    // items finished before their turn, by sequence number
+    held: BTreeMap<u64, isize>,
+    n_held_back: u64,
+    peak_held: usize,
+}
+
+/* Restores the order of items which a stage's workers pop from a buffer in order, but finish working on out of order,
+as they take different times over them: `pop` numbers the items in the order they leave the buffer, and `complete`
+passes each item on only once every item before it has been, holding back the ones which finish early.
+The window bounds how many items can be held back: a worker finishing an item `window` or more places ahead of the next
+one to be passed on waits for that one to be, which holds up the whole stage behind a slow item, so the smaller the
+window, the less the stage runs in parallel.
+*/
+pub struct Reorder {
+    // taken around a pop, so that items are numbered in the order they were popped
+    pop_lock: Mutex<u64>,
+    state: Mutex<State>,
+    advanced: Condvar,
+    window: usize,
+}
+impl Reorder {
+
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0, "a reorder window must have room for at least the next item");
+        Reorder {
+            pop_lock: Mutex::new(0),
+            state: Mutex::new(State { next: 0, held: BTreeMap::new(), n_held_back: 0, peak_held: 0 }),
+            advanced: Condvar::new(),
+            window,
+        }
+    }
+
+    pub fn window(&self) -> usize { self.window }
+    // how many items finished before their turn, and the most held back at once
+    pub fn n_held_back(&self) -> u64 { self.state.lock().unwrap().n_held_back }
+    pub fn peak_held(&self) -> usize { self.state.lock().unwrap().peak_held }
+
+    // pop an item from `queue`, with its sequence number
+    pub fn pop(&self, queue: &dyn Queue, locker: &mut Locker) -> Result<(u64, isize), Cancelled> {
+        let mut seq = self.pop_lock.lock().unwrap();
+        let item = queue.pop(locker)?;
+        *seq += 1;
+        Ok((*seq - 1, item))
+    }
+
+    /* Hand over item `seq`, once done with it, and `release` it if it's next, along with every item held back which is
+    then next, in order. `release` is called under a lock, so that the items reach wherever it puts them in order too.
+    */
+    pub fn complete(
+        &self, seq: u64, item: isize, locker: &mut Locker,
+        mut release: impl FnMut(isize, &mut Locker) -> Result<(), Cancelled>,
+    ) -> Result<(), Cancelled> {
+        let mut state = self.state.lock().unwrap();
+        while seq >= state.next + self.window as u64 {
+            locker.check()?;
+            state = match locker.poll_interval() {
+                None => self.advanced.wait(state).unwrap(),
+                Some(interval) => self.advanced.wait_timeout(state, interval).unwrap().0,
+            };
+        }
+        if seq != state.next {
+            state.held.insert(seq, item);
+            state.n_held_back += 1;
+            state.peak_held = state.peak_held.max(state.held.len());
+            return Ok(());
+        }
+
+        let State { next, held, .. } = &mut *state;
+        release(item, locker)?;
+        *next += 1;
+        while let Some(item) = held.remove(next) {
+            release(item, locker)?;
+            *next += 1;
+        }
+        drop(state);
+        self.advanced.notify_all();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, atomic::{AtomicBool, Ordering::SeqCst}}, thread, time::Duration};
+    use crate::{CancellationToken, Strategy, SyncedBoundedBuffer};
+    use super::*;
+
+    fn locker() -> Locker { Locker::new(Strategy::Block, "consumer", 0) }
+
+    // items popped in order but completed out of it are released in the order they were popped, holding back the ones
+    // which finish early
+    #[test]
+    fn releases_in_the_order_popped() {
+        let (queue, reorder, mut locker) = (SyncedBoundedBuffer::new(8, false), Reorder::new(8), locker());
+        for item in [10, 11, 12, 13] { queue.push(item, &mut locker).unwrap(); }
+        let popped: Vec<_> = (0..4).map(|_| reorder.pop(&queue, &mut locker).unwrap()).collect();
+        assert_eq!(popped, [(0, 10), (1, 11), (2, 12), (3, 13)]);
+
+        let mut released = Vec::new();
+        for &(seq, item) in &[popped[2], popped[3], popped[0], popped[1]] {
+            reorder.complete(seq, item, &mut locker, |item, _| {
+                released.push(item);
+                Ok(())
+            }).unwrap();
+            if seq == 0 { assert_eq!(released, [10]); }
+        }
+        assert_eq!(released, [10, 11, 12, 13]);
+        assert_eq!((reorder.n_held_back(), reorder.peak_held()), (2, 2));
+    }
+
+    // completing an item a window or more ahead of the next waits for the next to be released, and can be cancelled
+    #[test]
+    fn window_holds_up_items_too_far_ahead() {
+        let reorder = Arc::new(Reorder::new(2));
+        let done = Arc::new(AtomicBool::new(false));
+        let ahead = {
+            let (reorder, done) = (reorder.clone(), done.clone());
+            thread::spawn(move || {
+                reorder.complete(2, 12, &mut locker(), |_, _| Ok(())).unwrap();
+                done.store(true, SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!done.load(SeqCst));
+        reorder.complete(0, 10, &mut locker(), |_, _| Ok(())).unwrap();
+        ahead.join().unwrap();
+        assert!(done.load(SeqCst));
+
+        let token = CancellationToken::new();
+        let cancelled = {
+            let (reorder, token) = (reorder.clone(), token.clone());
+            thread::spawn(move || reorder.complete(3, 13, &mut locker().with_cancellation(token), |_, _| Ok(())))
+        };
+        thread::sleep(Duration::from_millis(50));
+        token.cancel();
+        assert_eq!(cancelled.join().unwrap(), Err(Cancelled));
+    }
+}
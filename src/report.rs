@@ -0,0 +1,190 @@
+// What `--report-json` writes at the end of `run`, `bench` or `verify`, for scripts (e.g. in CI) to check results by,
+// and what `bench` keeps as a baseline to compare later runs with.
+
+use std::{env, fs::{self, File}, io::{BufWriter, Write}, path::{Path, PathBuf}, process, time::Duration};
+use serde::{Deserialize, Serialize};
+use rpc::{Queue, Side, Strategy, lock::HoldTimes};
+use crate::{Consumed, cli::Config};
+
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    pub command: String,
+    // how long the run took, or for `bench`, how long it measured for
+    pub secs: f64,
+    pub buffers: Vec<BufferReport>,
+    // only for `verify`
+    pub verification: Option<Verification>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BufferReport {
+    // empty for the buffer of a run without named buffers
+    pub name: String,
+    pub config: ConfigReport,
+    pub n_ops: u64,
+    pub n_waits: u64,
+    pub n_wakes: u64,
+    pub n_steals: u64,
+    pub ops_per_sec: f64,
+    // the items thrown away, by `DropReason`
+    pub n_evicted: u64,
+    pub n_rejected: u64,
+    pub peak_items: u64,
+    // the total time each side spent blocked on the buffer
+    pub producers_blocked_secs: f64,
+    pub consumers_blocked_secs: f64,
+    // with `--hold-times`
+    pub hold_times: Option<HoldTimesReport>,
+    // with `bench --histogram`: how many samples found the buffer holding each number of items, from 0 to the capacity
+    pub occupancy_histogram: Option<Vec<u64>>,
+    // what each thread did, except in `verify`, which only counts the items lost, duplicated or reordered
+    pub producers: Vec<ProducerReport>,
+    pub consumers: Vec<ConsumerReport>,
+}
+impl BufferReport {
+
+    // from `counts` (as `Stats::load` returns them) and `blocked` over `secs`, with no producers or consumers yet
+    pub fn new(
+        name: &str, config: &Config, queue: &dyn Queue, counts: [u64; 4], blocked: [Duration; 2], secs: f64,
+    ) -> Self {
+        let [n_ops, n_waits, n_wakes, n_steals] = counts;
+        let [n_evicted, n_rejected] = queue.stats().drops();
+        BufferReport {
+            name: name.to_string(), config: ConfigReport::new(config), n_ops, n_waits, n_wakes, n_steals,
+            ops_per_sec: n_ops as f64 / secs, n_evicted, n_rejected, peak_items: queue.stats().peak_items(),
+            producers_blocked_secs: blocked[Side::Producer as usize].as_secs_f64(),
+            consumers_blocked_secs: blocked[Side::Consumer as usize].as_secs_f64(),
+            hold_times: queue.hold_times().map(|[pushes, pops]| HoldTimesReport {
+                pushes: Quantiles::new(pushes), pops: Quantiles::new(pops),
+            }),
+            occupancy_histogram: None, producers: Vec::new(), consumers: Vec::new(),
+        }
+    }
+}
+
+// the options which shape a buffer's run
+#[derive(Serialize, Deserialize)]
+pub struct ConfigReport {
+    pub backend: String,
+    pub capacity: usize,
+    pub n_producers: usize,
+    pub n_consumers: usize,
+    // items per second per producer, if limited
+    pub rate: Option<f64>,
+    pub producer_batch: usize,
+    pub prefetch: usize,
+    pub work_time: String,
+    pub strategy: String,
+    pub seed: u64,
+}
+impl ConfigReport {
+    fn new(config: &Config) -> Self {
+        let strategy = match config.strategy { Strategy::Block => "block", Strategy::Adaptive => "adaptive" };
+        ConfigReport {
+            backend: config.backend.name().to_string(), capacity: config.capacity, n_producers: config.n_producers,
+            n_consumers: config.n_consumers, rate: config.rate, producer_batch: config.producer_batch,
+            prefetch: config.prefetch, work_time: config.work_time.to_string(),
+            strategy: strategy.to_string(),
+            seed: config.seed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HoldTimesReport {
+    pub pushes: Quantiles,
+    pub pops: Quantiles,
+}
+
+// how long the lock was held, rounded up to a power of two nanoseconds as `HoldTimes` keeps them (except the max)
+#[derive(Serialize, Deserialize)]
+pub struct Quantiles {
+    pub n_holds: u64,
+    pub p50_nanos: u64,
+    pub p99_nanos: u64,
+    pub max_nanos: u64,
+}
+impl Quantiles {
+    fn new(times: &HoldTimes) -> Self {
+        let nanos = |time: Duration| time.as_nanos() as u64;
+        Quantiles {
+            n_holds: times.n_holds(), p50_nanos: nanos(times.quantile(0.5)), p99_nanos: nanos(times.quantile(0.99)),
+            max_nanos: nanos(times.max()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProducerReport {
+    pub n_pushed: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConsumerReport {
+    // for `bench`, not counting the items popped in the warm-up, which are `n_excluded`
+    pub n_popped: usize,
+    pub n_excluded: usize,
+    pub n_duplicates: usize,
+    pub busy_secs: f64,
+    pub stash_secs: f64,
+}
+impl From<&Consumed> for ConsumerReport {
+    fn from(consumed: &Consumed) -> Self {
+        ConsumerReport {
+            n_popped: consumed.n_popped, n_excluded: consumed.n_excluded, n_duplicates: consumed.n_duplicates,
+            busy_secs: consumed.busy_time.as_secs_f64(), stash_secs: consumed.stash_time.as_secs_f64(),
+        }
+    }
+}
+
+// whether every item `verify` passed through came out exactly once, and each consumer got each producer's in order
+#[derive(Serialize, Deserialize)]
+pub struct Verification {
+    pub n_items: usize,
+    pub n_lost: usize,
+    pub n_duplicated: usize,
+    pub n_reordered: usize,
+    pub passed: bool,
+}
+
+// write `report` to `path`, exiting if it can't be
+pub fn write(report: &Report, path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let written = File::create(path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut out, report)?;
+        writeln!(out)?;
+        out.flush()
+    });
+    if let Err(e) = written {
+        eprintln!("error: can't write `{}`: {}", path.display(), e);
+        process::exit(1);
+    }
+}
+
+// the `bench` report at `path`, for `--baseline`
+pub fn read_baseline(path: impl AsRef<Path>) -> Result<Report, String> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read `{}`: {}", path.display(), e))?;
+    let report: Report =
+        serde_json::from_str(&text).map_err(|e| format!("invalid report `{}`: {}", path.display(), e))?;
+    if report.command != "bench" {
+        return Err(format!("`{}` is a report of `{}`, not of `bench`", path.display(), report.command));
+    }
+    Ok(report)
+}
+
+// where `bench --save-baseline` keeps the baseline called `name`: in the target directory, with the build's output
+pub fn baseline_path(name: &str) -> PathBuf {
+    let target = env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from("target"), PathBuf::from);
+    target.join("pc-baselines").join(format!("{}.json", name))
+}
+
+// the baseline `bench --save-baseline` kept as `name`, for `--compare`
+pub fn saved_baseline(name: &str) -> Result<Report, String> {
+    let path = baseline_path(name);
+    if !path.exists() {
+        return Err(format!("there's no baseline called `{}`; save one with `--save-baseline {}`", name, name));
+    }
+    read_baseline(path)
+}
@@ -0,0 +1,91 @@
+use core::{
+    fmt::{self, Display},
+    marker::PhantomData,
+    mem,
+};
+use alloc::boxed::Box;
+
+/* A fixed-capacity FIFO queue, stored as a ring. Not synchronized; see the other modules for that.
+`S` is the storage, whose length is the capacity: see `BoundedBuffer` (capacity known at compile time, stored inline,
+needs nothing but `core`) and `RingBuffer` (capacity chosen at runtime, heap-allocated).
+*/
+pub struct Ring<T, S> {
+    array: S,
+    head: usize, // index of the oldest item
+    n_items: usize,
+    item: PhantomData<T>,
+}
+pub type BoundedBuffer<T, const BOUND: usize> = Ring<T, [T; BOUND]>;
+pub type RingBuffer<T> = Ring<T, Box<[T]>>;
+
+impl<T: Default, const BOUND: usize> BoundedBuffer<T, BOUND> {
+
+    // evaluated when `new` is compiled for a particular `BOUND`, so that `BoundedBuffer<_, 0>::new()` fails to build
+    const NONZERO_BOUND: () = assert!(BOUND > 0, "a BoundedBuffer with capacity 0 can never be pushed to");
+
+    pub fn new() -> Self {
+        let () = Self::NONZERO_BOUND;
+        Ring::with_storage(core::array::from_fn(|_| T::default()))
+    }
+}
+impl<T: Default, const BOUND: usize> Default for BoundedBuffer<T, BOUND> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<T: Default> RingBuffer<T> {
+
+    pub fn new(capacity: usize) -> Self { Ring::with_storage((0..capacity).map(|_| T::default()).collect()) }
+
+    // move the items into new storage with a different capacity, which must be enough to hold them
+    pub fn resize(&mut self, capacity: usize) {
+        assert!(self.n_items <= capacity, "{} items don't fit in a capacity of {}", self.n_items, capacity);
+        let mut resized = Self::new(capacity);
+        while !self.empty() { resized.push(self.pop()); }
+        *self = resized;
+    }
+}
+
+impl<T, S: AsRef<[T]> + AsMut<[T]>> Ring<T, S> {
+
+    fn with_storage(array: S) -> Self { Ring { array, head: 0, n_items: 0, item: PhantomData } }
+
+    pub fn capacity(&self) -> usize { self.array.as_ref().len() }
+    pub fn n_items (&self) -> usize { self.n_items }
+    pub fn empty   (&self) -> bool  { self.n_items == 0 }
+    pub fn full    (&self) -> bool  { self.n_items == self.capacity() }
+
+    pub fn push(&mut self, item: T) {
+        assert!(!self.full());
+        let i = (self.head + self.n_items) % self.capacity();
+        self.array.as_mut()[i] = item;
+        self.n_items += 1;
+    }
+
+    pub fn pop(&mut self) -> T where T: Default {
+        assert!(!self.empty());
+        let item = mem::take(&mut self.array.as_mut()[self.head]);
+        self.head = (self.head + 1) % self.capacity();
+        self.n_items -= 1;
+        item
+    }
+
+    // oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let array = self.array.as_ref();
+        (0..self.n_items).map(move |i| &array[(self.head + i) % array.len()])
+    }
+}
+impl<T: Display, S: AsRef<[T]> + AsMut<[T]>> Display for Ring<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        write!(f, "[")?;
+
+        let mut items = self.iter();
+        if let Some(first) = items.next() {
+            write!(f, "{}", first)?;
+            for item in items { write!(f, ", {}", item)?; };
+        };
+
+        write!(f, "]")
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use crate::{CancellationToken, Locker, Queue, Strategy, heartbeat::{Activity, Beating, Heartbeats}};
+
+// how often `Runner::drain_with_progress` checks whether the buffer is empty
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// what each of a `Runner`'s threads returned (or panicked with), in the order they were spawned
+pub struct Results<P, C> {
+    pub producers: Vec<thread::Result<P>>,
+    pub consumers: Vec<thread::Result<C>>,
+}
+
+/* Owns a buffer and the producer and consumer threads which use it.
+Each thread runs a closure, which is given the buffer and a `Locker` whose `id` is the thread's index among the
+producers or the consumers. The lockers carry a cancellation token for each side, so the closures should return once a
+push or pop fails: `stop_producers` lets the consumers carry on (e.g. to empty the buffer), and `shutdown` stops
+everyone. Threads are named after their role and index, e.g. "producer 3", which panic messages include. With
+`with_heartbeats`, each thread's pushes and pops beat a heartbeat named the same, for spotting stalled threads.
+*/
+pub struct Runner<P, C> {
+    queue: Arc<dyn Queue>,
+    strategy: Strategy,
+    producers: Vec<JoinHandle<P>>,
+    consumers: Vec<JoinHandle<C>>,
+    stop_producers: CancellationToken,
+    stop_consumers: CancellationToken,
+    heartbeats: Option<Arc<Heartbeats>>,
+}
+impl<P: Send + 'static, C: Send + 'static> Runner<P, C> {
+
+    // `strategy` is how every thread takes the buffer's lock
+    pub fn new(queue: Arc<dyn Queue>, strategy: Strategy) -> Self {
+        Runner {
+            queue, strategy, producers: Vec::new(), consumers: Vec::new(),
+            stop_producers: CancellationToken::new(), stop_consumers: CancellationToken::new(), heartbeats: None,
+        }
+    }
+
+    // register each thread spawned from now on in `heartbeats`
+    pub fn with_heartbeats(mut self, heartbeats: Arc<Heartbeats>) -> Self {
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    pub fn queue(&self) -> &Arc<dyn Queue> { &self.queue }
+    pub fn heartbeats(&self) -> Option<&Arc<Heartbeats>> { self.heartbeats.as_ref() }
+
+    fn spawn<T: Send + 'static>(
+        &self, role: &str, id: usize, token: &CancellationToken,
+        f: impl FnOnce(&dyn Queue, &mut Locker) -> T + Send + 'static,
+    ) -> JoinHandle<T> {
+        let queue = self.queue.clone();
+        let mut locker = Locker::new(self.strategy, role, id).with_cancellation(token.clone());
+        let heartbeat = self.heartbeats.as_ref().map(|heartbeats| heartbeats.register(format!("{} {}", role, id)));
+        thread::Builder::new()
+            .name(format!("{} {}", role, id))
+            .spawn(move || match heartbeat {
+                None => f(&*queue, &mut locker),
+                Some(heartbeat) => {
+                    let result = f(&Beating::new(&*queue, &heartbeat), &mut locker);
+                    heartbeat.beat(Activity::Finished);
+                    result
+                },
+            })
+            .expect("failed to spawn a thread")
+    }
+
+    pub fn spawn_producer(&mut self, f: impl FnOnce(&dyn Queue, &mut Locker) -> P + Send + 'static) {
+        let producer = self.spawn("producer", self.producers.len(), &self.stop_producers, f);
+        self.producers.push(producer);
+    }
+
+    pub fn spawn_consumer(&mut self, f: impl FnOnce(&dyn Queue, &mut Locker) -> C + Send + 'static) {
+        let consumer = self.spawn("consumer", self.consumers.len(), &self.stop_consumers, f);
+        self.consumers.push(consumer);
+    }
+
+    // cancel the producers' operations, but not the consumers'
+    pub fn stop_producers(&self) { self.stop_producers.cancel(); }
+
+    // cancel every thread's operations
+    pub fn shutdown(&self) {
+        self.stop_producers.cancel();
+        self.stop_consumers.cancel();
+    }
+
+    // each thread's name, and whether it has finished
+    pub fn threads(&self) -> impl Iterator<Item = (&str, bool)> {
+        let producers = self.producers.iter().map(|t| (t.thread(), t.is_finished()));
+        let consumers = self.consumers.iter().map(|t| (t.thread(), t.is_finished()));
+        producers.chain(consumers).map(|(thread, finished)| (thread.name().unwrap_or("<unnamed>"), finished))
+    }
+
+    pub fn producers_finished(&self) -> bool { self.producers.iter().all(|producer| producer.is_finished()) }
+
+    /* Shut down gracefully: stop the producers, and wait for them to finish and for the consumers to pop every item,
+    calling `progress` with the number left every `interval` meanwhile. If `deadline` comes first, fail with a copy of
+    the items left (which stay in the buffer), for the caller to log or save, rather than leave them without a trace;
+    like `ClosableBuffer::drain_with_progress`, but for any buffer, by checking on it every `DRAIN_POLL_INTERVAL`.
+    */
+    pub fn drain_with_progress(
+        &self, deadline: Instant, interval: Duration, mut progress: impl FnMut(usize),
+    ) -> Result<(), Vec<isize>> {
+        assert!(!interval.is_zero(), "progress can't be reported continuously");
+        self.stop_producers();
+        let mut next_report = Instant::now() + interval;
+        while !(self.producers_finished() && self.queue.n_items() == 0) {
+            let now = Instant::now();
+            if now >= deadline { return Err(self.queue.snapshot()); }
+            if now >= next_report {
+                progress(self.queue.n_items());
+                next_report += interval;
+            }
+            thread::sleep(DRAIN_POLL_INTERVAL.min(deadline - now));
+        }
+        Ok(())
+    }
+
+    // wait for every thread to return; to stop threads which would otherwise run forever, call `shutdown` first
+    pub fn join(self) -> Results<P, C> {
+        Results {
+            producers: self.producers.into_iter().map(JoinHandle::join).collect(),
+            consumers: self.consumers.into_iter().map(JoinHandle::join).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cancelled, SyncedBoundedBuffer};
+    use super::*;
+
+    const LONG: Duration = Duration::from_secs(10);
+
+    // a thread which does `op` until it's cancelled, and returns how many times it did
+    fn count(
+        op: fn(&dyn Queue, &mut Locker) -> Result<(), Cancelled>,
+    ) -> impl FnOnce(&dyn Queue, &mut Locker) -> usize {
+        move |queue, locker| {
+            let mut n = 0;
+            while op(queue, locker).is_ok() { n += 1; }
+            n
+        }
+    }
+
+    // draining stops the producers and waits for the consumers to pop everything they pushed
+    #[test]
+    fn drains_once_the_consumers_have_popped_everything() {
+        let mut runner = Runner::<usize, usize>::new(Arc::new(SyncedBoundedBuffer::new(4, false)), Strategy::Block);
+        runner.spawn_producer(count(|queue, locker| queue.push(1, locker)));
+        runner.spawn_consumer(count(|queue, locker| queue.pop(locker).map(drop)));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runner.drain_with_progress(Instant::now() + LONG, LONG, |_| {}), Ok(()));
+        assert_eq!(runner.queue().n_items(), 0);
+        runner.shutdown();
+        let results = runner.join();
+        assert_eq!(results.producers[0].as_ref().unwrap(), results.consumers[0].as_ref().unwrap());
+    }
+
+    // without consumers, the deadline passes with the items left over, reported on the way
+    #[test]
+    fn gives_back_leftovers_at_the_deadline() {
+        let runner = Runner::<(), ()>::new(Arc::new(SyncedBoundedBuffer::new(4, false)), Strategy::Block);
+        let mut locker = Locker::new(Strategy::Block, "thread", 0);
+        for item in [1, 2] { runner.queue().push(item, &mut locker).unwrap(); }
+        let mut reports = Vec::new();
+        let interval = Duration::from_millis(20);
+        let drained = runner.drain_with_progress(Instant::now() + interval * 3, interval, |n| reports.push(n));
+        assert_eq!(drained, Err(vec![1, 2]));
+        assert!(!reports.is_empty() && reports.iter().all(|&n_left| n_left == 2));
+        assert_eq!(runner.queue().snapshot(), [1, 2]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::{Mutex, Condvar};
+use crate::{Locker, Cancelled};
+
+/* A counting semaphore, for bounding how many items are anywhere in a pipeline at once, which no single buffer's
+capacity can: a producer acquires a permit before pushing an item into the first buffer, and whoever finishes with it
+at the other end releases it, so however the items are spread between the buffers and the stages working on them,
+there are never more than `permits` of them.
+Acquiring can be cancelled through the thread's `Locker`, like pushing and popping.
+*/
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+    permits: usize,
+}
+impl Semaphore {
+
+    pub fn new(permits: usize) -> Self {
+        assert!(permits > 0, "a semaphore without permits can never be acquired");
+        Semaphore { available: Mutex::new(permits), released: Condvar::new(), permits }
+    }
+
+    pub fn permits(&self) -> usize { self.permits }
+    // how many permits are held right now
+    pub fn n_acquired(&self) -> usize { self.permits - *self.available.lock().unwrap() }
+
+    // take a permit, waiting while there are none
+    pub fn acquire(&self, locker: &Locker) -> Result<(), Cancelled> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            locker.check()?;
+            available = match locker.poll_interval() {
+                None => self.released.wait(available).unwrap(),
+                Some(interval) => self.released.wait_timeout(available, interval).unwrap().0,
+            };
+        }
+        *available -= 1;
+        Ok(())
+    }
+
+    // take a permit if there is one
+    pub fn try_acquire(&self) -> bool {
+        let mut available = self.available.lock().unwrap();
+        let acquired = *available > 0;
+        if acquired { *available -= 1; }
+        acquired
+    }
+
+    // give back a permit taken with `acquire`, by this thread or any other
+    pub fn release(&self) { self.release_n(1); }
+
+    // give back `n` permits at once
+    pub fn release_n(&self, n: usize) {
+        let mut available = self.available.lock().unwrap();
+        assert!(*available + n <= self.permits, "released permits which weren't acquired");
+        *available += n;
+        drop(available);
+        match n {
+            1 => self.released.notify_one(),
+            _ => self.released.notify_all(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, atomic::{AtomicBool, Ordering::SeqCst}},
+        thread,
+        time::Duration,
+    };
+    use crate::{CancellationToken, Strategy};
+    use super::*;
+
+    // with every permit held, acquiring waits for one to be released, by whichever thread
+    #[test]
+    fn acquiring_waits_for_a_release() {
+        let semaphore = Arc::new(Semaphore::new(2));
+        let locker = Locker::new(Strategy::Block, "producer", 0);
+        semaphore.acquire(&locker).unwrap();
+        assert!(semaphore.try_acquire());
+        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.n_acquired(), 2);
+
+        let acquired = Arc::new(AtomicBool::new(false));
+        let waiting = {
+            let (semaphore, acquired) = (semaphore.clone(), acquired.clone());
+            thread::spawn(move || {
+                semaphore.acquire(&Locker::new(Strategy::Block, "producer", 1)).unwrap();
+                acquired.store(true, SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!acquired.load(SeqCst));
+        semaphore.release();
+        waiting.join().unwrap();
+        assert!(acquired.load(SeqCst));
+
+        semaphore.release_n(2);
+        assert_eq!(semaphore.n_acquired(), 0);
+    }
+
+    // a thread waiting for a permit gives up once its token is cancelled
+    #[test]
+    fn acquiring_can_be_cancelled() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        assert!(semaphore.try_acquire());
+        let token = CancellationToken::new();
+        let waiting = {
+            let (semaphore, token) = (semaphore.clone(), token.clone());
+            thread::spawn(move || {
+                semaphore.acquire(&Locker::new(Strategy::Block, "producer", 0).with_cancellation(token))
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        token.cancel();
+        assert_eq!(waiting.join().unwrap(), Err(Cancelled));
+        assert_eq!(semaphore.n_acquired(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "weren't acquired")]
+    fn releasing_more_than_acquired_panics() {
+        let semaphore = Semaphore::new(2);
+        assert!(semaphore.try_acquire());
+        semaphore.release_n(2);
+    }
+}
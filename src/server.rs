@@ -0,0 +1,250 @@
+/* `pc serve`: the buffer as a miniature queue server, for integration experiments with clients in other processes or
+languages, as the gRPC service `pc.Buffer` in `proto/pc.proto`, with `Push`, `Pop`, `Stats` and `Control` RPCs.
+
+A push waits while the buffer is full and a pop while it's empty, as a thread's would, each on a blocking thread of the
+runtime's, so that one waiting doesn't hold up the others. `Control` `CLOSE` makes every push and pop fail, including
+those waiting, until `OPEN`; `RESET_PEAK` forgets the peak occupancy so far; `SET_CAPACITY` resizes the buffer, if its
+backend can, waiting as long as shrinking takes. `SHUTDOWN` is only for a server started with `allow_shutdown`, as any
+client could send it: it closes the buffer, then the server answers the requests in hand and stops, and `serve` returns.
+*/
+
+use std::{
+    collections::VecDeque,
+    io,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed}},
+};
+use tokio::sync::Notify;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{Request, Response, Status};
+use rpc::{CancellationToken, Locker, Queue, Strategy};
+
+mod proto {
+    tonic::include_proto!("pc");
+}
+use proto::{
+    Action, ControlReply, ControlRequest, PopReply, PopRequest, PushReply, PushRequest, StatsReply, StatsRequest,
+    buffer_server::{Buffer, BufferServer},
+};
+
+// what every request shares
+struct Server {
+    queue: Arc<dyn Queue>,
+    strategy: Strategy,
+    // the flag behind every request's cancellation token, which `OPEN` can clear again
+    closed: Arc<AtomicBool>,
+    // numbering the requests, for their lockers
+    n_requests: AtomicUsize,
+    allow_shutdown: bool,
+    shutdown: Notify,
+}
+
+// listen on `address` and serve `queue` to clients, until one asks for a shutdown if `allow_shutdown`
+pub fn serve(queue: Arc<dyn Queue>, strategy: Strategy, address: SocketAddr, allow_shutdown: bool) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("serving on {}", listener.local_addr()?);
+    serve_on(listener, queue, strategy, allow_shutdown)
+}
+
+fn serve_on(listener: TcpListener, queue: Arc<dyn Queue>, strategy: Strategy, allow_shutdown: bool) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let server = Arc::new(Server {
+        queue, strategy, closed: Arc::new(AtomicBool::new(false)), n_requests: AtomicUsize::new(0), allow_shutdown,
+        shutdown: Notify::new(),
+    });
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let incoming = TcpListenerStream::new(tokio::net::TcpListener::from_std(listener)?);
+        let stopping = server.clone();
+        tonic::transport::Server::builder()
+            .add_service(BufferServer::from_arc(server))
+            .serve_with_incoming_shutdown(incoming, async move { stopping.shutdown.notified().await })
+            .await
+            .map_err(io::Error::other)
+    })
+}
+
+impl Server {
+
+    // run `op` on a thread which may block, with a locker whose operations fail once the buffer is closed
+    async fn blocking<R: Send + 'static>(
+        &self, op: impl FnOnce(&dyn Queue, &mut Locker) -> R + Send + 'static,
+    ) -> Result<R, Status> {
+        let queue = self.queue.clone();
+        let mut locker = Locker::new(self.strategy, "request", self.n_requests.fetch_add(1, Relaxed))
+            .with_cancellation(CancellationToken::from(self.closed.clone()));
+        tokio::task::spawn_blocking(move || op(&*queue, &mut locker)).await.map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+#[tonic::async_trait]
+impl Buffer for Server {
+
+    async fn push(&self, request: Request<PushRequest>) -> Result<Response<PushReply>, Status> {
+        let items: VecDeque<_> = request.into_inner().items.into_iter().map(|item| item as isize).collect();
+        let n_items = items.len();
+        let (n_left, closed) = self.blocking(move |queue, locker| {
+            let mut items = items;
+            let pushed = queue.push_batch(&mut items, locker);
+            (items.len(), pushed.is_err())
+        }).await?;
+        Ok(Response::new(PushReply { n_pushed: (n_items - n_left) as u64, closed }))
+    }
+
+    async fn pop(&self, request: Request<PopRequest>) -> Result<Response<PopReply>, Status> {
+        let max = request.into_inner().max.unwrap_or(1);
+        if max == 0 { return Err(Status::invalid_argument("`max` must be at least 1")); }
+        match self.blocking(move |queue, locker| queue.pop_batch(max as usize, locker)).await? {
+            Ok(items) => Ok(Response::new(PopReply { items: items.into_iter().map(|item| item as i64).collect() })),
+            Err(_) => Err(Status::failed_precondition("the buffer is closed")),
+        }
+    }
+
+    async fn stats(&self, _: Request<StatsRequest>) -> Result<Response<StatsReply>, Status> {
+        let stats = self.queue.stats();
+        let [n_ops, n_waits, n_wakes, n_steals] = stats.load();
+        let [n_evicted, n_rejected] = stats.drops();
+        Ok(Response::new(StatsReply {
+            n_items: self.queue.n_items() as u64, capacity: self.queue.capacity() as u64, n_ops, n_waits, n_wakes,
+            n_steals, peak_items: stats.peak_items(), n_evicted, n_rejected, closed: self.closed.load(Relaxed),
+        }))
+    }
+
+    async fn control(&self, request: Request<ControlRequest>) -> Result<Response<ControlReply>, Status> {
+        let request = request.into_inner();
+        match request.action() {
+            Action::Unspecified => return Err(Status::invalid_argument("no action given")),
+            Action::Close       => self.closed.store(true, Relaxed),
+            Action::Open        => self.closed.store(false, Relaxed),
+            Action::ResetPeak   => self.queue.stats().reset_peak(self.queue.n_items()),
+            Action::SetCapacity => match request.capacity {
+                None | Some(0) => return Err(Status::invalid_argument("`capacity` must be at least 1")),
+                Some(capacity) => if !self.blocking(move |queue, _| queue.set_capacity(capacity as usize)).await? {
+                    return Err(Status::unimplemented("this buffer's backend can't change its capacity"));
+                },
+            },
+            Action::Shutdown if !self.allow_shutdown => return Err(Status::permission_denied(
+                "this server doesn't allow shutting it down; start it with `--allow-shutdown true`",
+            )),
+            Action::Shutdown    => {
+                eprintln!("a client asked for a shutdown");
+                // so that the requests waiting on the buffer are answered, rather than holding up the shutdown
+                self.closed.store(true, Relaxed);
+                self.shutdown.notify_one();
+            },
+        }
+        Ok(Response::new(ControlReply {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+    use tonic::Code;
+    use rpc::{SyncedBoundedBuffer, swap::SwapBoundedBuffer};
+    use super::{*, proto::buffer_client::BufferClient};
+
+    fn control(action: Action) -> ControlRequest { ControlRequest { action: action as i32, capacity: None } }
+    fn set_capacity(capacity: u64) -> ControlRequest {
+        ControlRequest { action: Action::SetCapacity as i32, capacity: Some(capacity) }
+    }
+
+    // over the loopback, items go through in order, closing fails pushes and pops until reopened, and a shutdown
+    // answers a pop waiting on the buffer, then stops the server
+    #[test]
+    fn serves_over_the_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let queue = Arc::new(SyncedBoundedBuffer::new(4, false));
+        let server = thread::spawn(move || serve_on(listener, queue, Strategy::Block, true));
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut client = BufferClient::connect(format!("http://{}", address)).await.unwrap();
+            let pushed = client.push(PushRequest { items: vec![1, 2, 3] }).await.unwrap().into_inner();
+            assert_eq!((pushed.n_pushed, pushed.closed), (3, false));
+            assert_eq!(client.pop(PopRequest { max: Some(2) }).await.unwrap().into_inner().items, [1, 2]);
+            assert_eq!(client.stats(StatsRequest {}).await.unwrap().into_inner().n_items, 1);
+            assert_eq!(client.pop(PopRequest { max: Some(0) }).await.unwrap_err().code(), Code::InvalidArgument);
+
+            client.control(control(Action::Close)).await.unwrap();
+            assert_eq!(client.pop(PopRequest { max: None }).await.unwrap_err().code(), Code::FailedPrecondition);
+            let pushed = client.push(PushRequest { items: vec![4] }).await.unwrap().into_inner();
+            assert_eq!((pushed.n_pushed, pushed.closed), (0, true));
+            client.control(control(Action::Open)).await.unwrap();
+            assert_eq!(client.pop(PopRequest { max: None }).await.unwrap().into_inner().items, [3]);
+
+            let waiting = tokio::spawn({
+                let mut client = client.clone();
+                async move { client.pop(PopRequest { max: None }).await }
+            });
+            while client.stats(StatsRequest {}).await.unwrap().into_inner().n_waits == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            client.control(control(Action::Shutdown)).await.unwrap();
+            assert_eq!(waiting.await.unwrap().unwrap_err().code(), Code::FailedPrecondition);
+        });
+        server.join().unwrap().unwrap();
+    }
+
+    // shrinking the buffer below its occupancy answers once consumers have popped the items over the new capacity
+    #[test]
+    fn resizes_over_the_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let queue = Arc::new(SyncedBoundedBuffer::new(4, false));
+        let server = thread::spawn(move || serve_on(listener, queue, Strategy::Block, true));
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut client = BufferClient::connect(format!("http://{}", address)).await.unwrap();
+            client.push(PushRequest { items: vec![1, 2, 3, 4] }).await.unwrap();
+            assert_eq!(client.control(set_capacity(0)).await.unwrap_err().code(), Code::InvalidArgument);
+
+            let shrinking = tokio::spawn({
+                let mut client = client.clone();
+                async move { client.control(set_capacity(2)).await }
+            });
+            while client.stats(StatsRequest {}).await.unwrap().into_inner().capacity != 2 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            assert_eq!(client.pop(PopRequest { max: Some(1) }).await.unwrap().into_inner().items, [1]);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!shrinking.is_finished());
+            assert_eq!(client.pop(PopRequest { max: Some(1) }).await.unwrap().into_inner().items, [2]);
+            shrinking.await.unwrap().unwrap();
+            let stats = client.stats(StatsRequest {}).await.unwrap().into_inner();
+            assert_eq!((stats.capacity, stats.n_items), (2, 2));
+
+            client.control(set_capacity(3)).await.unwrap();
+            let pushed = client.push(PushRequest { items: vec![5] }).await.unwrap().into_inner();
+            assert_eq!(pushed.n_pushed, 1);
+            client.control(control(Action::Shutdown)).await.unwrap();
+        });
+        server.join().unwrap().unwrap();
+    }
+
+    // a backend which can't change its capacity says so
+    #[test]
+    fn resizing_needs_a_backend_which_can() {
+        let server = Server {
+            queue: Arc::new(SwapBoundedBuffer::new(4, false)), strategy: Strategy::Block,
+            closed: Arc::new(AtomicBool::new(false)), n_requests: AtomicUsize::new(0), allow_shutdown: false,
+            shutdown: Notify::new(),
+        };
+        let refused = tokio::runtime::Runtime::new().unwrap()
+            .block_on(server.control(Request::new(set_capacity(2))))
+            .unwrap_err();
+        assert_eq!(refused.code(), Code::Unimplemented);
+    }
+
+    // unless allowed, a shutdown is refused
+    #[test]
+    fn shutdown_is_opt_in() {
+        let server = Server {
+            queue: Arc::new(SyncedBoundedBuffer::new(4, false)), strategy: Strategy::Block,
+            closed: Arc::new(AtomicBool::new(false)), n_requests: AtomicUsize::new(0), allow_shutdown: false,
+            shutdown: Notify::new(),
+        };
+        let refused = tokio::runtime::Runtime::new().unwrap()
+            .block_on(server.control(Request::new(control(Action::Shutdown))))
+            .unwrap_err();
+        assert_eq!(refused.code(), Code::PermissionDenied);
+        assert!(!server.closed.load(Relaxed));
+    }
+}
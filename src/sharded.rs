@@ -0,0 +1,192 @@
+use std::sync::{Mutex, Condvar, atomic::{AtomicUsize, Ordering::Relaxed}};
+use crate::{
+    RingBuffer, Locker, Queue, Cancelled, eventcount::EventCount, stats::{Side, Stats},
+    echo::{self, Contents, Echoed},
+};
+
+struct Shard {
+    buffer: Mutex<RingBuffer<isize>>,
+    not_full: Condvar,
+}
+
+/* Bounded buffer split into independent shards, each with its own lock, so that threads working on different shards
+don't contend at all.
+Producer `i` always pushes to shard `i % n_shards`, waiting on that shard while it's full. Consumer `i` looks in shard
+`i % n_shards` first, and if that's empty steals from the others in turn, so no consumer idles while there are items
+anywhere, and no shard is left without consumers. Only if every shard is empty does a consumer sleep, on an eventcount
+shared by all the shards which every push notifies.
+Stealing can be turned off (`with_stealing(false)`) to measure what it gains: consumers then only ever pop from their
+own shard, so a shard whose producers are faster than its consumers fills up while others sit empty, and a shard with
+no consumer at all (fewer consumers than shards) is never emptied.
+Items are only in FIFO order within a shard. The capacity is split as evenly as it goes between the shards, the first
+`capacity % n_shards` getting a slot more than the rest, so that they add up to it.
+*/
+pub struct ShardedBoundedBuffer {
+    shards: Box<[Shard]>,
+    not_empty: EventCount,
+    // across all the shards; only for `Stats::occupancy`, as it's not updated atomically with them
+    n_items: AtomicUsize,
+    stats: Stats,
+    // pop from other shards when the consumer's own is empty
+    steal: bool,
+    // print a shard's contents after every operation on it (once its lock is released; see `SyncedBoundedBuffer`)
+    echo: bool,
+}
+impl ShardedBoundedBuffer {
+
+    // each shard gets `capacity / n_shards` slots, or one more for the remainder (and at least 1, so with fewer slots
+    // than shards the capacity is `n_shards`)
+    pub fn new(capacity: usize, n_shards: usize, echo: bool) -> Self {
+        assert!(n_shards > 0, "there must be at least one shard");
+        let shard_capacity = |i| (capacity / n_shards + usize::from(i < capacity % n_shards)).max(1);
+        ShardedBoundedBuffer {
+            shards: (0..n_shards).map(|i| Shard {
+                buffer: Mutex::new(RingBuffer::new(shard_capacity(i))), not_full: Condvar::new(),
+            }).collect(),
+            not_empty: EventCount::new(),
+            n_items: AtomicUsize::new(0),
+            stats: Stats::default(),
+            steal: true,
+            echo,
+        }
+    }
+
+    pub fn with_stealing(mut self, steal: bool) -> Self {
+        self.steal = steal;
+        self
+    }
+
+    pub fn n_shards(&self) -> usize { self.shards.len() }
+
+    // pop from the first non-empty shard, starting from `first` (and ending there, without stealing)
+    fn try_pop(&self, first: usize, locker: &mut Locker) -> Option<isize> {
+        let n_shards = self.shards.len();
+        let n_tried = if self.steal { n_shards } else { 1 };
+        for i in (0..n_tried).map(|k| (first + k) % n_shards) {
+            let shard = &self.shards[i];
+            let mut bbuf = locker.lock(&shard.buffer);
+            if bbuf.empty() { continue; }
+
+            let item = bbuf.pop();
+            self.n_items.fetch_sub(1, Relaxed);
+            let echoed = self.echo.then(|| {
+                let items = bbuf.iter().copied().collect();
+                Echoed::new(Side::Consumer, locker.id, Contents::Shard(i, items), bbuf.capacity())
+            });
+            drop(bbuf);
+            if let Some(echoed) = echoed { echo::print(echoed); }
+
+            if i != first { self.stats.steal(); }
+            self.stats.wake();
+            shard.not_full.notify_all();
+            return Some(item);
+        }
+        None
+    }
+}
+impl Queue for ShardedBoundedBuffer {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        locker.check()?;
+        let i = locker.id as usize % self.shards.len();
+        let shard = &self.shards[i];
+        let mut bbuf = locker.lock(&shard.buffer);
+        let mut n_waits = 0;
+        while bbuf.full() {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            self.stats.wait();
+            let waiting = self.stats.start_waiting(Side::Producer);
+            bbuf = match locker.poll_interval() {
+                None => shard.not_full.wait(bbuf).unwrap(),
+                Some(interval) => shard.not_full.wait_timeout(bbuf, interval).unwrap().0,
+            };
+            self.stats.stop_waiting(Side::Producer, waiting);
+            n_waits += 1;
+        }
+
+        bbuf.push(item);
+        self.stats.occupancy(self.n_items.fetch_add(1, Relaxed) + 1);
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Producer, locker.id, Contents::Shard(i, bbuf.iter().copied().collect()), bbuf.capacity())
+        });
+        drop(bbuf);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+
+        self.not_empty.notify_all(&self.stats);
+        self.stats.op();
+        Ok(())
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        let first = locker.id as usize % self.shards.len();
+        let mut n_waits = 0;
+        loop {
+            locker.check()?;
+            // see `EventCountBoundedBuffer`: a push after this can't be missed, even though we check each shard
+            // under a different lock
+            let key = self.not_empty.prepare_wait();
+            if let Some(item) = self.try_pop(first, locker) {
+                self.not_empty.cancel_wait();
+                self.stats.op();
+                return Ok(item);
+            }
+
+            if n_waits > 0 { locker.reblocked(); }
+            let waiting = self.stats.start_waiting(Side::Consumer);
+            self.not_empty.wait(key, locker.poll_interval(), &self.stats);
+            self.stats.stop_waiting(Side::Consumer, waiting);
+            n_waits += 1;
+        }
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { self.shards.iter().map(|shard| shard.buffer.lock().unwrap().capacity()).sum() }
+    fn n_items(&self) -> usize { self.shards.iter().map(|shard| shard.buffer.lock().unwrap().n_items()).sum() }
+    // shard by shard
+    fn snapshot(&self) -> Vec<isize> {
+        self.shards.iter().flat_map(|shard| shard.buffer.lock().unwrap().iter().copied().collect::<Vec<_>>()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Strategy;
+    use super::*;
+
+    fn locker(id: usize) -> Locker { Locker::new(Strategy::Block, "thread", id) }
+
+    fn shard_capacities(buffer: &ShardedBoundedBuffer) -> Vec<usize> {
+        buffer.shards.iter().map(|shard| shard.buffer.lock().unwrap().capacity()).collect()
+    }
+
+    // the remainder of the capacity goes to the first shards, so that the shards add up to it
+    #[test]
+    fn spreads_the_capacity_over_the_shards() {
+        let buffer = ShardedBoundedBuffer::new(10, 3, false);
+        assert_eq!((shard_capacities(&buffer), buffer.capacity()), (vec![4, 3, 3], 10));
+        let buffer = ShardedBoundedBuffer::new(12, 4, false);
+        assert_eq!((shard_capacities(&buffer), buffer.capacity()), (vec![3; 4], 12));
+    }
+
+    // with fewer slots than shards, every shard still gets one
+    #[test]
+    fn gives_every_shard_a_slot() {
+        let buffer = ShardedBoundedBuffer::new(2, 4, false);
+        assert_eq!((shard_capacities(&buffer), buffer.capacity()), (vec![1; 4], 4));
+    }
+
+    // producers fill their own shards, and a consumer steals from the others once its own is empty
+    #[test]
+    fn steals_from_other_shards() {
+        let buffer = ShardedBoundedBuffer::new(4, 2, false);
+        let (mut first, mut second) = (locker(0), locker(1));
+        buffer.push(1, &mut first).unwrap();
+        buffer.push(2, &mut second).unwrap();
+        buffer.push(3, &mut second).unwrap();
+        assert_eq!(buffer.snapshot(), [1, 2, 3]);
+        let popped: Vec<isize> = (0..3).map(|_| buffer.pop(&mut first).unwrap()).collect();
+        assert_eq!(popped, [1, 2, 3]);
+        assert_eq!(buffer.stats().n_steals.load(Relaxed), 2);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard},
+};
+use crate::closable::{ClosableBuffer, OnClose};
+
+struct Slot {
+    bytes: Box<[u8]>,
+    // how much of `bytes` the payload committed to it takes
+    len: usize,
+}
+
+/* Zero-copy handoff of large payloads: the buffer owns `n_slots` slots of `slot_bytes` bytes each, which producers
+`reserve` and write a payload straight into, then `commit`, and consumers `read` in place, releasing the slot once
+they're done with it, so that a payload is never copied, or allocated, on its way through.
+
+The slots' indices go round through two `ClosableBuffer`s, of free slots and of committed ones, in the order they were
+committed; each slot has a lock of its own, which only the thread with it reserved or being read ever takes, so that
+producers writing and consumers reading don't hold each other up.
+
+Closing fails the reservations in flight: a slot reserved before the close can still be written, but committing it
+then fails with `Closed`, and its payload is never read, as consumers only read what was committed before the close.
+*/
+pub struct SlotBuffer {
+    slots: Box<[Mutex<Slot>]>,
+    free: ClosableBuffer<usize>,
+    committed: ClosableBuffer<usize>,
+}
+impl SlotBuffer {
+
+    pub fn new(n_slots: usize, slot_bytes: usize) -> Self {
+        assert!(n_slots > 0, "a buffer with no slots can never be written to");
+        let free = ClosableBuffer::new(n_slots);
+        for index in 0..n_slots { free.push(index).unwrap(); }
+        SlotBuffer {
+            slots: (0..n_slots).map(|_| Mutex::new(Slot { bytes: vec![0; slot_bytes].into(), len: 0 })).collect(),
+            free, committed: ClosableBuffer::new(n_slots),
+        }
+    }
+
+    pub fn n_slots(&self) -> usize { self.slots.len() }
+    pub fn n_committed(&self) -> usize { self.committed.n_items() }
+
+    // wait for a free slot to write a payload into; `None` once closed
+    pub fn reserve(&self) -> Option<SlotGuard<'_>> {
+        let index = self.free.pop().ok()?;
+        Some(SlotGuard { buffer: self, index, slot: Some(self.slots[index].lock().unwrap()) })
+    }
+
+    // wait for the next payload committed, in order; `None` once closed, and every payload committed has been read
+    pub fn read(&self) -> Option<ReadGuard<'_>> {
+        let index = self.committed.pop().ok()?;
+        Some(ReadGuard { buffer: self, index, slot: Some(self.slots[index].lock().unwrap()) })
+    }
+
+    // stop producers reserving slots, including those waiting for one; consumers go on reading what's committed
+    pub fn close(&self) {
+        self.free.close_with(OnClose::Discard);
+        self.committed.close();
+    }
+}
+
+// the error from committing a slot to a `SlotBuffer` closed since it was reserved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
+
+// a slot reserved for writing a payload into, all `slot_bytes` of it; dropped without a `commit`, it's freed again
+pub struct SlotGuard<'a> {
+    buffer: &'a SlotBuffer,
+    index: usize,
+    // only `None` once committed or dropped
+    slot: Option<MutexGuard<'a, Slot>>,
+}
+impl SlotGuard<'_> {
+    // hand the first `len` bytes written to the consumers, unless the buffer was closed since the slot was reserved
+    pub fn commit(mut self, len: usize) -> Result<(), Closed> {
+        assert!(len <= self.len(), "a payload of {} bytes doesn't fit in a slot of {}", len, self.len());
+        let mut slot = self.slot.take().unwrap();
+        slot.len = len;
+        // unlocked before the consumers can get to it
+        drop(slot);
+        // once closed, the slot isn't freed either, as there's no one left to reserve it
+        self.buffer.committed.push(self.index).map_err(|_| Closed)
+    }
+}
+impl Deref for SlotGuard<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.slot.as_ref().unwrap().bytes }
+}
+impl DerefMut for SlotGuard<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] { &mut self.slot.as_mut().unwrap().bytes }
+}
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        let Some(mut slot) = self.slot.take() else { return };
+        slot.len = 0;
+        drop(slot);
+        // once closed, there's no one to hand it to
+        let _ = self.buffer.free.push(self.index);
+    }
+}
+
+// a committed payload being read in place; dropping it releases the slot for producers to reserve again
+pub struct ReadGuard<'a> {
+    buffer: &'a SlotBuffer,
+    index: usize,
+    // only `None` once dropped
+    slot: Option<MutexGuard<'a, Slot>>,
+}
+impl Deref for ReadGuard<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        let slot = self.slot.as_ref().unwrap();
+        &slot.bytes[..slot.len]
+    }
+}
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        drop(self.slot.take());
+        let _ = self.buffer.free.push(self.index);
+    }
+}
+
+// under shuttle, these would block outside of its scheduler
+#[cfg(all(test, not(feature = "shuttle")))]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    // payloads go through in the order they were committed, in the slots they were written into, and an abandoned
+    // reservation frees its slot
+    #[test]
+    fn hands_payloads_over_in_place() {
+        let buffer = SlotBuffer::new(2, 8);
+        let mut slot = buffer.reserve().unwrap();
+        slot[..3].copy_from_slice(b"abc");
+        let written = slot.as_ptr();
+        slot.commit(3).unwrap();
+        drop(buffer.reserve().unwrap());
+        let mut slot = buffer.reserve().unwrap();
+        slot[..2].copy_from_slice(b"de");
+        slot.commit(2).unwrap();
+        assert_eq!(buffer.n_committed(), 2);
+
+        let read = buffer.read().unwrap();
+        assert_eq!((&read[..], read.as_ptr()), (&b"abc"[..], written));
+        drop(read);
+        buffer.close();
+        assert!(buffer.reserve().is_none());
+        assert_eq!(&buffer.read().unwrap()[..], b"de");
+        assert!(buffer.read().is_none());
+    }
+
+    // a reservation in flight at the close fails to commit, and its payload is never read
+    #[test]
+    fn commits_fail_once_closed() {
+        let buffer = SlotBuffer::new(2, 8);
+        buffer.reserve().unwrap().commit(1).unwrap();
+        let slot = buffer.reserve().unwrap();
+        buffer.close();
+        assert_eq!(slot.commit(1), Err(Closed));
+        assert_eq!(buffer.read().unwrap().len(), 1);
+        assert!(buffer.read().is_none());
+    }
+
+    // with more payloads than slots, producers wait for consumers to release them, and none is lost
+    #[test]
+    fn producers_wait_for_slots() {
+        let buffer = SlotBuffer::new(2, 8);
+        let sum = thread::scope(|scope| {
+            scope.spawn(|| {
+                for n in 0..100u8 {
+                    let mut slot = buffer.reserve().unwrap();
+                    slot[0] = n;
+                    slot.commit(1).unwrap();
+                }
+                buffer.close();
+            });
+            let mut sum = 0;
+            while let Some(payload) = buffer.read() { sum += payload[0] as u32; }
+            sum
+        });
+        assert_eq!(sum, (0..100).sum::<u32>());
+    }
+}
@@ -0,0 +1,156 @@
+use core::{
+    cell::UnsafeCell,
+    hint,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering::{Acquire, Release, Relaxed}},
+};
+use crate::ring::BoundedBuffer;
+
+// A mutex which busy-waits instead of parking the thread, so it needs nothing but atomics.
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+// `data` is only accessed while holding the lock
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T> { lock: &'a SpinLock<T> }
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.lock.data.get() } }
+}
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.lock.data.get() } }
+}
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) { self.lock.locked.store(false, Release); }
+}
+impl<'a, T> SpinLockGuard<'a, T> {
+    // release the lock, keeping hold of it to take again
+    pub fn unlock(self) -> &'a SpinLock<T> { self.lock }
+}
+
+impl<T> SpinLock<T> {
+
+    pub const fn new(data: T) -> Self { SpinLock { locked: AtomicBool::new(false), data: UnsafeCell::new(data) } }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.locked.compare_exchange(false, true, Acquire, Relaxed).ok().map(|_| SpinLockGuard { lock: self })
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() { return guard; }
+            // wait with plain loads, so that waiting threads don't keep stealing the cache line from the holder
+            while self.locked.load(Relaxed) { hint::spin_loop(); }
+        }
+    }
+}
+
+/* Bounded buffer synchronized by a spinlock alone.
+There is no way to sleep without an OS, so `push` and `pop` spin (releasing the lock in between) until there is
+space/an item. This makes it usable on bare-metal targets, e.g. between an interrupt handler and the main loop via
+`try_push`/`try_pop`, which never wait for the lock either: an interrupt which hits while the main loop holds it would
+spin forever, so they fail as if the buffer were full/empty instead.
+*/
+pub struct SpinBoundedBuffer<T, const BOUND: usize> {
+    buffer: SpinLock<BoundedBuffer<T, BOUND>>,
+}
+impl<T: Default, const BOUND: usize> SpinBoundedBuffer<T, BOUND> {
+
+    pub fn new() -> Self { SpinBoundedBuffer { buffer: SpinLock::new(BoundedBuffer::new()) } }
+
+    // gives the item back if the buffer is full, or the lock is held
+    pub fn try_push(&self, item: T) -> Result<(), T> {
+        let Some(mut bbuf) = self.buffer.try_lock() else { return Err(item) };
+        if bbuf.full() { return Err(item); }
+        bbuf.push(item);
+        Ok(())
+    }
+
+    // `None` if the buffer is empty, or the lock is held
+    pub fn try_pop(&self) -> Option<T> {
+        let mut bbuf = self.buffer.try_lock()?;
+        if bbuf.empty() { None } else { Some(bbuf.pop()) }
+    }
+
+    pub fn push(&self, mut item: T) {
+        loop {
+            match self.try_push(item) {
+                Ok(()) => return,
+                Err(rejected) => item = rejected,
+            }
+            hint::spin_loop();
+        }
+    }
+
+    pub fn pop(&self) -> T {
+        loop {
+            if let Some(item) = self.try_pop() { return item; }
+            hint::spin_loop();
+        }
+    }
+
+    pub fn n_items(&self) -> usize { self.buffer.lock().n_items() }
+    pub fn empty  (&self) -> bool  { self.buffer.lock().empty()   }
+}
+impl<T: Default, const BOUND: usize> Default for SpinBoundedBuffer<T, BOUND> {
+    fn default() -> Self { Self::new() }
+}
+
+/* Sequential, and with few threads and items, so that they also run under Miri, which checks the unsafe code for
+undefined behaviour, and that every item is dropped exactly once: `cargo +nightly miri test --lib spin`.
+*/
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use alloc::{boxed::Box, string::{String, ToString}, sync::Arc, vec::Vec};
+    use std::thread;
+    use super::*;
+
+    // owned items, wrapping around the ring, some given back when it's full
+    #[test]
+    fn push_pop_owned_items() {
+        let buffer = SpinBoundedBuffer::<Option<Box<i32>>, 3>::new();
+        for round in 0..3 {
+            for k in 0..3 { buffer.push(Some(Box::new(round * 3 + k))); }
+            assert_eq!(buffer.try_push(Some(Box::new(-1))), Err(Some(Box::new(-1))));
+            for k in 0..3 { assert_eq!(buffer.pop(), Some(Box::new(round * 3 + k))); }
+            assert_eq!(buffer.try_pop(), None);
+        }
+    }
+
+    // as in an interrupt which hits while the main loop holds the lock, the non-blocking operations fail rather than
+    // spin
+    #[test]
+    fn try_ops_fail_while_locked() {
+        let buffer = SpinBoundedBuffer::<i32, 2>::new();
+        buffer.push(1);
+        let held = buffer.buffer.lock();
+        assert_eq!((buffer.try_push(2), buffer.try_pop()), (Err(2), None));
+        drop(held);
+        assert_eq!((buffer.try_push(2), buffer.try_pop()), (Ok(()), Some(1)));
+    }
+
+    // items still in the buffer are dropped with it
+    #[test]
+    fn drops_items_left() {
+        let buffer = SpinBoundedBuffer::<String, 4>::new();
+        for k in 0..3 { buffer.push(k.to_string()); }
+        assert_eq!(buffer.pop(), "0");
+        assert_eq!(buffer.n_items(), 2);
+    }
+
+    #[test]
+    fn handoff_between_threads() {
+        const N_ITEMS: usize = 20;
+        let buffer = Arc::new(SpinBoundedBuffer::<Option<Box<usize>>, 2>::new());
+        let consumer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || (0..N_ITEMS).map(|_| *buffer.pop().unwrap()).collect::<Vec<_>>())
+        };
+        for k in 0..N_ITEMS { buffer.push(Some(Box::new(k))); }
+        assert_eq!(consumer.join().unwrap(), (0..N_ITEMS).collect::<Vec<_>>());
+        assert!(buffer.empty());
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::thread;
+
+// why a buffer threw an item away instead of handing it to a consumer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    // the oldest item, to make room for a push (`Overflow::DropOldest`)
+    Evicted,
+    // the item being pushed, for lack of room (`Overflow::DropNewest`)
+    Rejected,
+}
+
+// which side of a buffer a thread is on, for counting the threads waiting there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    // waiting for room (`not_full`)
+    Producer,
+    // waiting for an item (`not_empty`)
+    Consumer,
+}
+
+/* Counters shared by all threads using a buffer.
+`n_waits` and `n_wakes` count the calls which may enter the kernel: for the futex backend these are exactly the
+`futex` syscalls made, for the condvar backend they are the `Condvar::wait` and `Condvar::notify_all` calls (which
+is a lower bound, since contention on the `Mutex` itself is invisible to us).
+`n_steals` counts the pops which the sharded backend took from a shard other than the consumer's own, and `n_drops`
+the items thrown away, by `DropReason`. `peak_items` is the most items the buffer has held at once. Unlike the others,
+`n_waiting` goes down as well as up: it's the number of threads blocked right now, by `Side`, for diagnosing a stalled
+run. `blocked_nanos` is the total time threads have spent blocked, by `Side`, counting each wait once it's over.
+*/
+#[derive(Default)]
+pub struct Stats {
+    pub n_ops: AtomicU64,
+    pub n_waits: AtomicU64,
+    pub n_wakes: AtomicU64,
+    pub n_steals: AtomicU64,
+    pub n_drops: [AtomicU64; 2],
+    pub peak_items: AtomicU64,
+    pub n_waiting: [AtomicU64; 2],
+    pub blocked_nanos: [AtomicU64; 2],
+}
+impl Stats {
+
+    pub fn op   (&self) { self.n_ops   .fetch_add(1, Relaxed); }
+    pub fn wait (&self) { self.n_waits .fetch_add(1, Relaxed); }
+    pub fn wake (&self) { self.n_wakes .fetch_add(1, Relaxed); }
+    pub fn steal(&self) { self.n_steals.fetch_add(1, Relaxed); }
+    pub fn dropped(&self, reason: DropReason) { self.n_drops[reason as usize].fetch_add(1, Relaxed); }
+
+    // called by a thread on `side` just before it blocks, and once it's woken, with what `start_waiting` returned
+    pub fn start_waiting(&self, side: Side) -> Instant {
+        self.n_waiting[side as usize].fetch_add(1, Relaxed);
+        Instant::now()
+    }
+    pub fn stop_waiting(&self, side: Side, since: Instant) {
+        self.blocked_nanos[side as usize].fetch_add(since.elapsed().as_nanos() as u64, Relaxed);
+        self.n_waiting[side as usize].fetch_sub(1, Relaxed);
+    }
+    // [producers, consumers]
+    pub fn n_waiting(&self) -> [u64; 2] { self.n_waiting.each_ref().map(|n| n.load(Relaxed)) }
+    // [producers, consumers]
+    pub fn blocked(&self) -> [Duration; 2] {
+        self.blocked_nanos.each_ref().map(|n| Duration::from_nanos(n.load(Relaxed)))
+    }
+
+    // called by pushes with the number of items they left the buffer holding
+    pub fn occupancy(&self, n_items: usize) { self.peak_items.fetch_max(n_items as u64, Relaxed); }
+    pub fn peak_items(&self) -> u64 { self.peak_items.load(Relaxed) }
+    // start over from the `n_items` the buffer holds now, e.g. after a warm-up
+    pub fn reset_peak(&self, n_items: usize) { self.peak_items.store(n_items as u64, Relaxed); }
+
+    // [evicted, rejected]
+    pub fn drops(&self) -> [u64; 2] { self.n_drops.each_ref().map(|n| n.load(Relaxed)) }
+
+    // [n_ops, n_waits, n_wakes, n_steals]
+    pub fn load(&self) -> [u64; 4] {
+        [self.n_ops.load(Relaxed), self.n_waits.load(Relaxed), self.n_wakes.load(Relaxed), self.n_steals.load(Relaxed)]
+    }
+
+    // print the rates of each counter to stderr every `interval`, forever (steals only once there have been
+    // any). Not available where threads can't sleep.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn monitor(&self, backend: &str, interval: Duration) -> ! {
+        let mut prev = self.load();
+        let mut prev_time = Instant::now();
+        loop {
+            thread::sleep(interval);
+
+            let now = self.load();
+            let now_time = Instant::now();
+            let secs = (now_time - prev_time).as_secs_f64();
+            let rate = |i: usize| (now[i] - prev[i]) as f64 / secs;
+            let steals = if now[3] > 0 { format!(", {:.0} steals/s", rate(3)) } else { String::new() };
+            eprintln!(
+                "[{}] {:.0} ops/s, {:.0} waits/s, {:.0} wakes/s{}",
+                backend, rate(0), rate(1), rate(2), steals,
+            );
+
+            prev = now;
+            prev_time = now_time;
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+use crate::{Locker, Queue, Cancelled, stats::{Side, Stats}, trace::{Op, Recorder}, echo::{self, Contents, Echoed}};
+
+/* Double buffering: the capacity is split into two halves, one which producers push to and one which consumers pop
+from, each under its own lock, so that producers and consumers never contend for a lock item by item. A consumer
+which finds its half empty swaps the two, taking whatever the producers have pushed so far (under both locks, taken in
+the order `draining`, then `filling`, as always), and a producer which finds its half full waits for that. Items in
+`draining` were all pushed before those in `filling`, so the buffer as a whole is still FIFO.
+*/
+pub struct SwapBoundedBuffer {
+    filling: Mutex<VecDeque<isize>>,
+    draining: Mutex<VecDeque<isize>>,
+    // each half's capacity
+    half: usize,
+    // signalled under `filling`'s lock once a swap has emptied it
+    not_full: Condvar,
+    // signalled under `draining`'s lock once a push has found `filling` empty, as consumers only wait when both are
+    not_empty: Condvar,
+    stats: Stats,
+    // print the buffer's contents after every operation (once the lock is released; see `SyncedBoundedBuffer`)
+    echo: bool,
+    recorder: Option<Arc<Recorder>>,
+}
+impl SwapBoundedBuffer {
+
+    // `capacity` is split evenly between the halves, rounding down
+    pub fn new(capacity: usize, echo: bool) -> Self {
+        assert!(capacity >= 2, "a double buffer needs room for at least one item in each half");
+        let half = capacity / 2;
+        SwapBoundedBuffer {
+            filling: Mutex::new(VecDeque::with_capacity(half)),
+            draining: Mutex::new(VecDeque::with_capacity(half)),
+            half,
+            not_full: Condvar::new(),
+            not_empty: Condvar::new(),
+            stats: Stats::default(),
+            echo,
+            recorder: None,
+        }
+    }
+
+    // record every operation in `recorder`, if there is one
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    // take the lock on `filling` with room in it, waiting for a swap if it's full
+    fn filling(&self, locker: &mut Locker) -> Result<MutexGuard<'_, VecDeque<isize>>, Cancelled> {
+        locker.check()?;
+        let mut filling = locker.lock(&self.filling);
+        let mut n_waits = 0;
+        while filling.len() == self.half {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            filling = self.wait(filling, &self.not_full, Side::Producer, locker);
+            n_waits += 1;
+        }
+        Ok(filling)
+    }
+
+    /* To be called once a push has released `filling`, with whether it found it empty: consumers may then be waiting,
+    having found both halves empty, and taking their lock to tell them means they can't be between that check and
+    their wait.
+    */
+    fn pushed(&self, was_empty: bool, locker: &mut Locker) {
+        if !was_empty { return; }
+        let _draining = locker.lock(&self.draining);
+        self.stats.wake();
+        self.not_empty.notify_all();
+    }
+
+    // take the lock on `draining` with at least one item in it, swapping the halves if it's empty
+    fn draining(&self, locker: &mut Locker) -> Result<MutexGuard<'_, VecDeque<isize>>, Cancelled> {
+        locker.check()?;
+        let mut draining = locker.lock(&self.draining);
+        let mut n_waits = 0;
+        while draining.is_empty() {
+            let mut filling = locker.lock(&self.filling);
+            if !filling.is_empty() {
+                mem::swap(&mut *draining, &mut *filling);
+                self.stats.wake();
+                self.not_full.notify_all();
+                break;
+            }
+            drop(filling);
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            draining = self.wait(draining, &self.not_empty, Side::Consumer, locker);
+            n_waits += 1;
+        }
+        Ok(draining)
+    }
+
+    // release the lock and wait on `condvar`, counting ourselves as waiting on `side` meanwhile
+    fn wait<'a>(
+        &self, guard: MutexGuard<'a, VecDeque<isize>>, condvar: &Condvar, side: Side, locker: &Locker,
+    ) -> MutexGuard<'a, VecDeque<isize>> {
+        self.stats.wait();
+        let waiting = self.stats.start_waiting(side);
+        let guard = match locker.poll_interval() {
+            None => condvar.wait(guard).unwrap(),
+            Some(interval) => condvar.wait_timeout(guard, interval).unwrap().0,
+        };
+        self.stats.stop_waiting(side, waiting);
+        guard
+    }
+}
+impl Queue for SwapBoundedBuffer {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        let mut filling = self.filling(locker)?;
+        let was_empty = filling.is_empty();
+        filling.push_back(item);
+        // only the producers' half, as counting the other would take the consumers' lock, so the peak is a lower bound
+        self.stats.occupancy(filling.len());
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Producer, locker.id, Contents::items(filling.iter()), self.half)
+        });
+        self.stats.op();
+        drop(filling);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        self.pushed(was_empty, locker);
+        Ok(())
+    }
+
+    // as many as fit at a time, letting consumers swap in between
+    fn push_batch(&self, items: &mut VecDeque<isize>, locker: &mut Locker) -> Result<(), Cancelled> {
+        while !items.is_empty() {
+            let mut filling = self.filling(locker)?;
+            let was_empty = filling.is_empty();
+            let n_pushed = items.len().min(self.half - filling.len());
+            for item in items.drain(..n_pushed) {
+                filling.push_back(item);
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+                self.stats.op();
+            }
+            self.stats.occupancy(filling.len());
+            let echoed = self.echo.then(|| {
+                Echoed::new(Side::Producer, locker.id, Contents::items(filling.iter()), self.half)
+            });
+            drop(filling);
+            if let Some(echoed) = echoed { echo::print(echoed); }
+            self.pushed(was_empty, locker);
+        }
+        Ok(())
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        let mut draining = self.draining(locker)?;
+        let item = draining.pop_front().unwrap();
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(draining.iter()), self.half)
+        });
+        self.stats.op();
+        drop(draining);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(item)
+    }
+
+    fn pop_batch(&self, max_len: usize, locker: &mut Locker) -> Result<Vec<isize>, Cancelled> {
+        let mut draining = self.draining(locker)?;
+        let n_popped = max_len.min(draining.len());
+        let batch: Vec<isize> = draining.drain(..n_popped).collect();
+        for &item in &batch {
+            if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+            self.stats.op();
+        }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(draining.iter()), self.half)
+        });
+        drop(draining);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(batch)
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { 2 * self.half }
+    fn n_items(&self) -> usize {
+        let draining = self.draining.lock().unwrap();
+        draining.len() + self.filling.lock().unwrap().len()
+    }
+    // oldest first: everything in `draining` was pushed before anything in `filling`
+    fn snapshot(&self) -> Vec<isize> {
+        let draining = self.draining.lock().unwrap();
+        draining.iter().chain(self.filling.lock().unwrap().iter()).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::{AtomicBool, Ordering::SeqCst}, thread, time::Duration};
+    use crate::{CancellationToken, Strategy};
+    use super::*;
+
+    fn locker(id: usize) -> Locker { Locker::new(Strategy::Block, "thread", id) }
+
+    // items come out in the order they went in across swaps, and a producer finding its half full waits for a
+    // consumer to swap, even with room in the other half
+    #[test]
+    fn swaps_in_order() {
+        let buffer = Arc::new(SwapBoundedBuffer::new(5, false));
+        assert_eq!(buffer.capacity(), 4);
+        let mut main = locker(0);
+        buffer.push_batch(&mut VecDeque::from([1, 2]), &mut main).unwrap();
+        assert_eq!(buffer.pop(&mut main), Ok(1));
+        buffer.push_batch(&mut VecDeque::from([3, 4]), &mut main).unwrap();
+        assert_eq!(buffer.snapshot(), [2, 3, 4]);
+
+        let pushed = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let (buffer, pushed) = (buffer.clone(), pushed.clone());
+            thread::spawn(move || {
+                buffer.push(5, &mut locker(1)).unwrap();
+                pushed.store(true, SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!pushed.load(SeqCst));
+        assert_eq!(buffer.pop(&mut main), Ok(2));
+        // swaps in 3 and 4, which makes room
+        assert_eq!(buffer.pop_batch(4, &mut main), Ok(vec![3, 4]));
+        producer.join().unwrap();
+        assert_eq!(buffer.pop(&mut main), Ok(5));
+        assert_eq!(buffer.n_items(), 0);
+    }
+
+    // a consumer waits with both halves empty until a push, and a producer with its half full until cancelled
+    #[test]
+    fn waits_until_pushed_or_cancelled() {
+        let buffer = Arc::new(SwapBoundedBuffer::new(2, false));
+        let consumer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.pop(&mut locker(1)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        buffer.push(1, &mut locker(0)).unwrap();
+        assert_eq!(consumer.join().unwrap(), Ok(1));
+
+        buffer.push(2, &mut locker(0)).unwrap();
+        let token = CancellationToken::new();
+        let producer = {
+            let (buffer, token) = (buffer.clone(), token.clone());
+            thread::spawn(move || buffer.push(3, &mut locker(0).with_cancellation(token)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        token.cancel();
+        assert_eq!(producer.join().unwrap(), Err(Cancelled));
+        assert_eq!(buffer.snapshot(), [2]);
+    }
+
+    // with several producers and consumers, every item comes out once, and each producer's in the order pushed
+    #[test]
+    fn loses_nothing_under_contention() {
+        const N_PRODUCERS: usize = 4;
+        const N_ITEMS: isize = 1000;
+        let buffer = SwapBoundedBuffer::new(8, false);
+        let popped: Vec<Vec<isize>> = thread::scope(|scope| {
+            for id in 0..N_PRODUCERS {
+                let buffer = &buffer;
+                scope.spawn(move || {
+                    for n in 0..N_ITEMS { buffer.push(id as isize * N_ITEMS + n, &mut locker(id)).unwrap(); }
+                });
+            }
+            let consumers: Vec<_> = (0..2).map(|id| {
+                let buffer = &buffer;
+                scope.spawn(move || {
+                    (0..N_PRODUCERS as isize * N_ITEMS / 2).map(|_| buffer.pop(&mut locker(id)).unwrap()).collect()
+                })
+            }).collect();
+            consumers.into_iter().map(|consumer| consumer.join().unwrap()).collect()
+        });
+        for items in &popped {
+            for id in 0..N_PRODUCERS as isize {
+                let theirs: Vec<_> = items.iter().filter(|&&item| item / N_ITEMS == id).collect();
+                assert!(theirs.is_sorted());
+            }
+        }
+        let mut all: Vec<_> = popped.concat();
+        all.sort();
+        assert_eq!(all, (0..N_PRODUCERS as isize * N_ITEMS).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,29 @@
+// Counting how often each thread has been switched out, from `/proc` (on Linux only).
+
+use std::{collections::BTreeMap, fs};
+
+#[derive(Clone, Copy, Default)]
+pub struct Switches {
+    // the thread blocked (e.g. waiting on a condvar), so the kernel ran something else
+    pub voluntary: u64,
+    // the kernel preempted it, e.g. at the end of its time slice
+    pub involuntary: u64,
+}
+
+/* Each of this process's threads, by thread id, with its name and how often it has been switched out so far. A thread
+which has only just been spawned may not have been given its name yet.
+*/
+pub fn threads() -> BTreeMap<u64, (String, Switches)> {
+    let Ok(tasks) = fs::read_dir("/proc/self/task") else { return BTreeMap::new() };
+    tasks.flatten().filter_map(|task| {
+        let id = task.file_name().to_str()?.parse().ok()?;
+        let status = fs::read_to_string(task.path().join("status")).ok()?;
+        let field = |key: &str| status.lines().find_map(|line| line.strip_prefix(key)).map(str::trim);
+        let name = field("Name:")?;
+        let switches = Switches {
+            voluntary: field("voluntary_ctxt_switches:")?.parse().ok()?,
+            involuntary: field("nonvoluntary_ctxt_switches:")?.parse().ok()?,
+        };
+        Some((id, (name.to_string(), switches)))
+    }).collect()
+}
@@ -0,0 +1,144 @@
+use std::{
+    sync::{Arc, mpsc::{self, Receiver, SyncSender, TrySendError}, atomic::{AtomicU64, Ordering::Relaxed}},
+    time::Instant,
+};
+use crate::{Hooked, Hooks, Locker, Queue, clock::{Clock, SystemClock}};
+
+// which of the items popped a tap passes on
+#[derive(Clone, Copy, Debug)]
+pub enum Sampling {
+    // every nth item, from the first
+    OneIn(u64),
+    // at most this many items a second: the first popped once the time since the last sample is up
+    PerSecond(f64),
+}
+
+/* A tap on a buffer, for watching live traffic without taking any part in it: in a `Tapped` buffer, every push and pop
+goes straight to the buffer, and a sample of the items popped is also sent to a receiver, e.g. a thread logging them.
+Sampling takes a counter, or a clock reading, and at most a send, so consumers hardly notice it. The samples wait in a
+channel of their own holding up to `backlog` of them; once the receiver falls that far behind, samples are missed (and
+counted) rather than holding up the consumer, which never waits for the receiver, and once it's gone they're not taken
+at all. A rate is by the real time, or the `Clock` given to `with_clock`.
+*/
+pub struct Tap {
+    sampling: Sampling,
+    samples: SyncSender<isize>,
+    n_popped: AtomicU64,
+    // for `Sampling::PerSecond`: when the next sample is due, in nanoseconds since `epoch`
+    epoch: Instant,
+    next_due: AtomicU64,
+    clock: Arc<dyn Clock>,
+    n_sampled: AtomicU64,
+    n_missed: AtomicU64,
+}
+impl Tap {
+
+    pub fn new(sampling: Sampling, backlog: usize) -> (Self, Receiver<isize>) {
+        match sampling {
+            Sampling::OneIn(n) => assert!(n > 0, "can't sample 1 in 0 items"),
+            Sampling::PerSecond(rate) => assert!(rate > 0.0, "the sampling rate must be positive"),
+        }
+        let (samples, receiver) = mpsc::sync_channel(backlog);
+        let tap = Tap {
+            sampling, samples, n_popped: AtomicU64::new(0), epoch: Instant::now(), next_due: AtomicU64::new(0),
+            clock: Arc::new(SystemClock), n_sampled: AtomicU64::new(0), n_missed: AtomicU64::new(0),
+        };
+        (tap, receiver)
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.epoch = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn sampling(&self) -> Sampling { self.sampling }
+    // the samples sent to the receiver so far, and those missed because it had a full backlog
+    pub fn n_sampled(&self) -> u64 { self.n_sampled.load(Relaxed) }
+    pub fn n_missed(&self) -> u64 { self.n_missed.load(Relaxed) }
+
+    fn offer(&self, item: isize) {
+        let due = match self.sampling {
+            Sampling::OneIn(n) => self.n_popped.fetch_add(1, Relaxed).is_multiple_of(n),
+            Sampling::PerSecond(rate) => {
+                let now = self.clock.now().saturating_duration_since(self.epoch).as_nanos() as u64;
+                let next_due = self.next_due.load(Relaxed);
+                // of consumers popping at once, only the one which moves the deadline on takes the sample
+                now >= next_due
+                    && self.next_due.compare_exchange(next_due, now + (1e9 / rate) as u64, Relaxed, Relaxed).is_ok()
+            },
+        };
+        if !due { return; }
+        match self.samples.try_send(item) {
+            Ok(()) => { self.n_sampled.fetch_add(1, Relaxed); },
+            Err(TrySendError::Full(_)) => { self.n_missed.fetch_add(1, Relaxed); },
+            Err(TrySendError::Disconnected(_)) => {},
+        }
+    }
+}
+impl Hooks for Tap {
+    fn after_pop(&self, popped: &[isize], _: &Locker) {
+        for &item in popped { self.offer(item); }
+    }
+}
+
+// a buffer with a `Tap` on it
+pub type Tapped = Hooked<Arc<dyn Queue>, Tap>;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::{Strategy, SyncedBoundedBuffer, clock::MockClock};
+    use super::*;
+
+    fn tapped(sampling: Sampling, backlog: usize) -> (Tapped, Receiver<isize>) {
+        let (tap, samples) = Tap::new(sampling, backlog);
+        (Hooked::new(Arc::new(SyncedBoundedBuffer::new(16, false)), tap), samples)
+    }
+
+    // every nth item popped is sampled, from the first, and the consumers still get every item
+    #[test]
+    fn samples_one_in_n() {
+        let (tapped, samples) = tapped(Sampling::OneIn(3), 16);
+        let mut locker = Locker::new(Strategy::Block, "consumer", 0);
+        tapped.push_batch(&mut (0..10).collect(), &mut locker).unwrap();
+        let mut popped = vec![tapped.pop(&mut locker).unwrap()];
+        popped.extend(tapped.pop_batch(9, &mut locker).unwrap());
+        assert_eq!(popped, (0..10).collect::<Vec<_>>());
+        assert_eq!(samples.try_iter().collect::<Vec<_>>(), [0, 3, 6, 9]);
+        assert_eq!((tapped.hooks().n_sampled(), tapped.hooks().n_missed()), (4, 0));
+    }
+
+    // once the backlog is full, samples are missed rather than holding up the consumer, and once the receiver is gone
+    // they're not taken at all
+    #[test]
+    fn misses_samples_rather_than_waiting() {
+        let (tapped, samples) = tapped(Sampling::OneIn(1), 2);
+        let mut locker = Locker::new(Strategy::Block, "consumer", 0);
+        tapped.push_batch(&mut (0..5).collect(), &mut locker).unwrap();
+        tapped.pop_batch(5, &mut locker).unwrap();
+        assert_eq!(samples.try_iter().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!((tapped.hooks().n_sampled(), tapped.hooks().n_missed()), (2, 3));
+
+        drop(samples);
+        tapped.push(5, &mut locker).unwrap();
+        assert_eq!(tapped.pop(&mut locker), Ok(5));
+        assert_eq!((tapped.hooks().n_sampled(), tapped.hooks().n_missed()), (2, 3));
+    }
+
+    // at a rate, the first item popped once the interval since the last sample is up is sampled
+    #[test]
+    fn samples_at_a_rate() {
+        let clock = Arc::new(MockClock::new());
+        let (tap, samples) = Tap::new(Sampling::PerSecond(2.0), 16);
+        let tapped: Tapped = Hooked::new(Arc::new(SyncedBoundedBuffer::new(16, false)), tap.with_clock(clock.clone()));
+        let mut locker = Locker::new(Strategy::Block, "consumer", 0);
+        tapped.push_batch(&mut (0..8).collect(), &mut locker).unwrap();
+        for n in 0..8 {
+            tapped.pop(&mut locker).unwrap();
+            if n % 2 == 1 { clock.advance(Duration::from_millis(300)); }
+        }
+        // due at 0ms, then 500ms (first reached at 600ms, by item 4), then 1100ms (not by the last pop, at 900ms)
+        assert_eq!(samples.try_iter().collect::<Vec<_>>(), [0, 4]);
+    }
+}
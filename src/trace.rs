@@ -0,0 +1,149 @@
+use std::{
+    io::{self, Read, Write},
+    sync::Mutex,
+    time::Instant,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op { Push, Pop }
+
+// one operation on a buffer; `thread` is the index of the producer (for pushes) or consumer (for pops), and `nanos`
+// when it took effect, since recording started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub op: Op,
+    pub thread: u32,
+    pub item: isize,
+    pub nanos: u64,
+}
+
+/* Records the global order of operations on a buffer.
+Backends call `record` while holding the buffer's lock, so the order of the events is exactly the order in which the
+operations took effect, and replaying them one at a time on a single thread reproduces the buffer's every state.
+*/
+pub struct Recorder {
+    epoch: Instant,
+    events: Mutex<Vec<Event>>,
+}
+impl Default for Recorder {
+    fn default() -> Self { Recorder { epoch: Instant::now(), events: Mutex::default() } }
+}
+impl Recorder {
+
+    pub fn record(&self, op: Op, thread: u32, item: isize) {
+        let nanos = self.epoch.elapsed().as_nanos() as u64;
+        self.events.lock().unwrap().push(Event { op, thread, item, nanos });
+    }
+
+    pub fn take(&self) -> Vec<Event> { std::mem::take(&mut self.events.lock().unwrap()) }
+}
+
+// a recording of a run: enough to replay it on a single thread, and with `timed`, at the pace it ran at
+pub struct Trace {
+    pub capacity: usize,
+    pub events: Vec<Event>,
+    // false for a file from before events were timed, whose events' `nanos` are all 0
+    pub timed: bool,
+}
+
+/* The trace file format: the magic bytes, the capacity (8 bytes, little-endian), then each event as
+    1 byte: 0 for a push, 1 for a pop
+    4 bytes: the thread index, little-endian
+    8 bytes: the item, little-endian
+    8 bytes: when it took effect, in nanoseconds since recording started, little-endian
+The first version had no times; it can still be read.
+*/
+const MAGIC: &[u8; 8] = b"PCTRACE2";
+const UNTIMED_MAGIC: &[u8; 8] = b"PCTRACE1";
+const EVENT_SIZE: usize = 21;
+const UNTIMED_EVENT_SIZE: usize = 13;
+
+impl Trace {
+
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&(self.capacity as u64).to_le_bytes())?;
+        for event in &self.events {
+            w.write_all(&[match event.op { Op::Push => 0, Op::Pop => 1 }])?;
+            w.write_all(&event.thread.to_le_bytes())?;
+            w.write_all(&(event.item as i64).to_le_bytes())?;
+            w.write_all(&event.nanos.to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    pub fn read_from(mut r: impl Read) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut header = [0; 16];
+        r.read_exact(&mut header)?;
+        let timed = match &header[..8] {
+            magic if magic == MAGIC => true,
+            magic if magic == UNTIMED_MAGIC => false,
+            _ => return Err(invalid("not a trace file")),
+        };
+        let capacity = u64::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        let event_size = if timed { EVENT_SIZE } else { UNTIMED_EVENT_SIZE };
+        if !bytes.len().is_multiple_of(event_size) { return Err(invalid("truncated trace file")); }
+
+        let events = bytes.chunks(event_size).map(|record| {
+            let op = match record[0] {
+                0 => Op::Push,
+                1 => Op::Pop,
+                _ => return Err(invalid("invalid operation in trace file")),
+            };
+            let thread = u32::from_le_bytes(record[1..5].try_into().unwrap());
+            let item = i64::from_le_bytes(record[5..13].try_into().unwrap()) as isize;
+            let nanos = if timed { u64::from_le_bytes(record[13..].try_into().unwrap()) } else { 0 };
+            Ok(Event { op, thread, item, nanos })
+        }).collect::<io::Result<_>>()?;
+
+        Ok(Trace { capacity, events, timed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(op: Op, thread: u32, item: isize, nanos: u64) -> Event { Event { op, thread, item, nanos } }
+
+    // what's written is read back, negative items and times included
+    #[test]
+    fn round_trips() {
+        let events = vec![event(Op::Push, 0, -7, 12), event(Op::Push, 3, isize::MAX, 40), event(Op::Pop, 1, -7, 95)];
+        let mut bytes = Vec::new();
+        Trace { capacity: 30, events: events.clone(), timed: true }.write_to(&mut bytes).unwrap();
+        assert_eq!((&bytes[..8], bytes.len()), (&MAGIC[..], 16 + 3 * EVENT_SIZE));
+        let trace = Trace::read_from(&bytes[..]).unwrap();
+        assert_eq!((trace.capacity, trace.events, trace.timed), (30, events, true));
+    }
+
+    // a file from before events were timed reads as untimed
+    #[test]
+    fn reads_untimed_traces() {
+        let mut bytes = UNTIMED_MAGIC.to_vec();
+        bytes.extend_from_slice(&4u64.to_le_bytes());
+        bytes.push(1);
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&9i64.to_le_bytes());
+        let trace = Trace::read_from(&bytes[..]).unwrap();
+        assert_eq!((trace.capacity, trace.events, trace.timed), (4, vec![event(Op::Pop, 2, 9, 0)], false));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let mut bytes = Vec::new();
+        Trace { capacity: 1, events: vec![event(Op::Push, 0, 1, 1)], timed: true }.write_to(&mut bytes).unwrap();
+        let error = |bytes: &[u8]| Trace::read_from(bytes).err().unwrap().to_string();
+        assert_eq!(error(&bytes[..bytes.len() - 1]), "truncated trace file");
+        let mut invalid = bytes.clone();
+        invalid[16] = 2;
+        assert_eq!(error(&invalid), "invalid operation in trace file");
+        invalid[..8].copy_from_slice(b"PCTRACE9");
+        assert_eq!(error(&invalid), "not a trace file");
+    }
+}
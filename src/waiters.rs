@@ -0,0 +1,278 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard, atomic::{AtomicBool, Ordering::{Acquire, Release}}},
+    thread::{self, Thread},
+    time::Instant,
+};
+use crate::{
+    RingBuffer, Locker, Queue, Cancelled, stats::{Side, Stats}, trace::{Op, Recorder},
+    echo::{self, Contents, Echoed},
+};
+
+// a blocked thread, parked until `woken` is set; each thread has one, which it queues whenever it waits
+struct Waiter {
+    thread: Thread,
+    woken: AtomicBool,
+}
+
+thread_local! {
+    // allocated the first time the thread waits, so that waiting again doesn't allocate
+    static WAITER: Arc<Waiter> = Arc::new(Waiter { thread: thread::current(), woken: AtomicBool::new(false) });
+}
+
+struct State {
+    ring: RingBuffer<isize>,
+    // the threads waiting for room and for an item, oldest first
+    producers: VecDeque<Arc<Waiter>>,
+    consumers: VecDeque<Arc<Waiter>>,
+}
+impl State {
+    fn waiters(&mut self, side: Side) -> &mut VecDeque<Arc<Waiter>> {
+        match side {
+            Side::Producer => &mut self.producers,
+            Side::Consumer => &mut self.consumers,
+        }
+    }
+}
+
+/* Bounded buffer whose blocked threads queue up to be woken one by one, each parked on its own, instead of sharing a
+condvar: a push wakes one waiting consumer for each item it adds, and a pop one waiting producer for each slot it
+frees, the ones which have waited longest, so no operation wakes threads which can't make progress, however many are
+waiting. A woken thread can still find that another got there first (one which wasn't waiting, and took the lock
+before it), in which case it queues again, at the back.
+Threads only wait while the buffer is empty (or full), and every item (or slot) either goes to a thread which wasn't
+waiting or wakes one which was, so none can be left waiting while there's something for it.
+*/
+pub struct WaiterQueueBoundedBuffer {
+    state: Mutex<State>,
+    stats: Stats,
+    // print the buffer's contents after every operation (once the lock is released; see `SyncedBoundedBuffer`)
+    echo: bool,
+    recorder: Option<Arc<Recorder>>,
+}
+impl WaiterQueueBoundedBuffer {
+
+    pub fn new(capacity: usize, echo: bool) -> Self {
+        assert!(capacity > 0, "a buffer with capacity 0 can never be pushed to");
+        WaiterQueueBoundedBuffer {
+            state: Mutex::new(State {
+                ring: RingBuffer::new(capacity), producers: VecDeque::new(), consumers: VecDeque::new(),
+            }),
+            stats: Stats::default(),
+            echo,
+            recorder: None,
+        }
+    }
+
+    // record every operation in `recorder`, if there is one
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /* Queue this thread on `side`, release the lock and park until an operation on the other side wakes it (or, with a
+    cancellation token, for at most the poll interval), then take the lock again. If it wasn't woken, it leaves the
+    queue; it's only ever woken under the lock, so that can't race with a wakeup. If it was, but its token was cancelled
+    meanwhile, it fails, and passes the wakeup on to the next thread waiting, which would otherwise go on waiting for an
+    item (or a slot) no one is going to take.
+    */
+    fn wait<'a>(
+        &'a self, mut state: MutexGuard<'a, State>, side: Side, locker: &mut Locker,
+    ) -> Result<MutexGuard<'a, State>, Cancelled> {
+        let waiter = WAITER.with(Arc::clone);
+        waiter.woken.store(false, Release);
+        state.waiters(side).push_back(waiter.clone());
+        drop(state);
+
+        self.stats.wait();
+        let waiting = self.stats.start_waiting(side);
+        match locker.poll_interval() {
+            None => while !waiter.woken.load(Acquire) { thread::park(); },
+            Some(interval) => {
+                let deadline = Instant::now() + interval;
+                while !waiter.woken.load(Acquire) {
+                    let now = Instant::now();
+                    if now >= deadline { break; }
+                    thread::park_timeout(deadline - now);
+                }
+            },
+        }
+        self.stats.stop_waiting(side, waiting);
+
+        let mut state = locker.lock(&self.state);
+        if !waiter.woken.load(Acquire) { state.waiters(side).retain(|queued| !Arc::ptr_eq(queued, &waiter)); }
+        else if let Err(cancelled) = locker.check() {
+            self.wake(&mut state, side, 1);
+            return Err(cancelled);
+        }
+        Ok(state)
+    }
+
+    // wake up to `n` of the threads waiting on `side`, oldest first; call while holding the lock
+    fn wake(&self, state: &mut State, side: Side, n: usize) {
+        let waiters = state.waiters(side);
+        for waiter in waiters.drain(..n.min(waiters.len())) {
+            waiter.woken.store(true, Release);
+            waiter.thread.unpark();
+            self.stats.wake();
+        }
+    }
+}
+// see `SyncedBoundedBuffer` for comments on the logic, which is the same but for waiting and waking
+impl Queue for WaiterQueueBoundedBuffer {
+
+    fn push(&self, item: isize, locker: &mut Locker) -> Result<(), Cancelled> {
+        locker.check()?;
+        let mut state = locker.lock(&self.state);
+        let mut n_waits = 0;
+        while state.ring.full() {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            state = self.wait(state, Side::Producer, locker)?;
+            n_waits += 1;
+        }
+
+        state.ring.push(item);
+        self.stats.occupancy(state.ring.n_items());
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Producer, locker.id, Contents::items(state.ring.iter()), state.ring.capacity())
+        });
+
+        self.wake(&mut state, Side::Consumer, 1);
+        self.stats.op();
+        drop(state);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(())
+    }
+
+    fn push_batch(&self, items: &mut VecDeque<isize>, locker: &mut Locker) -> Result<(), Cancelled> {
+        locker.check()?;
+        let mut state = locker.lock(&self.state);
+        let mut echoed = Vec::new();
+        while !items.is_empty() {
+            let mut n_waits = 0;
+            while state.ring.full() {
+                locker.check()?;
+                if n_waits > 0 { locker.reblocked(); }
+                state = self.wait(state, Side::Producer, locker)?;
+                n_waits += 1;
+            }
+
+            let n_pushed = items.len().min(state.ring.capacity() - state.ring.n_items());
+            for item in items.drain(..n_pushed) {
+                state.ring.push(item);
+                if let Some(recorder) = &self.recorder { recorder.record(Op::Push, locker.id, item); }
+                self.stats.op();
+            }
+            self.stats.occupancy(state.ring.n_items());
+            if self.echo {
+                let capacity = state.ring.capacity();
+                echoed.push(Echoed::new(Side::Producer, locker.id, Contents::items(state.ring.iter()), capacity));
+            }
+
+            self.wake(&mut state, Side::Consumer, n_pushed);
+        }
+        drop(state);
+        for echoed in echoed { echo::print(echoed); }
+        Ok(())
+    }
+
+    fn pop(&self, locker: &mut Locker) -> Result<isize, Cancelled> {
+        locker.check()?;
+        let mut state = locker.lock(&self.state);
+        let mut n_waits = 0;
+        while state.ring.empty() {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            state = self.wait(state, Side::Consumer, locker)?;
+            n_waits += 1;
+        }
+
+        let item = state.ring.pop();
+        if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(state.ring.iter()), state.ring.capacity())
+        });
+
+        self.wake(&mut state, Side::Producer, 1);
+        self.stats.op();
+        drop(state);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(item)
+    }
+
+    fn pop_batch(&self, max_len: usize, locker: &mut Locker) -> Result<Vec<isize>, Cancelled> {
+        locker.check()?;
+        let mut state = locker.lock(&self.state);
+        let mut n_waits = 0;
+        while state.ring.empty() {
+            locker.check()?;
+            if n_waits > 0 { locker.reblocked(); }
+            state = self.wait(state, Side::Consumer, locker)?;
+            n_waits += 1;
+        }
+
+        let batch: Vec<isize> = (0..max_len.min(state.ring.n_items())).map(|_| state.ring.pop()).collect();
+        for &item in &batch {
+            if let Some(recorder) = &self.recorder { recorder.record(Op::Pop, locker.id, item); }
+            self.stats.op();
+        }
+        let echoed = self.echo.then(|| {
+            Echoed::new(Side::Consumer, locker.id, Contents::items(state.ring.iter()), state.ring.capacity())
+        });
+
+        self.wake(&mut state, Side::Producer, batch.len());
+        drop(state);
+        if let Some(echoed) = echoed { echo::print(echoed); }
+        Ok(batch)
+    }
+
+    fn stats(&self) -> &Stats { &self.stats }
+    fn capacity(&self) -> usize { self.state.lock().unwrap().ring.capacity() }
+    fn n_items(&self) -> usize { self.state.lock().unwrap().ring.n_items() }
+    fn snapshot(&self) -> Vec<isize> { self.state.lock().unwrap().ring.iter().copied().collect() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread, time::Duration};
+    use crate::{CancellationToken, Strategy};
+    use super::*;
+
+    fn n_waiting(buffer: &WaiterQueueBoundedBuffer) -> usize { buffer.state.lock().unwrap().consumers.len() }
+
+    // a consumer woken for an item, but cancelled before it takes the lock again, hands the wakeup to the next
+    // consumer waiting, which gets the item
+    #[test]
+    fn cancelled_waiters_pass_their_wakeup_on() {
+        let buffer = Arc::new(WaiterQueueBoundedBuffer::new(1, false));
+        let token = CancellationToken::new();
+        let (results, received) = mpsc::channel();
+        let consumer = |locker: Locker| {
+            let (buffer, results) = (buffer.clone(), results.clone());
+            thread::spawn(move || results.send((locker.id, buffer.pop(&mut { locker }))).unwrap())
+        };
+        let cancelled = consumer(Locker::new(Strategy::Block, "consumer", 0).with_cancellation(token.clone()));
+        while n_waiting(&buffer) < 1 { thread::yield_now(); }
+        let waiting = consumer(Locker::new(Strategy::Block, "consumer", 1));
+        while n_waiting(&buffer) < 2 { thread::yield_now(); }
+
+        // wake the cancellable one, first put back at the front, as it queues again each time it polls its token; it
+        // can only see the cancellation once the lock is released
+        let mut state = buffer.state.lock().unwrap();
+        let index = state.consumers.iter().position(|waiter| waiter.thread.id() == cancelled.thread().id()).unwrap();
+        let waiter = state.consumers.remove(index).unwrap();
+        state.consumers.push_front(waiter);
+        state.ring.push(1);
+        buffer.wake(&mut state, Side::Consumer, 1);
+        token.cancel();
+        drop(state);
+
+        let mut got: Vec<_> = (0..2).map(|_| received.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+        got.sort_by_key(|&(id, _)| id);
+        assert_eq!(got, [(0, Err(Cancelled)), (1, Ok(1))]);
+        cancelled.join().unwrap();
+        waiting.join().unwrap();
+    }
+}